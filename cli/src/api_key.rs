@@ -0,0 +1,167 @@
+//! `api-key` subcommands to manage the API keys of `rowdy::auth::ApiKeyAuthenticator`
+use std::fs::File;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use rowdy::{Error, JsonValue};
+use rowdy::auth::api_key::{self, ApiKey, ApiKeys};
+use serde_json;
+
+use super::Args;
+
+/// Run the `api-key` subcommand in `args`
+pub fn run(args: &Args) -> Result<(), Error> {
+    let mut keys = read_keys(&args.arg_keys)?;
+
+    if args.cmd_add {
+        let key = add(&mut keys, args)?;
+        write_keys(&args.arg_keys, &keys)?;
+        println!("{}", key);
+    } else if args.cmd_remove {
+        remove(&mut keys, &args.arg_client_id)?;
+        write_keys(&args.arg_keys, &keys)?;
+    } else if args.cmd_list {
+        for line in list(&keys) {
+            println!("{}", line);
+        }
+    } else {
+        unreachable!("Should never happen");
+    }
+    Ok(())
+}
+
+/// Read the keys at `path`, or no keys if the file does not exist yet
+fn read_keys(path: &str) -> Result<ApiKeys, Error> {
+    if !Path::new(path).exists() {
+        return Ok(ApiKeys::new());
+    }
+    api_key::read_keys(File::open(path)?)
+}
+
+fn write_keys(path: &str, keys: &ApiKeys) -> Result<(), Error> {
+    api_key::write_keys(keys, File::create(path)?)
+}
+
+/// Add a new key for the client in `args` and return the key to give to the client
+fn add(keys: &mut ApiKeys, args: &Args) -> Result<String, Error> {
+    let client_id = &args.arg_client_id;
+    if client_id.is_empty() || client_id.contains(api_key::SEPARATOR) {
+        Err(format!("Client ID cannot be empty or contain `{}`", api_key::SEPARATOR))?;
+    }
+    if keys.contains_key(client_id) {
+        Err(format!("Client `{}` already has an API key. Remove it first.", client_id))?;
+    }
+
+    let secret = api_key::generate_secret()?;
+    let mut key = ApiKey::new(&secret)?;
+    key.subject = args.flag_subject.clone();
+    key.services = args.flag_service.clone();
+    if let Some(ref claims) = args.flag_claims {
+        let claims: JsonValue = serde_json::from_str(claims).map_err(|e| e.to_string())?;
+        if !claims.is_object() {
+            Err("Private claims must be a JSON map".to_string())?;
+        }
+        key.private_claims = claims;
+    }
+    if let Some(ref expires) = args.flag_expires {
+        let expires = DateTime::parse_from_rfc3339(expires).map_err(|e| e.to_string())?;
+        key.expires_at = Some(expires.with_timezone(&Utc));
+    }
+
+    let _ = keys.insert(client_id.to_string(), key);
+    Ok(format!("{}{}{}", client_id, api_key::SEPARATOR, secret))
+}
+
+fn remove(keys: &mut ApiKeys, client_id: &str) -> Result<(), Error> {
+    match keys.remove(client_id) {
+        Some(_) => Ok(()),
+        None => Err(format!("Client `{}` does not have an API key", client_id))?,
+    }
+}
+
+/// Describe each key, without its secret
+fn list(keys: &ApiKeys) -> Vec<String> {
+    keys.iter()
+        .map(|(client_id, key)| {
+            let subject = key.subject.as_ref().unwrap_or(client_id);
+            let services = if key.services.is_empty() {
+                "all services".to_string()
+            } else {
+                key.services.join(", ")
+            };
+            let expiry = match key.expires_at {
+                Some(expires_at) if key.is_expired() => format!("expired at {}", expires_at.to_rfc3339()),
+                Some(expires_at) => format!("expires at {}", expires_at.to_rfc3339()),
+                None => "does not expire".to_string(),
+            };
+            format!("{}\tsubject: {}\t{}\t{}", client_id, subject, services, expiry)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rowdy::auth::{ApiKeyAuthenticator, Authenticator, Authorization};
+
+    use super::*;
+
+    fn add_args(client_id: &str) -> Args {
+        Args {
+            arg_keys: "keys.json".to_string(),
+            arg_client_id: client_id.to_string(),
+            cmd_api_key: true,
+            cmd_add: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn added_keys_authenticate() {
+        let mut keys = ApiKeys::new();
+        let mut args = add_args("registry-ci");
+        args.flag_subject = Some("ci".to_string());
+        args.flag_service = vec!["https://registry.acme.com".to_string()];
+        args.flag_claims = Some(r#"{ "team": "infrastructure" }"#.to_string());
+        args.flag_expires = Some("2100-01-01T00:00:00+08:00".to_string());
+
+        let key = add(&mut keys, &args).unwrap();
+        assert!(key.starts_with("registry-ci:"));
+
+        let authenticator = ApiKeyAuthenticator::new(keys);
+        let authorization = Authorization::<String>::new(&key).unwrap();
+        let result = authenticator.authenticate(&authorization, false).unwrap();
+        assert_eq!(result.subject, "ci");
+        assert_eq!(result.private_claims["team"], "infrastructure");
+        authenticator
+            .verify_client_service(&result, "https://registry.acme.com")
+            .unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "already has an API key")]
+    fn existing_clients_are_not_replaced() {
+        let mut keys = ApiKeys::new();
+        let _ = add(&mut keys, &add_args("registry-ci")).unwrap();
+        let _ = add(&mut keys, &add_args("registry-ci")).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot be empty or contain `:`")]
+    fn client_ids_cannot_contain_the_separator() {
+        let mut keys = ApiKeys::new();
+        let _ = add(&mut keys, &add_args("registry:ci")).unwrap();
+    }
+
+    #[test]
+    fn keys_are_removed_and_listed() {
+        let mut keys = ApiKeys::new();
+        let _ = add(&mut keys, &add_args("deployer")).unwrap();
+        let _ = add(&mut keys, &add_args("registry-ci")).unwrap();
+
+        remove(&mut keys, "deployer").unwrap();
+        assert!(remove(&mut keys, "deployer").is_err());
+
+        let lines = list(&keys);
+        assert_eq!(lines, vec!["registry-ci\tsubject: registry-ci\tall services\tdoes not expire"]);
+    }
+}
@@ -0,0 +1,413 @@
+//! Reading of configuration files.
+//!
+//! Configuration files can be written in JSON, TOML or YAML. The format is picked based on the extension of the
+//! file: `.toml` for TOML, `.yaml` or `.yml` for YAML, and JSON for everything else.
+//!
+//! Once parsed, the configuration goes through the following steps, in order, before it is deserialized:
+//!
+//! 1. `${ENV_VAR}` in any string value is replaced with the value of the environment variable `ENV_VAR`. It is an
+//! error to refer to a variable that is not set. Use `$${` to write a literal `${`.
+//! 1. Environment variables prefixed with `ROWDY_` are overlaid onto the configuration. Nested keys are separated
+//! with a double underscore, and matched case insensitively. For example,
+//! `ROWDY_BASIC_AUTHENTICATOR__BIND_PASSWORD` sets the `bind_password` key of `basic_authenticator`. Array
+//! elements are referred to by their index. If the key being overridden is a string, the value is used verbatim.
+//! If the key is missing or `null`, the value is used verbatim too, unless it is a JSON object or array. Otherwise,
+//! the value is parsed as JSON, falling back to a string if that fails.
+//! 1. Any key with the suffix `_file` is replaced with the key without the suffix, whose value is the content of
+//! the file at the path provided. Trailing newlines are removed. This allows secrets like `bind_password` to be
+//! mounted from a file with `bind_password_file`.
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde_json::{self, Map, Value};
+use serde_yaml;
+use toml;
+
+/// Prefix of environment variables that are overlaid onto the configuration
+const ENV_PREFIX: &'static str = "ROWDY_";
+/// Separator for nested keys in the names of overlaid environment variables
+const ENV_SEPARATOR: &'static str = "__";
+/// Suffix of keys whose values are read from a file
+const FILE_SUFFIX: &'static str = "_file";
+
+/// Format of a configuration file
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Format {
+    /// JSON
+    Json,
+    /// TOML
+    Toml,
+    /// YAML
+    Yaml,
+}
+
+impl Format {
+    /// Determine the format of the file from its extension. Defaults to JSON.
+    pub fn from_path(path: &str) -> Self {
+        match Path::new(path).extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => Format::Toml,
+            Some("yaml") | Some("yml") => Format::Yaml,
+            _ => Format::Json,
+        }
+    }
+}
+
+/// Read the configuration file at `path`, using the environment of the current process
+pub fn read_config<T>(path: &str) -> Result<T, String>
+where
+    T: DeserializeOwned,
+{
+    info_!("Reading configuration from '{}'", path);
+    let contents = read_file(path)?;
+    let vars: Vec<(String, String)> = env::vars().collect();
+    from_str(&contents, Format::from_path(path), &vars)
+}
+
+/// Parse and deserialize the configuration from `contents`, using `vars` as the environment
+pub fn from_str<T>(contents: &str, format: Format, vars: &[(String, String)]) -> Result<T, String>
+where
+    T: DeserializeOwned,
+{
+    let mut value = parse(contents, format)?;
+    interpolate(&mut value, vars)?;
+    overlay(&mut value, vars)?;
+    resolve_files(&mut value)?;
+    serde_json::from_value(value).map_err(|e| format!("{:?}", e))
+}
+
+fn read_file(path: &str) -> Result<String, String> {
+    let mut file = File::open(path).map_err(|e| format!("{:?}", e))?;
+    let mut contents = String::new();
+    let _ = file.read_to_string(&mut contents).map_err(
+        |e| format!("{:?}", e),
+    )?;
+    Ok(contents)
+}
+
+fn parse(contents: &str, format: Format) -> Result<Value, String> {
+    match format {
+        Format::Json => serde_json::from_str(contents).map_err(|e| format!("{:?}", e)),
+        Format::Toml => toml::from_str(contents).map_err(|e| format!("{:?}", e)),
+        Format::Yaml => serde_yaml::from_str(contents).map_err(|e| format!("{:?}", e)),
+    }
+}
+
+fn lookup<'a>(vars: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    vars.iter().find(|&&(ref key, _)| key == name).map(
+        |&(_, ref value)| value.as_str(),
+    )
+}
+
+/// Replace `${ENV_VAR}` in all string values
+fn interpolate(value: &mut Value, vars: &[(String, String)]) -> Result<(), String> {
+    match *value {
+        Value::String(ref mut string) => {
+            let interpolated = interpolate_str(string, vars)?;
+            *string = interpolated;
+        }
+        Value::Array(ref mut array) => {
+            for value in array.iter_mut() {
+                interpolate(value, vars)?;
+            }
+        }
+        Value::Object(ref mut map) => {
+            for (_, value) in map.iter_mut() {
+                interpolate(value, vars)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn interpolate_str(input: &str, vars: &[(String, String)]) -> Result<String, String> {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find("${") {
+        let (before, after) = rest.split_at(start);
+        if before.ends_with('$') {
+            // Escaped with `$${`
+            output.push_str(&before[..before.len() - 1]);
+            output.push_str("${");
+            rest = &after[2..];
+            continue;
+        }
+
+        output.push_str(before);
+        let end = after.find('}').ok_or_else(|| {
+            format!("Unterminated `${{` in configuration value `{}`", input)
+        })?;
+        let name = &after[2..end];
+        let value = lookup(vars, name).ok_or_else(|| {
+            format!("Environment variable `{}` used in the configuration is not set", name)
+        })?;
+        output.push_str(value);
+        rest = &after[end + 1..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// Overlay `ROWDY_` prefixed environment variables onto the configuration
+fn overlay(value: &mut Value, vars: &[(String, String)]) -> Result<(), String> {
+    for &(ref name, ref raw) in vars {
+        if !name.starts_with(ENV_PREFIX) {
+            continue;
+        }
+
+        let path: Vec<String> = name[ENV_PREFIX.len()..]
+            .split(ENV_SEPARATOR)
+            .map(|segment| segment.to_lowercase())
+            .collect();
+        if path.iter().any(|segment| segment.is_empty()) {
+            Err(format!("Invalid configuration override `{}`", name))?;
+        }
+
+        let new_value = match get_path(value, &path) {
+            Some(&Value::String(_)) => Value::String(raw.to_string()),
+            // Secrets kept out of the file, like `bind_password`, must not become numbers or booleans
+            None | Some(&Value::Null) => {
+                match serde_json::from_str(raw) {
+                    Ok(parsed @ Value::Object(_)) |
+                    Ok(parsed @ Value::Array(_)) => parsed,
+                    _ => Value::String(raw.to_string()),
+                }
+            }
+            Some(_) => serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string())),
+        };
+
+        debug_!("Overriding configuration with `{}`", name);
+        set_path(value, &path, new_value).map_err(|e| {
+            format!("Unable to apply `{}`: {}", name, e)
+        })?;
+    }
+    Ok(())
+}
+
+/// Find the key in the map that matches `segment` case insensitively
+fn find_key(map: &Map<String, Value>, segment: &str) -> Option<String> {
+    map.keys()
+        .find(|key| key.to_lowercase() == segment)
+        .cloned()
+}
+
+fn get_path<'a>(value: &'a Value, path: &[String]) -> Option<&'a Value> {
+    match path.split_first() {
+        None => Some(value),
+        Some((segment, rest)) => {
+            let child = match *value {
+                Value::Object(ref map) => find_key(map, segment).and_then(|key| map.get(&key)),
+                Value::Array(ref array) => segment.parse::<usize>().ok().and_then(|index| array.get(index)),
+                _ => None,
+            };
+            child.and_then(|child| get_path(child, rest))
+        }
+    }
+}
+
+fn set_path(target: &mut Value, path: &[String], new_value: Value) -> Result<(), String> {
+    let (segment, rest) = match path.split_first() {
+        None => {
+            *target = new_value;
+            return Ok(());
+        }
+        Some(split) => split,
+    };
+
+    if target.is_null() {
+        *target = Value::Object(Map::new());
+    }
+
+    match *target {
+        Value::Object(ref mut map) => {
+            let key = find_key(map, segment).unwrap_or_else(|| segment.to_string());
+            if !map.contains_key(&key) {
+                let _ = map.insert(key.clone(), Value::Null);
+            }
+            set_path(map.get_mut(&key).unwrap(), rest, new_value) // safe to unwrap
+        }
+        Value::Array(ref mut array) => {
+            let index: usize = segment.parse().map_err(
+                |_| format!("`{}` is not an array index", segment),
+            )?;
+            match array.get_mut(index) {
+                Some(element) => set_path(element, rest, new_value),
+                None => Err(format!("Array index {} is out of bounds", index)),
+            }
+        }
+        _ => Err(format!("`{}` cannot be set on a value that is not a map", segment)),
+    }
+}
+
+/// Replace `*_file` keys with the content of the files they refer to
+fn resolve_files(value: &mut Value) -> Result<(), String> {
+    match *value {
+        Value::Object(ref mut map) => {
+            let file_keys: Vec<String> = map.keys()
+                .filter(|key| key.len() > FILE_SUFFIX.len() && key.ends_with(FILE_SUFFIX))
+                .cloned()
+                .collect();
+
+            for file_key in file_keys {
+                let key = file_key[..file_key.len() - FILE_SUFFIX.len()].to_string();
+                if map.contains_key(&key) {
+                    Err(format!("Only one of `{}` and `{}` can be set", key, file_key))?;
+                }
+                let path = match map.remove(&file_key) {
+                    Some(Value::String(path)) => path,
+                    _ => Err(format!("`{}` must be a path to a file", file_key))?,
+                };
+                debug_!("Reading `{}` from '{}'", key, path);
+                let contents = read_file(&path)?;
+                let _ = map.insert(key, Value::String(trim_trailing_newlines(&contents).to_string()));
+            }
+
+            for (_, value) in map.iter_mut() {
+                resolve_files(value)?;
+            }
+        }
+        Value::Array(ref mut array) => {
+            for value in array.iter_mut() {
+                resolve_files(value)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn trim_trailing_newlines(contents: &str) -> &str {
+    contents.trim_right_matches(|c| c == '\n' || c == '\r')
+}
+
+#[cfg(test)]
+mod tests {
+    use rowdy::{self, auth};
+    use super::*;
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|&(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn format_is_determined_from_extension() {
+        assert_eq!(Format::Json, Format::from_path("config.json"));
+        assert_eq!(Format::Toml, Format::from_path("config/config.toml"));
+        assert_eq!(Format::Yaml, Format::from_path("config.yaml"));
+        assert_eq!(Format::Yaml, Format::from_path("config.yml"));
+        assert_eq!(Format::Json, Format::from_path("config"));
+    }
+
+    #[test]
+    fn json_toml_and_yaml_are_equivalent() {
+        let json: Value = read_config("test/fixtures/config_noop.json").unwrap();
+        let toml: Value = read_config("test/fixtures/config_noop.toml").unwrap();
+        let yaml: Value = read_config("test/fixtures/config_noop.yaml").unwrap();
+
+        assert_eq!(json["token"], toml["token"]);
+        assert_eq!(json["token"], yaml["token"]);
+        assert_eq!(json["basic_authenticator"], toml["basic_authenticator"]);
+        assert_eq!(json["basic_authenticator"], yaml["basic_authenticator"]);
+    }
+
+    #[test]
+    fn toml_and_yaml_configuration_ignites() {
+        for path in &["test/fixtures/config_noop.toml", "test/fixtures/config_noop.yaml"] {
+            let config: rowdy::Configuration<auth::NoOpConfiguration> = read_config(path).unwrap();
+            let _ = config.ignite().unwrap();
+        }
+    }
+
+    #[test]
+    fn environment_variables_are_interpolated() {
+        let json = r#"{ "password": "${PASSWORD}", "url": "ldap://${HOST}:389", "literal": "$${HOST}" }"#;
+        let vars = vars(&[("PASSWORD", "hunter2"), ("HOST", "ldap.acme.com")]);
+
+        let value: Value = from_str(json, Format::Json, &vars).unwrap();
+        assert_eq!(value["password"], "hunter2");
+        assert_eq!(value["url"], "ldap://ldap.acme.com:389");
+        assert_eq!(value["literal"], "${HOST}");
+    }
+
+    #[test]
+    #[should_panic(expected = "`MISSING` used in the configuration is not set")]
+    fn missing_environment_variables_are_errors() {
+        let json = r#"{ "password": "${MISSING}" }"#;
+        let _: Value = from_str(json, Format::Json, &[]).unwrap();
+    }
+
+    #[test]
+    fn environment_overlay_is_applied() {
+        let json = r#"{
+            "token": { "expiry_duration": 86400, "allowed_origins": { "Some": ["https://www.example.com"] } },
+            "basic_authenticator": { "bind_password": "password", "search_filter": null }
+        }"#;
+        let vars = vars(
+            &[
+                ("ROWDY_TOKEN__EXPIRY_DURATION", "3600"),
+                ("ROWDY_TOKEN__ALLOWED_ORIGINS__SOME__0", "https://www.acme.com"),
+                ("ROWDY_BASIC_AUTHENTICATOR__BIND_PASSWORD", "1234"),
+                ("ROWDY_BASIC_AUTHENTICATOR__SEARCH_FILTER", "(uid={account})"),
+                ("ROWDY_BASIC_AUTHENTICATOR__INCLUDE_ATTRIBUTES", r#"["cn"]"#),
+                ("UNRELATED", "ignored"),
+            ],
+        );
+
+        let value: Value = from_str(json, Format::Json, &vars).unwrap();
+        assert_eq!(value["token"]["expiry_duration"], 3600);
+        assert_eq!(value["token"]["allowed_origins"]["Some"][0], "https://www.acme.com");
+        assert_eq!(value["basic_authenticator"]["bind_password"], "1234");
+        assert_eq!(value["basic_authenticator"]["search_filter"], "(uid={account})");
+        assert_eq!(value["basic_authenticator"]["include_attributes"][0], "cn");
+        assert!(value.get("unrelated").is_none());
+    }
+
+    #[test]
+    fn environment_overlay_of_missing_keys_is_a_string() {
+        let json = r#"{ "basic_authenticator": { "search_filter": null } }"#;
+        let vars = vars(
+            &[
+                ("ROWDY_BASIC_AUTHENTICATOR__BIND_PASSWORD", "123456"),
+                ("ROWDY_BASIC_AUTHENTICATOR__BIND_DN", "true"),
+                ("ROWDY_BASIC_AUTHENTICATOR__SEARCH_FILTER", "null"),
+                ("ROWDY_BASIC_AUTHENTICATOR__INCLUDE_ATTRIBUTES", r#"["cn"]"#),
+            ],
+        );
+
+        let value: Value = from_str(json, Format::Json, &vars).unwrap();
+        assert_eq!(value["basic_authenticator"]["bind_password"], "123456");
+        assert_eq!(value["basic_authenticator"]["bind_dn"], "true");
+        assert_eq!(value["basic_authenticator"]["search_filter"], "null");
+        assert_eq!(value["basic_authenticator"]["include_attributes"][0], "cn");
+    }
+
+    #[test]
+    fn file_indirection_is_resolved() {
+        let json = r#"{ "basic_authenticator": { "bind_password_file": "test/fixtures/bind_password.txt" } }"#;
+
+        let value: Value = from_str(json, Format::Json, &[]).unwrap();
+        assert_eq!(value["basic_authenticator"]["bind_password"], "password");
+        assert!(value["basic_authenticator"].get("bind_password_file").is_none());
+    }
+
+    #[test]
+    fn file_indirection_can_be_set_from_environment() {
+        let json = r#"{ "basic_authenticator": {} }"#;
+        let vars = vars(&[("ROWDY_BASIC_AUTHENTICATOR__BIND_PASSWORD_FILE", "test/fixtures/bind_password.txt")]);
+
+        let value: Value = from_str(json, Format::Json, &vars).unwrap();
+        assert_eq!(value["basic_authenticator"]["bind_password"], "password");
+    }
+
+    #[test]
+    #[should_panic(expected = "Only one of `bind_password` and `bind_password_file` can be set")]
+    fn file_indirection_conflicts_are_errors() {
+        let json = r#"{ "bind_password": "password", "bind_password_file": "test/fixtures/bind_password.txt" }"#;
+        let _: Value = from_str(json, Format::Json, &[]).unwrap();
+    }
+}
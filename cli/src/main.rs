@@ -1,6 +1,7 @@
 #![feature(plugin)]
 #![plugin(rocket_codegen)]
 
+extern crate chrono;
 extern crate docopt;
 #[macro_use]
 extern crate log;
@@ -10,6 +11,12 @@ extern crate rowdy;
 extern crate rustc_serialize;
 extern crate serde;
 extern crate serde_json;
+extern crate serde_yaml;
+extern crate toml;
+
+mod api_key;
+mod config;
+mod totp;
 
 use docopt::Docopt;
 use rowdy::auth;
@@ -19,89 +26,141 @@ const USAGE: &'static str = r#"
 rowdy JSON Web Token Authentication Server
 
 Usage:
-  rowdy noop <configuration-json>
-  rowdy csv <configuration-json>
-  rowdy ldap <configuration-json>
+  rowdy serve <configuration>
+  rowdy noop <configuration>
+  rowdy csv <configuration>
+  rowdy ldap <configuration>
+  rowdy api-key add <keys> <client-id> [--subject=<subject>] [--service=<service>...] [--claims=<claims>]
+                    [--expires=<expires>]
+  rowdy api-key remove <keys> <client-id>
+  rowdy api-key list <keys>
+  rowdy totp enroll <configuration> <username>
+  rowdy totp remove <configuration> <username>
   rowdy (-h | --help)
 
-Provide a configuration JSON file to run `rowdy` with. For available fields and examples for the JSON
+Provide a configuration file to run `rowdy` with. For available fields and examples for the JSON
 configuration, refer to the documentation at https://lawliet89.github.io/rowdy/rowdy/struct.Configuration.html
 
+The configuration file can be written in JSON, TOML (`.toml`) or YAML (`.yaml` or `.yml`), and is picked based on
+the file extension. In any string value, `${ENV_VAR}` is replaced with the value of the environment variable.
+Any key with the suffix `_file` (for example, `bind_password_file`) is replaced by the key without the suffix, with
+the content of the file at the path provided as its value.
+
+Environment variables prefixed with `ROWDY_` override keys in the configuration. Nested keys are separated with
+`__`. For example, `ROWDY_BASIC_AUTHENTICATOR__BIND_PASSWORD` sets the `bind_password` key of the
+`basic_authenticator` map.
+
 You can also, additionally, configure Rocket by using `Rocket.toml` file.
 See https://rocket.rs/guide/overview#configuration
 
-The `noop` subcommand allows all username and passwords to authenticate.
-The `csv` subcommand uses a CSV file as its username database. See
-https://lawliet89.github.io/rowdy/rowdy/auth/simple/index.html for the database format.
-
-The subcommands will change the format expected by the `basic_authenticator` key of the configuration JSON.
-  - noop: The key is expected to be simply an empty map: i.e. `{}`
-  - csv: The key should behave according to the format documented at
+The `serve` subcommand selects the authenticator with the `type` key of the `basic_authenticator` map. The rest of
+the keys in the map are the configuration for that authenticator type:
+  - noop: Allows all username and passwords to authenticate. There is nothing else to configure.
+  - csv: Uses a CSV file as its username database. The rest of the keys are documented at
     https://lawliet89.github.io/rowdy/rowdy/auth/struct.SimpleAuthenticatorConfiguration.html
-  - ldap: The key should behave according to the format documented at
+  - ldap: Authenticates against an LDAP server. The rest of the keys are documented at
     https://lawliet89.github.io/rowdy/rowdy/auth/struct.LdapAuthenticator.html
+  - api_key: Authenticates API keys with the client ID as the username. The rest of the keys are documented at
+    https://lawliet89.github.io/rowdy/rowdy/auth/struct.ApiKeyAuthenticatorConfiguration.html
+  - totp: Requires a one time password in addition to another authenticator. The rest of the keys are documented
+    at https://lawliet89.github.io/rowdy/rowdy/auth/struct.TotpAuthenticatorConfiguration.html
+  - chain: Tries several of the above in order. The rest of the keys are documented at
+    https://lawliet89.github.io/rowdy/rowdy/auth/struct.ChainAuthenticatorConfiguration.html
+
+The `noop`, `csv` and `ldap` subcommands are kept for compatibility. They select the authenticator of the same
+name, and expect the `basic_authenticator` map without the `type` key.
+
+The `api-key` subcommands manage the JSON file of API keys at <keys> for machine clients. `add` makes a new key for
+<client-id> and prints it once as `<client-id>:<secret>`. Clients send the key as the entire `Authorization`
+header, or the client ID and secret with HTTP Basic authentication. Only a hash of the secret is stored. Restart
+`rowdy` for changes to take effect.
+
+The `totp` subcommands manage the users enrolled with the first `totp` authenticator in <configuration>. `enroll`
+makes a new secret for <username> and prints the `otpauth://` URI to import into authenticator apps. Users then
+append the one time password to their password, or send it in the `X-One-Time-Password` header. Restart `rowdy` for
+changes to take effect.
 
 Options:
   -h --help                 Show this screen.
+  --subject=<subject>       Subject of the tokens issued to the client. Defaults to the client ID.
+  --service=<service>       Service that the client may request tokens for. Repeat for more services. All services
+                            are allowed if none is provided.
+  --claims=<claims>         JSON map of private claims to include in the tokens issued to the client.
+  --expires=<expires>       RFC 3339 time after which the key can no longer be used, like `2018-01-01T00:00:00Z`.
 "#;
 
-#[derive(Debug, RustcDecodable, PartialEq)]
+#[derive(Debug, Default, RustcDecodable, PartialEq)]
 struct Args {
-    arg_configuration_json: String,
+    arg_configuration: String,
+    arg_keys: String,
+    arg_client_id: String,
+    arg_username: String,
+    flag_subject: Option<String>,
+    flag_service: Vec<String>,
+    flag_claims: Option<String>,
+    flag_expires: Option<String>,
+    cmd_serve: bool,
     cmd_noop: bool,
     cmd_csv: bool,
     cmd_ldap: bool,
+    cmd_api_key: bool,
+    cmd_add: bool,
+    cmd_remove: bool,
+    cmd_list: bool,
+    cmd_totp: bool,
+    cmd_enroll: bool,
 }
 
 fn main() {
     let args: Args = Docopt::new(USAGE).and_then(|d| d.decode()).unwrap_or_else(|e| e.exit());
 
-    let rocket = if args.cmd_noop {
-        ignite::<auth::NoOpConfiguration>(&args.arg_configuration_json)
+    if args.cmd_api_key {
+        api_key::run(&args).unwrap_or_else(|e| panic!("{}", e));
+        return;
+    }
+    if args.cmd_totp {
+        totp::run(&args).unwrap_or_else(|e| panic!("{}", e));
+        return;
+    }
+
+    let rocket = if args.cmd_serve {
+        ignite::<auth::DynamicConfiguration>(&args.arg_configuration)
+    } else if args.cmd_noop {
+        ignite::<auth::NoOpConfiguration>(&args.arg_configuration)
     } else if args.cmd_csv {
-        ignite::<auth::SimpleAuthenticatorConfiguration>(&args.arg_configuration_json)
+        ignite::<auth::SimpleAuthenticatorConfiguration>(&args.arg_configuration)
     } else if args.cmd_ldap {
-        ignite::<auth::LdapAuthenticator>(&args.arg_configuration_json)
+        ignite::<auth::LdapAuthenticator>(&args.arg_configuration)
     } else {
         unreachable!("Should never happen");
     };
 
     let rocket = rocket.unwrap_or_else(|e| panic!("{}", e));
-    rocket.mount("/", rowdy::routes()).launch();
+    rowdy::mount(rocket, "/").launch();
 }
 
 /// Read configuration files, and ignite a `Rocket`
 fn ignite<B>(path: &str) -> Result<Rocket, rowdy::Error>
     where B: auth::AuthenticatorConfiguration<auth::Basic>
 {
-    let config = read_config::<B>(path)?;
+    let config: rowdy::Configuration<B> = config::read_config(path)?;
     config.ignite()
 }
 
-fn read_config<B>(path: &str) -> Result<rowdy::Configuration<B>, String>
-    where B: auth::AuthenticatorConfiguration<auth::Basic>
-{
-    use std::fs::File;
-    use std::io::Read;
-
-    info_!("Reading configuration from '{}'", path);
-    let mut file = File::open(&path).map_err(|e| format!("{:?}", e))?;
-    let mut config_json = String::new();
-    file.read_to_string(&mut config_json).map_err(|e| format!("{:?}", e))?;
-
-    deserialize_json(&config_json)
-}
-
-pub fn deserialize_json<T>(json: &str) -> Result<T, String>
-    where T: serde::de::DeserializeOwned
-{
-    serde_json::from_str(json).map_err(|e| format!("{:?}", e))
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn ignite_serve() {
+        ignite::<auth::DynamicConfiguration>("test/fixtures/config.json").unwrap();
+    }
+
+    #[test]
+    fn ignite_totp() {
+        ignite::<auth::DynamicConfiguration>("test/fixtures/config_totp.json").unwrap();
+    }
+
     #[test]
     fn ignite_noop() {
         ignite::<auth::NoOpConfiguration>("test/fixtures/config_noop.json").unwrap();
@@ -122,6 +181,24 @@ mod tests {
         Docopt::new(USAGE).unwrap();
     }
 
+    #[test]
+    fn docopt_serve() {
+        let docopt = Docopt::new(USAGE).unwrap();
+        let docopt = docopt.argv(["rowdy", "serve", "test/fixtures/config.json"].iter());
+        let args = docopt.decode().unwrap();
+
+        let expected_args = Args {
+            arg_configuration: "test/fixtures/config.json".to_string(),
+            cmd_serve: true,
+            cmd_noop: false,
+            cmd_csv: false,
+            cmd_ldap: false,
+            ..Default::default()
+        };
+
+        assert_eq!(expected_args, args);
+    }
+
     #[test]
     fn docopt_noop() {
         let docopt = Docopt::new(USAGE).unwrap();
@@ -129,10 +206,12 @@ mod tests {
         let args = docopt.decode().unwrap();
 
         let expected_args = Args {
-            arg_configuration_json: "test/fixtures/config/noop.json".to_string(),
+            arg_configuration: "test/fixtures/config/noop.json".to_string(),
+            cmd_serve: false,
             cmd_noop: true,
             cmd_csv: false,
             cmd_ldap: false,
+            ..Default::default()
         };
 
         assert_eq!(expected_args, args);
@@ -145,10 +224,12 @@ mod tests {
         let args = docopt.decode().unwrap();
 
         let expected_args = Args {
-            arg_configuration_json: "test/fixtures/config/csv.json".to_string(),
+            arg_configuration: "test/fixtures/config/csv.json".to_string(),
+            cmd_serve: false,
             cmd_noop: false,
             cmd_csv: true,
             cmd_ldap: false,
+            ..Default::default()
         };
 
         assert_eq!(expected_args, args);
@@ -161,10 +242,74 @@ mod tests {
         let args = docopt.decode().unwrap();
 
         let expected_args = Args {
-            arg_configuration_json: "test/fixtures/config/ldap.json".to_string(),
+            arg_configuration: "test/fixtures/config/ldap.json".to_string(),
+            cmd_serve: false,
             cmd_noop: false,
             cmd_csv: false,
             cmd_ldap: true,
+            ..Default::default()
+        };
+
+        assert_eq!(expected_args, args);
+    }
+
+    #[test]
+    fn docopt_api_key_add() {
+        let docopt = Docopt::new(USAGE).unwrap();
+        let argv = [
+            "rowdy",
+            "api-key",
+            "add",
+            "keys.json",
+            "registry-ci",
+            "--service=https://registry.acme.com",
+            "--service=https://www.acme.com",
+            "--expires=2018-01-01T00:00:00Z",
+        ];
+        let docopt = docopt.argv(argv.iter());
+        let args = docopt.decode().unwrap();
+
+        let expected_args = Args {
+            arg_keys: "keys.json".to_string(),
+            arg_client_id: "registry-ci".to_string(),
+            flag_service: vec!["https://registry.acme.com".to_string(), "https://www.acme.com".to_string()],
+            flag_expires: Some("2018-01-01T00:00:00Z".to_string()),
+            cmd_api_key: true,
+            cmd_add: true,
+            ..Default::default()
+        };
+
+        assert_eq!(expected_args, args);
+    }
+
+    #[test]
+    fn docopt_api_key_list() {
+        let docopt = Docopt::new(USAGE).unwrap();
+        let docopt = docopt.argv(["rowdy", "api-key", "list", "keys.json"].iter());
+        let args = docopt.decode().unwrap();
+
+        let expected_args = Args {
+            arg_keys: "keys.json".to_string(),
+            cmd_api_key: true,
+            cmd_list: true,
+            ..Default::default()
+        };
+
+        assert_eq!(expected_args, args);
+    }
+
+    #[test]
+    fn docopt_totp_enroll() {
+        let docopt = Docopt::new(USAGE).unwrap();
+        let docopt = docopt.argv(["rowdy", "totp", "enroll", "config.json", "mei"].iter());
+        let args = docopt.decode().unwrap();
+
+        let expected_args = Args {
+            arg_configuration: "config.json".to_string(),
+            arg_username: "mei".to_string(),
+            cmd_totp: true,
+            cmd_enroll: true,
+            ..Default::default()
         };
 
         assert_eq!(expected_args, args);
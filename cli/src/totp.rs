@@ -0,0 +1,62 @@
+//! `totp` subcommands to manage the users enrolled with `rowdy::auth::TotpAuthenticator`
+use rowdy::{Error, JsonValue};
+use rowdy::auth::TotpAuthenticatorConfiguration;
+use serde_json;
+
+use config;
+use super::Args;
+
+/// Run the `totp` subcommand in `args`
+pub fn run(args: &Args) -> Result<(), Error> {
+    let configuration: JsonValue = config::read_config(&args.arg_configuration)?;
+    let configuration = find_configuration(&configuration).ok_or_else(|| {
+        "No authenticator with the type `totp` was found in the configuration".to_string()
+    })?;
+    let configuration: TotpAuthenticatorConfiguration = serde_json::from_value(configuration.clone())
+        .map_err(|e| e.to_string())?;
+
+    if args.cmd_enroll {
+        let uri = configuration.enroll(&args.arg_username)?;
+        println!("{}", uri);
+    } else if args.cmd_remove {
+        configuration.unenroll(&args.arg_username)?;
+    } else {
+        unreachable!("Should never happen");
+    }
+    Ok(())
+}
+
+/// Find the first authenticator configuration with the type `totp`, which might be nested in other authenticators
+fn find_configuration(value: &JsonValue) -> Option<&JsonValue> {
+    match *value {
+        JsonValue::Object(ref map) => {
+            if map.get("type").and_then(|authenticator_type| authenticator_type.as_str()) == Some("totp") {
+                Some(value)
+            } else {
+                map.values().filter_map(find_configuration).next()
+            }
+        }
+        JsonValue::Array(ref values) => values.iter().filter_map(find_configuration).next(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_configuration_is_found() {
+        let configuration: JsonValue = config::read_config("test/fixtures/config_totp.json").unwrap();
+        let configuration = find_configuration(&configuration).unwrap();
+        let configuration: TotpAuthenticatorConfiguration = serde_json::from_value(configuration.clone()).unwrap();
+        assert_eq!(configuration.secrets_path, "test/fixtures/totp_secrets.jwe");
+        assert_eq!(configuration.issuer, "ACME");
+    }
+
+    #[test]
+    fn missing_configuration_is_not_found() {
+        let configuration: JsonValue = config::read_config("test/fixtures/config.json").unwrap();
+        assert!(find_configuration(&configuration).is_none());
+    }
+}
@@ -0,0 +1,338 @@
+//! Audit log of authentication and token events
+//!
+//! Each event is written as one line of JSON to the configured destination, independently of the log level of Rocket.
+//! Passwords, API keys and the bodies of tokens are never included. Tokens are identified by their ID (`jti`).
+//!
+//! An event looks like this:
+//!
+//! ```json
+//! {
+//!     "timestamp": "2017-07-13T08:00:00.000000Z",
+//!     "event": "login",
+//!     "outcome": "failure",
+//!     "reason": "Authentication has failed",
+//!     "username": "mei",
+//!     "service": "https://www.example.com",
+//!     "requested_scope": "all",
+//!     "client_address": "127.0.0.1",
+//!     "user_agent": "docker/17.06.0-ce"
+//! }
+//! ```
+//!
+//! # Configuration Example
+//!
+//! The audit log is enabled by the `audit` key of [`rowdy::Configuration`](../struct.Configuration.html), with the
+//! destination of the events:
+//!
+//! ```json
+//! "Stdout"
+//! ```
+//!
+//! or
+//!
+//! ```json
+//! { "File": { "path": "audit.log" } }
+//! ```
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use rocket::Outcome;
+use rocket::request::{self, FromRequest, Request};
+use serde_json;
+
+use throttle::Client;
+
+/// Where audit events are written to
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize, Debug)]
+pub enum Configuration {
+    /// The standard output of the process
+    Stdout,
+    /// A file, which is appended to
+    File {
+        /// Path to the file. This should be relative to the working directory, or an absolute path.
+        path: String,
+    },
+}
+
+/// The kind of an audit event
+#[derive(Clone, Copy, Eq, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// Authentication with credentials to retrieve an access token
+    Login,
+    /// Retrieval of an access token with a refresh token
+    Refresh,
+    /// Retrieval of an access token without credentials, for scopes granted to anonymous users
+    Anonymous,
+    /// Authentication with credentials on the login form to retrieve an authorization code
+    Authorize,
+    /// Exchange of an authorization code for an access token
+    AuthorizationCode,
+    /// Authentication with credentials on the device verification page to approve or deny a device code
+    ApproveDevice,
+    /// Exchange of an approved device code for an access token
+    DeviceCode,
+    /// Exchange of the access token of a user for an access token for another service, requested by a service
+    TokenExchange,
+}
+
+/// Whether the request of an audit event succeeded
+#[derive(Clone, Copy, Eq, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum EventOutcome {
+    /// A token was issued
+    Success,
+    /// The request was refused
+    Failure,
+}
+
+/// An audit event
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize, Debug)]
+pub struct Event {
+    /// Time of the event
+    pub timestamp: DateTime<Utc>,
+    /// Kind of event
+    pub event: EventKind,
+    /// Outcome of the event
+    pub outcome: EventOutcome,
+    /// Reason the request was refused
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// The username, or client ID for API keys of the form `client_id:secret`, that authentication was attempted
+    /// with. Other API keys are secrets in their entirety and are not recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Subject of the issued token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    /// Service the token was requested for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    /// Scope requested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requested_scope: Option<String>,
+    /// Scope of the issued token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub granted_scope: Option<String>,
+    /// Address of the remote end of the connection
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_address: Option<IpAddr>,
+    /// The first address in the `X-Forwarded-For` header
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forwarded_for: Option<IpAddr>,
+    /// The `User-Agent` header of the request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    /// ID (`jti`) of the issued access token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_id: Option<String>,
+}
+
+impl Event {
+    /// Create a new successful event for a request from `client` for `service` and `scope`
+    pub fn new(event: EventKind, client: &Client, user_agent: &UserAgent, service: &str, scope: &str) -> Self {
+        Event {
+            timestamp: Utc::now(),
+            event,
+            outcome: EventOutcome::Success,
+            reason: None,
+            username: None,
+            subject: None,
+            service: Some(service.to_string()),
+            requested_scope: Some(scope.to_string()),
+            granted_scope: None,
+            client_address: client.remote,
+            forwarded_for: client.forwarded_for,
+            user_agent: user_agent.0.clone(),
+            token_id: None,
+        }
+    }
+}
+
+/// The `User-Agent` request header, if any. This request guard always succeeds.
+#[derive(Clone, Debug)]
+pub struct UserAgent(pub Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for UserAgent {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        Outcome::Success(UserAgent(
+            request.headers().get_one("User-Agent").map(|s| s.to_string()),
+        ))
+    }
+}
+
+/// Writes audit events. This is managed by Rocket and should be retrieved with `State`.
+pub struct Auditor {
+    writer: Option<Mutex<Box<Write + Send>>>,
+}
+
+impl Auditor {
+    /// Create a new `Auditor` writing to the destination in the configuration
+    pub fn new(configuration: &Configuration) -> Result<Self, ::Error> {
+        let writer: Box<Write + Send> = match *configuration {
+            Configuration::Stdout => Box::new(io::stdout()),
+            Configuration::File { ref path } => Box::new(OpenOptions::new().create(true).append(true).open(path)?),
+        };
+        Ok(Self::with_writer(writer))
+    }
+
+    /// Create a new `Auditor` writing to `writer`
+    pub fn with_writer(writer: Box<Write + Send>) -> Self {
+        Auditor { writer: Some(Mutex::new(writer)) }
+    }
+
+    /// Create an `Auditor` that discards all events
+    pub fn disabled() -> Self {
+        Auditor { writer: None }
+    }
+
+    /// Call `request` with `event` for it to fill in, and then write the event with the outcome of `request`.
+    /// The result of `request` is returned.
+    pub fn audit<T, F>(&self, mut event: Event, request: F) -> Result<T, ::Error>
+    where
+        F: FnOnce(&mut Event) -> Result<T, ::Error>,
+    {
+        let result = request(&mut event);
+        if let Err(ref e) = result {
+            event.outcome = EventOutcome::Failure;
+            event.reason = Some(e.to_string());
+        }
+        self.write(&event);
+        result
+    }
+
+    /// Write `event`. Failures to write are logged but otherwise ignored.
+    pub fn write(&self, event: &Event) {
+        let writer = match self.writer {
+            Some(ref writer) => writer,
+            None => return,
+        };
+        let result = serde_json::to_string(event)
+            .map_err(|e| e.to_string())
+            .and_then(|line| {
+                let mut writer = writer.lock().map_err(
+                    |_| "Audit writer lock is poisoned".to_string(),
+                )?;
+                writeln!(writer, "{}", line).and_then(|_| writer.flush()).map_err(
+                    |e| e.to_string(),
+                )
+            });
+        if let Err(e) = result {
+            error_!("Failed to write audit event: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use JsonValue;
+    use auth;
+
+    /// A writer into a buffer that can be inspected after the `Auditor` has taken ownership of the writer
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl SharedBuffer {
+        fn lines(&self) -> Vec<JsonValue> {
+            let buffer = self.0.lock().unwrap();
+            let buffer = String::from_utf8(buffer.clone()).unwrap();
+            buffer.lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+        }
+    }
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn event() -> Event {
+        let client = Client {
+            remote: Some("127.0.0.1".parse().unwrap()),
+            forwarded_for: None,
+        };
+        Event::new(
+            EventKind::Login,
+            &client,
+            &UserAgent(Some("docker/17.06.0-ce".to_string())),
+            "https://www.example.com",
+            "all",
+        )
+    }
+
+    #[test]
+    fn configuration_deserialization() {
+        let deserialized: Configuration = not_err!(serde_json::from_str(r#""Stdout""#));
+        assert_eq!(deserialized, Configuration::Stdout);
+
+        let deserialized: Configuration = not_err!(serde_json::from_str(r#"{ "File": { "path": "audit.log" } }"#));
+        assert_eq!(deserialized, Configuration::File { path: "audit.log".to_string() });
+    }
+
+    #[test]
+    fn successful_requests_are_written() {
+        let buffer = SharedBuffer::default();
+        let auditor = Auditor::with_writer(Box::new(buffer.clone()));
+
+        let result = auditor.audit(event(), |event| {
+            event.username = Some("mei".to_string());
+            event.subject = Some("mei".to_string());
+            event.token_id = Some("urn:uuid:6d3a8b3d-7a1a-4b1d-9c9a-0e4f0b0e7d52".to_string());
+            Ok(42)
+        });
+        assert_eq!(not_err!(result), 42);
+
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 1);
+        let line = &lines[0];
+        assert_eq!(line["event"], "login");
+        assert_eq!(line["outcome"], "success");
+        assert_eq!(line["subject"], "mei");
+        assert_eq!(line["service"], "https://www.example.com");
+        assert_eq!(line["requested_scope"], "all");
+        assert_eq!(line["client_address"], "127.0.0.1");
+        assert_eq!(line["user_agent"], "docker/17.06.0-ce");
+        assert_eq!(line["token_id"], "urn:uuid:6d3a8b3d-7a1a-4b1d-9c9a-0e4f0b0e7d52");
+        assert!(line.get("reason").is_none());
+        assert!(line.get("forwarded_for").is_none());
+    }
+
+    #[test]
+    fn failed_requests_are_written_with_the_reason() {
+        let buffer = SharedBuffer::default();
+        let auditor = Auditor::with_writer(Box::new(buffer.clone()));
+
+        let result: Result<(), ::Error> = auditor.audit(event(), |event| {
+            event.username = Some("mei".to_string());
+            Err(auth::Error::AuthenticationFailure)?
+        });
+        assert!(result.is_err());
+
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 1);
+        let line = &lines[0];
+        assert_eq!(line["outcome"], "failure");
+        assert_eq!(line["reason"], "Authentication has failed");
+        assert_eq!(line["username"], "mei");
+        assert!(line.get("subject").is_none());
+    }
+
+    #[test]
+    fn disabled_auditor_passes_results_through() {
+        let auditor = Auditor::disabled();
+        let result = auditor.audit(event(), |_| Ok("ok"));
+        assert_eq!(not_err!(result), "ok");
+    }
+}
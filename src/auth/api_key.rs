@@ -0,0 +1,377 @@
+//! Authenticator for long lived API keys issued to machine clients
+//!
+//! Requires the `api_key_authenticator` feature.
+//!
+//! Each client is identified by a client ID, and authenticates with a secret. Clients can either send the client ID
+//! and secret with HTTP Basic authentication, or send `<client_id>:<secret>` as the entire `Authorization` header.
+//!
+//! The keys are stored in a JSON file which maps client IDs to [`ApiKey`](struct.ApiKey.html). Only a salted hash of
+//! the secret is stored. The file is usually managed with the `rowdy-cli api-key` subcommands.
+//!
+//! ```json
+//! {
+//!     "registry-ci": {
+//!         "hash": "e6e1111452a5574d8d64f6f4ba6fabc86af5c45c341df1eb23026373c41d24b8",
+//!         "salt": "0000000000000000000000000000000000000000000000000000000000000000",
+//!         "subject": "ci",
+//!         "private_claims": { "team": "infrastructure" },
+//!         "services": ["https://registry.acme.com"],
+//!         "expires_at": "2018-01-01T00:00:00Z"
+//!     }
+//! }
+//! ```
+//!
+//! API keys do not issue refresh tokens. Clients should authenticate with their key again instead.
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Write};
+
+use chrono::{DateTime, Utc};
+use ring::constant_time::verify_slices_are_equal;
+use ring::test;
+use serde_json;
+
+use {Error, JsonValue, JsonMap};
+use super::{Basic, Authenticator, Authorization, AuthenticatorConfiguration, AuthenticationResult};
+use super::simple::{SimpleAuthenticator, generate_salt, hex_dump};
+
+/// Private claim with the client ID of the API key used to authenticate
+pub const CLIENT_ID_CLAIM: &'static str = "client_id";
+
+/// Separates the client ID from the secret when the API key is sent as the entire `Authorization` header
+pub const SEPARATOR: char = ':';
+
+/// Length in bytes of the salt that secrets are hashed with
+const SALT_LENGTH: usize = 32;
+
+/// Length in bytes of secrets made by `generate_secret`
+const SECRET_LENGTH: usize = 32;
+
+/// API keys, indexed by their client ID
+pub type ApiKeys = BTreeMap<String, ApiKey>;
+
+/// An API key issued to a client
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct ApiKey {
+    /// Hex encoded hash of the secret. See `SimpleAuthenticator::hash_password` for the algorithm used.
+    pub hash: String,
+    /// Hex encoded salt that the secret is hashed with
+    pub salt: String,
+    /// Subject of the tokens issued to the client. Defaults to the client ID.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    /// Private claims to include in the tokens issued to the client. This must be a map.
+    #[serde(default = "default_private_claims")]
+    pub private_claims: JsonValue,
+    /// Services that the client is allowed to request tokens for. All services are allowed if this is empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub services: Vec<String>,
+    /// The key cannot be used after this time, if set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+fn default_private_claims() -> JsonValue {
+    JsonValue::Object(JsonMap::new())
+}
+
+impl ApiKey {
+    /// Make a key for `secret`, hashed with a random salt. The key allows all services and does not expire.
+    pub fn new(secret: &str) -> Result<Self, Error> {
+        let salt = generate_salt(SALT_LENGTH)?;
+        Ok(ApiKey {
+            hash: SimpleAuthenticator::hash_password(secret, &salt)?,
+            salt: hex_dump(&salt),
+            subject: None,
+            private_claims: default_private_claims(),
+            services: vec![],
+            expires_at: None,
+        })
+    }
+
+    /// Whether `secret` is the secret of this key
+    pub fn verify_secret(&self, secret: &str) -> Result<bool, Error> {
+        let salt = test::from_hex(&self.salt)?;
+        let hash = SimpleAuthenticator::hash_password(secret, &salt)?;
+        Ok(
+            verify_slices_are_equal(hash.as_bytes(), self.hash.to_lowercase().as_bytes()).is_ok(),
+        )
+    }
+
+    /// Whether the key has expired
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.map(|expiry| expiry <= Utc::now()).unwrap_or(
+            false,
+        )
+    }
+
+    /// Whether the client is allowed to request tokens for `service`
+    pub fn allows_service(&self, service: &str) -> bool {
+        self.services.is_empty() || self.services.iter().any(|allowed| allowed == service)
+    }
+}
+
+/// Generate a random hex encoded secret for a new API key
+pub fn generate_secret() -> Result<String, Error> {
+    Ok(hex_dump(&generate_salt(SECRET_LENGTH)?))
+}
+
+/// Read `ApiKeys` from a Reader of its JSON representation
+pub fn read_keys<R: Read>(reader: R) -> Result<ApiKeys, Error> {
+    Ok(serde_json::from_reader(reader)?)
+}
+
+/// Write `ApiKeys` in its JSON representation to a Writer
+pub fn write_keys<W: Write>(keys: &ApiKeys, writer: W) -> Result<(), Error> {
+    serde_json::to_writer_pretty(writer, keys).map_err(
+        |e| e.to_string(),
+    )?;
+    Ok(())
+}
+
+/// An authenticator for API keys. See the module documentation for details.
+pub struct ApiKeyAuthenticator {
+    keys: ApiKeys,
+}
+
+impl ApiKeyAuthenticator {
+    /// Create a new `ApiKeyAuthenticator` with the keys provided
+    pub fn new(keys: ApiKeys) -> Self {
+        ApiKeyAuthenticator { keys }
+    }
+
+    /// Create a new `ApiKeyAuthenticator` with the path to a JSON file of the keys
+    pub fn with_file(path: &str) -> Result<Self, Error> {
+        let file = File::open(path)?;
+        Ok(Self::new(read_keys(file)?))
+    }
+
+    /// Verify that a key with the client ID exists, has not expired, and that the secret is correct. The secret is
+    /// hashed even if the client ID is unknown, so that the time taken does not reveal which client IDs exist.
+    pub fn verify(&self, client_id: &str, secret: &str) -> Result<AuthenticationResult, Error> {
+        let key = match self.keys.get(client_id) {
+            Some(key) => key,
+            None => {
+                let _ = SimpleAuthenticator::hash_password(secret, &[0; SALT_LENGTH])?;
+                Err(super::Error::AuthenticationFailure)?
+            }
+        };
+        if !key.verify_secret(secret)? {
+            Err(super::Error::AuthenticationFailure)?;
+        }
+        if key.is_expired() {
+            warn_!("Expired API key of client `{}` was used", client_id);
+            Err(super::Error::AuthenticationFailure)?;
+        }
+
+        let mut private_claims = match key.private_claims {
+            JsonValue::Object(ref map) => map.clone(),
+            JsonValue::Null => JsonMap::new(),
+            _ => Err(Error::ClaimConflict(format!("Private claims of client `{}` are not a map", client_id)))?,
+        };
+        let _ = private_claims.insert(CLIENT_ID_CLAIM.to_string(), From::from(client_id));
+
+        Ok(AuthenticationResult {
+            subject: key.subject.clone().unwrap_or_else(|| client_id.to_string()),
+            private_claims: JsonValue::Object(private_claims),
+            refresh_payload: None,
+        })
+    }
+
+    /// Verify that the client which authenticated is allowed to request tokens for `service`
+    pub fn verify_client_service(&self, result: &AuthenticationResult, service: &str) -> Result<(), Error> {
+        let client_id = match result.private_claims.get(CLIENT_ID_CLAIM).and_then(
+            |client_id| client_id.as_str(),
+        ) {
+            Some(client_id) => client_id,
+            None => Err(format!("Authentication result is missing the `{}` claim", CLIENT_ID_CLAIM))?,
+        };
+        let key = self.keys.get(client_id).ok_or_else(
+            || super::Error::AuthenticationFailure,
+        )?;
+        if key.allows_service(service) {
+            Ok(())
+        } else {
+            Err(super::Error::Forbidden)?
+        }
+    }
+}
+
+impl Authenticator<Basic> for ApiKeyAuthenticator {
+    fn authenticate(&self, authorization: &Authorization<Basic>, _: bool) -> Result<AuthenticationResult, Error> {
+        let client_id = authorization.username();
+        let secret = authorization.password().unwrap_or_else(|| "".to_string());
+        self.verify(&client_id, &secret)
+    }
+
+    fn verify_service(&self, result: &AuthenticationResult, service: &str) -> Result<(), Error> {
+        self.verify_client_service(result, service)
+    }
+}
+
+impl Authenticator<String> for ApiKeyAuthenticator {
+    fn authenticate(&self, authorization: &Authorization<String>, _: bool) -> Result<AuthenticationResult, Error> {
+        let key = authorization.string();
+        let mut parts = key.trim().splitn(2, SEPARATOR);
+        match (parts.next(), parts.next()) {
+            (Some(client_id), Some(secret)) => self.verify(client_id, secret),
+            _ => Err(super::Error::AuthenticationFailure)?,
+        }
+    }
+
+    fn verify_service(&self, result: &AuthenticationResult, service: &str) -> Result<(), Error> {
+        self.verify_client_service(result, service)
+    }
+}
+
+/// (De)Serializable configuration for `ApiKeyAuthenticator`.
+///
+/// # Examples
+/// ```json
+/// {
+///     "keys_path": "test/fixtures/api_keys.json"
+/// }
+/// ```
+#[derive(Eq, PartialEq, Serialize, Deserialize, Debug)]
+pub struct ApiKeyAuthenticatorConfiguration {
+    /// Path to the JSON file of the keys, in the format described in the module documentation. This should be
+    /// relative to the working directory, or an absolute path. The file is only read on startup.
+    pub keys_path: String,
+}
+
+impl AuthenticatorConfiguration<Basic> for ApiKeyAuthenticatorConfiguration {
+    type Authenticator = ApiKeyAuthenticator;
+
+    fn make_authenticator(&self) -> Result<Self::Authenticator, Error> {
+        ApiKeyAuthenticator::with_file(&self.keys_path)
+    }
+}
+
+impl AuthenticatorConfiguration<String> for ApiKeyAuthenticatorConfiguration {
+    type Authenticator = ApiKeyAuthenticator;
+
+    fn make_authenticator(&self) -> Result<Self::Authenticator, Error> {
+        ApiKeyAuthenticator::with_file(&self.keys_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use hyper::header;
+
+    use super::*;
+
+    fn make_authenticator() -> ApiKeyAuthenticator {
+        not_err!(ApiKeyAuthenticator::with_file("test/fixtures/api_keys.json"))
+    }
+
+    fn basic(client_id: &str, secret: &str) -> Authorization<Basic> {
+        Authorization(header::Authorization(Basic {
+            username: client_id.to_string(),
+            password: Some(secret.to_string()),
+        }))
+    }
+
+    fn string(key: &str) -> Authorization<String> {
+        Authorization(header::Authorization(key.to_string()))
+    }
+
+    #[test]
+    fn keys_round_trip() {
+        let secret = not_err!(generate_secret());
+        let mut key = not_err!(ApiKey::new(&secret));
+        key.services = vec!["https://www.example.com".to_string()];
+
+        let mut keys = ApiKeys::new();
+        let _ = keys.insert("ci".to_string(), key);
+
+        let mut cursor: Cursor<Vec<u8>> = Cursor::new(vec![]);
+        not_err!(write_keys(&keys, &mut cursor));
+        cursor.set_position(0);
+        let read = not_err!(read_keys(&mut cursor));
+        assert_eq!(read, keys);
+
+        let authenticator = ApiKeyAuthenticator::new(read);
+        let result = not_err!(authenticator.verify("ci", &secret));
+        assert_eq!(result.subject, "ci");
+        assert!(authenticator.verify("ci", "wrong").is_err());
+    }
+
+    #[test]
+    fn authentication_with_basic() {
+        let authenticator = make_authenticator();
+
+        let result = not_err!(authenticator.authenticate(&basic("registry-ci", "password"), false));
+        assert_eq!(result.subject, "ci");
+        assert_eq!(result.private_claims["client_id"], "registry-ci");
+        assert_eq!(result.private_claims["team"], "infrastructure");
+        assert!(result.refresh_payload.is_none());
+
+        // API keys never issue refresh tokens
+        let result = not_err!(authenticator.authenticate(&basic("registry-ci", "password"), true));
+        assert!(result.refresh_payload.is_none());
+
+        assert!(
+            authenticator
+                .authenticate(&basic("registry-ci", "wrong"), false)
+                .is_err()
+        );
+        assert!(
+            authenticator
+                .authenticate(&basic("unknown", "password"), false)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn authentication_with_string() {
+        let authenticator = make_authenticator();
+
+        let result = not_err!(authenticator.authenticate(&string("deployer:冻住，不许走!"), false));
+        assert_eq!(result.subject, "deployer");
+        assert_eq!(result.private_claims["client_id"], "deployer");
+
+        assert!(authenticator.authenticate(&string("deployer"), false).is_err());
+        assert!(
+            authenticator
+                .authenticate(&string("deployer:wrong"), false)
+                .is_err()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "AuthenticationFailure")]
+    fn expired_keys_are_rejected() {
+        let authenticator = make_authenticator();
+        let _ = authenticator.verify("expired", "password").unwrap();
+    }
+
+    #[test]
+    fn services_are_verified() {
+        let authenticator = make_authenticator();
+
+        let result = not_err!(authenticator.verify("registry-ci", "password"));
+        not_err!(authenticator.verify_client_service(&result, "https://www.example.com"));
+        let error = authenticator
+            .verify_client_service(&result, "https://www.acme.com")
+            .unwrap_err();
+        assert_matches_non_debug!(error, Error::Auth(::auth::Error::Forbidden));
+
+        let result = not_err!(authenticator.verify("deployer", "冻住，不许走!"));
+        not_err!(authenticator.verify_client_service(&result, "https://www.acme.com"));
+    }
+
+    #[test]
+    fn api_key_authenticator_configuration_deserialization() {
+        let json = r#"{ "keys_path": "test/fixtures/api_keys.json" }"#;
+        let deserialized: ApiKeyAuthenticatorConfiguration = not_err!(serde_json::from_str(json));
+        let expected_config = ApiKeyAuthenticatorConfiguration { keys_path: "test/fixtures/api_keys.json".to_string() };
+        assert_eq!(deserialized, expected_config);
+
+        let _ = not_err!(AuthenticatorConfiguration::<Basic>::make_authenticator(
+            &expected_config,
+        ));
+    }
+}
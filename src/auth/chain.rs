@@ -0,0 +1,661 @@
+//! An authenticator that chains several authenticators together
+//!
+//! The `ChainAuthenticator` tries each of its backends in the order they are configured until one of them
+//! successfully authenticates the user. Backends can be restricted to usernames matching a pattern, or usernames
+//! beginning with a realm prefix (like `CORP\`), in which case they are skipped for other usernames. The realm prefix
+//! is kept in the subject of the token even if it is stripped from the username passed to the backend, so that users
+//! of different realms do not share a subject.
+//!
+//! The name of the backend that authenticated the user is recorded as a private claim, and refresh tokens are routed
+//! back to the same backend.
+//!
+//! # Configuration Example
+//!
+//! With the [registry](../registry/index.html), the chain is selected with the `chain` type:
+//!
+//! ```json
+//! {
+//!     "type": "chain",
+//!     "claim": "authenticator",
+//!     "backends": [
+//!         {
+//!             "name": "services",
+//!             "username_pattern": "svc-*",
+//!             "authenticator": { "type": "csv", "csv_path": "services.csv" }
+//!         },
+//!         {
+//!             "name": "corporate",
+//!             "realm_prefix": "CORP\\",
+//!             "authenticator": {
+//!                 "type": "ldap",
+//!                 "address": "ldap://ldap.acme.com",
+//!                 "bind_dn": "cn=read-only-admin,dc=acme,dc=com",
+//!                 "bind_password": "password",
+//!                 "search_base": "dc=acme,dc=com",
+//!                 "search_filter": "(uid={account})"
+//!             }
+//!         }
+//!     ]
+//! }
+//! ```
+use std::collections::HashSet;
+
+use hyper::header;
+
+use {Error, JsonValue, JsonMap};
+use super::{Basic, BasicAuthenticator, Authenticator, Authorization, AuthenticatorConfiguration,
+            AuthenticationResult, DynamicConfiguration};
+
+/// A backend of the `ChainAuthenticator`
+pub struct Backend {
+    /// Name of the backend. This is recorded in the claims of tokens issued to users authenticated by this backend.
+    pub name: String,
+    /// If set, the backend is only tried for usernames matching this pattern. `*` matches any sequence of
+    /// characters, and `?` matches any single character.
+    pub username_pattern: Option<String>,
+    /// If set, the backend is only tried for usernames beginning with this prefix.
+    pub realm_prefix: Option<String>,
+    /// Whether the `realm_prefix` is removed from the username before it is passed to the authenticator. The prefix is
+    /// added back to the subject the authenticator returns.
+    pub strip_realm_prefix: bool,
+    /// The authenticator of the backend
+    pub authenticator: Box<BasicAuthenticator>,
+}
+
+impl Backend {
+    /// Returns the username to pass to the authenticator if the backend should be tried for `username`
+    fn route(&self, username: &str) -> Option<String> {
+        if let Some(ref pattern) = self.username_pattern {
+            if !glob_match(pattern, username) {
+                return None;
+            }
+        }
+
+        match self.realm_prefix {
+            None => Some(username.to_string()),
+            Some(ref prefix) => {
+                if !username.starts_with(prefix.as_str()) {
+                    None
+                } else if self.strip_realm_prefix {
+                    Some(username[prefix.len()..].to_string())
+                } else {
+                    Some(username.to_string())
+                }
+            }
+        }
+    }
+
+    /// Returns the subject of a user authenticated by the backend, with the realm prefix if it was stripped
+    fn subject(&self, subject: String) -> String {
+        match self.realm_prefix {
+            Some(ref prefix) if self.strip_realm_prefix => format!("{}{}", prefix, subject),
+            _ => subject,
+        }
+    }
+}
+
+/// An authenticator that tries several authenticators in order. See the module documentation for details.
+pub struct ChainAuthenticator {
+    backends: Vec<Backend>,
+    claim: String,
+}
+
+impl ChainAuthenticator {
+    /// Create a new `ChainAuthenticator` which records the name of the backend that authenticated the user
+    /// in the private claim `claim`. The names of the backends must be unique.
+    pub fn new(claim: &str, backends: Vec<Backend>) -> Result<Self, Error> {
+        if backends.is_empty() {
+            Err(Error::ConfigurationError(
+                "At least one backend is required for the chain authenticator".to_string(),
+            ))?;
+        }
+
+        let mut names = HashSet::new();
+        for backend in &backends {
+            if !names.insert(backend.name.as_str()) {
+                Err(Error::ConfigurationError(
+                    format!("Duplicate backend `{}` in the chain authenticator", backend.name),
+                ))?;
+            }
+        }
+
+        Ok(ChainAuthenticator {
+            backends,
+            claim: claim.to_string(),
+        })
+    }
+
+    /// Record the backend in the private claims, and wrap the refresh payload, if any
+    fn wrap_result(&self, backend: &Backend, result: AuthenticationResult) -> Result<AuthenticationResult, Error> {
+        let mut private_claims = match result.private_claims {
+            JsonValue::Object(map) => map,
+            JsonValue::Null => JsonMap::new(),
+            _ => Err(Error::ClaimConflict(format!(
+                "Private claims from backend `{}` are not a map and the backend cannot be recorded",
+                backend.name
+            )))?,
+        };
+        if private_claims.contains_key(&self.claim) {
+            Err(Error::ClaimConflict(format!(
+                "Backend `{}` returned the claim `{}` which is reserved for the chain authenticator",
+                backend.name,
+                self.claim
+            )))?;
+        }
+        let _ = private_claims.insert(self.claim.clone(), From::from(backend.name.as_str()));
+
+        let refresh_payload = result.refresh_payload.map(|payload| {
+            let mut map = JsonMap::with_capacity(2);
+            let _ = map.insert("backend".to_string(), From::from(backend.name.as_str()));
+            let _ = map.insert("payload".to_string(), payload);
+            JsonValue::Object(map)
+        });
+
+        Ok(AuthenticationResult {
+            subject: backend.subject(result.subject),
+            private_claims: JsonValue::Object(private_claims),
+            refresh_payload,
+        })
+    }
+
+    /// Try each backend routed for the user in order, passing the one time password sent separately, if any
+    fn authenticate_backends(
+        &self,
+        authorization: &Authorization<Basic>,
+        one_time_password: Option<&str>,
+        include_refresh_payload: bool,
+    ) -> Result<AuthenticationResult, Error> {
+        let username = authorization.username();
+        let password = authorization.password();
+
+        // The first error that was not an authentication failure, if any
+        let mut backend_error = None;
+        for backend in &self.backends {
+            let routed_username = match backend.route(&username) {
+                Some(routed_username) => routed_username,
+                None => continue,
+            };
+
+            let routed_authorization = Authorization(header::Authorization(Basic {
+                username: routed_username,
+                password: password.clone(),
+            }));
+            let result = match one_time_password {
+                Some(one_time_password) => {
+                    backend.authenticator.authenticate_with_one_time_password(
+                        &routed_authorization,
+                        one_time_password,
+                        include_refresh_payload,
+                    )
+                }
+                None => {
+                    backend.authenticator.authenticate(
+                        &routed_authorization,
+                        include_refresh_payload,
+                    )
+                }
+            };
+            match result {
+                Ok(result) => {
+                    debug_!("User authenticated by backend `{}`", backend.name);
+                    return self.wrap_result(backend, result);
+                }
+                Err(ref e) if is_authentication_failure(e) => {
+                    debug_!("Backend `{}` did not authenticate the user", backend.name);
+                }
+                Err(e) => {
+                    error_!("Backend `{}` failed: {}", backend.name, e);
+                    if backend_error.is_none() {
+                        backend_error = Some(e);
+                    }
+                }
+            }
+        }
+
+        Err(backend_error.unwrap_or_else(
+            || Error::Auth(super::Error::AuthenticationFailure),
+        ))
+    }
+}
+
+/// Returns whether the error is a plain authentication failure, as opposed to a backend being unavailable or
+/// misbehaving
+fn is_authentication_failure(error: &Error) -> bool {
+    match *error {
+        Error::Auth(super::Error::AuthenticationFailure) => true,
+        _ => false,
+    }
+}
+
+impl Authenticator<Basic> for ChainAuthenticator {
+    fn authenticate(
+        &self,
+        authorization: &Authorization<Basic>,
+        include_refresh_payload: bool,
+    ) -> Result<AuthenticationResult, Error> {
+        self.authenticate_backends(authorization, None, include_refresh_payload)
+    }
+
+    /// The one time password is only passed on to backends that verify one time passwords
+    fn authenticate_with_one_time_password(
+        &self,
+        authorization: &Authorization<Basic>,
+        one_time_password: &str,
+        include_refresh_payload: bool,
+    ) -> Result<AuthenticationResult, Error> {
+        self.authenticate_backends(authorization, Some(one_time_password), include_refresh_payload)
+    }
+
+    fn authenticate_refresh_token(&self, refresh_payload: &JsonValue) -> Result<AuthenticationResult, Error> {
+        let map = refresh_payload.as_object().ok_or_else(
+            || super::Error::AuthenticationFailure,
+        )?;
+        let name = map.get("backend")
+            .and_then(|name| name.as_str())
+            .ok_or_else(|| super::Error::AuthenticationFailure)?;
+        let payload = map.get("payload").ok_or_else(
+            || super::Error::AuthenticationFailure,
+        )?;
+        let backend = self.backends
+            .iter()
+            .find(|backend| backend.name == name)
+            .ok_or_else(|| {
+                error_!("Refresh token was issued by backend `{}` which no longer exists", name);
+                super::Error::AuthenticationFailure
+            })?;
+
+        let result = backend.authenticator.authenticate_refresh_token(payload)?;
+        self.wrap_result(backend, result)
+    }
+
+    fn verify_service(&self, result: &AuthenticationResult, service: &str) -> Result<(), Error> {
+        let name = result
+            .private_claims
+            .get(self.claim.as_str())
+            .and_then(|name| name.as_str())
+            .ok_or_else(|| {
+                format!("Authentication result is missing the `{}` claim", self.claim)
+            })?;
+        let backend = self.backends
+            .iter()
+            .find(|backend| backend.name == name)
+            .ok_or_else(|| format!("Unknown backend `{}`", name))?;
+        backend.authenticator.verify_service(result, service)
+    }
+
+    /// The chain is only healthy if all of its backends are, because users routed to an unhealthy backend cannot
+    /// authenticate.
+    fn health_check(&self) -> Result<(), Error> {
+        let failures: Vec<String> = self.backends
+            .iter()
+            .filter_map(|backend| {
+                backend.authenticator.health_check().err().map(|e| {
+                    format!("Backend `{}`: {}", backend.name, e)
+                })
+            })
+            .collect();
+        if !failures.is_empty() {
+            Err(Error::BackendUnavailable(failures.join("; ")))?;
+        }
+        Ok(())
+    }
+}
+
+/// Configuration of a backend of the `ChainAuthenticator`
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct BackendConfiguration {
+    /// Name of the backend. See [`Backend`](struct.Backend.html).
+    pub name: String,
+    /// Only try the backend for usernames matching this pattern. See [`Backend`](struct.Backend.html).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username_pattern: Option<String>,
+    /// Only try the backend for usernames beginning with this prefix. See [`Backend`](struct.Backend.html).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub realm_prefix: Option<String>,
+    /// Whether the `realm_prefix` is removed from the username before it is passed to the authenticator. The subject
+    /// of the token keeps the prefix either way. Defaults to `true`.
+    #[serde(default = "default_strip_realm_prefix")]
+    pub strip_realm_prefix: bool,
+    /// Configuration of the authenticator, with its type selected by the `type` key.
+    pub authenticator: DynamicConfiguration,
+}
+
+fn default_strip_realm_prefix() -> bool {
+    true
+}
+
+/// (De)Serializable configuration for `ChainAuthenticator`. See the module documentation for an example.
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+pub struct ChainAuthenticatorConfiguration {
+    /// The backends to try, in order
+    pub backends: Vec<BackendConfiguration>,
+    /// The private claim to record the name of the backend that authenticated the user in.
+    /// Defaults to `authenticator`.
+    #[serde(default = "default_claim")]
+    pub claim: String,
+}
+
+fn default_claim() -> String {
+    "authenticator".to_string()
+}
+
+impl AuthenticatorConfiguration<Basic> for ChainAuthenticatorConfiguration {
+    type Authenticator = ChainAuthenticator;
+
+    fn make_authenticator(&self) -> Result<Self::Authenticator, Error> {
+        let mut backends = Vec::with_capacity(self.backends.len());
+        for backend in &self.backends {
+            backends.push(Backend {
+                name: backend.name.clone(),
+                username_pattern: backend.username_pattern.clone(),
+                realm_prefix: backend.realm_prefix.clone(),
+                strip_realm_prefix: backend.strip_realm_prefix,
+                authenticator: backend.authenticator.make_authenticator()?,
+            });
+        }
+        ChainAuthenticator::new(&self.claim, backends)
+    }
+}
+
+/// Match `input` against a pattern where `*` matches any sequence of characters and `?` matches any one character
+fn glob_match(pattern: &str, input: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let input: Vec<char> = input.chars().collect();
+
+    let (mut p, mut i) = (0, 0);
+    // Position of the last `*` in the pattern, and the position in the input it is matched up to
+    let mut star: Option<(usize, usize)> = None;
+    while i < input.len() {
+        if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, i));
+            p += 1;
+        } else if p < pattern.len() && (pattern[p] == '?' || pattern[p] == input[i]) {
+            p += 1;
+            i += 1;
+        } else if let Some((star_p, star_i)) = star {
+            // Let the last `*` consume one more character and try again
+            p = star_p + 1;
+            i = star_i + 1;
+            star = Some((star_p, star_i + 1));
+        } else {
+            return false;
+        }
+    }
+
+    while p < pattern.len() && pattern[p] == '*' {
+        p += 1;
+    }
+    p == pattern.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use ErrorKind;
+    use auth::NoOp;
+    use auth::tests::MockAuthenticator;
+    use super::*;
+
+    /// An authenticator whose backend is always unavailable
+    struct UnavailableAuthenticator {}
+
+    impl Authenticator<Basic> for UnavailableAuthenticator {
+        fn authenticate(&self, _: &Authorization<Basic>, _: bool) -> Result<AuthenticationResult, Error> {
+            Err(Error::BackendUnavailable("Connection refused".to_string()))
+        }
+
+        fn health_check(&self) -> Result<(), Error> {
+            Err(Error::BackendUnavailable("Connection refused".to_string()))
+        }
+    }
+
+    /// An authenticator that only allows the service `allowed`
+    struct RestrictedAuthenticator {}
+
+    impl Authenticator<Basic> for RestrictedAuthenticator {
+        fn authenticate(
+            &self,
+            authorization: &Authorization<Basic>,
+            _: bool,
+        ) -> Result<AuthenticationResult, Error> {
+            Ok(AuthenticationResult {
+                subject: authorization.username(),
+                private_claims: JsonValue::Object(JsonMap::new()),
+                refresh_payload: None,
+            })
+        }
+
+        fn verify_service(&self, _: &AuthenticationResult, service: &str) -> Result<(), Error> {
+            if service == "allowed" {
+                Ok(())
+            } else {
+                Err(::auth::Error::Forbidden)?
+            }
+        }
+    }
+
+    fn backend(name: &str, authenticator: Box<BasicAuthenticator>) -> Backend {
+        Backend {
+            name: name.to_string(),
+            username_pattern: None,
+            realm_prefix: None,
+            strip_realm_prefix: true,
+            authenticator,
+        }
+    }
+
+    fn authorization(username: &str, password: &str) -> Authorization<Basic> {
+        Authorization(header::Authorization(Basic {
+            username: username.to_string(),
+            password: Some(password.to_string()),
+        }))
+    }
+
+    fn backend_claim(result: &AuthenticationResult) -> &str {
+        result.private_claims["authenticator"].as_str().unwrap()
+    }
+
+    #[test]
+    fn glob_matching() {
+        assert!(glob_match("svc-*", "svc-registry"));
+        assert!(glob_match("svc-*", "svc-"));
+        assert!(!glob_match("svc-*", "mei"));
+        assert!(glob_match("*@services", "ci@services"));
+        assert!(!glob_match("*@services", "ci@services.com"));
+        assert!(glob_match("*a*b", "xxaxxab"));
+        assert!(glob_match("m?i", "mei"));
+        assert!(!glob_match("m?i", "mi"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("冻*", "冻住"));
+        assert!(glob_match("*b", "*ab"));
+        assert!(glob_match("a*", "a*"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Duplicate backend `mock`")]
+    fn backend_names_must_be_unique() {
+        let _ = ChainAuthenticator::new(
+            "authenticator",
+            vec![
+                backend("mock", Box::new(MockAuthenticator {})),
+                backend("mock", Box::new(NoOp {})),
+            ],
+        ).unwrap();
+    }
+
+    #[test]
+    fn first_successful_backend_is_used() {
+        let authenticator = not_err!(ChainAuthenticator::new(
+            "authenticator",
+            vec![
+                backend("mock", Box::new(MockAuthenticator {})),
+                backend("noop", Box::new(NoOp {})),
+            ],
+        ));
+
+        let result = not_err!(authenticator.authenticate(&authorization("mei", "冻住，不许走!"), false));
+        assert_eq!(result.subject, "mei");
+        assert_eq!(backend_claim(&result), "mock");
+
+        let result = not_err!(authenticator.authenticate(&authorization("foobar", "anything"), false));
+        assert_eq!(result.subject, "foobar");
+        assert_eq!(backend_claim(&result), "noop");
+    }
+
+    #[test]
+    fn unavailable_backend_does_not_hide_success() {
+        let authenticator = not_err!(ChainAuthenticator::new(
+            "authenticator",
+            vec![
+                backend("unavailable", Box::new(UnavailableAuthenticator {})),
+                backend("mock", Box::new(MockAuthenticator {})),
+            ],
+        ));
+
+        let result = not_err!(authenticator.authenticate(&authorization("mei", "冻住，不许走!"), false));
+        assert_eq!(backend_claim(&result), "mock");
+    }
+
+    #[test]
+    #[should_panic(expected = "AuthenticationFailure")]
+    fn all_backends_failing_is_authentication_failure() {
+        let authenticator = not_err!(ChainAuthenticator::new(
+            "authenticator",
+            vec![backend("mock", Box::new(MockAuthenticator {}))],
+        ));
+        let _ = authenticator
+            .authenticate(&authorization("mei", "wrong"), false)
+            .unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "Connection refused")]
+    fn unavailable_backend_is_reported_when_nothing_succeeds() {
+        let authenticator = not_err!(ChainAuthenticator::new(
+            "authenticator",
+            vec![
+                backend("mock", Box::new(MockAuthenticator {})),
+                backend("unavailable", Box::new(UnavailableAuthenticator {})),
+            ],
+        ));
+        let _ = authenticator
+            .authenticate(&authorization("mei", "wrong"), false)
+            .unwrap();
+    }
+
+    #[test]
+    fn unhealthy_backends_are_reported() {
+        let authenticator = not_err!(ChainAuthenticator::new(
+            "authenticator",
+            vec![backend("mock", Box::new(MockAuthenticator {}))],
+        ));
+        not_err!(authenticator.health_check());
+
+        let authenticator = not_err!(ChainAuthenticator::new(
+            "authenticator",
+            vec![
+                backend("mock", Box::new(MockAuthenticator {})),
+                backend("unavailable", Box::new(UnavailableAuthenticator {})),
+            ],
+        ));
+        let error = authenticator.health_check().unwrap_err();
+        assert_eq!(error.to_string(), "Backend `unavailable`: Connection refused");
+        assert_eq!(error.kind(), ErrorKind::BackendUnavailable);
+    }
+
+    #[test]
+    fn backends_are_routed_by_pattern_and_prefix() {
+        let mut services = backend("services", Box::new(NoOp {}));
+        services.username_pattern = Some("svc-*".to_string());
+        let mut corporate = backend("corporate", Box::new(MockAuthenticator {}));
+        corporate.realm_prefix = Some("CORP\\".to_string());
+
+        let authenticator = not_err!(ChainAuthenticator::new(
+            "authenticator",
+            vec![services, corporate],
+        ));
+
+        let result = not_err!(authenticator.authenticate(&authorization("svc-registry", "anything"), false));
+        assert_eq!(result.subject, "svc-registry");
+        assert_eq!(backend_claim(&result), "services");
+
+        // Prefix is stripped for the backend, but kept in the subject
+        let result = not_err!(authenticator.authenticate(&authorization("CORP\\mei", "冻住，不许走!"), false));
+        assert_eq!(result.subject, "CORP\\mei");
+        assert_eq!(backend_claim(&result), "corporate");
+
+        // No backend is routed, so even the `NoOp` backend is not tried
+        let result = authenticator.authenticate(&authorization("mei", "anything"), false);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn refresh_tokens_are_routed_to_the_same_backend() {
+        let authenticator = not_err!(ChainAuthenticator::new(
+            "backend",
+            vec![
+                backend("noop", Box::new(NoOp {})),
+                backend("mock", Box::new(MockAuthenticator {})),
+            ],
+        ));
+
+        let result = not_err!(authenticator.prepare_authentication_response(
+            &authorization("mei", "冻住，不许走!"),
+            true,
+        ));
+        assert_eq!(result.private_claims["backend"], "noop");
+        let refresh_payload = not_none!(result.refresh_payload);
+        assert_eq!(refresh_payload["backend"], "noop");
+
+        let result = not_err!(authenticator.prepare_refresh_response(&refresh_payload));
+        assert_eq!(result.subject, "mei");
+        assert_eq!(result.private_claims["backend"], "noop");
+        assert!(result.refresh_payload.is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "AuthenticationFailure")]
+    fn refresh_tokens_for_unknown_backends_are_rejected() {
+        let authenticator = not_err!(ChainAuthenticator::new(
+            "authenticator",
+            vec![backend("noop", Box::new(NoOp {}))],
+        ));
+        let refresh_payload: JsonValue = not_err!(serde_json::from_str(
+            r#"{ "backend": "removed", "payload": {} }"#,
+        ));
+        let _ = authenticator.authenticate_refresh_token(&refresh_payload).unwrap();
+    }
+
+    #[test]
+    fn services_are_verified_by_the_authenticating_backend() {
+        let mut restricted = backend("restricted", Box::new(RestrictedAuthenticator {}));
+        restricted.username_pattern = Some("svc-*".to_string());
+        let authenticator = not_err!(ChainAuthenticator::new(
+            "authenticator",
+            vec![restricted, backend("noop", Box::new(NoOp {}))],
+        ));
+
+        let result = not_err!(authenticator.authenticate(&authorization("svc-ci", "anything"), false));
+        not_err!(authenticator.verify_service(&result, "allowed"));
+        assert!(authenticator.verify_service(&result, "denied").is_err());
+
+        let result = not_err!(authenticator.authenticate(&authorization("mei", "anything"), false));
+        not_err!(authenticator.verify_service(&result, "denied"));
+    }
+
+    #[test]
+    fn configuration_makes_nested_authenticators() {
+        let json = r#"{
+            "type": "chain",
+            "backends": [
+                { "name": "noop", "username_pattern": "svc-*", "authenticator": { "type": "noop" } }
+            ]
+        }"#;
+        let config: DynamicConfiguration = not_err!(serde_json::from_str(json));
+        let authenticator = not_err!(config.make_authenticator());
+
+        let result = not_err!(authenticator.authenticate(&authorization("svc-ci", "anything"), false));
+        assert_eq!(backend_claim(&result), "noop");
+    }
+}
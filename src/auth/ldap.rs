@@ -1,7 +1,8 @@
 //! LDAP Authentication module
 use std::collections::HashMap;
+use std::time::Duration;
 
-use ldap3::{LdapConn, Scope, SearchEntry};
+use ldap3::{LdapConn, LdapConnBuilder, Scope, SearchEntry};
 use ldap3::ldap_escape;
 use strfmt::{FmtError, strfmt};
 use serde_json::value;
@@ -9,10 +10,10 @@ use serde_json::value;
 use {Error, JsonValue, JsonMap};
 use super::{Basic, AuthenticationResult};
 
-/// Error mapping for `FmtError`
+/// Error mapping for `FmtError`, raised when the search base or filter templates are invalid
 impl From<FmtError> for Error {
     fn from(e: FmtError) -> Error {
-        Error::GenericError(e.to_string())
+        Error::ConfigurationError(e.to_string())
     }
 }
 
@@ -38,6 +39,7 @@ impl From<SearchEntry> for User {
 ///
 /// # Example
 /// ```
+/// use std::time::Duration;
 /// use rowdy::auth::LdapAuthenticator;
 /// let authenticator = LdapAuthenticator {
 ///     address: "ldap://ldap.forumsys.com".to_string(),
@@ -48,6 +50,7 @@ impl From<SearchEntry> for User {
 ///     include_attributes: vec!["cn".to_string()],
 ///     attributes_namespace: Some("user".to_string()),
 ///     subject_attribute: Some("uid".to_string()),
+///     health_check_timeout: Duration::from_secs(5),
 /// };
 /// ```
 #[derive(Clone, Eq, PartialEq, Serialize, Deserialize, Debug)]
@@ -88,13 +91,35 @@ pub struct LdapAuthenticator {
     /// The first value returned by the attribute will be used as the subject.
     #[serde(default)]
     pub subject_attribute: Option<String>,
+    /// Seconds to wait for the LDAP server to accept a connection and the bind of the searcher user when checking
+    /// the health of the authenticator. Defaults to 5 seconds.
+    #[serde(with = "::serde_custom::duration", default = "LdapAuthenticator::default_health_check_timeout")]
+    pub health_check_timeout: Duration,
 }
 
 impl LdapAuthenticator {
+    fn default_health_check_timeout() -> Duration {
+        Duration::from_secs(5)
+    }
+
     /// Connects to the LDAP server
     fn connect(&self) -> Result<LdapConn, Error> {
         debug_!("Connecting to LDAP {}", self.address);
-        let connection = LdapConn::new(&self.address)?;
+        let connection = LdapConn::new(&self.address).map_err(|e| {
+            Error::BackendUnavailable(format!("Connecting to {} failed: {}", self.address, e))
+        })?;
+        Ok(connection)
+    }
+
+    /// Connects to the LDAP server, failing if it does not accept the connection within `timeout`
+    fn connect_with_timeout(&self, timeout: Duration) -> Result<LdapConn, Error> {
+        debug_!("Connecting to LDAP {} with a timeout of {:?}", self.address, timeout);
+        let connection = LdapConnBuilder::<LdapConn>::new()
+            .with_conn_timeout(timeout)
+            .connect(&self.address)
+            .map_err(|e| {
+                Error::BackendUnavailable(format!("Connecting to {} failed: {}", self.address, e))
+            })?;
         Ok(connection)
     }
 
@@ -114,9 +139,11 @@ impl LdapAuthenticator {
     /// Bind the connection to some dn
     fn bind(&self, connection: &LdapConn, dn: &str, password: &str) -> Result<(), Error> {
         debug_!("Binding to DN {}", dn);
-        let _s = connection.simple_bind(dn, password)?
+        let _s = connection
+            .simple_bind(dn, password)
+            .map_err(|e| Error::BackendUnavailable(format!("Bind failed: {}", e)))?
             .success()
-            .map_err(|e| Error::GenericError(format!("Bind failed: {}", e)))?;
+            .map_err(|e| Error::BackendUnavailable(format!("Bind failed: {}", e)))?;
         Ok(())
     }
 
@@ -149,7 +176,9 @@ impl LdapAuthenticator {
             Scope::Subtree,
             &search_filter,
             search_attrs_vec,
-        )?.success().map_err(|e| Error::GenericError(format!("Search failed: {}", e)))?;
+        ).map_err(|e| Error::BackendUnavailable(format!("Search failed: {}", e)))?
+            .success()
+            .map_err(|e| Error::BackendUnavailable(format!("Search failed: {}", e)))?;
 
         Ok(results.into_iter().map(SearchEntry::construct).collect())
     }
@@ -322,6 +351,13 @@ impl super::Authenticator<Basic> for LdapAuthenticator {
             false,
         )
     }
+
+    /// Connect to the LDAP server and bind the searcher user, each within the `health_check_timeout`
+    fn health_check(&self) -> Result<(), ::Error> {
+        let connection = self.connect_with_timeout(self.health_check_timeout)?;
+        let _ = connection.with_timeout(self.health_check_timeout);
+        self.searcher_bind(&connection)
+    }
 }
 
 impl super::AuthenticatorConfiguration<Basic> for LdapAuthenticator {
@@ -342,6 +378,10 @@ impl super::AuthenticatorConfiguration<Basic> for LdapAuthenticator {
 #[cfg(test)]
 mod tests {
     //! These tests might intermittently fail due to Test server being inaccessible
+    use std::net::TcpListener;
+    use std::time::Instant;
+
+    use ErrorKind;
     use auth::Authenticator;
     use super::*;
 
@@ -356,6 +396,7 @@ mod tests {
             include_attributes: vec!["cn".to_string()],
             attributes_namespace: None,
             subject_attribute: Some("uid".to_string()),
+            health_check_timeout: Duration::from_secs(5),
         }
     }
 
@@ -536,4 +577,26 @@ mod tests {
         let authenticator = make_authenticator();
         let _ = authenticator.verify("einstein", "FTL", false).unwrap();
     }
+
+    #[test]
+    fn health_check() {
+        let authenticator = make_authenticator();
+        not_err!(authenticator.health_check());
+    }
+
+    #[test]
+    fn health_check_times_out() {
+        // The listener accepts connections but never responds
+        let listener = not_err!(TcpListener::bind("127.0.0.1:0"));
+        let authenticator = LdapAuthenticator {
+            address: format!("ldap://{}", not_err!(listener.local_addr())),
+            health_check_timeout: Duration::from_secs(1),
+            ..make_authenticator()
+        };
+
+        let start = Instant::now();
+        let error = authenticator.health_check().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::BackendUnavailable);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
 }
@@ -9,7 +9,7 @@ use rocket;
 use rocket::http::Status;
 use rocket::request::{self, Request, FromRequest};
 use rocket::response;
-use rocket::Outcome;
+use rocket::{Outcome, State};
 use serde::Serialize;
 use serde::de::DeserializeOwned;
 
@@ -29,7 +29,29 @@ mod ldap;
 #[cfg(feature = "ldap_authenticator")]
 pub use self::ldap::LdapAuthenticator;
 
-use JsonValue;
+#[cfg(feature = "api_key_authenticator")]
+pub mod api_key;
+#[cfg(feature = "api_key_authenticator")]
+pub use self::api_key::ApiKeyAuthenticator;
+#[cfg(feature = "api_key_authenticator")]
+pub use self::api_key::ApiKeyAuthenticatorConfiguration;
+
+#[cfg(feature = "totp_authenticator")]
+pub mod totp;
+#[cfg(feature = "totp_authenticator")]
+pub use self::totp::TotpAuthenticator;
+#[cfg(feature = "totp_authenticator")]
+pub use self::totp::TotpAuthenticatorConfiguration;
+
+pub mod registry;
+pub use self::registry::DynamicConfiguration;
+
+pub mod chain;
+pub use self::chain::ChainAuthenticator;
+pub use self::chain::ChainAuthenticatorConfiguration;
+
+use {ErrorKind, JsonValue};
+use token;
 
 /// Re-exported [`hyper::header::Scheme`]
 pub type Scheme = hyper::header::Scheme<Err = hyper::error::Error>;
@@ -49,7 +71,11 @@ pub type StringAuthenticator = Authenticator<String>;
 #[derive(Debug)]
 pub enum Error {
     /// Authentication was attempted successfully, but failed because of bad user credentials, or other reasons.
+    /// This variant will `respond` with a `Basic` challenge in the `WWW-Authenticate` header if the token
+    /// configuration is managed by Rocket.
     AuthenticationFailure,
+    /// Authentication was successful, but the subject is not allowed to request tokens for the service requested
+    Forbidden,
     /// A generic error
     GenericError(String),
     /// An error due to `hyper`, such as header parsing failure
@@ -59,6 +85,19 @@ pub enum Error {
     MissingAuthorization {
         /// The HTTP basic authentication realm
         realm: String,
+        /// The service the client should request a token for, if known
+        service: Option<String>,
+        /// The scope the client should request a token for, if known
+        scope: Option<String>,
+    },
+    /// The bearer token sent is invalid, such as an expired refresh token. This variant will `respond` with an
+    /// [RFC 6750](https://tools.ietf.org/html/rfc6750#section-3) `invalid_token` challenge.
+    InvalidToken(String),
+    /// Too many authentication attempts have failed recently. This variant will `respond` with the `Retry-After`
+    /// header.
+    TooManyAttempts {
+        /// Seconds after which authentication can be attempted again
+        retry_after: u64,
     },
 }
 
@@ -69,7 +108,10 @@ impl error::Error for Error {
     fn description(&self) -> &str {
         match *self {
             Error::AuthenticationFailure => "Authentication has failed",
+            Error::Forbidden => "The service requested is not allowed for the subject",
             Error::MissingAuthorization { .. } => "The request header `Authorization` is required but is missing",
+            Error::InvalidToken(_) => "The bearer token is invalid",
+            Error::TooManyAttempts { .. } => "Too many authentication attempts have failed",
             Error::GenericError(ref e) => &**e,
             Error::HyperError(ref e) => e.description(),
         }
@@ -78,7 +120,22 @@ impl error::Error for Error {
     fn cause(&self) -> Option<&error::Error> {
         match *self {
             Error::HyperError(ref e) => Some(e),
-            _ => Some(self),
+            _ => None,
+        }
+    }
+}
+
+impl Error {
+    /// The kind of the error
+    pub fn kind(&self) -> ErrorKind {
+        match *self {
+            Error::AuthenticationFailure |
+            Error::MissingAuthorization { .. } => ErrorKind::Unauthorized,
+            Error::Forbidden => ErrorKind::Denied,
+            Error::InvalidToken(_) => ErrorKind::InvalidToken,
+            Error::TooManyAttempts { .. } => ErrorKind::TooManyRequests,
+            Error::HyperError(_) => ErrorKind::InvalidAuthorization,
+            Error::GenericError(_) => ErrorKind::Internal,
         }
     }
 }
@@ -87,33 +144,78 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::HyperError(ref e) => fmt::Display::fmt(e, f),
+            Error::InvalidToken(ref e) => write!(f, "{}: {}", error::Error::description(self), e),
             _ => write!(f, "{}", error::Error::description(self)),
         }
     }
 }
 
 impl<'r> response::Responder<'r> for Error {
-    fn respond_to(self, _: &Request) -> Result<response::Response<'r>, Status> {
+    fn respond_to(self, request: &Request) -> Result<response::Response<'r>, Status> {
         error_!("Headers Error: {:?}", self);
         match self {
-            Error::MissingAuthorization { ref realm } => {
-                // TODO: Support other schemes!
-                let www_header = rocket::http::Header::new("WWW-Authenticate", format!("Basic realm={}", realm));
+            Error::MissingAuthorization { realm, service, scope } => {
+                // The service and scope are the parameters of the Docker Registry challenge
+                let mut parameters = vec![("realm", realm.as_str())];
+                if let Some(ref service) = service {
+                    parameters.push(("service", service.as_str()));
+                }
+                if let Some(ref scope) = scope {
+                    parameters.push(("scope", scope.as_str()));
+                }
+                unauthorized(::challenge("Basic", &parameters))
+            }
+            Error::AuthenticationFailure => {
+                match managed_realm(request) {
+                    Some(realm) => unauthorized(::challenge("Basic", &[("realm", realm.as_str())])),
+                    None => Err(Status::Unauthorized),
+                }
+            }
+            Error::InvalidToken(description) => {
+                let realm = managed_realm(request);
+                let mut parameters = vec![];
+                if let Some(ref realm) = realm {
+                    parameters.push(("realm", realm.as_str()));
+                }
+                parameters.push(("error", "invalid_token"));
+                parameters.push(("error_description", description.as_str()));
+                unauthorized(::challenge("Bearer", &parameters))
+            }
+            Error::TooManyAttempts { retry_after } => {
+                let retry_header = rocket::http::Header::new("Retry-After", retry_after.to_string());
 
                 Ok(
                     response::Response::build()
-                        .status(Status::Unauthorized)
-                        .header(www_header)
+                        .status(Status::TooManyRequests)
+                        .header(retry_header)
                         .finalize(),
                 )
             }
-            Error::AuthenticationFailure => Err(Status::Unauthorized),
+            Error::Forbidden => Err(Status::Forbidden),
             Error::HyperError(_) => Err(Status::BadRequest),
             _ => Err(Status::InternalServerError),
         }
     }
 }
 
+/// The realm of the token configuration, if it is managed by Rocket
+fn managed_realm(request: &Request) -> Option<String> {
+    match request.guard::<State<token::Configuration>>() {
+        Outcome::Success(configuration) => Some(configuration.realm()),
+        _ => None,
+    }
+}
+
+/// A `401 Unauthorized` response with the `challenge` in the `WWW-Authenticate` header
+fn unauthorized<'r>(challenge: String) -> Result<response::Response<'r>, Status> {
+    Ok(
+        response::Response::build()
+            .status(Status::Unauthorized)
+            .header(rocket::http::Header::new("WWW-Authenticate", challenge))
+            .finalize(),
+    )
+}
+
 /// `Authorization` HTTP Request Header
 #[derive(Debug)]
 pub struct Authorization<S: header::Scheme + 'static>(pub header::Authorization<S>);
@@ -180,6 +282,18 @@ impl Authorization<Basic> {
         let Authorization(header::Authorization(Basic { ref password, .. })) = *self;
         password.clone()
     }
+
+    /// Returns a copy of the header with the one time password appended to the password
+    pub fn with_one_time_password(&self, one_time_password: &str) -> Self {
+        Authorization(header::Authorization(Basic {
+            username: self.username(),
+            password: Some(format!(
+                "{}{}",
+                self.password().unwrap_or_default(),
+                one_time_password
+            )),
+        }))
+    }
 }
 
 impl Authorization<Bearer> {
@@ -206,6 +320,25 @@ impl<S: header::Scheme + 'static> Deref for Authorization<S> {
     }
 }
 
+/// Name of the request header that clients can send a one time password in, instead of appending it to the password
+pub const ONE_TIME_PASSWORD_HEADER: &'static str = "X-One-Time-Password";
+
+/// The one time password sent in the `X-One-Time-Password` request header, if any. This request guard always
+/// succeeds.
+#[derive(Debug)]
+pub struct OneTimePassword(pub Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for OneTimePassword {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        let one_time_password = request.headers().get_one(ONE_TIME_PASSWORD_HEADER).map(
+            |one_time_password| one_time_password.trim().to_string(),
+        );
+        Outcome::Success(OneTimePassword(one_time_password))
+    }
+}
+
 /// Authenticator trait to be implemented by identity provider (idp) adapters to provide authentication.
 /// Each idp may support all the [schemes](https://hyper.rs/hyper/v0.10.5/hyper/header/trait.Scheme.html)
 /// supported, or just one.
@@ -236,6 +369,21 @@ pub trait Authenticator<S: header::Scheme + 'static>: Send + Sync {
         include_refresh_payload: bool,
     ) -> Result<AuthenticationResult, ::Error>;
 
+    /// Verify the credentials provided in the headers like `authenticate`, together with a one time password sent
+    /// separately, such as in the `X-One-Time-Password` request header. A default implementation that ignores the
+    /// one time password is provided for authenticators that do not verify one time passwords.
+    ///
+    /// Users should not use `authenticate_with_one_time_password` directly and use
+    /// `prepare_one_time_password_response` instead.
+    fn authenticate_with_one_time_password(
+        &self,
+        authorization: &Authorization<S>,
+        _one_time_password: &str,
+        include_refresh_payload: bool,
+    ) -> Result<AuthenticationResult, ::Error> {
+        self.authenticate(authorization, include_refresh_payload)
+    }
+
     /// Verify the credentials provided with the refresh token payload, if supported by the authenticator.
     /// A default implementation that returns an `Err(::Error::UnsupportedOperation)` is provided.
     ///
@@ -255,14 +403,23 @@ pub trait Authenticator<S: header::Scheme + 'static>: Send + Sync {
         request_refresh_token: bool,
     ) -> Result<AuthenticationResult, ::Error> {
         let result = self.authenticate(authorization, request_refresh_token)?;
-        if !request_refresh_token && result.refresh_payload.is_some() {
-            Err(Error::GenericError(
-                "Misbehaving authenticator: refresh token payload was \
-                                    returned when it was not requested for"
-                    .to_string(),
-            ))?;
-        }
-        Ok(result)
+        verify_refresh_payload(result, request_refresh_token)
+    }
+
+    /// Prepare a response to an authentication request with a one time password sent separately from the
+    /// credentials, in the same way as `prepare_authentication_response`
+    fn prepare_one_time_password_response(
+        &self,
+        authorization: &Authorization<S>,
+        one_time_password: &str,
+        request_refresh_token: bool,
+    ) -> Result<AuthenticationResult, ::Error> {
+        let result = self.authenticate_with_one_time_password(
+            authorization,
+            one_time_password,
+            request_refresh_token,
+        )?;
+        verify_refresh_payload(result, request_refresh_token)
     }
 
     /// Prepare a response to a refresh request
@@ -281,11 +438,78 @@ pub trait Authenticator<S: header::Scheme + 'static>: Send + Sync {
         }
         Ok(result)
     }
+
+    /// Verify that the subject of a successful authentication is allowed to request tokens for `service`.
+    /// If not, an `Err(Error::Forbidden)` should be returned. A default implementation that allows all services is
+    /// provided.
+    fn verify_service(&self, _result: &AuthenticationResult, _service: &str) -> Result<(), ::Error> {
+        Ok(())
+    }
+
+    /// Check that the authenticator is able to authenticate, for example that its backend is reachable.
+    /// This is used by the readiness route and should return within a few seconds. A default implementation that
+    /// always succeeds is provided.
+    fn health_check(&self) -> Result<(), ::Error> {
+        Ok(())
+    }
+}
+
+/// Check that an authenticator did not return a refresh token payload when it was not requested for
+fn verify_refresh_payload(
+    result: AuthenticationResult,
+    request_refresh_token: bool,
+) -> Result<AuthenticationResult, ::Error> {
+    if !request_refresh_token && result.refresh_payload.is_some() {
+        Err(Error::GenericError(
+            "Misbehaving authenticator: refresh token payload was \
+                                returned when it was not requested for"
+                .to_string(),
+        ))?;
+    }
+    Ok(result)
+}
+
+impl<S, A> Authenticator<S> for Box<A>
+where
+    S: header::Scheme + 'static,
+    A: Authenticator<S> + ?Sized,
+{
+    fn authenticate(
+        &self,
+        authorization: &Authorization<S>,
+        include_refresh_payload: bool,
+    ) -> Result<AuthenticationResult, ::Error> {
+        (**self).authenticate(authorization, include_refresh_payload)
+    }
+
+    fn authenticate_with_one_time_password(
+        &self,
+        authorization: &Authorization<S>,
+        one_time_password: &str,
+        include_refresh_payload: bool,
+    ) -> Result<AuthenticationResult, ::Error> {
+        (**self).authenticate_with_one_time_password(authorization, one_time_password, include_refresh_payload)
+    }
+
+    fn authenticate_refresh_token(&self, payload: &JsonValue) -> Result<AuthenticationResult, ::Error> {
+        (**self).authenticate_refresh_token(payload)
+    }
+    fn verify_service(&self, result: &AuthenticationResult, service: &str) -> Result<(), ::Error> {
+        (**self).verify_service(result, service)
+    }
+
+    fn health_check(&self) -> Result<(), ::Error> {
+        (**self).health_check()
+    }
 }
 
 /// Convenience function to respond with a missing authorization error
 pub fn missing_authorization<T>(realm: &str) -> Result<T, ::Error> {
-    Err(Error::MissingAuthorization { realm: realm.to_string() })?
+    Err(Error::MissingAuthorization {
+        realm: realm.to_string(),
+        service: None,
+        scope: None,
+    })?
 }
 
 /// Configuration for the associated type `Authenticator`. [`rowdy::Configuration`] expects its `authenticator` field
@@ -559,6 +783,17 @@ pub mod tests {
         assert_eq!(password, Some("open sesame".to_string()));
     }
 
+    #[test]
+    fn one_time_password_is_appended_to_the_password() {
+        let authorization = ::auth::Authorization(header::Authorization(Basic {
+            username: "mei".to_owned(),
+            password: Some("冻住，不许走!".to_string()),
+        }));
+        let authorization = authorization.with_one_time_password("123456");
+        assert_eq!(authorization.username(), "mei");
+        assert_eq!(authorization.password(), Some("冻住，不许走!123456".to_string()));
+    }
+
     #[test]
     #[allow(deprecated)]
     fn parses_bearer_auth_correctly() {
@@ -705,6 +940,6 @@ pub mod tests {
         assert_eq!(response.status(), Status::Unauthorized);
 
         let www_header: Vec<_> = response.headers().get("WWW-Authenticate").collect();
-        assert_eq!(www_header, vec!["Basic realm=https://www.acme.com"]);
+        assert_eq!(www_header, vec!["Basic realm=\"https://www.acme.com\""]);
     }
 }
@@ -0,0 +1,267 @@
+//! Registry of authenticator types that can be selected by name from the configuration at runtime.
+//!
+//! Instead of choosing the [`AuthenticatorConfiguration`] type at compile time, [`DynamicConfiguration`] can be used
+//! with [`rowdy::Configuration`]. The configuration is then
+//! [internally tagged](https://serde.rs/enum-representations.html) with a `type` key, which is the name
+//! that the authenticator type was registered with. The rest of the keys are deserialized by the registered type.
+//!
+//! The following types are registered by default:
+//!
+//! - `noop`: [`NoOpConfiguration`](../struct.NoOpConfiguration.html)
+//! - `chain`: [`ChainAuthenticatorConfiguration`](../struct.ChainAuthenticatorConfiguration.html), which tries
+//! several other authenticators in order
+//! - `csv`: [`SimpleAuthenticatorConfiguration`](../struct.SimpleAuthenticatorConfiguration.html), if the
+//! `simple_authenticator` feature is enabled
+//! - `ldap`: [`LdapAuthenticator`](../struct.LdapAuthenticator.html), if the `ldap_authenticator` feature is enabled
+//! - `api_key`: [`ApiKeyAuthenticatorConfiguration`](../struct.ApiKeyAuthenticatorConfiguration.html), if the
+//! `api_key_authenticator` feature is enabled
+//! - `totp`: [`TotpAuthenticatorConfiguration`](../struct.TotpAuthenticatorConfiguration.html), which requires a one
+//! time password in addition to another authenticator, if the `totp_authenticator` feature is enabled
+//!
+//! # Examples
+//!
+//! ```json
+//! {
+//!     "type": "csv",
+//!     "csv_path": "test/fixtures/users.csv",
+//!     "has_headers": false,
+//!     "delimiter": ","
+//! }
+//! ```
+//!
+//! Library users can register their own authenticator types with [`register`](fn.register.html).
+//!
+//! ```
+//! extern crate rowdy;
+//! extern crate serde_json;
+//!
+//! use rowdy::auth::{self, AuthenticatorConfiguration, DynamicConfiguration, NoOpConfiguration};
+//!
+//! # fn main() {
+//! // Register a type under a different name
+//! auth::registry::register::<NoOpConfiguration>("anything_goes");
+//!
+//! let json = r#"{ "type": "anything_goes" }"#;
+//! let config: DynamicConfiguration = serde_json::from_str(json).unwrap();
+//! let authenticator = config.make_authenticator().unwrap();
+//! # }
+//! ```
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de;
+use serde_json;
+
+use {Error, JsonValue, JsonMap};
+use super::{Basic, BasicAuthenticator, AuthenticatorConfiguration};
+
+/// Key used to tag the type of the authenticator in the configuration
+const TYPE_KEY: &'static str = "type";
+
+/// A function that makes a boxed `Authenticator` from its JSON configuration
+pub type Factory = fn(&JsonValue) -> Result<Box<BasicAuthenticator>, Error>;
+
+lazy_static! {
+    static ref REGISTRY: RwLock<HashMap<String, Factory>> = RwLock::new(builtin_factories());
+}
+
+fn builtin_factories() -> HashMap<String, Factory> {
+    let mut factories: HashMap<String, Factory> = HashMap::new();
+    let _ = factories.insert("noop".to_string(), factory::<super::NoOpConfiguration>);
+    let _ = factories.insert("chain".to_string(), factory::<super::ChainAuthenticatorConfiguration>);
+    #[cfg(feature = "simple_authenticator")]
+    let _ = factories.insert("csv".to_string(), factory::<super::SimpleAuthenticatorConfiguration>);
+    #[cfg(feature = "ldap_authenticator")]
+    let _ = factories.insert("ldap".to_string(), factory::<super::LdapAuthenticator>);
+    #[cfg(feature = "api_key_authenticator")]
+    let _ = factories.insert("api_key".to_string(), factory::<super::ApiKeyAuthenticatorConfiguration>);
+    #[cfg(feature = "totp_authenticator")]
+    let _ = factories.insert("totp".to_string(), factory::<super::TotpAuthenticatorConfiguration>);
+    factories
+}
+
+fn factory<C>(configuration: &JsonValue) -> Result<Box<BasicAuthenticator>, Error>
+where
+    C: AuthenticatorConfiguration<Basic>,
+{
+    let configuration: C = serde_json::from_value(configuration.clone()).map_err(
+        |e| e.to_string(),
+    )?;
+    let authenticator = configuration.make_authenticator()?;
+    Ok(Box::new(authenticator))
+}
+
+/// Register the configuration type `C` under `name`. Any type that was previously registered under the same name
+/// is replaced.
+pub fn register<C>(name: &str)
+where
+    C: AuthenticatorConfiguration<Basic>,
+{
+    let mut registry = REGISTRY.write().expect("Authenticator registry lock is poisoned");
+    let _ = registry.insert(name.to_string(), factory::<C>);
+}
+
+/// Returns the sorted names of the authenticator types registered
+pub fn registered() -> Vec<String> {
+    let registry = REGISTRY.read().expect("Authenticator registry lock is poisoned");
+    let mut names: Vec<String> = registry.keys().cloned().collect();
+    names.sort();
+    names
+}
+
+/// Make an `Authenticator` of the type registered under `authenticator_type` with its JSON configuration
+pub fn make_authenticator(
+    authenticator_type: &str,
+    configuration: &JsonValue,
+) -> Result<Box<BasicAuthenticator>, Error> {
+    // Copy the factory out so that the lock is not held while the authenticator is made
+    let factory = {
+        let registry = REGISTRY.read().map_err(|_| {
+            "Authenticator registry lock is poisoned".to_string()
+        })?;
+        let factory = *registry.get(authenticator_type).ok_or_else(|| {
+            format!("Unknown authenticator type `{}`", authenticator_type)
+        })?;
+        factory
+    };
+    debug_!("Making authenticator of type `{}`", authenticator_type);
+    factory(configuration)
+}
+
+/// Configuration for an authenticator whose type is looked up from the [registry](index.html) by the name
+/// in the `type` key.
+///
+/// See the module documentation for (de)serialization examples.
+#[derive(Clone, PartialEq, Debug)]
+pub struct DynamicConfiguration {
+    /// The name the authenticator type was registered with. (De)serialized as the `type` key.
+    pub authenticator_type: String,
+    /// The rest of the configuration, which will be deserialized by the registered type
+    pub configuration: JsonValue,
+}
+
+impl AuthenticatorConfiguration<Basic> for DynamicConfiguration {
+    type Authenticator = Box<BasicAuthenticator>;
+
+    fn make_authenticator(&self) -> Result<Self::Authenticator, Error> {
+        make_authenticator(&self.authenticator_type, &self.configuration)
+    }
+}
+
+impl Serialize for DynamicConfiguration {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = match self.configuration {
+            JsonValue::Object(ref map) => map.clone(),
+            _ => JsonMap::new(),
+        };
+        let _ = map.insert(
+            TYPE_KEY.to_string(),
+            JsonValue::String(self.authenticator_type.clone()),
+        );
+        map.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for DynamicConfiguration {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let mut map = JsonMap::deserialize(deserializer)?;
+        let authenticator_type = match map.remove(TYPE_KEY) {
+            Some(JsonValue::String(authenticator_type)) => authenticator_type,
+            Some(_) => return Err(de::Error::custom("the authenticator `type` must be a string")),
+            None => return Err(de::Error::missing_field(TYPE_KEY)),
+        };
+
+        Ok(DynamicConfiguration {
+            authenticator_type,
+            configuration: JsonValue::Object(map),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use auth::tests::MockAuthenticatorConfiguration;
+    use super::*;
+
+    #[test]
+    fn builtin_authenticators_are_registered() {
+        let names = registered();
+        assert!(names.contains(&"noop".to_string()));
+        assert!(names.contains(&"chain".to_string()));
+        if cfg!(feature = "simple_authenticator") {
+            assert!(names.contains(&"csv".to_string()));
+        }
+        if cfg!(feature = "ldap_authenticator") {
+            assert!(names.contains(&"ldap".to_string()));
+        }
+        if cfg!(feature = "api_key_authenticator") {
+            assert!(names.contains(&"api_key".to_string()));
+        }
+        if cfg!(feature = "totp_authenticator") {
+            assert!(names.contains(&"totp".to_string()));
+        }
+    }
+
+    #[test]
+    fn dynamic_configuration_round_trip() {
+        let json = r#"{"csv_path":"test/fixtures/users.csv","type":"csv"}"#;
+        let deserialized: DynamicConfiguration = not_err!(serde_json::from_str(json));
+        assert_eq!(deserialized.authenticator_type, "csv");
+        assert_eq!(
+            deserialized.configuration["csv_path"],
+            JsonValue::String("test/fixtures/users.csv".to_string())
+        );
+        assert!(deserialized.configuration.get("type").is_none());
+
+        let serialized = not_err!(serde_json::to_string(&deserialized));
+        assert_eq!(serialized, json);
+    }
+
+    #[test]
+    #[should_panic(expected = "missing field `type`")]
+    fn dynamic_configuration_requires_type() {
+        let _: DynamicConfiguration = serde_json::from_str(r#"{"csv_path": "users.csv"}"#).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown authenticator type `does_not_exist`")]
+    fn unknown_types_are_rejected() {
+        let json = r#"{ "type": "does_not_exist" }"#;
+        let config: DynamicConfiguration = not_err!(serde_json::from_str(json));
+        let _ = config.make_authenticator().unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "simple_authenticator")]
+    fn builtin_authenticator_is_made() {
+        let json = r#"{ "type": "csv", "csv_path": "test/fixtures/users.csv" }"#;
+        let config: DynamicConfiguration = not_err!(serde_json::from_str(json));
+        let authenticator = not_err!(config.make_authenticator());
+
+        let authorization = ::auth::Authorization(::hyper::header::Authorization(Basic {
+            username: "mei".to_string(),
+            password: Some("冻住，不许走!".to_string()),
+        }));
+        let result = not_err!(authenticator.authenticate(&authorization, false));
+        assert_eq!(result.subject, "mei");
+    }
+
+    #[test]
+    fn custom_authenticators_can_be_registered() {
+        register::<MockAuthenticatorConfiguration>("mock");
+        assert!(registered().contains(&"mock".to_string()));
+
+        let json = r#"{ "type": "mock" }"#;
+        let config: DynamicConfiguration = not_err!(serde_json::from_str(json));
+        let _ = not_err!(config.make_authenticator());
+    }
+}
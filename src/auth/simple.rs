@@ -49,7 +49,7 @@ impl SimpleAuthenticator {
     ///
     pub fn with_csv_file(path: &str, has_headers: bool, delimiter: u8) -> Result<Self, Error> {
         let reader = csv::Reader::from_file(path)
-            .map_err(|e| e.to_string())?
+            .map_err(|e| Error::ConfigurationError(format!("Reading users from {} failed: {}", path, e)))?
             .has_headers(has_headers)
             .delimiter(delimiter);
         Self::new(reader)
@@ -64,7 +64,7 @@ impl SimpleAuthenticator {
                 .into_iter()
                 .map(|r| r.unwrap_err().to_string())
                 .collect();
-            Err(errors.join("; "))?;
+            Err(Error::ConfigurationError(errors.join("; ")))?;
         }
 
         type ParsedRecordBytes = Vec<Result<(String, Vec<u8>, Vec<u8>), String>>;
@@ -81,7 +81,7 @@ impl SimpleAuthenticator {
 
         if !errors.is_empty() {
             let errors: Vec<String> = errors.into_iter().map(|r| r.unwrap_err()).collect();
-            Err(errors.join("; "))?;
+            Err(Error::ConfigurationError(errors.join("; ")))?;
         }
 
         let users: Users = users
@@ -175,6 +175,13 @@ impl super::Authenticator<Basic> for SimpleAuthenticator {
             _ => Err(super::Error::AuthenticationFailure)?,
         }
     }
+
+    fn health_check(&self) -> Result<(), ::Error> {
+        if self.users.is_empty() {
+            Err(Error::ConfigurationError("No users are loaded in the CSV database".to_string()))?;
+        }
+        Ok(())
+    }
 }
 
 /// (De)Serializable configuration for `SimpleAuthenticator`. This struct should be included
@@ -234,7 +241,7 @@ pub fn hash_passwords(users: &HashMap<String, String>, salt_len: usize) -> Resul
 /// Generate a new random salt based on the configured salt length
 pub fn generate_salt(salt_length: usize) -> Result<Vec<u8>, Error> {
     let mut salt: Vec<u8> = vec![0; salt_length];
-    jwa::rng().fill(&mut salt).map_err(|e| e.to_string())?;
+    jwa::rng().fill(&mut salt).map_err(|_| Error::CryptographicError)?;
     Ok(salt)
 }
 
@@ -248,7 +255,7 @@ pub fn write_csv<W: Write>(users: &Users, mut writer: W) -> Result<(), Error> {
     Ok(())
 }
 
-fn hex_dump(bytes: &[u8]) -> String {
+pub(crate) fn hex_dump(bytes: &[u8]) -> String {
     let mut v = Vec::with_capacity(bytes.len() * 2);
     for &byte in bytes.iter() {
         v.push(CHARS[(byte >> 4) as usize]);
@@ -0,0 +1,858 @@
+//! Time-based one time password (TOTP) second factor
+//!
+//! Requires the `totp_authenticator` feature.
+//!
+//! The `TotpAuthenticator` wraps another `Authenticator<Basic>` and additionally requires users to provide an
+//! [RFC 6238](https://tools.ietf.org/html/rfc6238) one time password. Clients can either append the one time password
+//! to the password, or send it in the `X-One-Time-Password` request header.
+//!
+//! Usernames are matched case insensitively, because directories like LDAP match them so. If enrollment is not
+//! required, users who are not enrolled are authenticated without a one time password, unless the subject returned
+//! by the wrapped authenticator is enrolled.
+//!
+//! Each one time password can only be used once. The time step of the last one time password used by each user is
+//! remembered in memory, and passwords from the same or earlier time steps are rejected.
+//!
+//! The `amr` (Authentication Methods References) private claim records the methods used to authenticate, as defined
+//! in [RFC 8176](https://tools.ietf.org/html/rfc8176).
+//!
+//! # Secrets
+//!
+//! The secrets of enrolled users are stored in a file as a compact JWE, encrypted with the configured `key`.
+//! Users are enrolled with `rowdy-cli totp enroll`, which prints an `otpauth://` URI that can be imported into
+//! authenticator apps.
+//!
+//! # Configuration Example
+//!
+//! With the [registry](../registry/index.html), the TOTP authenticator is selected with the `totp` type:
+//!
+//! ```json
+//! {
+//!     "type": "totp",
+//!     "secrets_path": "totp_secrets.jwe",
+//!     "key": "a secret key of 32 bytes........",
+//!     "issuer": "ACME",
+//!     "authenticator": {
+//!         "type": "csv",
+//!         "csv_path": "test/fixtures/users.csv"
+//!     }
+//! }
+//! ```
+use std::ascii::AsciiExt;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use chrono::Utc;
+use hyper::header;
+use jwt::{self, jwa, jwk, jws};
+use jwt::jwa::SecureRandom;
+use ring::{digest, hmac};
+use ring::constant_time::verify_slices_are_equal;
+use serde_json;
+
+use {Error, JsonValue, JsonMap};
+use token;
+use super::{Basic, BasicAuthenticator, Authenticator, Authorization, AuthenticatorConfiguration,
+            AuthenticationResult, DynamicConfiguration};
+
+/// Private claim recording the authentication methods used
+pub const AMR_CLAIM: &'static str = "amr";
+
+/// Authentication methods used when a one time password was verified
+const AMR_WITH_OTP: &[&str] = &["pwd", "otp", "mfa"];
+/// Authentication methods used when the user is not enrolled and enrollment is not required
+const AMR_WITHOUT_OTP: &[&str] = &["pwd"];
+
+/// Length in bytes of the secrets generated on enrollment, as recommended by RFC 4226
+const SECRET_LENGTH: usize = 20;
+
+const BASE32_ALPHABET: &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Base32 encoded TOTP secrets, indexed by username
+pub type TotpSecrets = BTreeMap<String, String>;
+
+/// Usernames are compared case insensitively
+fn normalize_username(username: &str) -> String {
+    username.to_lowercase()
+}
+
+/// The JWE that secrets are stored in
+type SecretsJWE = jwt::jwe::Compact<jwt::JWT<JsonValue, jwt::Empty>, jwt::Empty>;
+
+/// Generate the HOTP value of `counter` as defined in [RFC 4226](https://tools.ietf.org/html/rfc4226)
+fn hotp(secret: &[u8], counter: u64, digits: u32) -> u32 {
+    let key = hmac::SigningKey::new(&digest::SHA1, secret);
+    let mut message = [0u8; 8];
+    for (i, byte) in message.iter_mut().enumerate() {
+        *byte = (counter >> (8 * (7 - i))) as u8;
+    }
+    let signature = hmac::sign(&key, &message);
+    let hash = signature.as_ref();
+
+    // Dynamic truncation
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let code = (u32::from(hash[offset] & 0x7f) << 24) | (u32::from(hash[offset + 1]) << 16) |
+        (u32::from(hash[offset + 2]) << 8) | u32::from(hash[offset + 3]);
+    code % 10u32.pow(digits)
+}
+
+/// Encode bytes in unpadded base32 as defined in [RFC 4648](https://tools.ietf.org/html/rfc4648)
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity((bytes.len() * 8 + 4) / 5);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &byte in bytes {
+        buffer = (buffer << 8) | u32::from(byte);
+        bits += 8;
+        while bits >= 5 {
+            encoded.push(BASE32_ALPHABET[((buffer >> (bits - 5)) & 0x1f) as usize] as char);
+            bits -= 5;
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+/// Decode base32, ignoring padding, whitespace and case
+fn base32_decode(encoded: &str) -> Result<Vec<u8>, Error> {
+    let mut decoded = Vec::with_capacity(encoded.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in encoded.chars().filter(|c| *c != '=' && !c.is_whitespace()) {
+        let value = match c.to_ascii_uppercase() {
+            c @ 'A'...'Z' => c as u32 - 'A' as u32,
+            c @ '2'...'7' => c as u32 - '2' as u32 + 26,
+            _ => Err(Error::ConfigurationError(format!("Invalid base32 character `{}`", c)))?,
+        };
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            decoded.push((buffer >> (bits - 8)) as u8);
+            bits -= 8;
+        }
+    }
+    Ok(decoded)
+}
+
+/// Percent encode everything except unreserved characters for use in URIs
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Encrypt the secrets into a compact JWE
+pub fn encrypt_secrets(
+    secrets: &TotpSecrets,
+    key: &jwk::JWK<jwt::Empty>,
+    cek_algorithm: jwa::KeyManagementAlgorithm,
+    enc_algorithm: jwa::ContentEncryptionAlgorithm,
+) -> Result<String, Error> {
+    let payload = serde_json::to_value(secrets)?;
+    let jws = jwt::JWT::new_decoded(
+        jws::Header::from_registered_header(jws::RegisteredHeader {
+            algorithm: jwa::SignatureAlgorithm::None,
+            ..Default::default()
+        }),
+        jwt::ClaimsSet {
+            registered: Default::default(),
+            private: payload,
+        },
+    );
+    let jws = jws.into_encoded(&jws::Secret::None).map_err(
+        token::Error::JWTError,
+    )?;
+
+    let jwe: SecretsJWE = jwt::JWE::new_decrypted(
+        From::from(jwt::jwe::RegisteredHeader {
+            cek_algorithm: cek_algorithm,
+            enc_algorithm: enc_algorithm,
+            media_type: Some("JOSE".to_string()),
+            content_type: Some("JOSE".to_string()),
+            ..Default::default()
+        }),
+        jws,
+    );
+    let jwe = jwe.into_encrypted(key).map_err(token::Error::JWTError)?;
+    Ok(jwe.encrypted().map_err(token::Error::JWTError)?.to_string())
+}
+
+/// Decrypt secrets from a compact JWE made by `encrypt_secrets`
+pub fn decrypt_secrets(
+    encrypted: &str,
+    key: &jwk::JWK<jwt::Empty>,
+    cek_algorithm: jwa::KeyManagementAlgorithm,
+    enc_algorithm: jwa::ContentEncryptionAlgorithm,
+) -> Result<TotpSecrets, Error> {
+    let jwe: SecretsJWE = jwt::JWE::new_encrypted(encrypted);
+    let jwe = jwe.into_decrypted(key, cek_algorithm, enc_algorithm).map_err(
+        token::Error::JWTError,
+    )?;
+
+    let (_, jws) = jwe.unwrap_decrypted();
+    let jws = jws.into_decoded(&jws::Secret::None, jwa::SignatureAlgorithm::None)
+        .map_err(token::Error::JWTError)?;
+    let claims = jws.payload().map_err(token::Error::JWTError)?;
+    Ok(serde_json::from_value(claims.private.clone()).map_err(
+        |e| e.to_string(),
+    )?)
+}
+
+/// An authenticator that requires a TOTP in addition to the credentials verified by another authenticator.
+/// See the module documentation for details.
+pub struct TotpAuthenticator {
+    authenticator: Box<BasicAuthenticator>,
+    secrets: HashMap<String, Vec<u8>>,
+    digits: u32,
+    period: u64,
+    skew: u64,
+    require_enrollment: bool,
+    /// The time step of the last one time password used by each user
+    last_steps: Mutex<HashMap<String, u64>>,
+}
+
+impl TotpAuthenticator {
+    /// Create a new `TotpAuthenticator` wrapping `authenticator`.
+    ///
+    /// One time passwords have `digits` digits, and change every `period` seconds. Passwords up to `skew` periods
+    /// before or after the current time are accepted. If `require_enrollment` is `false`, users without a secret
+    /// are authenticated by `authenticator` alone.
+    pub fn new(
+        authenticator: Box<BasicAuthenticator>,
+        secrets: &TotpSecrets,
+        digits: u32,
+        period: u64,
+        skew: u64,
+        require_enrollment: bool,
+    ) -> Result<Self, Error> {
+        if digits < 6 || digits > 8 {
+            Err(Error::ConfigurationError(format!("TOTP must have 6 to 8 digits, not {}", digits)))?;
+        }
+        if period == 0 {
+            Err(Error::ConfigurationError("TOTP period cannot be zero".to_string()))?;
+        }
+
+        let mut decoded = HashMap::with_capacity(secrets.len());
+        for (username, secret) in secrets {
+            let _ = decoded.insert(normalize_username(username), base32_decode(secret)?);
+        }
+
+        Ok(TotpAuthenticator {
+            authenticator,
+            secrets: decoded,
+            digits,
+            period,
+            skew,
+            require_enrollment,
+            last_steps: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The one time password of `secret` at the time step `step`
+    fn code(&self, secret: &[u8], step: u64) -> String {
+        format!(
+            "{:0width$}",
+            hotp(secret, step, self.digits),
+            width = self.digits as usize
+        )
+    }
+
+    /// Returns the time step that `code` is valid for at `now` seconds since the Unix epoch, if any
+    fn verify_code(&self, secret: &[u8], code: &str, now: u64) -> Option<u64> {
+        let current = now / self.period;
+        let earliest = current.saturating_sub(self.skew);
+        (earliest..current + self.skew + 1).find(|step| {
+            verify_slices_are_equal(self.code(secret, *step).as_bytes(), code.as_bytes()).is_ok()
+        })
+    }
+
+    /// Record that the user has used the one time password of `step`, unless a password from the same or a later
+    /// step was already used
+    fn use_step(&self, username: &str, step: u64) -> Result<(), Error> {
+        let mut last_steps = self.last_steps.lock().map_err(|_| {
+            "TOTP replay protection lock is poisoned".to_string()
+        })?;
+        if let Some(&last_step) = last_steps.get(username) {
+            if last_step >= step {
+                warn_!("One time password of user `{}` was replayed", username);
+                Err(super::Error::AuthenticationFailure)?;
+            }
+        }
+        let _ = last_steps.insert(username.to_string(), step);
+        Ok(())
+    }
+
+    /// Record the authentication methods in the private claims, and in the refresh payload, if any
+    fn wrap_result(&self, result: AuthenticationResult, amr: &[&str]) -> Result<AuthenticationResult, Error> {
+        let amr: Vec<JsonValue> = amr.iter().map(|method| From::from(*method)).collect();
+
+        let mut private_claims = match result.private_claims {
+            JsonValue::Object(map) => map,
+            JsonValue::Null => JsonMap::new(),
+            _ => Err(Error::ClaimConflict(
+                "Private claims are not a map and the authentication methods cannot be recorded".to_string(),
+            ))?,
+        };
+        if private_claims.contains_key(AMR_CLAIM) {
+            Err(Error::ClaimConflict(
+                format!("The claim `{}` is reserved for the TOTP authenticator", AMR_CLAIM),
+            ))?;
+        }
+        let _ = private_claims.insert(AMR_CLAIM.to_string(), JsonValue::Array(amr.clone()));
+
+        let refresh_payload = result.refresh_payload.map(|payload| {
+            let mut map = JsonMap::with_capacity(2);
+            let _ = map.insert("amr".to_string(), JsonValue::Array(amr));
+            let _ = map.insert("payload".to_string(), payload);
+            JsonValue::Object(map)
+        });
+
+        Ok(AuthenticationResult {
+            subject: result.subject,
+            private_claims: JsonValue::Object(private_claims),
+            refresh_payload,
+        })
+    }
+
+    /// Authenticate at `now` seconds since the Unix epoch
+    fn authenticate_at(
+        &self,
+        authorization: &Authorization<Basic>,
+        include_refresh_payload: bool,
+        now: u64,
+    ) -> Result<AuthenticationResult, Error> {
+        let username = authorization.username();
+        let normalized_username = normalize_username(&username);
+        let secret = match self.secrets.get(&normalized_username) {
+            Some(secret) => secret,
+            None if self.require_enrollment => {
+                debug_!("User `{}` is not enrolled for TOTP", username);
+                Err(super::Error::AuthenticationFailure)?
+            }
+            None => {
+                let result = self.authenticator.authenticate(
+                    authorization,
+                    include_refresh_payload,
+                )?;
+                // The wrapped authenticator may know the user by another name than the one sent
+                if self.secrets.contains_key(&normalize_username(&result.subject)) {
+                    warn_!("User `{}` is enrolled for TOTP as `{}`", username, result.subject);
+                    Err(super::Error::AuthenticationFailure)?;
+                }
+                return self.wrap_result(result, AMR_WITHOUT_OTP);
+            }
+        };
+
+        // The one time password is the last `digits` characters of the password
+        let password = authorization.password().unwrap_or_else(String::new);
+        let digits = self.digits as usize;
+        if password.len() < digits || !password.is_char_boundary(password.len() - digits) {
+            Err(super::Error::AuthenticationFailure)?;
+        }
+        let (password, code) = password.split_at(password.len() - digits);
+        let step = self.verify_code(secret, code, now).ok_or_else(
+            || super::Error::AuthenticationFailure,
+        )?;
+
+        let authorization = Authorization(header::Authorization(Basic {
+            username: username.clone(),
+            password: Some(password.to_string()),
+        }));
+        let result = self.authenticator.authenticate(
+            &authorization,
+            include_refresh_payload,
+        )?;
+        self.use_step(&normalized_username, step)?;
+        self.wrap_result(result, AMR_WITH_OTP)
+    }
+}
+
+impl Authenticator<Basic> for TotpAuthenticator {
+    fn authenticate(
+        &self,
+        authorization: &Authorization<Basic>,
+        include_refresh_payload: bool,
+    ) -> Result<AuthenticationResult, Error> {
+        self.authenticate_at(
+            authorization,
+            include_refresh_payload,
+            Utc::now().timestamp() as u64,
+        )
+    }
+
+    fn authenticate_with_one_time_password(
+        &self,
+        authorization: &Authorization<Basic>,
+        one_time_password: &str,
+        include_refresh_payload: bool,
+    ) -> Result<AuthenticationResult, Error> {
+        self.authenticate(
+            &authorization.with_one_time_password(one_time_password),
+            include_refresh_payload,
+        )
+    }
+
+    fn authenticate_refresh_token(&self, refresh_payload: &JsonValue) -> Result<AuthenticationResult, Error> {
+        let amr = refresh_payload
+            .get("amr")
+            .and_then(|amr| amr.as_array())
+            .ok_or_else(|| super::Error::AuthenticationFailure)?;
+        let amr: Vec<&str> = amr.iter().filter_map(|method| method.as_str()).collect();
+        let payload = refresh_payload.get("payload").ok_or_else(
+            || super::Error::AuthenticationFailure,
+        )?;
+
+        let result = self.authenticator.authenticate_refresh_token(payload)?;
+        self.wrap_result(result, &amr)
+    }
+
+    fn verify_service(&self, result: &AuthenticationResult, service: &str) -> Result<(), Error> {
+        self.authenticator.verify_service(result, service)
+    }
+
+    fn health_check(&self) -> Result<(), Error> {
+        self.authenticator.health_check()
+    }
+}
+
+/// (De)Serializable configuration for `TotpAuthenticator`. See the module documentation for an example.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TotpAuthenticatorConfiguration {
+    /// Configuration of the authenticator that verifies the username and password, with its type selected by the
+    /// `type` key.
+    pub authenticator: DynamicConfiguration,
+    /// Path to the encrypted file of secrets. This should be relative to the working directory, or an absolute path.
+    /// The file is only read on startup.
+    pub secrets_path: String,
+    /// Key used to encrypt the file of secrets. See [`token::Secret`](../../token/enum.Secret.html).
+    pub key: token::Secret,
+    /// Algorithm used to determine and/or encrypt the CEK. Defaults to `A256GCMKW`.
+    #[serde(default = "default_cek_algorithm")]
+    pub cek_algorithm: jwa::KeyManagementAlgorithm,
+    /// Algorithm used to encrypt the file of secrets. Defaults to `A256GCM`.
+    #[serde(default = "default_enc_algorithm")]
+    pub enc_algorithm: jwa::ContentEncryptionAlgorithm,
+    /// Issuer displayed by authenticator apps. Defaults to `rowdy`.
+    #[serde(default = "default_issuer")]
+    pub issuer: String,
+    /// Number of digits in each one time password, from 6 to 8. Defaults to 6.
+    #[serde(default = "default_digits")]
+    pub digits: u32,
+    /// Seconds that each one time password is valid for. Defaults to 30.
+    #[serde(default = "default_period")]
+    pub period: u64,
+    /// Number of periods before or after the current time that one time passwords are accepted from. Defaults to 1.
+    #[serde(default = "default_skew")]
+    pub skew: u64,
+    /// Whether users must be enrolled to authenticate. Defaults to `true`.
+    #[serde(default = "default_require_enrollment")]
+    pub require_enrollment: bool,
+}
+
+fn default_cek_algorithm() -> jwa::KeyManagementAlgorithm {
+    jwa::KeyManagementAlgorithm::A256GCMKW
+}
+
+fn default_enc_algorithm() -> jwa::ContentEncryptionAlgorithm {
+    jwa::ContentEncryptionAlgorithm::A256GCM
+}
+
+fn default_issuer() -> String {
+    "rowdy".to_string()
+}
+
+fn default_digits() -> u32 {
+    6
+}
+
+fn default_period() -> u64 {
+    30
+}
+
+fn default_skew() -> u64 {
+    1
+}
+
+fn default_require_enrollment() -> bool {
+    true
+}
+
+impl TotpAuthenticatorConfiguration {
+    /// Read and decrypt the secrets. There are no secrets if the file does not exist yet.
+    pub fn read_secrets(&self) -> Result<TotpSecrets, Error> {
+        if !Path::new(&self.secrets_path).exists() {
+            warn_!("TOTP secrets file `{}` does not exist", self.secrets_path);
+            return Ok(TotpSecrets::new());
+        }
+
+        let mut encrypted = String::new();
+        let _ = File::open(&self.secrets_path)?.read_to_string(&mut encrypted)?;
+        decrypt_secrets(
+            encrypted.trim(),
+            &self.key.for_decryption()?,
+            self.cek_algorithm,
+            self.enc_algorithm,
+        )
+    }
+
+    /// Encrypt and write the secrets, replacing the file
+    pub fn write_secrets(&self, secrets: &TotpSecrets) -> Result<(), Error> {
+        let encrypted = encrypt_secrets(
+            secrets,
+            &self.key.for_encryption()?,
+            self.cek_algorithm,
+            self.enc_algorithm,
+        )?;
+        File::create(&self.secrets_path)?.write_all(
+            encrypted.as_bytes(),
+        )?;
+        Ok(())
+    }
+
+    /// Enroll the user with a new random secret, replacing any existing secret. Returns the `otpauth://` URI to
+    /// import into authenticator apps.
+    pub fn enroll(&self, username: &str) -> Result<String, Error> {
+        let mut secret = vec![0; SECRET_LENGTH];
+        jwa::rng().fill(&mut secret).map_err(|_| Error::CryptographicError)?;
+        let secret = base32_encode(&secret);
+
+        let mut secrets = self.read_secrets()?;
+        let _ = remove_secret(&mut secrets, username);
+        let _ = secrets.insert(normalize_username(username), secret.clone());
+        self.write_secrets(&secrets)?;
+
+        Ok(self.otpauth_uri(username, &secret))
+    }
+
+    /// Remove the secret of the user
+    pub fn unenroll(&self, username: &str) -> Result<(), Error> {
+        let mut secrets = self.read_secrets()?;
+        if !remove_secret(&mut secrets, username) {
+            Err(Error::BadRequest(format!("User `{}` is not enrolled", username)))?;
+        }
+        self.write_secrets(&secrets)
+    }
+
+    /// The `otpauth://` URI for the user with the base32 encoded `secret`
+    pub fn otpauth_uri(&self, username: &str, secret: &str) -> String {
+        format!(
+            "otpauth://totp/{issuer}:{username}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={digits}\
+             &period={period}",
+            issuer = percent_encode(&self.issuer),
+            username = percent_encode(username),
+            secret = secret,
+            digits = self.digits,
+            period = self.period
+        )
+    }
+}
+
+/// Remove the secrets of the user, including those enrolled with the username in another case. Returns whether the
+/// user was enrolled.
+fn remove_secret(secrets: &mut TotpSecrets, username: &str) -> bool {
+    let username = normalize_username(username);
+    let enrolled: Vec<String> = secrets
+        .keys()
+        .filter(|enrolled| normalize_username(enrolled) == username)
+        .cloned()
+        .collect();
+    for enrolled in &enrolled {
+        let _ = secrets.remove(enrolled);
+    }
+    !enrolled.is_empty()
+}
+
+impl AuthenticatorConfiguration<Basic> for TotpAuthenticatorConfiguration {
+    type Authenticator = TotpAuthenticator;
+
+    fn make_authenticator(&self) -> Result<Self::Authenticator, Error> {
+        TotpAuthenticator::new(
+            self.authenticator.make_authenticator()?,
+            &self.read_secrets()?,
+            self.digits,
+            self.period,
+            self.skew,
+            self.require_enrollment,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use ByteSequence;
+    use auth::NoOp;
+    use auth::tests::MockAuthenticator;
+    use super::*;
+
+    /// Secret of the test vectors in RFC 6238
+    const RFC_SECRET: &'static [u8] = b"12345678901234567890";
+
+    fn authorization(username: &str, password: &str) -> Authorization<Basic> {
+        Authorization(header::Authorization(Basic {
+            username: username.to_string(),
+            password: Some(password.to_string()),
+        }))
+    }
+
+    fn make_authenticator(require_enrollment: bool) -> TotpAuthenticator {
+        let mut secrets = TotpSecrets::new();
+        let _ = secrets.insert("mei".to_string(), base32_encode(RFC_SECRET));
+        not_err!(TotpAuthenticator::new(
+            Box::new(MockAuthenticator {}),
+            &secrets,
+            8,
+            30,
+            1,
+            require_enrollment,
+        ))
+    }
+
+    fn configuration(secrets_path: &str) -> TotpAuthenticatorConfiguration {
+        TotpAuthenticatorConfiguration {
+            authenticator: DynamicConfiguration {
+                authenticator_type: "noop".to_string(),
+                configuration: JsonValue::Object(JsonMap::new()),
+            },
+            secrets_path: secrets_path.to_string(),
+            key: token::Secret::ByteSequence(ByteSequence::Bytes(vec![0; 256 / 8])),
+            cek_algorithm: default_cek_algorithm(),
+            enc_algorithm: default_enc_algorithm(),
+            issuer: "ACME Corp".to_string(),
+            digits: default_digits(),
+            period: default_period(),
+            skew: default_skew(),
+            require_enrollment: default_require_enrollment(),
+        }
+    }
+
+    #[test]
+    fn rfc6238_test_vectors() {
+        let vectors = [
+            (59, 94287082),
+            (1111111109, 7081804),
+            (1111111111, 14050471),
+            (1234567890, 89005924),
+            (2000000000, 69279037),
+            (20000000000, 65353130),
+        ];
+        for &(time, expected) in vectors.iter() {
+            assert_eq!(hotp(RFC_SECRET, time / 30, 8), expected);
+        }
+    }
+
+    #[test]
+    fn base32_round_trip() {
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(not_err!(base32_decode("MZXW6YTBOI======")), b"foobar");
+        assert_eq!(not_err!(base32_decode("mzxw 6ytb oi")), b"foobar");
+        assert_eq!(not_err!(base32_decode(&base32_encode(RFC_SECRET))), RFC_SECRET);
+        assert!(base32_decode("MZXW1").is_err());
+    }
+
+    #[test]
+    fn one_time_password_is_appended_to_the_password() {
+        let authenticator = make_authenticator(true);
+
+        let result = not_err!(authenticator.authenticate_at(
+            &authorization("mei", "冻住，不许走!07081804"),
+            false,
+            1111111109,
+        ));
+        assert_eq!(result.subject, "mei");
+        assert_eq!(result.private_claims["amr"], json_amr(AMR_WITH_OTP));
+
+        // Wrong password, right one time password
+        assert!(
+            authenticator
+                .authenticate_at(&authorization("mei", "wrong14050471"), false, 1111111111)
+                .is_err()
+        );
+        // Right password, wrong one time password
+        assert!(
+            authenticator
+                .authenticate_at(&authorization("mei", "冻住，不许走!12345678"), false, 1111111111)
+                .is_err()
+        );
+        // Missing one time password
+        assert!(
+            authenticator
+                .authenticate_at(&authorization("mei", "冻住，不许走!"), false, 1111111111)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn one_time_passwords_within_skew_are_accepted() {
+        let authenticator = make_authenticator(true);
+        // One step after the password was generated
+        let _ = not_err!(authenticator.authenticate_at(
+            &authorization("mei", "冻住，不许走!07081804"),
+            false,
+            1111111109 + 30,
+        ));
+        // Two steps after the password was generated
+        assert!(
+            make_authenticator(true)
+                .authenticate_at(&authorization("mei", "冻住，不许走!07081804"), false, 1111111109 + 60)
+                .is_err()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "AuthenticationFailure")]
+    fn one_time_passwords_cannot_be_replayed() {
+        let authenticator = make_authenticator(true);
+        let authorization = authorization("mei", "冻住，不许走!07081804");
+        let _ = not_err!(authenticator.authenticate_at(&authorization, false, 1111111109));
+        let _ = authenticator
+            .authenticate_at(&authorization, false, 1111111109)
+            .unwrap();
+    }
+
+    #[test]
+    fn enrollment_can_be_optional() {
+        let authenticator = make_authenticator(true);
+        assert!(
+            authenticator
+                .authenticate_at(&authorization("foobar", "password"), false, 59)
+                .is_err()
+        );
+
+        let authenticator = not_err!(TotpAuthenticator::new(
+            Box::new(NoOp {}),
+            &TotpSecrets::new(),
+            6,
+            30,
+            1,
+            false,
+        ));
+        let result = not_err!(authenticator.authenticate_at(&authorization("foobar", "password"), false, 59));
+        assert_eq!(result.private_claims["amr"], json_amr(AMR_WITHOUT_OTP));
+    }
+
+    #[test]
+    fn usernames_are_matched_case_insensitively() {
+        let mut secrets = TotpSecrets::new();
+        let _ = secrets.insert("Mei".to_string(), base32_encode(RFC_SECRET));
+        let authenticator = not_err!(TotpAuthenticator::new(Box::new(NoOp {}), &secrets, 8, 30, 1, false));
+        assert!(
+            authenticator
+                .authenticate_at(&authorization("MEI", "password"), false, 59)
+                .is_err()
+        );
+        let result = not_err!(authenticator.authenticate_at(&authorization("MEI", "password94287082"), false, 59));
+        assert_eq!(result.private_claims["amr"], json_amr(AMR_WITH_OTP));
+        // The one time password cannot be replayed with another case either
+        assert!(
+            authenticator
+                .authenticate_at(&authorization("mei", "password94287082"), false, 59)
+                .is_err()
+        );
+    }
+
+    /// Authenticates every user as `mei`, like directories that accept aliases of users
+    struct Alias;
+
+    impl Authenticator<Basic> for Alias {
+        fn authenticate(
+            &self,
+            _authorization: &Authorization<Basic>,
+            _include_refresh_payload: bool,
+        ) -> Result<AuthenticationResult, Error> {
+            Ok(AuthenticationResult {
+                subject: "mei".to_string(),
+                private_claims: JsonValue::Object(JsonMap::new()),
+                refresh_payload: None,
+            })
+        }
+    }
+
+    #[test]
+    fn enrolled_subjects_require_a_one_time_password() {
+        let mut secrets = TotpSecrets::new();
+        let _ = secrets.insert("mei".to_string(), base32_encode(RFC_SECRET));
+        let authenticator = not_err!(TotpAuthenticator::new(Box::new(Alias), &secrets, 8, 30, 1, false));
+        assert!(
+            authenticator
+                .authenticate_at(&authorization("mei@acme.com", "password"), false, 59)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn refresh_tokens_keep_the_authentication_methods() {
+        let authenticator = make_authenticator(true);
+
+        let result = not_err!(authenticator.authenticate_at(
+            &authorization("mei", "冻住，不许走!89005924"),
+            true,
+            1234567890,
+        ));
+        let refresh_payload = not_none!(result.refresh_payload);
+
+        let result = not_err!(authenticator.prepare_refresh_response(&refresh_payload));
+        assert_eq!(result.subject, "mei");
+        assert_eq!(result.private_claims["amr"], json_amr(AMR_WITH_OTP));
+    }
+
+    #[test]
+    fn secrets_round_trip() {
+        let config = configuration("unused");
+        let mut secrets = TotpSecrets::new();
+        let _ = secrets.insert("mei".to_string(), base32_encode(RFC_SECRET));
+
+        let key = not_err!(config.key.for_encryption());
+        let encrypted = not_err!(encrypt_secrets(
+            &secrets,
+            &key,
+            config.cek_algorithm,
+            config.enc_algorithm,
+        ));
+        assert!(!encrypted.contains(&base32_encode(RFC_SECRET)));
+
+        let decrypted = not_err!(decrypt_secrets(
+            &encrypted,
+            &key,
+            config.cek_algorithm,
+            config.enc_algorithm,
+        ));
+        assert_eq!(decrypted, secrets);
+    }
+
+    #[test]
+    fn enrolled_users_can_authenticate() {
+        let path = env::temp_dir().join("rowdy_totp_enrollment_test.jwe");
+        let config = configuration(not_none!(path.to_str()));
+        let _ = ::std::fs::remove_file(&path);
+
+        let uri = not_err!(config.enroll("mei@acme.com"));
+        assert!(uri.starts_with("otpauth://totp/ACME%20Corp:mei%40acme.com?secret="));
+        assert!(uri.ends_with("&issuer=ACME%20Corp&algorithm=SHA1&digits=6&period=30"));
+
+        let secrets = not_err!(config.read_secrets());
+        let secret = not_err!(base32_decode(not_none!(secrets.get("mei@acme.com"))));
+        let authenticator = not_err!(config.make_authenticator());
+        let now = Utc::now().timestamp() as u64;
+        let password = format!("password{}", authenticator.code(&secret, now / 30));
+        let result = not_err!(authenticator.authenticate_at(&authorization("mei@acme.com", &password), false, now));
+        assert_eq!(result.subject, "mei@acme.com");
+
+        not_err!(config.unenroll("Mei@ACME.com"));
+        assert!(not_err!(config.read_secrets()).is_empty());
+        not_err!(::std::fs::remove_file(&path));
+    }
+
+    fn json_amr(amr: &[&str]) -> JsonValue {
+        JsonValue::Array(amr.iter().map(|method| From::from(*method)).collect())
+    }
+}
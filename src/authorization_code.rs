@@ -0,0 +1,647 @@
+//! Authorization code grant with PKCE for browser based clients
+//!
+//! Browser based clients must not collect passwords themselves. Instead, they send the user to `GET /authorize` as
+//! described in [RFC 6749](https://tools.ietf.org/html/rfc6749#section-4.1), where rowdy renders a login form. The
+//! credentials entered are verified by the authenticator of HTTP Basic authentication, and attempts are throttled
+//! and audited in the same way as the other routes. The browser is then redirected to the `redirect_uri` of the
+//! client with a short lived `code` and the `state` of the request. The client exchanges the code at `POST /token`
+//! with the `authorization_code` grant.
+//!
+//! Clients are public, and prove that they started the request with
+//! [PKCE](https://tools.ietf.org/html/rfc7636) instead of a secret. The `code_challenge` is required and only the
+//! `S256` method is supported. Codes can be exchanged only once, by the client they were issued to, with the same
+//! `redirect_uri` and the `code_verifier` of the challenge. Codes are kept in memory, so they are lost on restart
+//! and are not shared by multiple instances of rowdy.
+//!
+//! The request parameters are `response_type`, which must be `code`, `client_id`, `redirect_uri`, `scope`, `state`,
+//! `code_challenge`, `code_challenge_method`, `nonce` and `resource`. The `redirect_uri` can be left out if the
+//! client has only one registered. The `resource` is the service the access token is intended for, and can be left
+//! out if only one audience is configured.
+//!
+//! # Configuration Example
+//!
+//! The authorization code grant is enabled by the `authorization_code` key of the
+//! [configuration](../struct.Configuration.html), which registers the clients by their client ID.
+//! Redirect URIs are compared exactly.
+//!
+//! ```json
+//! {
+//!     "clients": {
+//!         "registry-ui": {
+//!             "name": "Registry UI",
+//!             "redirect_uris": ["https://registry.example.com/callback"]
+//!         }
+//!     },
+//!     "expiry_duration": 60
+//! }
+//! ```
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{self, DateTime, Utc};
+use hyper;
+use jwt::jwa::{self, SecureRandom};
+use ring::constant_time::verify_slices_are_equal;
+use ring::digest;
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::Request;
+use rocket::response::{Responder, Response};
+
+use auth;
+use jwks;
+use oauth;
+use token;
+
+/// Response type of the authorization code grant
+pub const CODE_RESPONSE_TYPE: &'static str = "code";
+/// The only supported PKCE code challenge method
+pub const S256_CHALLENGE_METHOD: &'static str = "S256";
+
+/// Length in bytes of the random part of codes
+const CODE_LENGTH: usize = 32;
+
+/// Settings of the authorization code grant. See the module documentation for an example.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Configuration {
+    /// Clients that can request codes, keyed by their client ID
+    pub clients: HashMap<String, ClientConfiguration>,
+    /// Expiry duration of codes, in seconds. Defaults to 60 seconds.
+    #[serde(with = "::serde_custom::duration", default = "default_expiry_duration")]
+    pub expiry_duration: Duration,
+}
+
+fn default_expiry_duration() -> Duration {
+    Duration::from_secs(60)
+}
+
+/// A client that can request codes
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ClientConfiguration {
+    /// Name of the client shown on the login form. Defaults to the client ID.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// URIs that the browser can be redirected to with a code
+    pub redirect_uris: Vec<String>,
+}
+
+/// Parameters of the authorization endpoint, sent in the query to display the login form, and in the form encoded
+/// body with the credentials of the user to log in
+#[derive(FromForm, Clone, Default, Debug)]
+pub struct AuthorizationRequest {
+    /// Must be `code`
+    pub response_type: Option<String>,
+    /// ID of a registered client
+    pub client_id: Option<String>,
+    /// One of the redirect URIs of the client
+    pub redirect_uri: Option<String>,
+    /// The scope requested
+    pub scope: Option<String>,
+    /// Opaque value sent back to the client with the code
+    pub state: Option<String>,
+    /// The PKCE code challenge
+    pub code_challenge: Option<String>,
+    /// The PKCE code challenge method, which must be `S256`
+    pub code_challenge_method: Option<String>,
+    /// Value to include in the ID token, if the `openid` scope is requested
+    pub nonce: Option<String>,
+    /// The service the access token is intended for
+    pub resource: Option<String>,
+    /// Username entered in the login form
+    pub username: Option<String>,
+    /// Password entered in the login form
+    pub password: Option<String>,
+}
+
+impl AuthorizationRequest {
+    /// Verify the request against the configuration and the settings of the grant, if it is enabled. Requests for
+    /// unknown clients or redirect URIs are answered with an error page, because the browser cannot be trusted to be
+    /// redirected. Other errors are sent to the redirect URI of the client.
+    pub fn verify<'a>(
+        &self,
+        configuration: &token::Configuration,
+        authorization_code: &'a Option<Configuration>,
+    ) -> Result<VerifiedRequest<'a>, AuthorizationResponse> {
+        let (clients, expiry_duration) = match *authorization_code {
+            Some(ref authorization_code) => (&authorization_code.clients, authorization_code.expiry_duration),
+            None => {
+                return Err(AuthorizationResponse::error_page(
+                    Status::NotFound,
+                    "The authorization code grant is not enabled",
+                ))
+            }
+        };
+        let (client_id, client) = match self.client_id {
+            Some(ref client_id) => {
+                match clients.get(client_id) {
+                    Some(client) => (client_id.to_string(), client),
+                    None => {
+                        return Err(AuthorizationResponse::error_page(
+                            Status::BadRequest,
+                            "The client is not registered",
+                        ))
+                    }
+                }
+            }
+            None => {
+                return Err(AuthorizationResponse::error_page(
+                    Status::BadRequest,
+                    "The `client_id` parameter is required",
+                ))
+            }
+        };
+        let redirect_uri = match self.redirect_uri {
+            Some(ref redirect_uri) if client.redirect_uris.contains(redirect_uri) => redirect_uri.to_string(),
+            None if client.redirect_uris.len() == 1 => client.redirect_uris[0].to_string(),
+            _ => {
+                return Err(AuthorizationResponse::error_page(
+                    Status::BadRequest,
+                    "The redirect URI is not registered for the client",
+                ))
+            }
+        };
+
+        let redirect_error = |error: oauth::ErrorCode, description: &str| {
+            AuthorizationResponse::redirect_error(&redirect_uri, &oauth::Error::new(error, description), &self.state)
+        };
+        if self.response_type.as_ref().map(|response_type| response_type.as_str()) != Some(CODE_RESPONSE_TYPE) {
+            Err(redirect_error(
+                oauth::ErrorCode::UnsupportedResponseType,
+                "Only the `code` response type is supported",
+            ))?;
+        }
+        let code_challenge = match self.code_challenge {
+            Some(ref code_challenge) if is_code_challenge(code_challenge) => code_challenge.to_string(),
+            Some(_) => {
+                Err(redirect_error(
+                    oauth::ErrorCode::InvalidRequest,
+                    "The `code_challenge` is invalid",
+                ))?
+            }
+            None => {
+                Err(redirect_error(
+                    oauth::ErrorCode::InvalidRequest,
+                    "The `code_challenge` parameter is required",
+                ))?
+            }
+        };
+        if self.code_challenge_method.as_ref().map(|method| method.as_str()) != Some(S256_CHALLENGE_METHOD) {
+            Err(redirect_error(
+                oauth::ErrorCode::InvalidRequest,
+                "Only the `S256` code challenge method is supported",
+            ))?;
+        }
+        let service = match oauth::resource(&self.resource, configuration) {
+            Ok(service) => service,
+            Err(e) => Err(AuthorizationResponse::redirect_error(&redirect_uri, &e, &self.state))?,
+        };
+        if let Err(e) = token::verify_service(configuration, &service) {
+            Err(AuthorizationResponse::redirect_error(
+                &redirect_uri,
+                &From::from(::Error::from(e)),
+                &self.state,
+            ))?;
+        }
+
+        Ok(VerifiedRequest {
+            client_id,
+            client,
+            redirect_uri: redirect_uri.clone(),
+            service,
+            code_challenge,
+            expiry_duration,
+        })
+    }
+}
+
+/// An authorization request that was verified against the configuration
+#[derive(Debug)]
+pub struct VerifiedRequest<'a> {
+    /// ID of the client
+    pub client_id: String,
+    /// The registered client
+    pub client: &'a ClientConfiguration,
+    /// The redirect URI requested, or the only one registered for the client
+    pub redirect_uri: String,
+    /// The service the access token is intended for
+    pub service: String,
+    /// The PKCE code challenge
+    pub code_challenge: String,
+    /// Expiry duration of the code
+    pub expiry_duration: Duration,
+}
+
+/// What a code was issued for. The code can be exchanged for an access token for the `result` of authentication.
+#[derive(Clone, Debug)]
+pub struct Grant {
+    /// ID of the client the code was issued to
+    pub client_id: String,
+    /// The `redirect_uri` parameter of the request, which has to be sent again to exchange the code
+    pub redirect_uri: Option<String>,
+    /// The PKCE code challenge
+    pub code_challenge: String,
+    /// The service the access token is intended for
+    pub service: String,
+    /// The scope requested
+    pub scope: String,
+    /// Value to include in the ID token, if any
+    pub nonce: Option<String>,
+    /// The username the user logged in with
+    pub username: String,
+    /// Time the user logged in
+    pub auth_time: DateTime<Utc>,
+    /// Result of the authentication
+    pub result: auth::AuthenticationResult,
+}
+
+/// Codes that have been issued and not yet exchanged. This is managed by Rocket.
+#[derive(Default)]
+pub struct AuthorizationCodes {
+    codes: Mutex<HashMap<String, (Grant, DateTime<Utc>)>>,
+}
+
+impl AuthorizationCodes {
+    /// Create an empty store of codes
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Issue a new code for `grant` that expires after `expiry_duration`
+    pub fn issue(&self, grant: Grant, expiry_duration: Duration) -> Result<String, ::Error> {
+        let mut code = vec![0; CODE_LENGTH];
+        jwa::rng().fill(&mut code).map_err(|_| ::Error::CryptographicError)?;
+        let code = jwks::base64url_encode(&code);
+
+        let now = Utc::now();
+        let expiry_duration = chrono::Duration::from_std(expiry_duration).map_err(|e| e.to_string())?;
+        let mut codes = self.lock()?;
+        codes.retain(|_, &mut (_, expires_at)| expires_at > now);
+        let _ = codes.insert(code.clone(), (grant, now + expiry_duration));
+        Ok(code)
+    }
+
+    /// Exchange `code` for its grant. The code cannot be exchanged again, even if the exchange fails.
+    pub fn redeem(
+        &self,
+        code: &str,
+        client_id: &str,
+        redirect_uri: &Option<String>,
+        code_verifier: &str,
+    ) -> Result<Grant, oauth::Error> {
+        let invalid_grant = || oauth::Error::new(
+            oauth::ErrorCode::InvalidGrant,
+            "The authorization code is invalid or expired",
+        );
+        let (grant, expires_at) = self.lock()?.remove(code).ok_or_else(&invalid_grant)?;
+        if expires_at <= Utc::now() || grant.client_id != client_id || grant.redirect_uri != *redirect_uri {
+            Err(invalid_grant())?;
+        }
+        if !verify_code_verifier(code_verifier, &grant.code_challenge) {
+            Err(oauth::Error::new(
+                oauth::ErrorCode::InvalidGrant,
+                "The `code_verifier` does not match the code challenge",
+            ))?;
+        }
+        Ok(grant)
+    }
+
+    fn lock(&self) -> Result<::std::sync::MutexGuard<HashMap<String, (Grant, DateTime<Utc>)>>, ::Error> {
+        Ok(self.codes.lock().map_err(
+            |_| "Authorization code store lock is poisoned".to_string(),
+        )?)
+    }
+}
+
+/// Code challenges are base64url encoded SHA-256 digests
+fn is_code_challenge(code_challenge: &str) -> bool {
+    code_challenge.len() == 43 && jwks::base64url_decode(code_challenge).is_some()
+}
+
+/// Verify that `code_verifier` is valid as defined in [RFC 7636](https://tools.ietf.org/html/rfc7636#section-4.1),
+/// and that it is the `S256` code challenge
+fn verify_code_verifier(code_verifier: &str, code_challenge: &str) -> bool {
+    let valid = code_verifier.len() >= 43 && code_verifier.len() <= 128 &&
+        code_verifier.bytes().all(|byte| match byte {
+            b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'-' | b'.' | b'_' | b'~' => true,
+            _ => false,
+        });
+    if !valid {
+        return false;
+    }
+    let digest = digest::digest(&digest::SHA256, code_verifier.as_bytes());
+    let expected = jwks::base64url_encode(digest.as_ref());
+    verify_slices_are_equal(expected.as_bytes(), code_challenge.as_bytes()).is_ok()
+}
+
+/// Response of the authorization endpoint, and of the verification page of the
+/// [device authorization grant](../device/index.html)
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum AuthorizationResponse {
+    /// An HTML page with the status
+    Page(Status, String),
+    /// A redirect of the browser to the URL
+    Redirect(String),
+}
+
+impl AuthorizationResponse {
+    /// The login form for the verified `request`, with an `error` message if a previous attempt failed
+    pub fn login(request: &AuthorizationRequest, verified: &VerifiedRequest, error: Option<&str>) -> Self {
+        let name = verified.client.name.as_ref().unwrap_or(&verified.client_id);
+        let parameters = [
+            ("response_type", &request.response_type),
+            ("client_id", &request.client_id),
+            ("redirect_uri", &request.redirect_uri),
+            ("scope", &request.scope),
+            ("state", &request.state),
+            ("code_challenge", &request.code_challenge),
+            ("code_challenge_method", &request.code_challenge_method),
+            ("nonce", &request.nonce),
+            ("resource", &request.resource),
+        ];
+        let hidden: String = parameters
+            .iter()
+            .filter_map(|&(name, value)| value.as_ref().map(|value| (name, value)))
+            .map(|(name, value)| {
+                format!(
+                    "<input type=\"hidden\" name=\"{}\" value=\"{}\">\n",
+                    name,
+                    escape_html(value)
+                )
+            })
+            .collect();
+        let error = match error {
+            Some(error) => format!("<p role=\"alert\">{}</p>\n", escape_html(error)),
+            None => String::new(),
+        };
+        let username = request.username.as_ref().map(|username| escape_html(username)).unwrap_or_default();
+        let body = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Sign in</title>\n</head>\n<body>\n\
+             <h1>Sign in to {}</h1>\n{}<form method=\"post\" action=\"authorize\">\n{}\
+             <label>Username <input name=\"username\" value=\"{}\" autocomplete=\"username\" required></label>\n\
+             <label>Password <input type=\"password\" name=\"password\" autocomplete=\"current-password\" \
+             required></label>\n<button type=\"submit\">Sign in</button>\n</form>\n</body>\n</html>\n",
+            escape_html(name),
+            error,
+            hidden,
+            username
+        );
+        AuthorizationResponse::Page(Status::Ok, body)
+    }
+
+    /// A page with an error that cannot be sent to the client
+    pub fn error_page(status: Status, message: &str) -> Self {
+        let body = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Error</title>\n</head>\n<body>\n\
+             <p role=\"alert\">{}</p>\n</body>\n</html>\n",
+            escape_html(message)
+        );
+        AuthorizationResponse::Page(status, body)
+    }
+
+    /// Redirect to `redirect_uri` with a code
+    pub fn redirect_code(redirect_uri: &str, code: &str, state: &Option<String>) -> Self {
+        Self::redirect(redirect_uri, &[("code", code)], state)
+    }
+
+    /// Redirect to `redirect_uri` with an error
+    pub fn redirect_error(redirect_uri: &str, error: &oauth::Error, state: &Option<String>) -> Self {
+        let code = match ::serde_json::to_value(error.error) {
+            Ok(::JsonValue::String(code)) => code,
+            _ => "server_error".to_string(),
+        };
+        let description = error.error_description.clone().unwrap_or_default();
+        Self::redirect(
+            redirect_uri,
+            &[("error", &code), ("error_description", &description)],
+            state,
+        )
+    }
+
+    fn redirect(redirect_uri: &str, parameters: &[(&str, &str)], state: &Option<String>) -> Self {
+        // The redirect URI was registered, so it is expected to be valid
+        let mut url = match hyper::Url::parse(redirect_uri) {
+            Ok(url) => url,
+            Err(e) => {
+                error_!("Registered redirect URI {} is invalid: {}", redirect_uri, e);
+                return Self::error_page(Status::InternalServerError, "The redirect URI of the client is invalid");
+            }
+        };
+        {
+            let mut query = url.query_pairs_mut();
+            for &(name, value) in parameters {
+                let _ = query.append_pair(name, value);
+            }
+            if let Some(ref state) = *state {
+                let _ = query.append_pair("state", state);
+            }
+        }
+        AuthorizationResponse::Redirect(url.to_string())
+    }
+}
+
+impl<'r> Responder<'r> for AuthorizationResponse {
+    fn respond_to(self, _: &Request) -> Result<Response<'r>, Status> {
+        let mut response = Response::build();
+        let _ = response
+            .header(Header::new("Cache-Control", "no-store"))
+            .header(Header::new("Pragma", "no-cache"));
+        match self {
+            AuthorizationResponse::Page(status, body) => {
+                let _ = response
+                    .status(status)
+                    .header(ContentType::HTML)
+                    .header(Header::new("X-Frame-Options", "DENY"))
+                    .header(Header::new(
+                        "Content-Security-Policy",
+                        "default-src 'none'; frame-ancestors 'none'",
+                    ))
+                    .sized_body(Cursor::new(body));
+            }
+            AuthorizationResponse::Redirect(url) => {
+                let _ = response.status(Status::SeeOther).header(
+                    Header::new("Location", url),
+                );
+            }
+        }
+        response.ok()
+    }
+}
+
+/// Escape `value` to be included in HTML text or attribute values
+pub(crate) fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use JsonMap;
+    use super::*;
+
+    /// The verifier and challenge of [RFC 7636](https://tools.ietf.org/html/rfc7636#appendix-B)
+    const CODE_VERIFIER: &'static str = "dBjftJeZ4CK-mf6Yl7Ld2svJhR0S0j4LyMrMkmgpTIo";
+    const CODE_CHALLENGE: &'static str = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+
+    fn grant() -> Grant {
+        Grant {
+            client_id: "registry-ui".to_string(),
+            redirect_uri: Some("https://registry.example.com/callback".to_string()),
+            code_challenge: CODE_CHALLENGE.to_string(),
+            service: "https://www.example.com".to_string(),
+            scope: "all".to_string(),
+            nonce: None,
+            username: "mei".to_string(),
+            auth_time: Utc::now(),
+            result: auth::AuthenticationResult {
+                subject: "mei".to_string(),
+                private_claims: ::JsonValue::Object(JsonMap::new()),
+                refresh_payload: None,
+            },
+        }
+    }
+
+    fn token_configuration() -> token::Configuration {
+        let json = r#"{
+            "issuer": "https://www.acme.com",
+            "audience": "https://www.example.com"
+        }"#;
+        not_err!(serde_json::from_str(json))
+    }
+
+    fn client_configuration() -> Option<Configuration> {
+        let json = r#"{
+            "clients": {
+                "registry-ui": {
+                    "name": "Registry <UI>",
+                    "redirect_uris": ["https://registry.example.com/callback"]
+                }
+            }
+        }"#;
+        Some(not_err!(serde_json::from_str(json)))
+    }
+
+    fn request() -> AuthorizationRequest {
+        AuthorizationRequest {
+            response_type: Some("code".to_string()),
+            client_id: Some("registry-ui".to_string()),
+            state: Some("xyz".to_string()),
+            code_challenge: Some(CODE_CHALLENGE.to_string()),
+            code_challenge_method: Some("S256".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn code_verifiers_are_verified() {
+        assert!(is_code_challenge(CODE_CHALLENGE));
+        assert!(verify_code_verifier(CODE_VERIFIER, CODE_CHALLENGE));
+        assert!(!verify_code_verifier(&CODE_VERIFIER[1..], CODE_CHALLENGE));
+        assert!(!verify_code_verifier(CODE_CHALLENGE, CODE_CHALLENGE));
+    }
+
+    #[test]
+    fn codes_are_single_use() {
+        let codes = AuthorizationCodes::new();
+        let code = not_err!(codes.issue(grant(), Duration::from_secs(60)));
+        let redirect_uri = Some("https://registry.example.com/callback".to_string());
+
+        let redeemed = not_err!(codes.redeem(&code, "registry-ui", &redirect_uri, CODE_VERIFIER));
+        assert_eq!(redeemed.username, "mei");
+
+        let error = codes.redeem(&code, "registry-ui", &redirect_uri, CODE_VERIFIER).unwrap_err();
+        assert_eq!(error.error, oauth::ErrorCode::InvalidGrant);
+    }
+
+    #[test]
+    fn codes_are_bound_to_the_client_redirect_uri_and_challenge() {
+        let codes = AuthorizationCodes::new();
+        let redirect_uri = Some("https://registry.example.com/callback".to_string());
+
+        let code = not_err!(codes.issue(grant(), Duration::from_secs(60)));
+        assert!(codes.redeem(&code, "other-client", &redirect_uri, CODE_VERIFIER).is_err());
+
+        let code = not_err!(codes.issue(grant(), Duration::from_secs(60)));
+        assert!(codes.redeem(&code, "registry-ui", &None, CODE_VERIFIER).is_err());
+
+        let code = not_err!(codes.issue(grant(), Duration::from_secs(60)));
+        assert!(codes.redeem(&code, "registry-ui", &redirect_uri, &"a".repeat(43)).is_err());
+        // The failed exchange used up the code
+        assert!(codes.redeem(&code, "registry-ui", &redirect_uri, CODE_VERIFIER).is_err());
+
+        let code = not_err!(codes.issue(grant(), Duration::from_secs(0)));
+        assert!(codes.redeem(&code, "registry-ui", &redirect_uri, CODE_VERIFIER).is_err());
+    }
+
+    #[test]
+    fn requests_are_verified() {
+        let configuration = token_configuration();
+        let authorization_code = client_configuration();
+
+        let verified = not_err!(request().verify(&configuration, &authorization_code));
+        assert_eq!(verified.redirect_uri, "https://registry.example.com/callback");
+        assert_eq!(verified.service, "https://www.example.com");
+
+        let unknown_client = AuthorizationRequest {
+            client_id: Some("evil".to_string()),
+            ..request()
+        };
+        assert_matches_non_debug!(
+            unknown_client.verify(&configuration, &authorization_code),
+            Err(AuthorizationResponse::Page(Status::BadRequest, _))
+        );
+
+        let unknown_redirect_uri = AuthorizationRequest {
+            redirect_uri: Some("https://evil.example.com/callback".to_string()),
+            ..request()
+        };
+        assert_matches_non_debug!(
+            unknown_redirect_uri.verify(&configuration, &authorization_code),
+            Err(AuthorizationResponse::Page(Status::BadRequest, _))
+        );
+
+        let plain_challenge = AuthorizationRequest {
+            code_challenge_method: Some("plain".to_string()),
+            ..request()
+        };
+        let redirect = match plain_challenge.verify(&configuration, &authorization_code) {
+            Err(AuthorizationResponse::Redirect(redirect)) => redirect,
+            _ => panic!("Expected a redirect"),
+        };
+        let redirect = not_err!(hyper::Url::parse(&redirect));
+        let query: HashMap<_, _> = redirect.query_pairs().into_owned().collect();
+        assert_eq!(query["error"], "invalid_request");
+        assert_eq!(query["state"], "xyz");
+    }
+
+    #[test]
+    fn login_form_is_escaped() {
+        let configuration = token_configuration();
+        let authorization_code = client_configuration();
+        let request = AuthorizationRequest {
+            state: Some("\"><script>".to_string()),
+            ..request()
+        };
+        let verified = not_err!(request.verify(&configuration, &authorization_code));
+        let body = match AuthorizationResponse::login(&request, &verified, Some("Invalid username or password")) {
+            AuthorizationResponse::Page(Status::Ok, body) => body,
+            _ => panic!("Expected a page"),
+        };
+        assert!(body.contains("Sign in to Registry &lt;UI&gt;"));
+        assert!(body.contains("name=\"state\" value=\"&quot;&gt;&lt;script&gt;\""));
+        assert!(body.contains("Invalid username or password"));
+        assert!(!body.contains("<script>"));
+    }
+}
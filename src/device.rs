@@ -0,0 +1,705 @@
+//! Device authorization grant for CLI tools and other devices that should not handle passwords
+//!
+//! Instead of asking for the password of the user, the device requests a device code at `POST /device/code` as
+//! described in [RFC 8628](https://tools.ietf.org/html/rfc8628). The response has a short `user_code`, which the user
+//! enters at the `verification_uri` in a browser, where rowdy renders a page to log in and approve or deny the
+//! request. The credentials entered are verified by the authenticator of HTTP Basic authentication, and attempts are
+//! throttled and audited in the same way as the other routes.
+//!
+//! Meanwhile, the device polls `POST /token` with the
+//! `urn:ietf:params:oauth:grant-type:device_code` grant, its `device_code` and `client_id`, no more often than every
+//! `interval` seconds. The token endpoint responds with:
+//!
+//! - `authorization_pending` until the user approves or denies the request.
+//! - `slow_down` if the device polls too often, after which it has to wait five more seconds between requests.
+//! - `access_denied` if the user denied the request.
+//! - `expired_token` if the device code expired.
+//! - An access token once the request was approved. The device code cannot be used again.
+//!
+//! Clients are public and do not have a secret. The request parameters of `POST /device/code` are `client_id`,
+//! `scope` and `resource`, which is the service the access token is intended for, and can be left out if only one
+//! audience is configured. Device codes are kept in memory, so they are lost on restart and are not shared by
+//! multiple instances of rowdy. At most 10,000 device codes can be pending at once, after which requests for new ones
+//! are refused with `slow_down` until codes expire.
+//!
+//! Requests for device codes, and user codes entered on the verification page, are throttled by the address of the
+//! client. The user has to log in to deny a request as well as to approve it.
+//!
+//! # Example
+//!
+//! ```text
+//! POST /device/code HTTP/1.1
+//! Content-Type: application/x-www-form-urlencoded
+//!
+//! client_id=registry-cli&resource=https%3A%2F%2Fwww.example.com
+//! ```
+//!
+//! ```json
+//! {
+//!     "device_code": "GmRhmhcxhwAzkoEqiMEg_DnyEysNkuNhszIySk9eS",
+//!     "user_code": "WDJB-MJHT",
+//!     "verification_uri": "https://www.acme.com/device",
+//!     "verification_uri_complete": "https://www.acme.com/device?user_code=WDJB-MJHT",
+//!     "expires_in": 600,
+//!     "interval": 5
+//! }
+//! ```
+//!
+//! # Configuration Example
+//!
+//! The device authorization grant is enabled by the `device` key of the
+//! [configuration](../struct.Configuration.html), which registers the clients by their client ID.
+//! `expiry_duration` and `interval` are in seconds.
+//!
+//! ```json
+//! {
+//!     "clients": {
+//!         "registry-cli": { "name": "Registry CLI" }
+//!     },
+//!     "expiry_duration": 600,
+//!     "interval": 5
+//! }
+//! ```
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{self, DateTime, Utc};
+use jwt::jwa::{self, SecureRandom};
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::Request;
+use rocket::response::{Responder, Response};
+use serde_json;
+
+use auth;
+use authorization_code::{escape_html, AuthorizationResponse};
+use jwks;
+use oauth;
+use token;
+
+/// Characters of user codes, which are consonants that are hard to confuse, as recommended by
+/// [RFC 8628](https://tools.ietf.org/html/rfc8628#section-6.1)
+const USER_CODE_CHARACTERS: &'static [u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+/// Number of characters in user codes
+const USER_CODE_LENGTH: usize = 8;
+/// Length in bytes of the random part of device codes
+const DEVICE_CODE_LENGTH: usize = 32;
+/// Seconds added to the polling interval of a device that polls too often
+const SLOW_DOWN_SECONDS: u64 = 5;
+/// Maximum number of device codes that can be pending at once
+const MAX_PENDING: usize = 10_000;
+
+/// Settings of the device authorization grant. See the module documentation for an example.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Configuration {
+    /// Clients that can request device codes, keyed by their client ID
+    pub clients: HashMap<String, ClientConfiguration>,
+    /// Expiry duration of device codes, in seconds. Defaults to 10 minutes.
+    #[serde(with = "::serde_custom::duration", default = "default_expiry_duration")]
+    pub expiry_duration: Duration,
+    /// Minimum duration between polling requests of the device, in seconds. Defaults to 5 seconds.
+    #[serde(with = "::serde_custom::duration", default = "default_interval")]
+    pub interval: Duration,
+}
+
+fn default_expiry_duration() -> Duration {
+    Duration::from_secs(600)
+}
+
+fn default_interval() -> Duration {
+    Duration::from_secs(5)
+}
+
+/// A client that can request device codes
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct ClientConfiguration {
+    /// Name of the client shown on the verification page. Defaults to the client ID.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// Form encoded request to the device authorization endpoint
+#[derive(FromForm, Clone, Default, Debug)]
+pub struct DeviceAuthorizationRequest {
+    /// ID of a registered client
+    pub client_id: Option<String>,
+    /// The scope requested
+    pub scope: Option<String>,
+    /// The service the access token is intended for
+    pub resource: Option<String>,
+}
+
+impl DeviceAuthorizationRequest {
+    /// Verify the request against the configuration and the settings of the grant, if it is enabled, and return what
+    /// the device code is requested for
+    pub fn verify(
+        &self,
+        configuration: &token::Configuration,
+        device: &Option<Configuration>,
+    ) -> Result<DeviceRequest, oauth::Error> {
+        let device = device.as_ref().ok_or_else(|| {
+            oauth::Error::new(
+                oauth::ErrorCode::UnauthorizedClient,
+                "The device authorization grant is not enabled",
+            )
+        })?;
+        let client_id = match self.client_id {
+            Some(ref client_id) if device.clients.contains_key(client_id) => client_id.to_string(),
+            Some(_) => {
+                Err(oauth::Error::new(
+                    oauth::ErrorCode::InvalidClient,
+                    "The client is not registered",
+                ))?
+            }
+            None => {
+                Err(oauth::Error::new(
+                    oauth::ErrorCode::InvalidRequest,
+                    "The `client_id` parameter is required",
+                ))?
+            }
+        };
+        let service = oauth::resource(&self.resource, configuration)?;
+        token::verify_service(configuration, &service).map_err(|e| oauth::Error::from(::Error::from(e)))?;
+
+        Ok(DeviceRequest {
+            client_id,
+            service,
+            scope: self.scope.clone().unwrap_or_default(),
+        })
+    }
+}
+
+/// Successful response of the device authorization endpoint
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize, Debug)]
+pub struct DeviceAuthorizationResponse {
+    /// The code the device polls the token endpoint with
+    pub device_code: String,
+    /// The code the user enters on the verification page
+    pub user_code: String,
+    /// URL of the verification page
+    pub verification_uri: String,
+    /// URL of the verification page with the user code filled in
+    pub verification_uri_complete: String,
+    /// Seconds until the codes expire
+    pub expires_in: u64,
+    /// Minimum seconds between polling requests
+    pub interval: u64,
+}
+
+impl<'r> Responder<'r> for DeviceAuthorizationResponse {
+    fn respond_to(self, request: &Request) -> Result<Response<'r>, Status> {
+        let body = match serde_json::to_string(&self) {
+            Ok(body) => body,
+            Err(e) => return ::Error::JsonError(e).respond_to(request),
+        };
+        Response::build()
+            .header(ContentType::JSON)
+            .header(Header::new("Cache-Control", "no-store"))
+            .header(Header::new("Pragma", "no-cache"))
+            .sized_body(Cursor::new(body))
+            .ok()
+    }
+}
+
+/// What a device code was requested for
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct DeviceRequest {
+    /// ID of the client the device code was issued to
+    pub client_id: String,
+    /// The service the access token is intended for
+    pub service: String,
+    /// The scope requested
+    pub scope: String,
+}
+
+/// The approval of a device code by a user who logged in on the verification page
+#[derive(Clone, Debug)]
+pub struct Approval {
+    /// The username the user logged in with
+    pub username: String,
+    /// Time the user logged in
+    pub auth_time: DateTime<Utc>,
+    /// Result of the authentication
+    pub result: auth::AuthenticationResult,
+}
+
+#[derive(Debug)]
+enum Decision {
+    Pending,
+    Approved(Approval),
+    Denied,
+}
+
+#[derive(Debug)]
+struct DeviceAuthorization {
+    request: DeviceRequest,
+    user_code: String,
+    expires_at: DateTime<Utc>,
+    interval: Duration,
+    polled_at: Option<DateTime<Utc>>,
+    decision: Decision,
+}
+
+impl DeviceAuthorization {
+    /// Whether this is the authorization of `user_code`, and is waiting for the user to approve or deny it
+    fn is_pending(&self, user_code: &str, now: DateTime<Utc>) -> bool {
+        match self.decision {
+            Decision::Pending => self.user_code == user_code && self.expires_at > now,
+            _ => false,
+        }
+    }
+
+    /// The error for the device polling at `now` while the request is pending. Devices that poll too often have to
+    /// slow down.
+    fn poll_pending(&mut self, now: DateTime<Utc>) -> oauth::Error {
+        let interval = chrono::Duration::seconds(self.interval.as_secs() as i64);
+        let too_often = self.polled_at.map(|polled_at| polled_at + interval > now).unwrap_or(false);
+        self.polled_at = Some(now);
+        if too_often {
+            self.interval += Duration::from_secs(SLOW_DOWN_SECONDS);
+            oauth::Error::new(
+                oauth::ErrorCode::SlowDown,
+                &format!("Poll at most every {} seconds", self.interval.as_secs()),
+            )
+        } else {
+            oauth::Error::new(
+                oauth::ErrorCode::AuthorizationPending,
+                "The user has not approved the request yet",
+            )
+        }
+    }
+}
+
+/// Device codes that have been issued and not yet exchanged, keyed by the device code. This is managed by Rocket.
+pub struct DeviceAuthorizations {
+    authorizations: Mutex<HashMap<String, DeviceAuthorization>>,
+    max_pending: usize,
+}
+
+impl Default for DeviceAuthorizations {
+    fn default() -> Self {
+        DeviceAuthorizations {
+            authorizations: Default::default(),
+            max_pending: MAX_PENDING,
+        }
+    }
+}
+
+impl DeviceAuthorizations {
+    /// Create an empty store of device codes
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Issue a new device code and user code for `request`. New codes are refused while the store is full of
+    /// unexpired codes.
+    pub fn issue(
+        &self,
+        request: DeviceRequest,
+        configuration: &Configuration,
+        verification_uri: &str,
+    ) -> Result<DeviceAuthorizationResponse, oauth::Error> {
+        let mut device_code = vec![0; DEVICE_CODE_LENGTH];
+        jwa::rng().fill(&mut device_code).map_err(|_| ::Error::CryptographicError)?;
+        let device_code = jwks::base64url_encode(&device_code);
+
+        let now = Utc::now();
+        let expiry_duration = chrono::Duration::from_std(configuration.expiry_duration)
+            .map_err(|e| ::Error::from(e.to_string()))?;
+        let mut authorizations = self.lock()?;
+        authorizations.retain(|_, authorization| authorization.expires_at > now);
+        if authorizations.len() >= self.max_pending {
+            let next_expiry = authorizations.values().map(|authorization| authorization.expires_at).min();
+            let retry_after = next_expiry.map(|expiry| (expiry - now).num_seconds() + 1).unwrap_or(1);
+            warn_!("Refusing device code because {} codes are pending", authorizations.len());
+            Err(oauth::Error {
+                retry_after: Some(retry_after as u64),
+                ..oauth::Error::new(
+                    oauth::ErrorCode::SlowDown,
+                    "Too many device codes are pending. Try again later.",
+                )
+            })?;
+        }
+        let mut user_code = generate_user_code()?;
+        while authorizations.values().any(|authorization| authorization.user_code == user_code) {
+            user_code = generate_user_code()?;
+        }
+        let _ = authorizations.insert(
+            device_code.clone(),
+            DeviceAuthorization {
+                request,
+                user_code: user_code.clone(),
+                expires_at: now + expiry_duration,
+                interval: configuration.interval,
+                polled_at: None,
+                decision: Decision::Pending,
+            },
+        );
+
+        let user_code = format_user_code(&user_code);
+        Ok(DeviceAuthorizationResponse {
+            device_code,
+            verification_uri: verification_uri.to_string(),
+            verification_uri_complete: format!("{}?user_code={}", verification_uri, user_code),
+            user_code,
+            expires_in: configuration.expiry_duration.as_secs(),
+            interval: configuration.interval.as_secs(),
+        })
+    }
+
+    /// The request of `user_code`, if it is waiting for the user to approve or deny it
+    pub fn pending(&self, user_code: &str) -> Result<Option<DeviceRequest>, ::Error> {
+        let user_code = normalize_user_code(user_code);
+        let now = Utc::now();
+        let authorizations = self.lock()?;
+        let authorization = authorizations.values().find(
+            |authorization| authorization.is_pending(&user_code, now),
+        );
+        Ok(authorization.map(|authorization| authorization.request.clone()))
+    }
+
+    /// Approve the request of `user_code`, or deny it if there is no `approval`. Returns whether the request was
+    /// waiting for a decision.
+    pub fn decide(&self, user_code: &str, approval: Option<Approval>) -> Result<bool, ::Error> {
+        let user_code = normalize_user_code(user_code);
+        let now = Utc::now();
+        let mut authorizations = self.lock()?;
+        let authorization = authorizations.values_mut().find(
+            |authorization| authorization.is_pending(&user_code, now),
+        );
+        match authorization {
+            Some(authorization) => {
+                authorization.decision = match approval {
+                    Some(approval) => Decision::Approved(approval),
+                    None => Decision::Denied,
+                };
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Poll for the decision on `device_code`. The device code is removed once it is approved, denied or expired.
+    pub fn poll(&self, device_code: &str, client_id: &str) -> Result<(DeviceRequest, Approval), oauth::Error> {
+        let invalid_grant = || oauth::Error::new(oauth::ErrorCode::InvalidGrant, "The device code is invalid");
+        let now = Utc::now();
+        let mut authorizations = self.lock()?;
+        let expired = match authorizations.get_mut(device_code) {
+            Some(authorization) => {
+                if authorization.request.client_id != client_id {
+                    Err(invalid_grant())?
+                }
+                let expired = authorization.expires_at <= now;
+                let pending = match authorization.decision {
+                    Decision::Pending => true,
+                    _ => false,
+                };
+                if pending && !expired {
+                    Err(authorization.poll_pending(now))?
+                }
+                expired
+            }
+            None => Err(invalid_grant())?,
+        };
+
+        let authorization = authorizations.remove(device_code).expect("Device authorization was found");
+        match authorization.decision {
+            _ if expired => Err(oauth::Error::new(
+                oauth::ErrorCode::ExpiredToken,
+                "The device code has expired",
+            )),
+            Decision::Approved(approval) => Ok((authorization.request, approval)),
+            _ => Err(oauth::Error::new(
+                oauth::ErrorCode::AccessDenied,
+                "The user denied the request",
+            )),
+        }
+    }
+
+    fn lock(&self) -> Result<::std::sync::MutexGuard<HashMap<String, DeviceAuthorization>>, ::Error> {
+        Ok(self.authorizations.lock().map_err(
+            |_| "Device authorization store lock is poisoned".to_string(),
+        )?)
+    }
+}
+
+/// Make a random user code. Bytes above the largest multiple of the number of characters are skipped so that each
+/// character is equally likely.
+fn generate_user_code() -> Result<String, ::Error> {
+    let limit = 256 - 256 % USER_CODE_CHARACTERS.len();
+    let mut user_code = String::with_capacity(USER_CODE_LENGTH);
+    let mut bytes = [0; USER_CODE_LENGTH * 2];
+    while user_code.len() < USER_CODE_LENGTH {
+        jwa::rng().fill(&mut bytes).map_err(|_| ::Error::CryptographicError)?;
+        for byte in bytes.iter().map(|&byte| byte as usize).filter(|&byte| byte < limit) {
+            if user_code.len() == USER_CODE_LENGTH {
+                break;
+            }
+            user_code.push(USER_CODE_CHARACTERS[byte % USER_CODE_CHARACTERS.len()] as char);
+        }
+    }
+    Ok(user_code)
+}
+
+/// Split the user code in two halves for display
+fn format_user_code(user_code: &str) -> String {
+    let (first, second) = user_code.split_at(user_code.len() / 2);
+    format!("{}-{}", first, second)
+}
+
+/// Users may enter codes in lowercase and without or with other separators
+fn normalize_user_code(user_code: &str) -> String {
+    user_code
+        .to_uppercase()
+        .bytes()
+        .filter(|byte| USER_CODE_CHARACTERS.contains(byte))
+        .map(|byte| byte as char)
+        .collect()
+}
+
+/// Form encoded parameters of the verification page, sent in the query to display the page, and in the body with the
+/// credentials of the user to approve or deny the request
+#[derive(FromForm, Clone, Default, Debug)]
+pub struct VerificationRequest {
+    /// The user code displayed by the device
+    pub user_code: Option<String>,
+    /// Username entered in the form
+    pub username: Option<String>,
+    /// Password entered in the form
+    pub password: Option<String>,
+    /// `approve` or `deny`
+    pub action: Option<String>,
+}
+
+impl VerificationRequest {
+    /// Whether the user chose to deny the request
+    pub fn is_denied(&self) -> bool {
+        self.action.as_ref().map(|action| action.as_str()) == Some("deny")
+    }
+
+    /// The verification page, with a description of the pending `request` of the user code if it was found, and an
+    /// `error` message if a previous attempt failed
+    pub fn page(
+        &self,
+        configuration: &Configuration,
+        request: Option<&DeviceRequest>,
+        error: Option<&str>,
+    ) -> AuthorizationResponse {
+        let description = match request {
+            Some(request) => {
+                let name = configuration
+                    .clients
+                    .get(&request.client_id)
+                    .and_then(|client| client.name.as_ref())
+                    .unwrap_or(&request.client_id);
+                let scope = if request.scope.is_empty() {
+                    String::new()
+                } else {
+                    format!(" with the scope <code>{}</code>", escape_html(&request.scope))
+                };
+                format!(
+                    "<p>{} is requesting access to {}{}.</p>\n",
+                    escape_html(name),
+                    escape_html(&request.service),
+                    scope
+                )
+            }
+            None => String::new(),
+        };
+        let error = match error {
+            Some(error) => format!("<p role=\"alert\">{}</p>\n", escape_html(error)),
+            None => String::new(),
+        };
+        let user_code = self.user_code.as_ref().map(|user_code| escape_html(user_code)).unwrap_or_default();
+        let username = self.username.as_ref().map(|username| escape_html(username)).unwrap_or_default();
+        let body = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Connect a device</title>\n</head>\n\
+             <body>\n<h1>Connect a device</h1>\n{}{}<form method=\"post\" action=\"device\">\n\
+             <label>Code <input name=\"user_code\" value=\"{}\" autocomplete=\"off\" required></label>\n\
+             <label>Username <input name=\"username\" value=\"{}\" autocomplete=\"username\"></label>\n\
+             <label>Password <input type=\"password\" name=\"password\" autocomplete=\"current-password\"></label>\n\
+             <button type=\"submit\" name=\"action\" value=\"approve\">Approve</button>\n\
+             <button type=\"submit\" name=\"action\" value=\"deny\">Deny</button>\n</form>\n</body>\n</html>\n",
+            description,
+            error,
+            user_code,
+            username
+        );
+        AuthorizationResponse::Page(Status::Ok, body)
+    }
+
+    /// The page shown once the request was approved or denied
+    pub fn decided_page(&self, approved: bool) -> AuthorizationResponse {
+        let message = if approved {
+            "The device is connected. You can close this page and return to the device."
+        } else {
+            "The request of the device was denied. You can close this page."
+        };
+        let body = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Connect a device</title>\n</head>\n\
+             <body>\n<p>{}</p>\n</body>\n</html>\n",
+            message
+        );
+        AuthorizationResponse::Page(Status::Ok, body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn configuration() -> Configuration {
+        let mut clients = HashMap::new();
+        let _ = clients.insert("registry-cli".to_string(), Default::default());
+        Configuration {
+            clients,
+            expiry_duration: Duration::from_secs(600),
+            interval: Duration::from_secs(5),
+        }
+    }
+
+    fn request() -> DeviceRequest {
+        DeviceRequest {
+            client_id: "registry-cli".to_string(),
+            service: "https://www.example.com".to_string(),
+            scope: "all".to_string(),
+        }
+    }
+
+    fn approval() -> Approval {
+        Approval {
+            username: "mei".to_string(),
+            auth_time: Utc::now(),
+            result: auth::AuthenticationResult {
+                subject: "mei".to_string(),
+                private_claims: Default::default(),
+                refresh_payload: None,
+            },
+        }
+    }
+
+    fn poll_error(authorizations: &DeviceAuthorizations, device_code: &str, client_id: &str) -> oauth::ErrorCode {
+        match authorizations.poll(device_code, client_id) {
+            Ok(_) => panic!("Device code was exchanged"),
+            Err(e) => e.error,
+        }
+    }
+
+    #[test]
+    fn user_codes_are_formatted_and_normalized() {
+        let user_code = not_err!(generate_user_code());
+        assert_eq!(user_code.len(), USER_CODE_LENGTH);
+        assert!(user_code.bytes().all(|byte| USER_CODE_CHARACTERS.contains(&byte)));
+
+        assert_eq!(format_user_code("WDJBMJHT"), "WDJB-MJHT");
+        assert_eq!(normalize_user_code("wdjb mjht"), "WDJBMJHT");
+        assert_eq!(normalize_user_code("WDJB-MJHT"), "WDJBMJHT");
+    }
+
+    #[test]
+    fn device_codes_are_exchanged_once_approved() {
+        let authorizations = DeviceAuthorizations::new();
+        let response = not_err!(authorizations.issue(request(), &configuration(), "https://www.acme.com/device"));
+        assert_eq!(response.expires_in, 600);
+        assert_eq!(response.interval, 5);
+        assert_eq!(
+            response.verification_uri_complete,
+            format!("https://www.acme.com/device?user_code={}", response.user_code)
+        );
+
+        let device_code = &response.device_code;
+        assert_eq!(
+            poll_error(&authorizations, device_code, "registry-cli"),
+            oauth::ErrorCode::AuthorizationPending
+        );
+        // Polling again right away is too fast
+        assert_eq!(
+            poll_error(&authorizations, device_code, "registry-cli"),
+            oauth::ErrorCode::SlowDown
+        );
+        assert_eq!(
+            poll_error(&authorizations, device_code, "other-cli"),
+            oauth::ErrorCode::InvalidGrant
+        );
+
+        let user_code = response.user_code.to_lowercase();
+        assert_eq!(not_err!(authorizations.pending(&user_code)), Some(request()));
+        assert!(not_err!(authorizations.decide(&user_code, Some(approval()))));
+        assert_eq!(not_err!(authorizations.pending(&user_code)), None);
+        assert!(!not_err!(authorizations.decide(&user_code, None)));
+
+        let (request, approval) = not_err!(authorizations.poll(device_code, "registry-cli"));
+        assert_eq!(request.service, "https://www.example.com");
+        assert_eq!(approval.username, "mei");
+        assert_eq!(
+            poll_error(&authorizations, device_code, "registry-cli"),
+            oauth::ErrorCode::InvalidGrant
+        );
+    }
+
+    #[test]
+    fn denied_and_expired_device_codes_are_refused() {
+        let authorizations = DeviceAuthorizations::new();
+        let response = not_err!(authorizations.issue(request(), &configuration(), "https://www.acme.com/device"));
+        assert!(not_err!(authorizations.decide(&response.user_code, None)));
+        assert_eq!(
+            poll_error(&authorizations, &response.device_code, "registry-cli"),
+            oauth::ErrorCode::AccessDenied
+        );
+
+        let mut configuration = configuration();
+        configuration.expiry_duration = Duration::from_secs(0);
+        let response = not_err!(authorizations.issue(request(), &configuration, "https://www.acme.com/device"));
+        assert_eq!(not_err!(authorizations.pending(&response.user_code)), None);
+        assert_eq!(
+            poll_error(&authorizations, &response.device_code, "registry-cli"),
+            oauth::ErrorCode::ExpiredToken
+        );
+    }
+
+    #[test]
+    fn pending_device_codes_are_limited() {
+        let authorizations = DeviceAuthorizations {
+            max_pending: 2,
+            ..Default::default()
+        };
+        for _ in 0..2 {
+            let _ = not_err!(authorizations.issue(request(), &configuration(), "https://www.acme.com/device"));
+        }
+        let error = authorizations.issue(request(), &configuration(), "https://www.acme.com/device").unwrap_err();
+        assert_eq!(error.error, oauth::ErrorCode::SlowDown);
+        assert!(not_none!(error.retry_after) > 0);
+
+        // Expired codes make room for new ones
+        let mut configuration = configuration();
+        configuration.expiry_duration = Duration::from_secs(0);
+        let authorizations = DeviceAuthorizations {
+            max_pending: 1,
+            ..Default::default()
+        };
+        for _ in 0..2 {
+            let _ = not_err!(authorizations.issue(request(), &configuration, "https://www.acme.com/device"));
+        }
+    }
+
+    #[test]
+    fn verification_page_is_escaped() {
+        let verification = VerificationRequest {
+            user_code: Some("\"><script>".to_string()),
+            ..Default::default()
+        };
+        let mut request = request();
+        request.scope = "<all>".to_string();
+        match verification.page(&configuration(), Some(&request), None) {
+            AuthorizationResponse::Page(Status::Ok, body) => {
+                assert!(body.contains("name=\"user_code\" value=\"&quot;&gt;&lt;script&gt;\""));
+                assert!(body.contains("<code>&lt;all&gt;</code>"));
+                assert!(body.contains("registry-cli is requesting access to https://www.example.com"));
+            }
+            response => panic!("Unexpected response {:?}", response),
+        }
+    }
+}
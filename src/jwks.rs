@@ -0,0 +1,305 @@
+//! JSON Web Key Sets described in [RFC 7517](https://tools.ietf.org/html/rfc7517)
+//!
+//! Only the parameters needed to verify signatures are supported: symmetric keys (`oct`) and RSA public keys
+//! (`RSA`). Keys are converted into [`jws::Secret`](../../biscuit/jws/enum.Secret.html) for use with
+//! [`Token::decode`](../token/struct.Token.html#method.decode).
+//!
+//! rowdy publishes the RSA public keys its tokens are signed with at `GET /jwks`.
+//!
+//! # Example
+//!
+//! ```json
+//! {
+//!     "keys": [
+//!         {
+//!             "kty": "RSA",
+//!             "kid": "2017-07",
+//!             "alg": "RS256",
+//!             "use": "sig",
+//!             "n": "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_...",
+//!             "e": "AQAB"
+//!         }
+//!     ]
+//! }
+//! ```
+use std::fs::File;
+use std::io::Read;
+use std::iter;
+use std::time::Duration;
+
+use data_encoding::base64url;
+use hyper;
+use jwt::jws;
+use num::BigUint;
+use serde_json;
+use yasna;
+
+use token;
+
+/// A JSON Web Key. Parameters of private keys are ignored.
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize, Debug)]
+pub struct JsonWebKey {
+    /// Key type, either `oct` or `RSA`
+    pub kty: String,
+    /// Key ID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+    /// Algorithm the key is intended for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alg: Option<String>,
+    /// Whether the key is used for signatures (`sig`) or encryption (`enc`)
+    #[serde(rename = "use", default, skip_serializing_if = "Option::is_none")]
+    pub key_use: Option<String>,
+    /// Base64url encoded modulus of an RSA key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    /// Base64url encoded public exponent of an RSA key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+    /// Base64url encoded value of a symmetric key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub k: Option<String>,
+}
+
+impl JsonWebKey {
+    /// Make a key for a DER encoded RSA public key (`RSAPublicKey` of PKCS#1)
+    pub fn from_rsa_public_key(der: &[u8], kid: Option<String>, alg: Option<String>) -> Result<Self, ::Error> {
+        let (n, e) = rsa_public_key_components(der).ok_or_else(
+            || "Invalid DER encoded RSA public key".to_string(),
+        )?;
+        Ok(JsonWebKey {
+            kty: "RSA".to_string(),
+            kid,
+            alg,
+            key_use: Some("sig".to_string()),
+            n: Some(base64url_encode(&n)),
+            e: Some(base64url_encode(&e)),
+            k: None,
+        })
+    }
+
+    /// Whether the key can be used to verify signatures
+    pub fn is_signing_key(&self) -> bool {
+        match self.key_use {
+            Some(ref key_use) => key_use == "sig",
+            None => true,
+        }
+    }
+
+    /// Convert the key into a secret for verifying signatures
+    pub fn secret(&self) -> Result<jws::Secret, ::Error> {
+        let decode = |parameter: &Option<String>, name: &str| -> Result<Vec<u8>, ::Error> {
+            let parameter = parameter.as_ref().ok_or_else(|| {
+                format!("The `{}` parameter of the key is missing", name)
+            })?;
+            Ok(base64url_decode(parameter).ok_or_else(|| {
+                format!("The `{}` parameter of the key is not valid base64url", name)
+            })?)
+        };
+        match &*self.kty {
+            "oct" => Ok(jws::Secret::Bytes(decode(&self.k, "k")?)),
+            "RSA" => {
+                let n = decode(&self.n, "n")?;
+                let e = decode(&self.e, "e")?;
+                Ok(jws::Secret::PublicKey(rsa_public_key(&n, &e)))
+            }
+            kty => Err(::Error::KeyLoadingError(format!("Unsupported key type `{}`", kty))),
+        }
+    }
+}
+
+/// A JSON Web Key Set
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize, Default, Debug)]
+pub struct JsonWebKeySet {
+    /// The keys of the set
+    pub keys: Vec<JsonWebKey>,
+}
+
+impl JsonWebKeySet {
+    /// Read a key set from a JSON file
+    pub fn from_file(path: &str) -> Result<Self, ::Error> {
+        let mut file = File::open(path)?;
+        let mut json = String::new();
+        let _ = file.read_to_string(&mut json)?;
+        Self::from_str(&json)
+    }
+
+    /// Fetch a key set from `url` over plain `http`, because there is no TLS connector for the HTTP client. The key
+    /// set is not authenticated, so only use this on a trusted network. Use a local copy of the key set otherwise.
+    pub fn from_url(url: &str, timeout: Duration) -> Result<Self, ::Error> {
+        if !url.starts_with("http://") {
+            Err(::Error::KeyLoadingError(
+                format!("Key sets can only be fetched from `http` URLs, not {}", url),
+            ))?;
+        }
+        let mut client = hyper::Client::new();
+        client.set_read_timeout(Some(timeout));
+        client.set_write_timeout(Some(timeout));
+        let mut response = client.get(url).send().map_err(|e| {
+            ::Error::KeyLoadingError(format!("Fetching key set from {} failed: {}", url, e))
+        })?;
+        if !response.status.is_success() {
+            Err(::Error::KeyLoadingError(
+                format!("Fetching key set from {} failed with {}", url, response.status),
+            ))?;
+        }
+        let mut json = String::new();
+        let _ = response.read_to_string(&mut json)?;
+        Self::from_str(&json)
+    }
+
+    /// The RSA public keys that tokens issued with the configuration are signed with. The key of a service with its
+    /// own `secret` has the service as its key ID. Symmetric keys are never published.
+    pub fn from_keys(configuration: &token::Configuration, keys: &token::Keys) -> Result<Self, ::Error> {
+        let global = iter::once((None, &keys.signature_verification, configuration.signature_algorithm));
+        let services = keys.services.iter().map(|(service, service_keys)| {
+            (
+                Some(service.to_string()),
+                &service_keys.signature_verification,
+                configuration.signature_algorithm_for(service),
+            )
+        });
+        let mut set = JsonWebKeySet::default();
+        for (kid, secret, algorithm) in global.chain(services) {
+            if let jws::Secret::PublicKey(ref der) = *secret {
+                let algorithm = match algorithm {
+                    Some(algorithm) => serde_json::to_value(algorithm)?.as_str().map(|alg| alg.to_string()),
+                    None => None,
+                };
+                set.keys.push(JsonWebKey::from_rsa_public_key(der, kid, algorithm)?);
+            }
+        }
+        Ok(set)
+    }
+
+    fn from_str(json: &str) -> Result<Self, ::Error> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Convert the signing keys of the set into secrets for verifying signatures. Keys that cannot be converted are
+    /// logged and left out.
+    pub fn secrets(&self) -> Vec<jws::Secret> {
+        self.keys
+            .iter()
+            .filter(|key| key.is_signing_key())
+            .filter_map(|key| match key.secret() {
+                Ok(secret) => Some(secret),
+                Err(e) => {
+                    warn_!("Ignoring key {:?} of key set: {}", key.kid, e);
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+/// Encode bytes as base64url without padding
+pub(crate) fn base64url_encode(bytes: &[u8]) -> String {
+    base64url::encode_nopad(bytes)
+}
+
+/// Decode base64url, with or without padding
+pub(crate) fn base64url_decode(encoded: &str) -> Option<Vec<u8>> {
+    base64url::decode_nopad(encoded.trim_right_matches('=').as_bytes()).ok()
+}
+
+/// DER encode the `RSAPublicKey` of PKCS#1 with modulus `n` and public exponent `e`, both unsigned big-endian
+fn rsa_public_key(n: &[u8], e: &[u8]) -> Vec<u8> {
+    let n = BigUint::from_bytes_be(n);
+    let e = BigUint::from_bytes_be(e);
+    yasna::construct_der(|writer| {
+        writer.write_sequence(|writer| {
+            writer.next().write_biguint(&n);
+            writer.next().write_biguint(&e);
+        })
+    })
+}
+
+/// The unsigned big-endian modulus and public exponent of a DER encoded `RSAPublicKey`
+fn rsa_public_key_components(der: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    yasna::parse_der(der, |reader| {
+        reader.read_sequence(|reader| {
+            let n = reader.next().read_biguint()?;
+            let e = reader.next().read_biguint()?;
+            Ok((n.to_bytes_be(), e.to_bytes_be()))
+        })
+    }).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64url_round_trip() {
+        for bytes in &[&b""[..], b"f", b"fo", b"foo", b"foob", b"fooba", b"foobar", &[0xfb, 0xff, 0xfe]] {
+            let encoded = base64url_encode(bytes);
+            assert!(!encoded.contains('='));
+            assert_eq!(not_none!(base64url_decode(&encoded)), bytes.to_vec());
+        }
+        assert_eq!(base64url_encode(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64url_encode(&[0xfb, 0xff, 0xfe]), "-__-");
+        assert_eq!(not_none!(base64url_decode("Zm9vYg==")), b"foob".to_vec());
+        assert!(base64url_decode("Zm9vY").is_none());
+        assert!(base64url_decode("Zm9v+A").is_none());
+    }
+
+    #[test]
+    fn rsa_public_keys_round_trip() {
+        let mut der = Vec::new();
+        let _ = not_err!(not_err!(File::open("test/fixtures/rsa_public_key.der")).read_to_end(&mut der));
+
+        let key = not_err!(JsonWebKey::from_rsa_public_key(&der, Some("1".to_string()), Some("RS256".to_string())));
+        assert_eq!(key.e, Some("AQAB".to_string()));
+        match not_err!(key.secret()) {
+            jws::Secret::PublicKey(public_key) => assert_eq!(public_key, der),
+            _ => panic!("Unexpected secret"),
+        }
+    }
+
+    #[test]
+    fn only_rsa_public_keys_are_published() {
+        let json = r#"{
+            "issuer": "https://www.acme.com",
+            "allowed_origins": { "Some": ["https://www.example.com"] },
+            "audience": ["https://www.example.com", "https://www.foobar.com"],
+            "signature_algorithm": "RS256",
+            "secret": {
+                "rsa_private": "test/fixtures/rsa_private_key.der",
+                "rsa_public": "test/fixtures/rsa_public_key.der"
+            },
+            "services": {
+                "https://www.foobar.com": { "signature_algorithm": "HS256", "secret": "legacy" }
+            }
+        }"#;
+        let configuration: token::Configuration = not_err!(serde_json::from_str(json));
+        let keys = not_err!(configuration.keys());
+
+        let set = not_err!(JsonWebKeySet::from_keys(&configuration, &keys));
+        assert_eq!(set.keys.len(), 1);
+        assert_eq!(set.keys[0].kid, None);
+        assert_eq!(set.keys[0].alg, Some("RS256".to_string()));
+        assert_eq!(set.keys[0].e, Some("AQAB".to_string()));
+    }
+
+    #[test]
+    fn key_sets_are_deserialized() {
+        let json = r#"{
+            "keys": [
+                { "kty": "oct", "kid": "hmac", "k": "c2VjcmV0" },
+                { "kty": "oct", "use": "enc", "k": "AAAA" },
+                { "kty": "EC", "crv": "P-256" }
+            ]
+        }"#;
+        let key_set = not_err!(JsonWebKeySet::from_str(json));
+        assert_eq!(key_set.keys.len(), 3);
+        assert_eq!(key_set.keys[0].kid, Some("hmac".to_string()));
+
+        let secrets = key_set.secrets();
+        assert_eq!(secrets.len(), 1);
+        match secrets[0] {
+            jws::Secret::Bytes(ref bytes) => assert_eq!(bytes, b"secret"),
+            _ => panic!("Unexpected secret"),
+        }
+    }
+}
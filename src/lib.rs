@@ -11,10 +11,16 @@
 //!
 //! # Features
 //!
+//! - `server`: The Rocket based authentication server. Without this feature, only the creation and verification of
+//! tokens in the [`token`](token/index.html), [`jwks`](jwks/index.html), [`oidc`](oidc/index.html) and
+//! [`verifier`](verifier/index.html) modules are available, and Rocket and its code generation plugin are not required.
 //! - `simple_authenticator`: A simple CSV based authenticator
 //! - `ldap_authenticator`: An LDAP based authenticator
+//! - `api_key_authenticator`: A file based authenticator of API keys for machine clients
+//! - `totp_authenticator`: Time-based one time passwords as a second factor for another authenticator
 //!
-//! By default, the `simple_authenticator` feature is turned on.
+//! By default, the `server` and `simple_authenticator` features are turned on. The authenticators require the
+//! `server` feature.
 //!
 //! # `rowdy` Authentication Flow
 //!
@@ -160,8 +166,8 @@
 //!
 //! Not in use at the moment. Just use `all`.
 //!
-#![feature(plugin, custom_derive)]
-#![plugin(rocket_codegen)]
+#![cfg_attr(feature = "server", feature(plugin, custom_derive))]
+#![cfg_attr(feature = "server", plugin(rocket_codegen))]
 
 // See https://github.com/rust-unofficial/patterns/blob/master/anti_patterns/deny-warnings.md
 #![allow(
@@ -219,23 +225,31 @@
 
 extern crate biscuit as jwt;
 extern crate chrono;
+extern crate data_encoding;
 extern crate hyper;
+#[cfg(feature = "server")]
+#[macro_use]
+extern crate lazy_static;
 #[macro_use]
 extern crate log;
+extern crate num;
+#[cfg(feature = "server")]
 #[macro_use]
 extern crate rocket; // we are using the "log_!" macros which are redefined from `log`'s
+#[cfg(feature = "server")]
 extern crate rocket_cors as cors;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
 extern crate uuid;
+extern crate yasna;
 
 #[cfg(feature = "simple_authenticator")]
 extern crate argon2rs;
 #[cfg(feature = "simple_authenticator")]
 extern crate csv;
-#[cfg(feature = "simple_authenticator")]
+#[cfg(feature = "server")]
 extern crate ring;
 #[cfg(feature = "ldap_authenticator")]
 extern crate ldap3;
@@ -250,21 +264,48 @@ mod macros;
 #[cfg(test)]
 #[macro_use]
 mod test;
+#[cfg(feature = "server")]
+pub mod audit;
+#[cfg(feature = "server")]
 pub mod auth;
+#[cfg(feature = "server")]
+pub mod authorization_code;
+#[cfg(feature = "server")]
+pub mod device;
+pub mod jwks;
+#[cfg(feature = "server")]
+pub mod metrics;
+#[cfg(feature = "server")]
+pub mod oauth;
+pub mod oidc;
+#[cfg(feature = "server")]
 mod routes;
 pub mod serde_custom;
+#[cfg(feature = "server")]
+pub mod throttle;
 pub mod token;
+#[cfg(feature = "server")]
+pub mod token_exchange;
+pub mod verifier;
 
+#[cfg(feature = "server")]
 pub use self::routes::routes;
 
 use std::error;
 use std::fmt;
 use std::io;
+#[cfg(feature = "server")]
+use std::io::Cursor;
 use std::ops::Deref;
 use std::str::FromStr;
+#[cfg(feature = "server")]
+use std::sync::Arc;
 
+#[cfg(feature = "server")]
 use rocket::Request;
-use rocket::http::Status;
+#[cfg(feature = "server")]
+use rocket::http::{ContentType, Status};
+#[cfg(feature = "server")]
 use rocket::response::{Response, Responder};
 use serde::{Serialize, Serializer, Deserialize, Deserializer};
 use serde::de;
@@ -272,16 +313,30 @@ use serde::de;
 pub use serde_json::Value as JsonValue;
 pub use serde_json::Map as JsonMap;
 
-/// Top level error enum
+/// Top level error enum. Use [`Error::kind`] to match on the kind of error regardless of where it was raised.
 #[derive(Debug)]
 pub enum Error {
     /// A generic/unknown error
     GenericError(String),
     /// A bad request resulting from bad request parameters/headers
     BadRequest(String),
-    /// Authentication error
+    /// The configuration is invalid, such as an authenticator configured with conflicting settings
+    ConfigurationError(String),
+    /// Keys could not be read or fetched, such as a JSON Web Key Set that is unavailable
+    KeyLoadingError(String),
+    /// An authentication backend, such as the LDAP server, is unavailable
+    BackendUnavailable(String),
+    /// A cryptographic operation, such as generating random bytes, has failed
+    CryptographicError,
+    /// A private claim conflicts with a claim reserved by an authenticator, or private claims are not a map
+    ClaimConflict(String),
+    /// Errors during JSON serialization and deserialization
+    JsonError(serde_json::Error),
+    /// Authentication error. Requires the `server` feature.
+    #[cfg(feature = "server")]
     Auth(auth::Error),
-    /// CORS error
+    /// CORS error. Requires the `server` feature.
+    #[cfg(feature = "server")]
     CORS(cors::Error),
     /// Token Error
     Token(token::Error),
@@ -292,20 +347,31 @@ pub enum Error {
     UnsupportedOperation,
 }
 
+#[cfg(feature = "server")]
 impl_from_error!(auth::Error, Error::Auth);
+#[cfg(feature = "server")]
 impl_from_error!(cors::Error, Error::CORS);
 impl_from_error!(token::Error, Error::Token);
 impl_from_error!(String, Error::GenericError);
 impl_from_error!(io::Error, Error::IOError);
+impl_from_error!(serde_json::Error, Error::JsonError);
 
 impl error::Error for Error {
     fn description(&self) -> &str {
         match *self {
             Error::UnsupportedOperation => "This operation is not supported",
+            #[cfg(feature = "server")]
             Error::Auth(ref e) => e.description(),
+            #[cfg(feature = "server")]
             Error::CORS(ref e) => e.description(),
             Error::Token(ref e) => e.description(),
             Error::IOError(ref e) => e.description(),
+            Error::JsonError(ref e) => e.description(),
+            Error::ConfigurationError(_) => "The configuration is invalid",
+            Error::KeyLoadingError(_) => "Keys could not be loaded",
+            Error::BackendUnavailable(_) => "An authentication backend is unavailable",
+            Error::CryptographicError => "A cryptographic operation has failed",
+            Error::ClaimConflict(_) => "The private claims conflict",
             Error::GenericError(ref e) |
             Error::BadRequest(ref e) => e,
         }
@@ -313,13 +379,14 @@ impl error::Error for Error {
 
     fn cause(&self) -> Option<&error::Error> {
         match *self {
+            #[cfg(feature = "server")]
             Error::Auth(ref e) => Some(e),
+            #[cfg(feature = "server")]
             Error::CORS(ref e) => Some(e),
             Error::Token(ref e) => Some(e),
             Error::IOError(ref e) => Some(e),
-            Error::UnsupportedOperation |
-            Error::GenericError(_) |
-            Error::BadRequest(_) => Some(self),
+            Error::JsonError(ref e) => Some(e),
+            _ => None,
         }
     }
 }
@@ -328,19 +395,197 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::UnsupportedOperation => write!(f, "{}", error::Error::description(self)),
+            #[cfg(feature = "server")]
             Error::Auth(ref e) => fmt::Display::fmt(e, f),
+            #[cfg(feature = "server")]
             Error::CORS(ref e) => fmt::Display::fmt(e, f),
             Error::Token(ref e) => fmt::Display::fmt(e, f),
             Error::IOError(ref e) => fmt::Display::fmt(e, f),
+            Error::JsonError(ref e) => fmt::Display::fmt(e, f),
             Error::GenericError(ref e) => fmt::Display::fmt(e, f),
             Error::BadRequest(ref e) => fmt::Display::fmt(e, f),
+            Error::ConfigurationError(ref e) |
+            Error::KeyLoadingError(ref e) |
+            Error::BackendUnavailable(ref e) |
+            Error::ClaimConflict(ref e) => fmt::Display::fmt(e, f),
+            Error::CryptographicError => write!(f, "{}", error::Error::description(self)),
+        }
+    }
+}
+
+/// The kind of an [`Error`]
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
+pub enum ErrorKind {
+    /// The request is malformed
+    BadRequest,
+    /// The operation is not supported
+    Unsupported,
+    /// Authentication is required, or has failed
+    Unauthorized,
+    /// The subject is not allowed to request tokens for the service
+    Denied,
+    /// The `Authorization` header is malformed
+    InvalidAuthorization,
+    /// The token is invalid, such as an expired token or a token with an invalid signature
+    InvalidToken,
+    /// The service requested is not in the list of intended audiences
+    InvalidService,
+    /// Refresh tokens are not enabled
+    RefreshTokenDisabled,
+    /// An offline token was requested when authenticating with a refresh token
+    OfflineTokenNotAllowed,
+    /// Too many authentication attempts have failed
+    TooManyRequests,
+    /// The request was rejected by the CORS policy
+    CorsRejected,
+    /// An authentication backend, such as the LDAP server, is unavailable
+    BackendUnavailable,
+    /// The configuration is invalid
+    Configuration,
+    /// Keys could not be loaded
+    KeyLoading,
+    /// A cryptographic operation has failed
+    Cryptographic,
+    /// A private claim conflicts with a claim reserved by an authenticator
+    ClaimConflict,
+    /// Any other unexpected error
+    Internal,
+}
+
+impl ErrorKind {
+    /// Whether errors of this kind are unexpected, and their details are withheld from clients
+    pub fn is_internal(&self) -> bool {
+        match *self {
+            ErrorKind::Configuration |
+            ErrorKind::KeyLoading |
+            ErrorKind::Cryptographic |
+            ErrorKind::ClaimConflict |
+            ErrorKind::Internal => true,
+            _ => false,
+        }
+    }
+
+    /// A stable code for the kind that is sent in the body of failed responses. All internal kinds share the code
+    /// `INTERNAL_ERROR`.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            _ if self.is_internal() => "INTERNAL_ERROR",
+            ErrorKind::BadRequest => "BAD_REQUEST",
+            ErrorKind::Unsupported => "UNSUPPORTED",
+            ErrorKind::Unauthorized => "UNAUTHORIZED",
+            ErrorKind::Denied => "DENIED",
+            ErrorKind::InvalidAuthorization => "INVALID_AUTHORIZATION",
+            ErrorKind::InvalidToken => "INVALID_TOKEN",
+            ErrorKind::InvalidService => "INVALID_SERVICE",
+            ErrorKind::RefreshTokenDisabled => "REFRESH_TOKEN_DISABLED",
+            ErrorKind::OfflineTokenNotAllowed => "OFFLINE_TOKEN_NOT_ALLOWED",
+            ErrorKind::TooManyRequests => "TOOMANYREQUESTS",
+            ErrorKind::CorsRejected => "CORS_REJECTED",
+            ErrorKind::BackendUnavailable => "UNAVAILABLE",
+            _ => "INTERNAL_ERROR",
+        }
+    }
+}
+
+impl Error {
+    /// The kind of the error
+    pub fn kind(&self) -> ErrorKind {
+        match *self {
+            #[cfg(feature = "server")]
+            Error::Auth(ref e) => e.kind(),
+            #[cfg(feature = "server")]
+            Error::CORS(_) => ErrorKind::CorsRejected,
+            Error::Token(ref e) => e.kind(),
+            Error::BadRequest(_) => ErrorKind::BadRequest,
+            Error::UnsupportedOperation => ErrorKind::Unsupported,
+            Error::ConfigurationError(_) => ErrorKind::Configuration,
+            Error::KeyLoadingError(_) => ErrorKind::KeyLoading,
+            Error::BackendUnavailable(_) => ErrorKind::BackendUnavailable,
+            Error::CryptographicError => ErrorKind::Cryptographic,
+            Error::ClaimConflict(_) => ErrorKind::ClaimConflict,
+            Error::GenericError(_) |
+            Error::JsonError(_) |
+            Error::IOError(_) => ErrorKind::Internal,
+        }
+    }
+
+    /// A stable code for the error that is sent in the body of failed responses
+    pub fn code(&self) -> &'static str {
+        self.kind().code()
+    }
+
+    /// A message describing the error that is safe to send to clients. The details of unexpected errors and
+    /// unavailable backends, such as the messages of LDAP servers, are only logged.
+    pub fn message(&self) -> String {
+        match self.kind() {
+            kind if kind.is_internal() => "An unexpected error occurred".to_string(),
+            ErrorKind::BackendUnavailable => "An authentication backend is unavailable".to_string(),
+            ErrorKind::InvalidAuthorization => "The request header `Authorization` is malformed".to_string(),
+            ErrorKind::InvalidToken => {
+                match *self {
+                    Error::Token(token::Error::JWTError(_)) => "The token is invalid".to_string(),
+                    _ => self.to_string(),
+                }
+            }
+            _ => self.to_string(),
+        }
+    }
+
+    /// Additional information about the error for clients, if any
+    #[cfg(feature = "server")]
+    fn detail(&self) -> Option<JsonValue> {
+        match *self {
+            Error::Auth(auth::Error::TooManyAttempts { retry_after }) => {
+                let mut detail = JsonMap::with_capacity(1);
+                let _ = detail.insert("retry_after".to_string(), From::from(retry_after));
+                Some(JsonValue::Object(detail))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The body of failed responses, in the shape of
+/// [Docker Registry errors](https://docs.docker.com/registry/spec/api/#errors)
+#[cfg(feature = "server")]
+#[derive(Serialize, Debug)]
+struct ErrorResponse {
+    errors: Vec<ErrorResponseEntry>,
+}
+
+#[cfg(feature = "server")]
+#[derive(Serialize, Debug)]
+struct ErrorResponseEntry {
+    code: &'static str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<JsonValue>,
+}
+
+#[cfg(feature = "server")]
+impl<'a> From<&'a Error> for ErrorResponse {
+    fn from(error: &'a Error) -> Self {
+        ErrorResponse {
+            errors: vec![
+                ErrorResponseEntry {
+                    code: error.code(),
+                    message: error.message(),
+                    detail: error.detail(),
+                },
+            ],
         }
     }
 }
 
+/// Responds with the status and headers of the error, and an [`ErrorResponse`] JSON body
+#[cfg(feature = "server")]
 impl<'r> Responder<'r> for Error {
     fn respond_to(self, request: &Request) -> Result<Response<'r>, Status> {
-        match self {
+        let body = serde_json::to_string(&ErrorResponse::from(&self)).map_err(|e| {
+            error_!("Error response serialization failed: {}", e);
+            Status::InternalServerError
+        })?;
+        let response = match self {
             Error::Auth(e) => e.respond_to(request),
             Error::CORS(e) => e.respond_to(request),
             Error::Token(e) => e.respond_to(request),
@@ -348,11 +593,39 @@ impl<'r> Responder<'r> for Error {
                 error_!("{}", e);
                 Err(Status::BadRequest)
             }
+            Error::BackendUnavailable(e) => {
+                error_!("Authentication backend is unavailable: {}", e);
+                Err(Status::ServiceUnavailable)
+            }
             e => {
                 error_!("{}", e);
                 Err(Status::InternalServerError)
             }
-        }
+        };
+
+        let mut response = match response {
+            Ok(response) => response,
+            Err(status) => Response::build().status(status).finalize(),
+        };
+        let _ = response.set_header(ContentType::JSON);
+        response.set_sized_body(Cursor::new(body));
+        Ok(response)
+    }
+}
+
+/// Format the value of a `WWW-Authenticate` header for `scheme` with the authentication `parameters`, whose values
+/// are quoted as described in [RFC 7235](https://tools.ietf.org/html/rfc7235#section-2.1)
+pub(crate) fn challenge(scheme: &str, parameters: &[(&str, &str)]) -> String {
+    let parameters: Vec<String> = parameters
+        .iter()
+        .map(|&(name, value)| {
+            format!("{}=\"{}\"", name, value.replace('\\', "\\\\").replace('"', "\\\""))
+        })
+        .collect();
+    if parameters.is_empty() {
+        scheme.to_string()
+    } else {
+        format!("{} {}", scheme, parameters.join(", "))
     }
 }
 
@@ -447,6 +720,9 @@ impl ByteSequence {
 /// The type parameter `B` is the [`auth::AuthenticatorConfiguration`] and by its associated
 /// type, the `Authenticator` that is going to be used for HTTP Basic Authentication.
 ///
+/// By default, `B` is [`auth::DynamicConfiguration`], which selects the authenticator at runtime based on the
+/// `type` key of `basic_authenticator`. See the [`auth::registry`] module for more information.
+///
 /// # Examples
 /// ```
 /// extern crate rowdy;
@@ -472,41 +748,151 @@ impl ByteSequence {
 ///        "basic_authenticator": {}
 /// }"#;
 /// let config: Configuration<NoOpConfiguration> = serde_json::from_str(json).unwrap();
-/// let rocket = config.ignite().unwrap().mount("/", rowdy::routes());
+/// let rocket = rowdy::mount(config.ignite().unwrap(), "/");
 /// // then `rocket.launch()`!
 /// # }
 /// ```
+///
+/// Selecting the authenticator by its `type`:
+///
+/// ```
+/// extern crate rowdy;
+/// extern crate serde_json;
+///
+/// use rowdy::Configuration;
+///
+/// # fn main() {
+/// let json = r#"{
+///     "token" : {
+///         "issuer": "https://www.acme.com",
+///         "allowed_origins": { "Some": ["https://www.example.com", "https://www.foobar.com"] },
+///         "audience": ["https://www.example.com", "https://www.foobar.com"],
+///         "signature_algorithm": "RS256",
+///         "secret": {
+///                     "rsa_private": "test/fixtures/rsa_private_key.der",
+///                     "rsa_public": "test/fixtures/rsa_public_key.der"
+///                    },
+///         "expiry_duration": 86400
+///        },
+///        "basic_authenticator": {
+///            "type": "csv",
+///            "csv_path": "test/fixtures/users.csv"
+///        }
+/// }"#;
+/// let config: Configuration = serde_json::from_str(json).unwrap();
+/// let rocket = rowdy::mount(config.ignite().unwrap(), "/");
+/// # }
+/// ```
+#[cfg(feature = "server")]
 #[derive(Serialize, Deserialize, Debug)]
-pub struct Configuration<B> {
+pub struct Configuration<B = auth::DynamicConfiguration> {
     /// Token configuration. See the type documentation for deserialization examples
     pub token: token::Configuration,
+    /// Clients that can use the authorization code grant, if it is enabled.
+    /// See the [`authorization_code`](authorization_code/index.html) module documentation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authorization_code: Option<authorization_code::Configuration>,
+    /// Clients that can use the device authorization grant, if it is enabled.
+    /// See the [`device`](device/index.html) module documentation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<device::Configuration>,
+    /// Services that can exchange the access tokens of users for tokens for other services, if the token exchange
+    /// grant is enabled. See the [`token_exchange`](token_exchange/index.html) module documentation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_exchange: Option<token_exchange::Configuration>,
     /// The configuration for the authenticator that will handle HTTP Basic Authentication.
     pub basic_authenticator: B,
+    /// The configuration for the authenticator of API keys sent as the entire `Authorization` header, if any.
+    /// Requires the `api_key_authenticator` feature.
+    #[cfg(feature = "api_key_authenticator")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_authenticator: Option<auth::ApiKeyAuthenticatorConfiguration>,
+    /// The configuration for throttling failed authentication attempts, if any.
+    /// See the [`throttle`](throttle/index.html) module documentation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub throttle: Option<throttle::Configuration>,
+    /// The destination of the audit log, if any.
+    /// See the [`audit`](audit/index.html) module documentation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audit: Option<audit::Configuration>,
+    /// The configuration for serving metrics, if any.
+    /// See the [`metrics`](metrics/index.html) module documentation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<metrics::Configuration>,
 }
 
+#[cfg(feature = "server")]
 impl<B: auth::AuthenticatorConfiguration<auth::Basic>> Configuration<B> {
     /// Ignites the rocket with various configuration objects, but does not mount any routes.
-    /// Remember to mount routes and call `launch` on the returned Rocket object.
+    /// Remember to mount routes with [`mount`](fn.mount.html) and call `launch` on the returned Rocket object.
+    /// If metrics are configured with a separate port, their server is launched in a new thread when the returned
+    /// Rocket is launched.
     /// See the struct documentation for an example.
     pub fn ignite(self) -> Result<rocket::Rocket, Error> {
         let token_getter_cors_options = self.token.cors_option();
+        let grants = oauth::Grants {
+            authorization_code: self.authorization_code,
+            device: self.device,
+            token_exchange: self.token_exchange,
+        };
 
         let basic_authenticator = self.basic_authenticator.make_authenticator()?;
         let basic_authenticator: Box<auth::BasicAuthenticator> = Box::new(basic_authenticator);
 
+        #[cfg(feature = "api_key_authenticator")]
+        let string_authenticator: Option<Box<auth::StringAuthenticator>> = match self.api_key_authenticator {
+            Some(ref configuration) => {
+                let authenticator = auth::ApiKeyAuthenticator::with_file(&configuration.keys_path)?;
+                Some(Box::new(authenticator) as Box<auth::StringAuthenticator>)
+            }
+            None => None,
+        };
+        #[cfg(not(feature = "api_key_authenticator"))]
+        let string_authenticator: Option<Box<auth::StringAuthenticator>> = None;
+
+        let throttle = match self.throttle {
+            Some(configuration) => throttle::Throttle::new(configuration),
+            None => throttle::Throttle::disabled(),
+        };
+        let auditor = match self.audit {
+            Some(ref configuration) => audit::Auditor::new(configuration)?,
+            None => audit::Auditor::disabled(),
+        };
+
+        let metrics = Arc::new(metrics::Metrics::new(self.metrics, &self.token));
+        let metrics_server = metrics::server_fairing(metrics.clone())?;
+
         // Prepare the keys
         let keys = self.token.keys()?;
 
-        Ok(
-            rocket::ignite()
-                .manage(self.token)
-                .manage(basic_authenticator)
-                .manage(keys)
-                .attach(token_getter_cors_options),
-        )
+        let rocket = rocket::ignite()
+            .manage(self.token)
+            .manage(grants)
+            .manage(basic_authenticator)
+            .manage(string_authenticator)
+            .manage(keys)
+            .manage(throttle)
+            .manage(auditor)
+            .manage(metrics)
+            .manage(authorization_code::AuthorizationCodes::new())
+            .manage(device::DeviceAuthorizations::new())
+            .attach(token_getter_cors_options);
+        Ok(match metrics_server {
+            Some(metrics_server) => rocket.attach(metrics_server),
+            None => rocket,
+        })
     }
 }
 
+/// Mount the routes provided by rowdy at `base`, along with the paths of the OAuth 2.0 endpoints for the
+/// [authorization server metadata](oauth/index.html#metadata).
+#[cfg(feature = "server")]
+pub fn mount(rocket: rocket::Rocket, base: &str) -> rocket::Rocket {
+    let routes = routes();
+    let endpoints = oauth::Endpoints::new(base, &routes);
+    rocket.manage(endpoints).mount(base, routes)
+}
+
 /// Convenience function to ignite and launch rowdy. This function will never return
 ///
 /// # Panics
@@ -541,11 +927,12 @@ impl<B: auth::AuthenticatorConfiguration<auth::Basic>> Configuration<B> {
 /// rowdy::launch(config);
 /// # }
 /// ```
+#[cfg(feature = "server")]
 pub fn launch<B: auth::AuthenticatorConfiguration<auth::Basic>>(
     config: Configuration<B>,
 ) -> rocket::error::LaunchError {
     let rocket = config.ignite().unwrap_or_else(|e| panic!("{}", e));
-    rocket.mount("/", routes()).launch()
+    mount(rocket, "/").launch()
 }
 
 #[cfg(test)]
@@ -593,4 +980,88 @@ mod tests {
             ],
         );
     }
+    #[test]
+    fn error_codes_are_stable() {
+        assert_eq!(Error::BadRequest("foobar".to_string()).code(), "BAD_REQUEST");
+        assert_eq!(Error::UnsupportedOperation.code(), "UNSUPPORTED");
+        assert_eq!(Error::Token(token::Error::InvalidService).code(), "INVALID_SERVICE");
+        assert_eq!(Error::Token(token::Error::Expired).code(), "INVALID_TOKEN");
+        assert_eq!(Error::GenericError("foobar".to_string()).code(), "INTERNAL_ERROR");
+    }
+
+    #[test]
+    fn error_kinds() {
+        assert_eq!(Error::ConfigurationError("foobar".to_string()).kind(), ErrorKind::Configuration);
+        assert_eq!(Error::Token(token::Error::KeyRequired).kind(), ErrorKind::Configuration);
+        assert_eq!(Error::Token(token::Error::Expired).kind(), ErrorKind::InvalidToken);
+        assert_eq!(Error::CryptographicError.kind(), ErrorKind::Cryptographic);
+
+        assert_eq!(Error::BackendUnavailable("foobar".to_string()).code(), "UNAVAILABLE");
+        assert_eq!(Error::KeyLoadingError("foobar".to_string()).code(), "INTERNAL_ERROR");
+        assert_eq!(Error::ClaimConflict("foobar".to_string()).code(), "INTERNAL_ERROR");
+    }
+
+    #[test]
+    fn error_causes_terminate() {
+        use std::error::Error as StdError;
+
+        let errors = vec![
+            Error::GenericError("foobar".to_string()),
+            Error::BadRequest("foobar".to_string()),
+            Error::ConfigurationError("foobar".to_string()),
+            Error::CryptographicError,
+            Error::UnsupportedOperation,
+            Error::Token(token::Error::Expired),
+            Error::Token(token::Error::GenericError("foobar".to_string())),
+        ];
+        for error in errors {
+            let mut depth = 0;
+            let mut cause = error.cause();
+            while let Some(e) = cause {
+                depth += 1;
+                assert!(depth < 5, "The causes of `{}` do not terminate", error);
+                cause = e.cause();
+            }
+        }
+        assert!(Error::GenericError("foobar".to_string()).cause().is_none());
+        assert!(Error::Token(token::Error::Expired).cause().is_some());
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn auth_error_causes_terminate() {
+        use std::error::Error as StdError;
+
+        assert!(auth::Error::AuthenticationFailure.cause().is_none());
+        assert!(auth::Error::GenericError("foobar".to_string()).cause().is_none());
+        let error = Error::Auth(auth::Error::Forbidden);
+        assert!(not_none!(error.cause()).cause().is_none());
+    }
+
+    #[test]
+    fn internal_error_messages_are_masked() {
+        let error = Error::GenericError("LDAP server at 10.0.0.1 is unreachable".to_string());
+        assert_eq!(error.message(), "An unexpected error occurred");
+
+        let error = Error::BackendUnavailable("LDAP server at 10.0.0.1 is unreachable".to_string());
+        assert_eq!(error.message(), "An authentication backend is unavailable");
+
+        let error = Error::BadRequest("The `service` parameter is missing".to_string());
+        assert_eq!(error.message(), "The `service` parameter is missing");
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn error_response_serialization() {
+        let error = Error::Auth(auth::Error::TooManyAttempts { retry_after: 30 });
+        let expected = concat!(
+            r#"{"errors":[{"code":"TOOMANYREQUESTS","#,
+            r#""message":"Too many authentication attempts have failed","detail":{"retry_after":30}}]}"#
+        );
+        assert_eq!(not_err!(serde_json::to_string(&ErrorResponse::from(&error))), expected);
+
+        let error = Error::BadRequest("foobar".to_string());
+        let expected = r#"{"errors":[{"code":"BAD_REQUEST","message":"foobar"}]}"#;
+        assert_eq!(not_err!(serde_json::to_string(&ErrorResponse::from(&error))), expected);
+    }
 }
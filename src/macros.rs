@@ -29,3 +29,10 @@ macro_rules! match_extract {
         }
     })
 }
+
+/// Rocket redefines the logging macros of `log` as `warn_!` and so on. Fall back to those of `log` when Rocket is
+/// not used.
+#[cfg(not(feature = "server"))]
+macro_rules! warn_ {
+    ($($args:tt)*) => (warn!($($args)*))
+}
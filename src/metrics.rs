@@ -0,0 +1,529 @@
+//! Metrics in the Prometheus text exposition format
+//!
+//! When enabled, the `/metrics` route exposes:
+//!
+//! - `rowdy_tokens_issued_total{grant, service}`: Access tokens issued, by `login`, `refresh`, `anonymous`,
+//!   `authorization_code`, `device_code` or `token_exchange` and service
+//! - `rowdy_authentication_failures_total{reason, authenticator}`: Refused token requests, by reason and
+//!   authenticator. The authenticator is `basic` for the authenticator of HTTP Basic authentication, `api_key`
+//!   for the authenticator of API keys, and `anonymous` for requests without credentials.
+//! - `rowdy_refresh_attempts_total{outcome}`: Token requests with a refresh token, by `success` or `failure`
+//! - `rowdy_authentication_duration_seconds{authenticator}`: Histogram of the time spent in the authenticator,
+//!   which is dominated by the directory for the LDAP authenticator
+//! - `rowdy_info`, `rowdy_token_info` and `rowdy_token_expiry_duration_seconds`: The version of rowdy and the
+//!   token and key configuration
+//!
+//! Because the token endpoint is usually public, the metrics can require a bearer token, or be served on a
+//! separate port instead of alongside the other routes.
+//!
+//! # Configuration Example
+//!
+//! Metrics are enabled by the `metrics` key of [`rowdy::Configuration`](../struct.Configuration.html). All keys are
+//! optional.
+//!
+//! ```json
+//! {
+//!     "bearer_token": "correct horse battery staple",
+//!     "address": "127.0.0.1",
+//!     "port": 9090
+//! }
+//! ```
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use rocket;
+use rocket::config::{Config, Environment};
+use rocket::fairing::AdHoc;
+
+use audit::EventKind;
+use auth;
+use token;
+
+/// Upper bounds of the buckets of the authentication duration histogram, in seconds
+const DURATION_BUCKETS: &'static [f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Metrics configuration. Usually deserialized as part of [`rowdy::Configuration`] from JSON for use.
+/// See the module documentation for an example.
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize, Debug, Default)]
+pub struct Configuration {
+    /// Token that scrapers must send in an `Authorization: Bearer` request header, if any
+    #[serde(default)]
+    pub bearer_token: Option<String>,
+    /// Address to bind the separate metrics server to. Defaults to the address of Rocket.
+    #[serde(default)]
+    pub address: Option<String>,
+    /// Port of a separate server for the metrics. If set, `/metrics` is only served on this port, and not alongside
+    /// the other routes.
+    #[serde(default)]
+    pub port: Option<u16>,
+}
+
+#[derive(Clone, Debug)]
+struct Histogram {
+    /// Number of observations in each bucket of `DURATION_BUCKETS`, not cumulative
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Histogram {
+            buckets: vec![0; DURATION_BUCKETS.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        if let Some(index) = DURATION_BUCKETS.iter().position(|bound| value <= *bound) {
+            self.buckets[index] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+#[derive(Default, Debug)]
+struct Registry {
+    tokens_issued: BTreeMap<(&'static str, String), u64>,
+    authentication_failures: BTreeMap<(&'static str, &'static str), u64>,
+    refresh_attempts: BTreeMap<&'static str, u64>,
+    authentication_duration: BTreeMap<&'static str, Histogram>,
+}
+
+/// Collects and renders metrics. This is managed by Rocket in an `Arc` and should be retrieved with `State`.
+#[derive(Debug)]
+pub struct Metrics {
+    configuration: Option<Configuration>,
+    /// Rendered gauges for information that does not change
+    info: String,
+    registry: Mutex<Registry>,
+}
+
+impl Metrics {
+    /// Create new `Metrics` with the information gauges for the token configuration. If `configuration` is `None`,
+    /// metrics are collected but not served.
+    pub fn new(configuration: Option<Configuration>, token: &token::Configuration) -> Self {
+        let mut info = String::new();
+        gauge(
+            &mut info,
+            "rowdy_info",
+            "Version of rowdy",
+            &[("version", env!("CARGO_PKG_VERSION"))],
+            1.0,
+        );
+
+        let signature_algorithm = format!("{:?}", token.signature_algorithm.unwrap_or_default());
+        let (cek_algorithm, enc_algorithm) = match token.refresh_token {
+            Some(ref refresh_token) => (
+                format!("{:?}", refresh_token.cek_algorithm),
+                format!("{:?}", refresh_token.enc_algorithm),
+            ),
+            None => ("none".to_string(), "none".to_string()),
+        };
+        gauge(
+            &mut info,
+            "rowdy_token_info",
+            "Algorithms of the access and refresh tokens",
+            &[
+                ("signature_algorithm", &signature_algorithm),
+                ("refresh_token_cek_algorithm", &cek_algorithm),
+                ("refresh_token_enc_algorithm", &enc_algorithm),
+            ],
+            1.0,
+        );
+        gauge(
+            &mut info,
+            "rowdy_token_expiry_duration_seconds",
+            "Duration that access tokens are valid for",
+            &[],
+            token.expiry_duration.as_secs() as f64,
+        );
+
+        Metrics {
+            configuration,
+            info,
+            registry: Default::default(),
+        }
+    }
+
+    /// Whether the metrics are served
+    pub fn is_enabled(&self) -> bool {
+        self.configuration.is_some()
+    }
+
+    /// The address and port of the separate metrics server, if any
+    pub fn separate_server(&self) -> Option<(Option<&str>, u16)> {
+        self.configuration.as_ref().and_then(|configuration| {
+            configuration.port.map(|port| {
+                (configuration.address.as_ref().map(|s| &**s), port)
+            })
+        })
+    }
+
+    /// Whether `token` is allowed to retrieve the metrics
+    pub fn authorize(&self, token: Option<&str>) -> bool {
+        match self.configuration {
+            Some(Configuration { bearer_token: Some(ref expected), .. }) => {
+                token.map_or(false, |token| constant_time_eq(token.as_bytes(), expected.as_bytes()))
+            }
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    /// Call `authenticate` and record its duration for the `authenticator`
+    pub fn time_authentication<T, F>(&self, authenticator: &'static str, authenticate: F) -> Result<T, ::Error>
+    where
+        F: FnOnce() -> Result<T, ::Error>,
+    {
+        let start = Instant::now();
+        let result = authenticate();
+        let elapsed = start.elapsed();
+        let seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+
+        if let Ok(mut registry) = self.registry.lock() {
+            registry
+                .authentication_duration
+                .entry(authenticator)
+                .or_insert_with(Histogram::new)
+                .observe(seconds);
+        }
+        result
+    }
+
+    /// Record the outcome of a token request for `service` with the `authenticator`
+    pub fn record<T>(&self, kind: EventKind, authenticator: &'static str, service: &str, result: &Result<T, ::Error>) {
+        let mut registry = match self.registry.lock() {
+            Ok(registry) => registry,
+            Err(_) => return,
+        };
+        let grant = match kind {
+            EventKind::Login => Some("login"),
+            EventKind::Refresh => Some("refresh"),
+            EventKind::Anonymous => Some("anonymous"),
+            EventKind::AuthorizationCode => Some("authorization_code"),
+            EventKind::DeviceCode => Some("device_code"),
+            EventKind::TokenExchange => Some("token_exchange"),
+            // Authorization and device codes are not counted until they are exchanged for access tokens
+            EventKind::Authorize | EventKind::ApproveDevice => None,
+        };
+        match *result {
+            Ok(_) => {
+                let grant = match grant {
+                    Some(grant) => grant,
+                    None => return,
+                };
+                // The service has been validated against the audience, so this does not grow without bounds
+                *registry
+                    .tokens_issued
+                    .entry((grant, service.to_string()))
+                    .or_insert(0) += 1;
+            }
+            Err(ref e) => {
+                *registry
+                    .authentication_failures
+                    .entry((reason(e), authenticator))
+                    .or_insert(0) += 1;
+            }
+        }
+        if kind == EventKind::Refresh {
+            let outcome = if result.is_ok() { "success" } else { "failure" };
+            *registry.refresh_attempts.entry(outcome).or_insert(0) += 1;
+        }
+    }
+
+    /// Render the metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut output = self.info.clone();
+        let registry = match self.registry.lock() {
+            Ok(registry) => registry,
+            Err(_) => return output,
+        };
+
+        header(&mut output, "rowdy_tokens_issued_total", "Access tokens issued", "counter");
+        for (&(grant, ref service), count) in &registry.tokens_issued {
+            sample(
+                &mut output,
+                "rowdy_tokens_issued_total",
+                &[("grant", grant), ("service", service)],
+                *count as f64,
+            );
+        }
+
+        header(
+            &mut output,
+            "rowdy_authentication_failures_total",
+            "Refused token requests",
+            "counter",
+        );
+        for (&(reason, authenticator), count) in &registry.authentication_failures {
+            sample(
+                &mut output,
+                "rowdy_authentication_failures_total",
+                &[("reason", reason), ("authenticator", authenticator)],
+                *count as f64,
+            );
+        }
+
+        header(
+            &mut output,
+            "rowdy_refresh_attempts_total",
+            "Token requests with a refresh token",
+            "counter",
+        );
+        for (&outcome, count) in &registry.refresh_attempts {
+            sample(
+                &mut output,
+                "rowdy_refresh_attempts_total",
+                &[("outcome", outcome)],
+                *count as f64,
+            );
+        }
+
+        header(
+            &mut output,
+            "rowdy_authentication_duration_seconds",
+            "Time spent in the authenticator",
+            "histogram",
+        );
+        for (&authenticator, histogram) in &registry.authentication_duration {
+            let mut cumulative = 0;
+            for (bound, count) in DURATION_BUCKETS.iter().zip(histogram.buckets.iter()) {
+                cumulative += *count;
+                sample(
+                    &mut output,
+                    "rowdy_authentication_duration_seconds_bucket",
+                    &[("authenticator", authenticator), ("le", &bound.to_string())],
+                    cumulative as f64,
+                );
+            }
+            sample(
+                &mut output,
+                "rowdy_authentication_duration_seconds_bucket",
+                &[("authenticator", authenticator), ("le", "+Inf")],
+                histogram.count as f64,
+            );
+            sample(
+                &mut output,
+                "rowdy_authentication_duration_seconds_sum",
+                &[("authenticator", authenticator)],
+                histogram.sum,
+            );
+            sample(
+                &mut output,
+                "rowdy_authentication_duration_seconds_count",
+                &[("authenticator", authenticator)],
+                histogram.count as f64,
+            );
+        }
+
+        output
+    }
+}
+
+/// A fairing that launches a separate server for the metrics in a new thread when Rocket launches, if it is
+/// configured. The configuration of the server is checked right away, so that nothing is launched if it is invalid.
+pub fn server_fairing(metrics: Arc<Metrics>) -> Result<Option<AdHoc>, ::Error> {
+    let (address, port) = match metrics.separate_server() {
+        Some((address, port)) => (address.map(|s| s.to_string()), port),
+        None => return Ok(None),
+    };
+
+    let environment = Environment::active().map_err(|e| ::Error::ConfigurationError(e.to_string()))?;
+    let mut config = Config::build(environment).port(port);
+    if let Some(address) = address {
+        config = config.address(address);
+    }
+    let config = config.finalize().map_err(|e| ::Error::ConfigurationError(e.to_string()))?;
+
+    Ok(Some(AdHoc::on_launch(move |_| {
+        let _ = thread::spawn(move || {
+            let server = rocket::custom(config, true)
+                .manage(metrics)
+                .mount("/", ::routes::metrics_server_routes());
+            let error = server.launch();
+            error_!("Metrics server failed to launch: {}", error);
+        });
+    })))
+}
+
+/// The reason label for a refused token request
+fn reason(error: &::Error) -> &'static str {
+    match *error {
+        ::Error::Auth(auth::Error::AuthenticationFailure) => "invalid_credentials",
+        ::Error::Auth(auth::Error::Forbidden) => "forbidden",
+        ::Error::Auth(auth::Error::TooManyAttempts { .. }) => "throttled",
+        ::Error::Auth(auth::Error::MissingAuthorization { .. }) => "missing_authorization",
+        ::Error::BadRequest(_) |
+        ::Error::Token(token::Error::RefreshTokenDisabled) |
+        ::Error::Token(token::Error::OfflineTokenNotAllowed) => "bad_request",
+        ::Error::Auth(auth::Error::InvalidToken(_)) |
+        ::Error::Token(_) => "invalid_token",
+        ::Error::BackendUnavailable(_) => "unavailable",
+        _ => "error",
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+fn header(output: &mut String, name: &str, help: &str, metric_type: &str) {
+    let _ = writeln!(output, "# HELP {} {}", name, help);
+    let _ = writeln!(output, "# TYPE {} {}", name, metric_type);
+}
+
+fn gauge(output: &mut String, name: &str, help: &str, labels: &[(&str, &str)], value: f64) {
+    header(output, name, help, "gauge");
+    sample(output, name, labels, value);
+}
+
+fn sample(output: &mut String, name: &str, labels: &[(&str, &str)], value: f64) {
+    output.push_str(name);
+    if !labels.is_empty() {
+        let labels: Vec<String> = labels
+            .iter()
+            .map(|&(label, value)| format!("{}=\"{}\"", label, escape(value)))
+            .collect();
+        let _ = write!(output, "{{{}}}", labels.join(","));
+    }
+    let _ = writeln!(output, " {}", value);
+}
+
+/// Escape a label value
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    use jwt;
+
+    use super::*;
+    use token::Secret;
+
+    fn token_configuration() -> token::Configuration {
+        let (allowed_origins, _) = ::cors::AllowedOrigins::some(&["https://www.example.com"]);
+        token::Configuration {
+            issuer: FromStr::from_str("https://www.acme.com").unwrap(),
+            realm: None,
+            allowed_origins: allowed_origins,
+            audience: jwt::SingleOrMultiple::Single(not_err!(FromStr::from_str("https://www.example.com"))),
+            include_all_audiences: false,
+            signature_algorithm: Some(jwt::jwa::SignatureAlgorithm::HS512),
+            secret: Secret::None,
+            expiry_duration: Duration::from_secs(120),
+            refresh_token: None,
+            services: Default::default(),
+            anonymous: None,
+            openid: Default::default(),
+        }
+    }
+
+    #[test]
+    fn info_is_rendered() {
+        let metrics = Metrics::new(None, &token_configuration());
+        let rendered = metrics.render();
+        assert!(rendered.contains(&format!("rowdy_info{{version=\"{}\"}} 1\n", env!("CARGO_PKG_VERSION"))));
+        assert!(rendered.contains(
+            "rowdy_token_info{signature_algorithm=\"HS512\",refresh_token_cek_algorithm=\"none\",\
+             refresh_token_enc_algorithm=\"none\"} 1\n",
+        ));
+        assert!(rendered.contains("rowdy_token_expiry_duration_seconds 120\n"));
+    }
+
+    #[test]
+    fn separate_server_is_only_launched_with_a_port() {
+        let metrics = Arc::new(Metrics::new(Some(Default::default()), &token_configuration()));
+        assert!(not_err!(server_fairing(metrics)).is_none());
+
+        let configuration = Configuration {
+            port: Some(9090),
+            ..Default::default()
+        };
+        let metrics = Arc::new(Metrics::new(Some(configuration), &token_configuration()));
+        assert!(not_err!(server_fairing(metrics)).is_some());
+    }
+
+    #[test]
+    fn requests_are_counted() {
+        let metrics = Metrics::new(None, &token_configuration());
+        metrics.record(EventKind::Login, "basic", "https://www.example.com", &Ok(()));
+        metrics.record(EventKind::Login, "basic", "https://www.example.com", &Ok(()));
+        metrics.record::<()>(
+            EventKind::Login,
+            "api_key",
+            "https://www.example.com",
+            &Err(auth::Error::AuthenticationFailure.into()),
+        );
+        metrics.record::<()>(
+            EventKind::Refresh,
+            "basic",
+            "https://www.example.com",
+            &Err(auth::Error::Forbidden.into()),
+        );
+
+        let rendered = metrics.render();
+        assert!(rendered.contains(
+            "rowdy_tokens_issued_total{grant=\"login\",service=\"https://www.example.com\"} 2\n",
+        ));
+        assert!(rendered.contains(
+            "rowdy_authentication_failures_total{reason=\"invalid_credentials\",authenticator=\"api_key\"} 1\n",
+        ));
+        assert!(rendered.contains(
+            "rowdy_authentication_failures_total{reason=\"forbidden\",authenticator=\"basic\"} 1\n",
+        ));
+        assert!(rendered.contains("rowdy_refresh_attempts_total{outcome=\"failure\"} 1\n"));
+    }
+
+    #[test]
+    fn authentication_duration_is_a_histogram() {
+        let metrics = Metrics::new(None, &token_configuration());
+        not_err!(metrics.time_authentication("basic", || Ok(())));
+        not_err!(metrics.time_authentication("basic", || Ok(())));
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("# TYPE rowdy_authentication_duration_seconds histogram\n"));
+        assert!(rendered.contains(
+            "rowdy_authentication_duration_seconds_bucket{authenticator=\"basic\",le=\"10\"} 2\n",
+        ));
+        assert!(rendered.contains(
+            "rowdy_authentication_duration_seconds_bucket{authenticator=\"basic\",le=\"+Inf\"} 2\n",
+        ));
+        assert!(rendered.contains("rowdy_authentication_duration_seconds_count{authenticator=\"basic\"} 2\n"));
+    }
+
+    #[test]
+    fn bearer_token_is_required_when_configured() {
+        let metrics = Metrics::new(None, &token_configuration());
+        assert!(!metrics.authorize(None));
+
+        let metrics = Metrics::new(Some(Default::default()), &token_configuration());
+        assert!(metrics.authorize(None));
+
+        let configuration = Configuration {
+            bearer_token: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let metrics = Metrics::new(Some(configuration), &token_configuration());
+        assert!(!metrics.authorize(None));
+        assert!(!metrics.authorize(Some("wrong")));
+        assert!(metrics.authorize(Some("secret")));
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
@@ -0,0 +1,1012 @@
+//! Types for the OAuth 2.0 token, introspection and metadata endpoints
+//!
+//! In addition to the Docker style `GET /` routes, rowdy serves a token endpoint at `POST /token` as described in
+//! [RFC 6749](https://tools.ietf.org/html/rfc6749#section-3.2) for generic OAuth 2.0 clients. The form encoded
+//! request body supports the following grants:
+//!
+//! - `password`: The `username` and `password` are verified by the authenticator of HTTP Basic authentication.
+//! - `client_credentials`: The client ID and secret are sent with HTTP Basic authentication, or as the `client_id`
+//!   and `client_secret` parameters. They are verified by the authenticator of API keys. The credentials of users
+//!   are not accepted, so this grant requires the authenticator of API keys to be configured.
+//! - `refresh_token`: The `refresh_token` issued with an earlier access token is verified.
+//! - `authorization_code`: The `code` issued to a browser based client is exchanged along with the `client_id`,
+//!   `redirect_uri` and PKCE `code_verifier`. See the [`authorization_code`](../authorization_code/index.html)
+//!   module.
+//! - `urn:ietf:params:oauth:grant-type:device_code`: The `device_code` issued to a device is polled for along with
+//!   the `client_id` until the user approves it. See the [`device`](../device/index.html) module.
+//! - `urn:ietf:params:oauth:grant-type:token-exchange`: A service exchanges the `subject_token` of a user for a
+//!   token for another service, authenticating with its client ID and secret as an API key. See the
+//!   [`token_exchange`](../token_exchange/index.html) module.
+//!
+//! The service the access token is intended for is the `resource` parameter described in
+//! [RFC 8707](https://tools.ietf.org/html/rfc8707), and may be omitted if only one audience is configured.
+//! Requesting the `openid` scope with the `client_id` of a registered client adds an `id_token` to the response. See
+//! the [`oidc`](../oidc/index.html) module.
+//!
+//! # Example
+//!
+//! ```text
+//! POST /token HTTP/1.1
+//! Content-Type: application/x-www-form-urlencoded
+//!
+//! grant_type=password&username=mei&password=password&resource=https%3A%2F%2Fwww.example.com&scope=all
+//! ```
+//!
+//! ```json
+//! {
+//!     "access_token": "eyJhbGciOiJIUzI1NiJ9...",
+//!     "token_type": "Bearer",
+//!     "expires_in": 86400,
+//!     "refresh_token": "eyJhbGciOiJBMjU2R0NNS1ciLCJlbmMiOiJBMjU2R0NNIn0...",
+//!     "scope": "all"
+//! }
+//! ```
+//!
+//! # Introspection
+//!
+//! Services that cannot validate tokens themselves can ask rowdy about a token at `POST /introspect` as described in
+//! [RFC 7662](https://tools.ietf.org/html/rfc7662). The service authenticates with its client ID and secret, which
+//! are verified as an API key, and sends the `token` parameter. The credentials of users are not accepted, so
+//! introspection requires the authenticator of API keys to be configured.
+//!
+//! The response has `active` set to `true` along with the claims of the token if the token was issued by rowdy, has
+//! not expired and is intended for a service the client is allowed to request tokens for. Refresh tokens are
+//! decrypted, but only their registered claims are reported. Otherwise, the response is `{"active": false}`.
+//!
+//! ```json
+//! {
+//!     "active": true,
+//!     "token_type": "access_token",
+//!     "iss": "https://www.acme.com/",
+//!     "sub": "mei",
+//!     "aud": "https://www.example.com/",
+//!     "exp": 1500000120,
+//!     "nbf": 1500000000,
+//!     "iat": 1500000000,
+//!     "jti": "urn:uuid:6d3a8b3d-7a1a-4b1d-9c9a-0e4f0b0e7d52"
+//! }
+//! ```
+//!
+//! # Metadata
+//!
+//! Clients can discover the endpoints, grant types and signature algorithm at
+//! `GET /.well-known/oauth-authorization-server` as described in [RFC 8414](https://tools.ietf.org/html/rfc8414).
+//! The endpoints in the document are found from the routes that are mounted, so routes have to be mounted with
+//! [`rowdy::mount`](../fn.mount.html) for the document to be served. The paths of the endpoints are resolved against
+//! the `issuer` in the configuration.
+use std::error;
+use std::fmt;
+use std::io::Cursor;
+use std::iter;
+
+use rocket::Route;
+use rocket::http::{ContentType, Header, Method, Status};
+use rocket::request::Request;
+use rocket::response::{Responder, Response};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+
+use JsonValue;
+use auth;
+use authorization_code;
+use device;
+use jwt;
+use oidc;
+use token::{self, RefreshToken, Token};
+use token_exchange;
+
+/// Grant type of the resource owner password credentials grant
+pub const PASSWORD_GRANT: &'static str = "password";
+/// Grant type of the client credentials grant
+pub const CLIENT_CREDENTIALS_GRANT: &'static str = "client_credentials";
+/// Grant type of the refresh token grant
+pub const REFRESH_TOKEN_GRANT: &'static str = "refresh_token";
+/// Grant type of the authorization code grant
+pub const AUTHORIZATION_CODE_GRANT: &'static str = "authorization_code";
+/// Grant type of the device authorization grant
+pub const DEVICE_CODE_GRANT: &'static str = "urn:ietf:params:oauth:grant-type:device_code";
+/// Grant type of the token exchange grant
+pub const TOKEN_EXCHANGE_GRANT: &'static str = "urn:ietf:params:oauth:grant-type:token-exchange";
+
+/// Token type of access tokens in introspection responses
+pub const ACCESS_TOKEN_TYPE: &'static str = "access_token";
+/// Token type of refresh tokens in introspection responses
+pub const REFRESH_TOKEN_TYPE: &'static str = "refresh_token";
+
+/// Form encoded request to the token endpoint. Parameters that are not used by the grant type are ignored.
+#[derive(FromForm, Clone, Default, Debug)]
+pub struct TokenRequest {
+    /// The grant type
+    pub grant_type: String,
+    /// The service the access token is intended for
+    pub resource: Option<String>,
+    /// The scope requested
+    pub scope: Option<String>,
+    /// Username for the `password` grant
+    pub username: Option<String>,
+    /// Password for the `password` grant
+    pub password: Option<String>,
+    /// Client ID for the `client_credentials` and token exchange grants, if it is not sent with HTTP Basic
+    /// authentication, or of the public client for the `authorization_code` and device code grants
+    pub client_id: Option<String>,
+    /// Client secret for the `client_credentials` and token exchange grants, if it is not sent with HTTP Basic
+    /// authentication
+    pub client_secret: Option<String>,
+    /// Refresh token for the `refresh_token` grant
+    pub refresh_token: Option<String>,
+    /// Code for the `authorization_code` grant
+    pub code: Option<String>,
+    /// Redirect URI for the `authorization_code` grant, if it was sent to the authorization endpoint
+    pub redirect_uri: Option<String>,
+    /// PKCE code verifier for the `authorization_code` grant
+    pub code_verifier: Option<String>,
+    /// Device code for the device code grant
+    pub device_code: Option<String>,
+    /// Access token to exchange for the token exchange grant
+    pub subject_token: Option<String>,
+    /// Type of the `subject_token`, which must be `urn:ietf:params:oauth:token-type:access_token`
+    pub subject_token_type: Option<String>,
+    /// Type of the token requested with the token exchange grant. Only access tokens can be requested.
+    pub requested_token_type: Option<String>,
+    /// Value to include in the ID token, if the `openid` scope is requested
+    pub nonce: Option<String>,
+}
+
+impl TokenRequest {
+    /// The service the access token is intended for. If the `resource` parameter is missing, this is the audience
+    /// in the configuration when there is only one.
+    pub fn resource(&self, configuration: &token::Configuration) -> Result<String, Error> {
+        resource(&self.resource, configuration)
+    }
+
+    /// The client ID and secret sent with HTTP Basic authentication, or the request parameters. Clients must not use
+    /// both.
+    pub fn client_credentials(
+        &self,
+        authorization: Option<&auth::Authorization<auth::Basic>>,
+    ) -> Result<(String, String), Error> {
+        client_credentials(authorization, &self.client_id, &self.client_secret)
+    }
+
+    /// The `code`, `client_id` and `code_verifier` parameters of the `authorization_code` grant, which are required
+    pub fn authorization_code(&self) -> Result<(String, String, String), Error> {
+        match (&self.code, &self.client_id, &self.code_verifier) {
+            (&Some(ref code), &Some(ref client_id), &Some(ref code_verifier)) => {
+                Ok((code.to_string(), client_id.to_string(), code_verifier.to_string()))
+            }
+            _ => Err(Error::new(
+                ErrorCode::InvalidRequest,
+                "The `code`, `client_id` and `code_verifier` parameters are required",
+            )),
+        }
+    }
+
+    /// The `device_code` and `client_id` parameters of the device code grant, which are required
+    pub fn device_code(&self) -> Result<(String, String), Error> {
+        match (&self.device_code, &self.client_id) {
+            (&Some(ref device_code), &Some(ref client_id)) => Ok((device_code.to_string(), client_id.to_string())),
+            _ => Err(Error::new(
+                ErrorCode::InvalidRequest,
+                "The `device_code` and `client_id` parameters are required",
+            )),
+        }
+    }
+}
+
+/// Form encoded request to the introspection endpoint
+#[derive(FromForm, Clone, Default, Debug)]
+pub struct IntrospectionRequest {
+    /// The encoded access token, or encrypted refresh token
+    pub token: String,
+    /// The type of the token. Access and refresh tokens can be told apart without the hint, so it is ignored.
+    pub token_type_hint: Option<String>,
+    /// Client ID of the service, if it is not sent with HTTP Basic authentication
+    pub client_id: Option<String>,
+    /// Client secret of the service, if it is not sent with HTTP Basic authentication
+    pub client_secret: Option<String>,
+}
+
+impl IntrospectionRequest {
+    /// The client ID and secret sent with HTTP Basic authentication, or the request parameters. Clients must not use
+    /// both.
+    pub fn client_credentials(
+        &self,
+        authorization: Option<&auth::Authorization<auth::Basic>>,
+    ) -> Result<(String, String), Error> {
+        client_credentials(authorization, &self.client_id, &self.client_secret)
+    }
+}
+
+/// The `resource` parameter, or the audience in the configuration when there is only one
+pub(crate) fn resource(resource: &Option<String>, configuration: &token::Configuration) -> Result<String, Error> {
+    match *resource {
+        Some(ref resource) => Ok(resource.to_string()),
+        None => {
+            match configuration.audience {
+                ::jwt::SingleOrMultiple::Single(ref audience) => Ok(audience.to_string()),
+                ::jwt::SingleOrMultiple::Multiple(_) => Err(Error::new(
+                    ErrorCode::InvalidRequest,
+                    "The `resource` parameter is required",
+                )),
+            }
+        }
+    }
+}
+
+fn client_credentials(
+    authorization: Option<&auth::Authorization<auth::Basic>>,
+    client_id: &Option<String>,
+    client_secret: &Option<String>,
+) -> Result<(String, String), Error> {
+    match (authorization, client_id, client_secret) {
+        (Some(_), &Some(_), _) |
+        (Some(_), _, &Some(_)) => Err(Error::new(
+            ErrorCode::InvalidRequest,
+            "Client credentials must not be sent with more than one method",
+        )),
+        (Some(authorization), _, _) => Ok((
+            authorization.username(),
+            authorization.password().unwrap_or_default(),
+        )),
+        (None, &Some(ref client_id), &Some(ref client_secret)) => {
+            Ok((client_id.to_string(), client_secret.to_string()))
+        }
+        _ => Err(Error::new(
+            ErrorCode::InvalidClient,
+            "Client authentication is required",
+        )),
+    }
+}
+
+/// Successful response of the token endpoint
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize, Debug)]
+pub struct AccessTokenResponse {
+    /// The encoded access token
+    pub access_token: String,
+    /// Type of the access token, which is always `Bearer`
+    pub token_type: String,
+    /// Seconds until the access token expires
+    pub expires_in: u64,
+    /// The encrypted refresh token, if one was issued
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    /// The OpenID Connect ID token, if the `openid` scope was requested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+    /// Type of the token issued by the token exchange grant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issued_token_type: Option<String>,
+    /// Scope of the access token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+}
+
+impl AccessTokenResponse {
+    /// Make a response from an encoded `token` for `scope`
+    pub fn new<T: Serialize + DeserializeOwned + 'static>(token: &Token<T>, scope: &str) -> Result<Self, ::Error> {
+        let refresh_token = match token.refresh_token {
+            Some(ref refresh_token) => Some(refresh_token.to_string()?),
+            None => None,
+        };
+        Ok(AccessTokenResponse {
+            access_token: token.encoded_token()?,
+            token_type: "Bearer".to_string(),
+            expires_in: token.expires_in.as_secs(),
+            refresh_token,
+            id_token: token.id_token.clone(),
+            issued_token_type: None,
+            scope: if scope.is_empty() {
+                None
+            } else {
+                Some(scope.to_string())
+            },
+        })
+    }
+}
+
+impl<'r> Responder<'r> for AccessTokenResponse {
+    fn respond_to(self, request: &Request) -> Result<Response<'r>, Status> {
+        let body = match serde_json::to_string(&self) {
+            Ok(body) => body,
+            Err(e) => return ::Error::JsonError(e).respond_to(request),
+        };
+        Response::build()
+            .header(ContentType::JSON)
+            .header(Header::new("Cache-Control", "no-store"))
+            .header(Header::new("Pragma", "no-cache"))
+            .sized_body(Cursor::new(body))
+            .ok()
+    }
+}
+
+/// Response of the introspection endpoint
+#[derive(Clone, PartialEq, Debug)]
+pub struct IntrospectionResponse {
+    /// Whether the token is valid
+    pub active: bool,
+    /// `access_token` or `refresh_token`, if the token is active
+    pub token_type: Option<String>,
+    /// Registered claims of the token, if the token is active
+    pub registered: Option<jwt::RegisteredClaims>,
+    /// Private claims of the token, if the token is an active access token
+    pub private: Option<JsonValue>,
+}
+
+impl IntrospectionResponse {
+    /// Introspect an encoded access token or encrypted refresh token issued with the configuration. Tokens that
+    /// cannot be decoded or validated are inactive.
+    pub fn new(token: &str, configuration: &token::Configuration, keys: &token::Keys) -> Self {
+        // Refresh tokens are JWE with five parts while access tokens are JWS with three parts
+        let result = if token.split('.').count() == 5 {
+            Self::refresh_token(token, configuration, keys)
+        } else {
+            Self::access_token(token, configuration, keys)
+        };
+        match result {
+            Ok(response) => response,
+            Err(e) => {
+                debug_!("Introspected token is not active: {}", e);
+                Self::inactive()
+            }
+        }
+    }
+
+    /// A response for an inactive token
+    pub fn inactive() -> Self {
+        IntrospectionResponse {
+            active: false,
+            token_type: None,
+            registered: None,
+            private: None,
+        }
+    }
+
+    fn access_token(token: &str, configuration: &token::Configuration, keys: &token::Keys) -> Result<Self, ::Error> {
+        // Services with their own secret have their tokens signed with their own keys and algorithms. A token is only
+        // valid if the key that verifies it signs tokens for its audience.
+        let services = keys.services.iter().map(|(service, service_keys)| {
+            (
+                Some(service.as_str()),
+                &service_keys.signature_verification,
+                configuration.signature_algorithm_for(service),
+            )
+        });
+        let token = iter::once((None, &keys.signature_verification, configuration.signature_algorithm))
+            .chain(services)
+            .filter_map(|(service, secret, algorithm)| {
+                jwt::JWT::<JsonValue, jwt::Empty>::new_encoded(token)
+                    .into_decoded(secret, algorithm.unwrap_or_default())
+                    .ok()
+                    .map(|token| (service, token))
+            })
+            .find(|&(service, ref token)| match token.payload() {
+                Ok(claims_set) => {
+                    let audience = claims_set.registered.audience.as_ref();
+                    audience.map_or(false, |audience| keys.signs_for(service, audience))
+                }
+                Err(_) => false,
+            })
+            .map(|(_, token)| token)
+            .ok_or_else(|| auth::Error::InvalidToken("The signature of the token is invalid".to_string()))?;
+        let claims_set = token.payload().map_err(token::Error::JWTError)?;
+        if oidc::is_id_token(&claims_set.private) {
+            Err(auth::Error::InvalidToken("ID tokens are not access tokens".to_string()))?
+        }
+        token::verify_registered_claims(configuration, &claims_set.registered, None)?;
+
+        Ok(IntrospectionResponse {
+            active: true,
+            token_type: Some(ACCESS_TOKEN_TYPE.to_string()),
+            registered: Some(claims_set.registered.clone()),
+            private: Some(claims_set.private.clone()),
+        })
+    }
+
+    fn refresh_token(token: &str, configuration: &token::Configuration, keys: &token::Keys) -> Result<Self, ::Error> {
+        if !configuration.refresh_token_enabled() {
+            Err(token::Error::RefreshTokenDisabled)?
+        }
+        let refresh_token_configuration = configuration.refresh_token();
+        let refresh_token = RefreshToken::new_encrypted(token).decrypt(
+            &keys.signature_verification,
+            keys.decryption.as_ref().expect(
+                "Refresh token was enabled but decryption key is missing",
+            ),
+            configuration.signature_algorithm.unwrap_or_default(),
+            refresh_token_configuration.cek_algorithm,
+            refresh_token_configuration.enc_algorithm,
+        )?;
+        let registered = refresh_token.registered_claims()?;
+        token::verify_registered_claims(configuration, registered, None)?;
+
+        // The payload holds the credentials to authenticate with again, and is never reported
+        Ok(IntrospectionResponse {
+            active: true,
+            token_type: Some(REFRESH_TOKEN_TYPE.to_string()),
+            registered: Some(registered.clone()),
+            private: None,
+        })
+    }
+
+    /// The services the token is intended for
+    pub fn audience(&self) -> Vec<String> {
+        match self.registered {
+            Some(jwt::RegisteredClaims { audience: Some(ref audience), .. }) => {
+                audience.iter().map(|audience| audience.to_string()).collect()
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Serialize the response. Private claims are included as top level members, unless they conflict with the
+    /// registered claims or the members defined by RFC 7662.
+    pub fn to_json(&self) -> Result<JsonValue, ::Error> {
+        let mut response = ::JsonMap::new();
+        if let Some(ref registered) = self.registered {
+            let registered = serde_json::to_value(registered).map_err(token::Error::TokenSerializationError)?;
+            if let JsonValue::Object(registered) = registered {
+                response.extend(registered);
+            }
+        }
+        if let Some(JsonValue::Object(ref private)) = self.private {
+            for (key, value) in private {
+                if !response.contains_key(key) && key != "active" && key != "token_type" {
+                    let _ = response.insert(key.to_string(), value.clone());
+                }
+            }
+        }
+        let _ = response.insert("active".to_string(), From::from(self.active));
+        if let Some(ref token_type) = self.token_type {
+            let _ = response.insert("token_type".to_string(), From::from(token_type.to_string()));
+        }
+        Ok(JsonValue::Object(response))
+    }
+}
+
+impl<'r> Responder<'r> for IntrospectionResponse {
+    fn respond_to(self, request: &Request) -> Result<Response<'r>, Status> {
+        let body = self.to_json().and_then(|json| {
+            Ok(serde_json::to_string(&json).map_err(
+                token::Error::TokenSerializationError,
+            )?)
+        });
+        let body = match body {
+            Ok(body) => body,
+            Err(e) => return e.respond_to(request),
+        };
+        Response::build()
+            .header(ContentType::JSON)
+            .header(Header::new("Cache-Control", "no-store"))
+            .header(Header::new("Pragma", "no-cache"))
+            .sized_body(Cursor::new(body))
+            .ok()
+    }
+}
+
+/// Settings of the grants that are only served by the server, which are read from
+/// [`rowdy::Configuration`](../struct.Configuration.html). This is managed by Rocket.
+#[derive(Default, Debug)]
+pub struct Grants {
+    /// Clients that can use the authorization code grant, if it is enabled.
+    /// See the [`authorization_code`](../authorization_code/index.html) module documentation.
+    pub authorization_code: Option<authorization_code::Configuration>,
+    /// Clients that can use the device authorization grant, if it is enabled.
+    /// See the [`device`](../device/index.html) module documentation.
+    pub device: Option<device::Configuration>,
+    /// Services that can exchange the access tokens of users for tokens for other services, if the token exchange
+    /// grant is enabled. See the [`token_exchange`](../token_exchange/index.html) module documentation.
+    pub token_exchange: Option<token_exchange::Configuration>,
+}
+
+impl Grants {
+    /// Whether ID tokens can be issued for `client_id`. Only clients registered for the authorization code or device
+    /// authorization grants get ID tokens, and never with a client ID that is also a service of the `configuration`,
+    /// so that ID tokens cannot be passed off as access tokens for the service.
+    pub fn issues_id_tokens_for(&self, configuration: &token::Configuration, client_id: &str) -> bool {
+        let registered = self.authorization_code
+            .as_ref()
+            .map(|grant| grant.clients.contains_key(client_id))
+            .unwrap_or(false) ||
+            self.device
+                .as_ref()
+                .map(|grant| grant.clients.contains_key(client_id))
+                .unwrap_or(false);
+        if registered && token::verify_service(configuration, client_id).is_ok() {
+            warn_!("Not issuing an ID token for client `{}`, which is also a service", client_id);
+            return false;
+        }
+        registered
+    }
+}
+
+/// Paths of the OAuth 2.0 endpoints among the mounted routes. This is managed by Rocket when routes are mounted
+/// with [`rowdy::mount`](../fn.mount.html).
+#[derive(Clone, Eq, PartialEq, Default, Debug)]
+pub struct Endpoints {
+    /// Path of the authorization endpoint
+    pub authorization: Option<String>,
+    /// Path of the token endpoint
+    pub token: Option<String>,
+    /// Path of the introspection endpoint
+    pub introspection: Option<String>,
+    /// Path of the revocation endpoint
+    pub revocation: Option<String>,
+    /// Path of the JSON Web Key Set document
+    pub jwks: Option<String>,
+    /// Path of the OpenID Connect user info endpoint
+    pub userinfo: Option<String>,
+    /// Path of the device authorization endpoint
+    pub device_authorization: Option<String>,
+    /// Path of the verification page of the device authorization grant
+    pub device_verification: Option<String>,
+}
+
+impl Endpoints {
+    /// Find the endpoints among `routes` mounted at `base`. Endpoints without a route are left out.
+    pub fn new(base: &str, routes: &[Route]) -> Self {
+        let find = |method: Method, path: &str| {
+            routes
+                .iter()
+                .find(|route| route.method == method && route.uri.path() == path)
+                .map(|route| format!("{}{}", base.trim_right_matches('/'), route.uri.path()))
+        };
+        Endpoints {
+            authorization: find(Method::Get, "/authorize"),
+            token: find(Method::Post, "/token"),
+            introspection: find(Method::Post, "/introspect"),
+            revocation: find(Method::Post, "/revoke"),
+            jwks: find(Method::Get, "/jwks"),
+            userinfo: find(Method::Get, "/userinfo"),
+            device_authorization: find(Method::Post, "/device/code"),
+            device_verification: find(Method::Get, "/device"),
+        }
+    }
+}
+
+/// Authorization server metadata document described in [RFC 8414](https://tools.ietf.org/html/rfc8414)
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize, Debug)]
+pub struct AuthorizationServerMetadata {
+    /// The issuer of tokens
+    pub issuer: String,
+    /// URL of the authorization endpoint, if the authorization code grant is enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authorization_endpoint: Option<String>,
+    /// URL of the token endpoint
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_endpoint: Option<String>,
+    /// URL of the JSON Web Key Set document
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jwks_uri: Option<String>,
+    /// URL of the introspection endpoint
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub introspection_endpoint: Option<String>,
+    /// URL of the revocation endpoint
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revocation_endpoint: Option<String>,
+    /// URL of the device authorization endpoint, if the device authorization grant is enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_authorization_endpoint: Option<String>,
+    /// Scopes that can be requested
+    pub scopes_supported: Vec<String>,
+    /// Response types of the authorization endpoint, which is `code` if the authorization code grant is enabled
+    pub response_types_supported: Vec<String>,
+    /// Grant types of the token endpoint
+    pub grant_types_supported: Vec<String>,
+    /// Methods clients can authenticate with at the token endpoint
+    pub token_endpoint_auth_methods_supported: Vec<String>,
+    /// Methods services can authenticate with at the introspection endpoint
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub introspection_endpoint_auth_methods_supported: Vec<String>,
+    /// PKCE code challenge methods of the authorization endpoint
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub code_challenge_methods_supported: Vec<String>,
+    /// Algorithms access tokens are signed with. This is not defined by RFC 8414.
+    pub access_token_signing_alg_values_supported: Vec<jwt::jwa::SignatureAlgorithm>,
+}
+
+impl AuthorizationServerMetadata {
+    /// Make the document for the configuration, the enabled `grants` and the mounted `endpoints`
+    pub fn new(configuration: &token::Configuration, grants: &Grants, endpoints: &Endpoints) -> Result<Self, ::Error> {
+        let client_authentication_methods = vec!["client_secret_basic".to_string(), "client_secret_post".to_string()];
+
+        let mut grant_types_supported = vec![PASSWORD_GRANT.to_string(), CLIENT_CREDENTIALS_GRANT.to_string()];
+        if configuration.refresh_token_enabled() {
+            grant_types_supported.push(REFRESH_TOKEN_GRANT.to_string());
+        }
+        let mut token_endpoint_auth_methods_supported = client_authentication_methods.clone();
+        let authorization_code = grants.authorization_code.is_some() && endpoints.authorization.is_some();
+        if authorization_code {
+            grant_types_supported.push(AUTHORIZATION_CODE_GRANT.to_string());
+        }
+        let device = grants.device.is_some() && endpoints.device_authorization.is_some() &&
+            endpoints.device_verification.is_some();
+        if device {
+            grant_types_supported.push(DEVICE_CODE_GRANT.to_string());
+        }
+        if grants.token_exchange.is_some() {
+            grant_types_supported.push(TOKEN_EXCHANGE_GRANT.to_string());
+        }
+        if authorization_code || device {
+            // Clients of the authorization code and device code grants are public
+            token_endpoint_auth_methods_supported.push("none".to_string());
+        }
+
+        let mut signing_algorithms = vec![configuration.signature_algorithm.unwrap_or_default()];
+        for service in configuration.services.keys() {
+            let algorithm = configuration.signature_algorithm_for(service).unwrap_or_default();
+            if !signing_algorithms.contains(&algorithm) {
+                signing_algorithms.push(algorithm);
+            }
+        }
+
+        Ok(AuthorizationServerMetadata {
+            issuer: configuration.issuer.to_string(),
+            authorization_endpoint: if authorization_code {
+                resolve(configuration, &endpoints.authorization)?
+            } else {
+                None
+            },
+            token_endpoint: resolve(configuration, &endpoints.token)?,
+            jwks_uri: resolve(configuration, &endpoints.jwks)?,
+            introspection_endpoint: resolve(configuration, &endpoints.introspection)?,
+            revocation_endpoint: resolve(configuration, &endpoints.revocation)?,
+            device_authorization_endpoint: if device {
+                resolve(configuration, &endpoints.device_authorization)?
+            } else {
+                None
+            },
+            // Scopes are not in use at the moment, other than the one requesting ID tokens
+            scopes_supported: vec!["all".to_string(), oidc::OPENID_SCOPE.to_string()],
+            response_types_supported: if authorization_code {
+                vec![authorization_code::CODE_RESPONSE_TYPE.to_string()]
+            } else {
+                vec![]
+            },
+            grant_types_supported,
+            token_endpoint_auth_methods_supported,
+            introspection_endpoint_auth_methods_supported: if endpoints.introspection.is_some() {
+                client_authentication_methods
+            } else {
+                vec![]
+            },
+            code_challenge_methods_supported: if authorization_code {
+                vec![authorization_code::S256_CHALLENGE_METHOD.to_string()]
+            } else {
+                vec![]
+            },
+            access_token_signing_alg_values_supported: signing_algorithms,
+        })
+    }
+}
+
+/// Resolve the path of an endpoint against the `issuer` in the configuration
+pub(crate) fn resolve(configuration: &token::Configuration, path: &Option<String>) -> Result<Option<String>, ::Error> {
+    let path = match *path {
+        Some(ref path) => path,
+        None => return Ok(None),
+    };
+    match configuration.issuer {
+        jwt::StringOrUri::Uri(ref issuer) => Ok(Some(issuer.join(path).map_err(|e| e.to_string())?.to_string())),
+        jwt::StringOrUri::String(_) => Ok(Some(path.to_string())),
+    }
+}
+
+/// Error codes of the token, authorization and device authorization endpoints
+#[derive(Clone, Copy, Eq, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The request is missing a parameter, or is otherwise malformed
+    InvalidRequest,
+    /// Client authentication failed
+    InvalidClient,
+    /// The credentials or refresh token are invalid or expired
+    InvalidGrant,
+    /// The client is not authorized to use the grant type
+    UnauthorizedClient,
+    /// The grant type is not supported
+    UnsupportedGrantType,
+    /// The response type of the authorization endpoint is not supported
+    UnsupportedResponseType,
+    /// The user or the authorization server denied the request of the authorization endpoint, or the user denied the
+    /// request of a device
+    AccessDenied,
+    /// The user has not approved or denied the request of the device yet
+    AuthorizationPending,
+    /// The device polls the token endpoint too often, or too many device codes are pending
+    SlowDown,
+    /// The device code has expired
+    ExpiredToken,
+    /// The scope requested is invalid
+    InvalidScope,
+    /// The service requested is invalid, or the subject is not allowed to request tokens for it.
+    /// See [RFC 8707](https://tools.ietf.org/html/rfc8707#section-2).
+    InvalidTarget,
+    /// An unexpected error occurred
+    ServerError,
+}
+
+/// Error response of the token endpoint
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize, Debug)]
+pub struct Error {
+    /// The error code
+    pub error: ErrorCode,
+    /// Human readable description of the error
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_description: Option<String>,
+    /// Seconds after which the request can be retried, if the request was throttled
+    #[serde(skip_serializing, skip_deserializing)]
+    pub retry_after: Option<u64>,
+}
+
+impl Error {
+    /// Make a new error with a description
+    pub fn new(error: ErrorCode, description: &str) -> Self {
+        Error {
+            error,
+            error_description: Some(description.to_string()),
+            retry_after: None,
+        }
+    }
+
+    /// Map an error from authenticating a client. Authentication failures are `invalid_client` rather than
+    /// `invalid_grant`.
+    pub fn from_client_error(error: ::Error) -> Self {
+        match error {
+            ::Error::Auth(auth::Error::AuthenticationFailure) => {
+                Error::new(ErrorCode::InvalidClient, "Client authentication failed")
+            }
+            error => From::from(error),
+        }
+    }
+
+    /// The HTTP status of the error
+    pub fn status(&self) -> Status {
+        match self.error {
+            _ if self.retry_after.is_some() => Status::TooManyRequests,
+            ErrorCode::InvalidClient => Status::Unauthorized,
+            ErrorCode::ServerError => Status::InternalServerError,
+            _ => Status::BadRequest,
+        }
+    }
+}
+
+impl From<::Error> for Error {
+    fn from(error: ::Error) -> Self {
+        let code = match error {
+            ::Error::Auth(auth::Error::AuthenticationFailure) |
+            ::Error::Auth(auth::Error::InvalidToken(_)) |
+            ::Error::Auth(auth::Error::TooManyAttempts { .. }) |
+            ::Error::UnsupportedOperation => ErrorCode::InvalidGrant,
+            ::Error::Auth(auth::Error::MissingAuthorization { .. }) => ErrorCode::InvalidClient,
+            ::Error::Auth(auth::Error::Forbidden) |
+            ::Error::Token(token::Error::InvalidService) => ErrorCode::InvalidTarget,
+            ::Error::Token(token::Error::InvalidAudience) |
+            ::Error::Token(token::Error::InvalidIssuer) |
+            ::Error::Token(token::Error::JWTError(_)) => ErrorCode::InvalidGrant,
+            ::Error::BadRequest(_) |
+            ::Error::Token(token::Error::RefreshTokenDisabled) |
+            ::Error::Token(token::Error::OfflineTokenNotAllowed) => ErrorCode::InvalidRequest,
+            _ => ErrorCode::ServerError,
+        };
+        let retry_after = match error {
+            ::Error::Auth(auth::Error::TooManyAttempts { retry_after }) => Some(retry_after),
+            _ => None,
+        };
+        let description = match code {
+            ErrorCode::ServerError => {
+                error_!("Token endpoint failed: {}", error);
+                "An unexpected error occurred".to_string()
+            }
+            ErrorCode::InvalidGrant if retry_after.is_none() => {
+                match error {
+                    ::Error::UnsupportedOperation => "Refresh tokens are not supported".to_string(),
+                    _ => "The credentials or refresh token are invalid".to_string(),
+                }
+            }
+            _ => error.message(),
+        };
+        Error {
+            error: code,
+            error_description: Some(description),
+            retry_after,
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match self.error_description {
+            Some(ref description) => description,
+            None => "OAuth 2.0 error",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}: {}", self.error, error::Error::description(self))
+    }
+}
+
+impl<'r> Responder<'r> for Error {
+    fn respond_to(self, request: &Request) -> Result<Response<'r>, Status> {
+        let body = match serde_json::to_string(&self) {
+            Ok(body) => body,
+            Err(e) => return ::Error::JsonError(e).respond_to(request),
+        };
+        let mut response = Response::build();
+        let _ = response
+            .status(self.status())
+            .header(ContentType::JSON)
+            .header(Header::new("Cache-Control", "no-store"))
+            .header(Header::new("Pragma", "no-cache"))
+            .sized_body(Cursor::new(body));
+        if let Some(retry_after) = self.retry_after {
+            let _ = response.header(Header::new("Retry-After", retry_after.to_string()));
+        }
+        if self.error == ErrorCode::InvalidClient && request.headers().contains("Authorization") {
+            let _ = response.header(Header::new("WWW-Authenticate", "Basic"));
+        }
+        response.ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::{self, Utc};
+    use jwt::{jwa, jws};
+
+    use super::*;
+
+    /// Sign a token for `audience` with `secret`, in the same way as a service holding the secret can
+    fn sign_token(audience: &str, secret: &str) -> String {
+        let now = Utc::now();
+        let header = jws::Header::from_registered_header(jws::RegisteredHeader {
+            algorithm: jwa::SignatureAlgorithm::HS256,
+            ..Default::default()
+        });
+        let claims_set = jwt::ClaimsSet {
+            registered: jwt::RegisteredClaims {
+                issuer: Some(not_err!(FromStr::from_str("https://www.acme.com"))),
+                subject: Some(not_err!(FromStr::from_str("admin"))),
+                audience: Some(jwt::SingleOrMultiple::Single(not_err!(FromStr::from_str(audience)))),
+                issued_at: Some(now.into()),
+                expiry: Some((now + chrono::Duration::minutes(5)).into()),
+                ..Default::default()
+            },
+            private: JsonValue::Object(Default::default()),
+        };
+        let token = jwt::JWT::new_decoded(header, claims_set);
+        let token = not_err!(token.into_encoded(&jws::Secret::Bytes(secret.as_bytes().to_vec())));
+        not_err!(token.encoded()).to_string()
+    }
+
+    #[test]
+    fn service_keys_only_verify_tokens_for_their_service() {
+        let configuration: token::Configuration = not_err!(serde_json::from_str(
+            r#"{
+                "issuer": "https://www.acme.com",
+                "audience": ["https://www.example.com", "https://legacy.example.com"],
+                "signature_algorithm": "HS256",
+                "secret": "secret",
+                "services": {
+                    "https://legacy.example.com": { "signature_algorithm": "HS256", "secret": "shared" }
+                }
+            }"#,
+        ));
+        let keys = not_err!(configuration.keys());
+
+        let legacy_token = sign_token("https://legacy.example.com", "shared");
+        assert!(IntrospectionResponse::new(&legacy_token, &configuration, &keys).active);
+        let token = sign_token("https://www.example.com", "secret");
+        assert!(IntrospectionResponse::new(&token, &configuration, &keys).active);
+
+        // The legacy service cannot sign tokens for other services with the shared secret
+        let forged_token = sign_token("https://www.example.com", "shared");
+        assert!(!IntrospectionResponse::new(&forged_token, &configuration, &keys).active);
+        // Tokens of the legacy service are only signed with its own key
+        let token = sign_token("https://legacy.example.com", "secret");
+        assert!(!IntrospectionResponse::new(&token, &configuration, &keys).active);
+    }
+
+    #[test]
+    fn errors_are_serialized() {
+        let error = Error::new(ErrorCode::UnsupportedGrantType, "Unsupported grant type `implicit`");
+        let serialized = not_err!(serde_json::to_string(&error));
+        assert_eq!(
+            serialized,
+            r#"{"error":"unsupported_grant_type","error_description":"Unsupported grant type `implicit`"}"#
+        );
+        assert_eq!(error.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn errors_are_mapped() {
+        let error: Error = ::Error::Auth(auth::Error::AuthenticationFailure).into();
+        assert_eq!(error.error, ErrorCode::InvalidGrant);
+        assert_eq!(error.status(), Status::BadRequest);
+
+        let error: Error = ::Error::Auth(auth::Error::Forbidden).into();
+        assert_eq!(error.error, ErrorCode::InvalidTarget);
+
+        let error: Error = ::Error::Token(token::Error::InvalidService).into();
+        assert_eq!(error.error, ErrorCode::InvalidTarget);
+
+        let error: Error = ::Error::Auth(auth::Error::TooManyAttempts { retry_after: 10 }).into();
+        assert_eq!(error.error, ErrorCode::InvalidGrant);
+        assert_eq!(error.retry_after, Some(10));
+        assert_eq!(error.status(), Status::TooManyRequests);
+
+        let error: Error = ::Error::BadRequest("Missing".to_string()).into();
+        assert_eq!(error.error, ErrorCode::InvalidRequest);
+        assert_eq!(error.error_description, Some("Missing".to_string()));
+
+        let error: Error = ::Error::KeyLoadingError("Key file is missing".to_string()).into();
+        assert_eq!(error.error, ErrorCode::ServerError);
+        assert_eq!(error.status(), Status::InternalServerError);
+        assert_eq!(error.error_description, Some("An unexpected error occurred".to_string()));
+    }
+
+    #[test]
+    fn client_credentials_are_read_from_one_method() {
+        let authorization = auth::Authorization(::hyper::header::Authorization(auth::Basic {
+            username: "registry-ci".to_string(),
+            password: Some("password".to_string()),
+        }));
+        let request = TokenRequest {
+            grant_type: CLIENT_CREDENTIALS_GRANT.to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            not_err!(request.client_credentials(Some(&authorization))),
+            ("registry-ci".to_string(), "password".to_string())
+        );
+        assert_eq!(
+            request.client_credentials(None).unwrap_err().error,
+            ErrorCode::InvalidClient
+        );
+
+        let request = TokenRequest {
+            grant_type: CLIENT_CREDENTIALS_GRANT.to_string(),
+            client_id: Some("registry-ci".to_string()),
+            client_secret: Some("password".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            not_err!(request.client_credentials(None)),
+            ("registry-ci".to_string(), "password".to_string())
+        );
+        assert_eq!(
+            request.client_credentials(Some(&authorization)).unwrap_err().error,
+            ErrorCode::InvalidRequest
+        );
+    }
+
+    #[test]
+    fn inactive_introspection_responses_have_no_claims() {
+        let response = IntrospectionResponse::inactive();
+        let serialized = not_err!(serde_json::to_string(&not_err!(response.to_json())));
+        assert_eq!(serialized, r#"{"active":false}"#);
+    }
+
+    #[test]
+    fn private_claims_do_not_override_registered_claims() {
+        let response = IntrospectionResponse {
+            active: true,
+            token_type: Some(ACCESS_TOKEN_TYPE.to_string()),
+            registered: Some(jwt::RegisteredClaims {
+                subject: Some(not_err!(::std::str::FromStr::from_str("mei"))),
+                ..Default::default()
+            }),
+            private: Some(not_err!(serde_json::from_str(r#"{"sub": "admin", "active": false, "team": "ops"}"#))),
+        };
+        let json = not_err!(response.to_json());
+        assert_eq!(json["active"], true);
+        assert_eq!(json["token_type"], "access_token");
+        assert_eq!(json["sub"], "mei");
+        assert_eq!(json["team"], "ops");
+    }
+}
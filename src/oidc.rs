@@ -0,0 +1,301 @@
+//! OpenID Connect ID tokens, user info and discovery
+//!
+//! When the `openid` scope is requested with a `client_id`, the token response includes an `id_token` alongside the
+//! access token as described in
+//! [OpenID Connect Core 1.0](https://openid.net/specs/openid-connect-core-1_0.html#IDToken). The ID token is intended
+//! for the client, whose client ID is its `aud`, and is signed with the key and algorithm of the service the access
+//! token was requested for. It has the standard claims of the user, `auth_time` if the user authenticated with
+//! credentials, and the `nonce` parameter of the request, if any. Anonymous tokens do not come with ID tokens.
+//!
+//! ID tokens are only issued to clients registered for the authorization code or device authorization grants. A
+//! client ID that is also an audience of the token configuration gets no ID token, so that an ID token cannot be
+//! passed off as an access token to a service that only checks the `aud` claim.
+//!
+//! ID tokens have the `token_use` claim set to `id`. They are not access tokens, so they are rejected by
+//! introspection, the user info endpoint, the token exchange grant and the
+//! [`VerifiedToken`](../verifier/struct.VerifiedToken.html) request guard.
+//!
+//! Standard claims are read from the private claims returned by the authenticator. A claim is read from the private
+//! claim of the same name, unless a [JSON pointer](https://tools.ietf.org/html/rfc6901) into the private claims is
+//! configured for it. Arrays with a single value, such as LDAP attributes, are unwrapped, except for `groups`.
+//!
+//! ```json
+//! {
+//!     "claims": {
+//!         "name": "/cn",
+//!         "email": "/mail",
+//!         "groups": "/memberOf"
+//!     }
+//! }
+//! ```
+//!
+//! The standard claims of the subject of an access token are served at `GET /userinfo`, and the
+//! [discovery document](https://openid.net/specs/openid-connect-discovery-1_0.html) is served at
+//! `GET /.well-known/openid-configuration`.
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use {JsonMap, JsonValue};
+use jwt;
+#[cfg(feature = "server")]
+use oauth;
+use token;
+
+/// Scope that requests an ID token
+pub const OPENID_SCOPE: &'static str = "openid";
+
+/// Claim that marks ID tokens, so that they are not accepted as access tokens
+pub const TOKEN_USE_CLAIM: &'static str = "token_use";
+
+/// Value of the [`TOKEN_USE_CLAIM`](constant.TOKEN_USE_CLAIM.html) of ID tokens
+pub const ID_TOKEN_USE: &'static str = "id";
+
+/// Standard claims read from the private claims of the authenticator
+pub const STANDARD_CLAIMS: &'static [&'static str] = &[
+    "name",
+    "given_name",
+    "family_name",
+    "preferred_username",
+    "email",
+    "email_verified",
+    "groups",
+];
+
+/// Returns whether `scope` requests an ID token
+pub fn requested(scope: &str) -> bool {
+    scope.split(' ').any(|scope| scope == OPENID_SCOPE)
+}
+
+/// Returns whether a token with the `private_claims` is an ID token, which must not be accepted as an access token
+pub fn is_id_token(private_claims: &JsonValue) -> bool {
+    private_claims.get(TOKEN_USE_CLAIM).and_then(JsonValue::as_str) == Some(ID_TOKEN_USE)
+}
+
+/// Settings of ID tokens and the user info endpoint
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct Configuration {
+    /// Expiry duration of ID tokens, in seconds. Defaults to the expiry duration of the access tokens of the service.
+    #[serde(with = "::serde_custom::option_duration", default, skip_serializing_if = "Option::is_none")]
+    pub expiry_duration: Option<Duration>,
+    /// JSON pointers into the private claims of the authenticator, keyed by the claim they are read into. Claims
+    /// that are not standard can be added too.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub claims: HashMap<String, String>,
+}
+
+impl Configuration {
+    /// Map `private_claims` into the standard claims. Claims that are missing or `null` are left out.
+    pub fn standard_claims(&self, private_claims: &JsonValue) -> JsonMap<String, JsonValue> {
+        let extra_claims = self.claims.keys().map(|claim| claim.as_str()).filter(
+            |claim| !STANDARD_CLAIMS.contains(claim),
+        );
+        let mut claims = JsonMap::new();
+        for claim in STANDARD_CLAIMS.iter().cloned().chain(extra_claims) {
+            let pointer = match self.claims.get(claim) {
+                Some(pointer) => pointer.to_string(),
+                None => format!("/{}", claim),
+            };
+            let value = match private_claims.pointer(&pointer) {
+                None | Some(&JsonValue::Null) => continue,
+                Some(value) => value.clone(),
+            };
+            let value = match value {
+                JsonValue::Array(mut values) => {
+                    if claim != "groups" && values.len() == 1 {
+                        values.remove(0)
+                    } else {
+                        JsonValue::Array(values)
+                    }
+                }
+                value => {
+                    if claim == "groups" {
+                        JsonValue::Array(vec![value])
+                    } else {
+                        value
+                    }
+                }
+            };
+            let _ = claims.insert(claim.to_string(), value);
+        }
+        claims
+    }
+
+    /// The user info of `subject`, which are the standard claims of `private_claims` and `sub`
+    pub fn user_info(&self, subject: &str, private_claims: &JsonValue) -> JsonValue {
+        let mut user_info = self.standard_claims(private_claims);
+        let _ = user_info.insert("sub".to_string(), From::from(subject));
+        JsonValue::Object(user_info)
+    }
+}
+
+/// Make and sign an ID token for `subject` intended for the client `client_id`, with the standard claims of
+/// `private_claims`. The token is signed with the key of `service`, which the access token was requested for.
+/// `auth_time` is the time the user authenticated with credentials, if they did.
+#[cfg_attr(feature = "clippy_lints", allow(too_many_arguments))]
+pub fn id_token(
+    configuration: &token::Configuration,
+    keys: &token::Keys,
+    subject: &str,
+    service: &str,
+    client_id: &str,
+    private_claims: &JsonValue,
+    auth_time: Option<DateTime<Utc>>,
+    nonce: Option<&str>,
+) -> Result<String, ::Error> {
+    let mut claims = configuration.openid.standard_claims(private_claims);
+    if let Some(auth_time) = auth_time {
+        let _ = claims.insert("auth_time".to_string(), From::from(auth_time.timestamp()));
+    }
+    if let Some(nonce) = nonce {
+        let _ = claims.insert("nonce".to_string(), From::from(nonce));
+    }
+    let _ = claims.insert(TOKEN_USE_CLAIM.to_string(), From::from(ID_TOKEN_USE));
+    let expiry_duration = match configuration.openid.expiry_duration {
+        Some(expiry_duration) => expiry_duration,
+        None => configuration.expiry_duration_for(service),
+    };
+    let audience = jwt::SingleOrMultiple::Single(FromStr::from_str(client_id).map_err(token::Error::JWTError)?);
+
+    let id_token = token::make_token(
+        subject,
+        &configuration.issuer,
+        &audience,
+        expiry_duration,
+        JsonValue::Object(claims),
+        configuration.signature_algorithm_for(service),
+        Utc::now(),
+    )?;
+    let id_token = id_token.into_encoded(keys.signing_for(service)).map_err(token::Error::JWTError)?;
+    Ok(id_token.encoded().map_err(token::Error::JWTError)?.to_string())
+}
+
+/// OpenID Provider metadata described in
+/// [OpenID Connect Discovery 1.0](https://openid.net/specs/openid-connect-discovery-1_0.html#ProviderMetadata).
+/// Requires the `server` feature.
+#[cfg(feature = "server")]
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize, Debug)]
+pub struct ProviderMetadata {
+    /// The issuer of tokens
+    pub issuer: String,
+    /// URL of the authorization endpoint, if the authorization code grant is enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authorization_endpoint: Option<String>,
+    /// URL of the token endpoint
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_endpoint: Option<String>,
+    /// URL of the user info endpoint
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub userinfo_endpoint: Option<String>,
+    /// URL of the JSON Web Key Set document
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jwks_uri: Option<String>,
+    /// Scopes that can be requested
+    pub scopes_supported: Vec<String>,
+    /// Response types of the authorization endpoint
+    pub response_types_supported: Vec<String>,
+    /// Grant types of the token endpoint
+    pub grant_types_supported: Vec<String>,
+    /// Subject identifier types, which is only `public`
+    pub subject_types_supported: Vec<String>,
+    /// Algorithms ID tokens are signed with
+    pub id_token_signing_alg_values_supported: Vec<jwt::jwa::SignatureAlgorithm>,
+    /// Methods clients can authenticate with at the token endpoint
+    pub token_endpoint_auth_methods_supported: Vec<String>,
+    /// Claims that can be included in ID tokens and user info
+    pub claims_supported: Vec<String>,
+}
+
+#[cfg(feature = "server")]
+impl ProviderMetadata {
+    /// Make the document for the configuration, the enabled `grants` and the mounted `endpoints`
+    pub fn new(
+        configuration: &token::Configuration,
+        grants: &oauth::Grants,
+        endpoints: &oauth::Endpoints,
+    ) -> Result<Self, ::Error> {
+        let metadata = oauth::AuthorizationServerMetadata::new(configuration, grants, endpoints)?;
+
+        let mut claims_supported: Vec<String> = ["iss", "sub", "aud", "exp", "iat", "auth_time", "nonce"]
+            .iter()
+            .chain(STANDARD_CLAIMS)
+            .map(|claim| claim.to_string())
+            .collect();
+        for claim in configuration.openid.claims.keys() {
+            if !claims_supported.contains(claim) {
+                claims_supported.push(claim.to_string());
+            }
+        }
+
+        Ok(ProviderMetadata {
+            issuer: metadata.issuer,
+            authorization_endpoint: metadata.authorization_endpoint,
+            token_endpoint: metadata.token_endpoint,
+            userinfo_endpoint: oauth::resolve(configuration, &endpoints.userinfo)?,
+            jwks_uri: metadata.jwks_uri,
+            scopes_supported: metadata.scopes_supported,
+            response_types_supported: metadata.response_types_supported,
+            grant_types_supported: metadata.grant_types_supported,
+            subject_types_supported: vec!["public".to_string()],
+            id_token_signing_alg_values_supported: metadata.access_token_signing_alg_values_supported,
+            token_endpoint_auth_methods_supported: metadata.token_endpoint_auth_methods_supported,
+            claims_supported,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use super::*;
+
+    fn ldap_claims() -> JsonValue {
+        not_err!(serde_json::from_str(
+            r#"{
+                "cn": ["Mei"],
+                "mail": ["mei@example.com"],
+                "memberOf": "admins",
+                "email_verified": true,
+                "preferred_username": null
+            }"#,
+        ))
+    }
+
+    #[test]
+    fn openid_scope_is_requested() {
+        assert!(requested("openid"));
+        assert!(requested("repository:library/ubuntu:pull openid"));
+        assert!(!requested("openid_connect"));
+        assert!(!requested(""));
+    }
+
+    #[test]
+    fn standard_claims_are_mapped() {
+        let configuration: Configuration = not_err!(serde_json::from_str(
+            r#"{
+                "claims": { "name": "/cn", "email": "/mail", "groups": "/memberOf", "department": "/ou" }
+            }"#,
+        ));
+        let claims = configuration.standard_claims(&ldap_claims());
+
+        assert_eq!(claims["name"], From::from("Mei"));
+        assert_eq!(claims["email"], From::from("mei@example.com"));
+        assert_eq!(claims["groups"], JsonValue::Array(vec![From::from("admins")]));
+        assert_eq!(claims["email_verified"], From::from(true));
+        assert!(!claims.contains_key("preferred_username"));
+        assert!(!claims.contains_key("department"));
+    }
+
+    #[test]
+    fn user_info_has_the_subject() {
+        let configuration = Configuration::default();
+        let user_info = configuration.user_info("mei", &ldap_claims());
+
+        assert_eq!(user_info["sub"], From::from("mei"));
+        assert_eq!(user_info["email_verified"], From::from(true));
+        assert!(user_info.get("name").is_none());
+    }
+}
@@ -3,17 +3,41 @@
 // mounted via `::launch()`
 #![allow(unmounted_route)]
 
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
 use hyper;
 use rocket::{State, Route};
+use rocket::http::Status;
+use rocket::request::LenientForm;
+use rocket::response::{content, status};
+use serde_json;
 
+use {JsonMap, JsonValue};
+use audit::{self, Auditor, Event, EventKind, UserAgent};
 use auth;
-use token::{Token, PrivateClaim, Configuration, RefreshToken, Keys};
+use authorization_code::{self, AuthorizationCodes, AuthorizationRequest, AuthorizationResponse, VerifiedRequest};
+use device::{self, DeviceAuthorizationRequest, DeviceAuthorizationResponse, DeviceAuthorizations, VerificationRequest};
+use jwks::JsonWebKeySet;
+use jwt;
+use metrics::Metrics;
+use oauth;
+use oidc;
+use throttle::{Client, Throttle};
+use token::{self, Token, PrivateClaim, Configuration, RefreshToken, Keys};
+use token_exchange;
 
 #[derive(FromForm, Default, Clone, Debug)]
 struct AuthParam {
     service: String,
     scope: String,
     offline_token: Option<bool>,
+    /// Value to include in the ID token, if the `openid` scope is requested
+    nonce: Option<String>,
+    /// The client that requests the token, which is the audience of the ID token. ID tokens are only issued to
+    /// clients that send their client ID.
+    client_id: Option<String>,
 }
 
 impl AuthParam {
@@ -24,109 +48,1040 @@ impl AuthParam {
         authorization: &auth::Authorization<S>,
     ) -> Result<(), ::Error> {
         if authorization.is_bearer() && self.offline_token.is_some() {
-            Err(::Error::BadRequest(
-                "Offline token cannot be requested for when authenticating with a refresh token"
-                    .to_string(),
-            ))?
+            Err(token::Error::OfflineTokenNotAllowed)?
         }
         Ok(())
     }
+
+    /// Respond to a request without the `Authorization` header with a challenge for the service and scope
+    fn missing_authorization<T>(&self, configuration: &Configuration) -> Result<T, ::Error> {
+        Err(auth::Error::MissingAuthorization {
+            realm: configuration.realm(),
+            service: Some(self.service.clone()),
+            scope: Some(self.scope.clone()),
+        })?
+    }
+}
+
+/// Refresh tokens that cannot be decrypted or are not valid are challenged as invalid bearer tokens, but services that
+/// are not configured are still forbidden. The challenge only describes the error with the message that is safe to
+/// send to clients.
+fn invalid_refresh_token(error: token::Error) -> ::Error {
+    match error {
+        token::Error::InvalidService => From::from(error),
+        error => {
+            debug_!("Refresh token is invalid: {}", error);
+            From::from(auth::Error::InvalidToken(::Error::from(error).message()))
+        }
+    }
+}
+
+/// The authenticator of the client ID and secret of services at the routes for services, which is the authenticator of
+/// API keys. The credentials of users are not accepted, so these routes are unavailable if it is not configured.
+struct ClientAuthenticator<'a> {
+    authenticator: &'a auth::StringAuthenticator,
+}
+
+impl<'a> ClientAuthenticator<'a> {
+    /// Returns the authenticator of API keys, or an `invalid_client` error if it is not configured
+    fn new(authenticator: &'a Option<Box<auth::StringAuthenticator>>) -> Result<Self, oauth::Error> {
+        match *authenticator {
+            Some(ref authenticator) => Ok(ClientAuthenticator { authenticator: &**authenticator }),
+            None => Err(oauth::Error::new(
+                oauth::ErrorCode::InvalidClient,
+                "Services cannot authenticate because no API key authenticator is configured",
+            )),
+        }
+    }
+
+    /// Authenticate a service with its client ID and secret. Attempts are throttled by the client ID.
+    fn authenticate(
+        &self,
+        throttle: &Throttle,
+        client: &Client,
+        client_id: &str,
+        client_secret: &str,
+    ) -> Result<auth::AuthenticationResult, oauth::Error> {
+        let authorization = auth::Authorization(hyper::header::Authorization(
+            format!("{}:{}", client_id, client_secret),
+        ));
+        throttle
+            .attempt(client, Some(client_id), || {
+                self.authenticator.prepare_authentication_response(&authorization, false)
+            })
+            .map_err(oauth::Error::from_client_error)
+    }
+
+    /// Whether the authenticated service is allowed to request tokens for `service`
+    fn allows(&self, result: &auth::AuthenticationResult, service: &str) -> bool {
+        self.authenticator.verify_service(result, service).is_ok()
+    }
+}
+
+/// The state and request guards needed to issue tokens, shared by the routes that issue tokens
+struct Issuer<'a> {
+    client: &'a Client,
+    user_agent: &'a UserAgent,
+    configuration: &'a Configuration,
+    grants: &'a oauth::Grants,
+    keys: &'a Keys,
+    throttle: &'a Throttle,
+    auditor: &'a Auditor,
+    metrics: &'a Metrics,
+}
+
+impl<'a> Issuer<'a> {
+    /// Authenticate with `authenticator` and issue an access token for the service in `auth_param`. The one time
+    /// password sent separately from the credentials, if any, is passed to the authenticator.
+    /// Attempts are throttled by `username`, and recorded in the audit log and in the metrics under
+    /// `authenticator_name`.
+    #[cfg_attr(feature = "clippy_lints", allow(too_many_arguments))] // Internal function
+    fn login<S: hyper::header::Scheme + 'static>(
+        &self,
+        authenticator: &auth::Authenticator<S>,
+        authenticator_name: &'static str,
+        authorization: &auth::Authorization<S>,
+        one_time_password: Option<&str>,
+        username: Option<&str>,
+        auth_param: &AuthParam,
+    ) -> Result<Token<PrivateClaim>, ::Error> {
+        let event = Event::new(
+            EventKind::Login,
+            self.client,
+            self.user_agent,
+            &auth_param.service,
+            &auth_param.scope,
+        );
+        let token = self.auditor.audit(event, |event| {
+            auth_param.verify(authorization)?;
+            event.username = username.map(|username| username.to_string());
+            let result = self.throttle.attempt(self.client, username, || {
+                self.metrics.time_authentication(authenticator_name, || {
+                    let offline_token = auth_param.offline_token.unwrap_or(false);
+                    match one_time_password {
+                        Some(one_time_password) => {
+                            authenticator.prepare_one_time_password_response(
+                                authorization,
+                                one_time_password,
+                                offline_token,
+                            )
+                        }
+                        None => authenticator.prepare_authentication_response(authorization, offline_token),
+                    }
+                })
+            })?;
+            authenticator.verify_service(&result, &auth_param.service)?;
+            self.issue_token(&result, auth_param, Some(Utc::now()), event)
+        });
+        self.metrics.record(
+            EventKind::Login,
+            authenticator_name,
+            &auth_param.service,
+            &token,
+        );
+        token
+    }
+
+    /// Decrypt and verify the encrypted refresh token in `authorization`, and issue a new access token for the
+    /// service in `auth_param`
+    fn refresh(
+        &self,
+        authenticator: &auth::BasicAuthenticator,
+        authorization: &auth::Authorization<auth::Bearer>,
+        auth_param: &AuthParam,
+    ) -> Result<Token<PrivateClaim>, ::Error> {
+        let event = Event::new(
+            EventKind::Refresh,
+            self.client,
+            self.user_agent,
+            &auth_param.service,
+            &auth_param.scope,
+        );
+        let token = self.auditor.audit(event, |event| {
+            let configuration = self.configuration;
+            if !configuration.refresh_token_enabled() {
+                Err(token::Error::RefreshTokenDisabled)?
+            }
+            let refresh_token_configuration = configuration.refresh_token();
+
+            auth_param.verify(authorization)?;
+            let refresh_token = RefreshToken::new_encrypted(&authorization.token());
+            let refresh_token = refresh_token.decrypt(
+                &self.keys.signature_verification,
+                self.keys.decryption.as_ref().expect(
+                    "Refresh token was enabled but decryption key is missing",
+                ),
+                configuration
+                    .signature_algorithm
+                    .unwrap_or_default(),
+                refresh_token_configuration.cek_algorithm,
+                refresh_token_configuration.enc_algorithm,
+            ).map_err(invalid_refresh_token)?;
+
+            refresh_token
+                .validate(&auth_param.service, configuration, None)
+                .map_err(invalid_refresh_token)?;
+
+            let result = self.metrics.time_authentication("basic", || {
+                authenticator.prepare_refresh_response(refresh_token.payload()?)
+            })?;
+            authenticator.verify_service(&result, &auth_param.service)?;
+            self.issue_token(&result, auth_param, None, event)
+        });
+        self.metrics.record(
+            EventKind::Refresh,
+            "basic",
+            &auth_param.service,
+            &token,
+        );
+        token
+    }
+
+    /// Issue an anonymous access token for the service in `auth_param` if every action of the scope requested is
+    /// granted to anonymous users, or challenge the client for credentials otherwise
+    fn anonymous(
+        &self,
+        anonymous: &token::AnonymousConfiguration,
+        auth_param: &AuthParam,
+    ) -> Result<Token<PrivateClaim>, ::Error> {
+        let access = match anonymous.grant(&auth_param.service, &auth_param.scope) {
+            Some(access) => access,
+            None => return auth_param.missing_authorization(self.configuration),
+        };
+
+        let event = Event::new(
+            EventKind::Anonymous,
+            self.client,
+            self.user_agent,
+            &auth_param.service,
+            &auth_param.scope,
+        );
+        let token = self.auditor.audit(event, |event| {
+            let mut private_claims = JsonMap::with_capacity(1);
+            let _ = private_claims.insert("access".to_string(), serde_json::to_value(&access)?);
+            let result = auth::AuthenticationResult {
+                subject: anonymous.subject.clone(),
+                private_claims: JsonValue::Object(private_claims),
+                refresh_payload: None,
+            };
+            self.issue_token(&result, auth_param, None, event)
+        });
+        self.metrics.record(
+            EventKind::Anonymous,
+            "anonymous",
+            &auth_param.service,
+            &token,
+        );
+        token
+    }
+
+    /// Authenticate the user of the login form with `authenticator` and issue an authorization code for the verified
+    /// `request`. Attempts are throttled by `username`, and recorded in the audit log and in the metrics.
+    #[cfg_attr(feature = "clippy_lints", allow(too_many_arguments))] // Internal function
+    fn authorize(
+        &self,
+        authenticator: &auth::BasicAuthenticator,
+        codes: &AuthorizationCodes,
+        request: &AuthorizationRequest,
+        verified: &VerifiedRequest,
+        username: &str,
+        password: &str,
+    ) -> Result<String, ::Error> {
+        let scope = request.scope.clone().unwrap_or_default();
+        let event = Event::new(
+            EventKind::Authorize,
+            self.client,
+            self.user_agent,
+            &verified.service,
+            &scope,
+        );
+        let code = self.auditor.audit(event, |event| {
+            event.username = Some(username.to_string());
+            let authorization = auth::Authorization(hyper::header::Authorization(auth::Basic {
+                username: username.to_string(),
+                password: Some(password.to_string()),
+            }));
+            let result = self.throttle.attempt(self.client, Some(username), || {
+                self.metrics.time_authentication("basic", || {
+                    let offline_token = self.configuration.refresh_token_enabled();
+                    authenticator.prepare_authentication_response(&authorization, offline_token)
+                })
+            })?;
+            authenticator.verify_service(&result, &verified.service)?;
+            event.subject = Some(result.subject.clone());
+
+            let grant = authorization_code::Grant {
+                client_id: verified.client_id.clone(),
+                redirect_uri: request.redirect_uri.clone(),
+                code_challenge: verified.code_challenge.clone(),
+                service: verified.service.clone(),
+                scope: scope.clone(),
+                nonce: request.nonce.clone(),
+                username: username.to_string(),
+                auth_time: Utc::now(),
+                result: result,
+            };
+            codes.issue(grant, verified.expiry_duration)
+        });
+        self.metrics.record(
+            EventKind::Authorize,
+            "basic",
+            &verified.service,
+            &code,
+        );
+        code
+    }
+
+    /// Authenticate the user of the device verification page with `authenticator`, then approve the pending request of
+    /// the user code, or deny it unless `approve`. Attempts are throttled by `username`, and recorded in the audit log
+    /// and in the metrics.
+    #[cfg_attr(feature = "clippy_lints", allow(too_many_arguments))] // Internal function
+    fn decide_device(
+        &self,
+        authenticator: &auth::BasicAuthenticator,
+        authorizations: &DeviceAuthorizations,
+        user_code: &str,
+        request: &device::DeviceRequest,
+        username: &str,
+        password: &str,
+        approve: bool,
+    ) -> Result<bool, ::Error> {
+        let event = Event::new(
+            EventKind::ApproveDevice,
+            self.client,
+            self.user_agent,
+            &request.service,
+            &request.scope,
+        );
+        let decided = self.auditor.audit(event, |event| {
+            event.username = Some(username.to_string());
+            let authorization = auth::Authorization(hyper::header::Authorization(auth::Basic {
+                username: username.to_string(),
+                password: Some(password.to_string()),
+            }));
+            let result = self.throttle.attempt(self.client, Some(username), || {
+                self.metrics.time_authentication("basic", || {
+                    let offline_token = self.configuration.refresh_token_enabled();
+                    authenticator.prepare_authentication_response(&authorization, offline_token)
+                })
+            })?;
+            authenticator.verify_service(&result, &request.service)?;
+            event.subject = Some(result.subject.clone());
+            if !approve {
+                return authorizations.decide(user_code, None);
+            }
+
+            let approval = device::Approval {
+                username: username.to_string(),
+                auth_time: Utc::now(),
+                result: result,
+            };
+            authorizations.decide(user_code, Some(approval))
+        });
+        self.metrics.record(
+            EventKind::ApproveDevice,
+            "basic",
+            &request.service,
+            &decided,
+        );
+        decided
+    }
+
+    /// Issue an access token for a user who authenticated earlier at `auth_time`, when the authorization or device
+    /// code of the grant of `kind` is exchanged
+    fn exchange(
+        &self,
+        kind: EventKind,
+        auth_param: &AuthParam,
+        username: &str,
+        result: &auth::AuthenticationResult,
+        auth_time: DateTime<Utc>,
+    ) -> Result<Token<PrivateClaim>, ::Error> {
+        let event = Event::new(
+            kind,
+            self.client,
+            self.user_agent,
+            &auth_param.service,
+            &auth_param.scope,
+        );
+        let token = self.auditor.audit(event, |event| {
+            event.username = Some(username.to_string());
+            self.issue_token(result, auth_param, Some(auth_time), event)
+        });
+        self.metrics.record(kind, "basic", &auth_param.service, &token);
+        token
+    }
+
+    /// Issue an access token for the verified token `exchange` requested by the service `client_id`
+    fn exchange_token(
+        &self,
+        exchange: &token_exchange::Exchange,
+        client_id: &str,
+    ) -> Result<Token<PrivateClaim>, ::Error> {
+        let auth_param = AuthParam {
+            service: exchange.service.clone(),
+            scope: exchange.scope.clone(),
+            offline_token: None,
+            nonce: None,
+            client_id: None,
+        };
+        let event = Event::new(
+            EventKind::TokenExchange,
+            self.client,
+            self.user_agent,
+            &auth_param.service,
+            &auth_param.scope,
+        );
+        let token = self.auditor.audit(event, |event| {
+            event.username = Some(client_id.to_string());
+            let token = Token::<PrivateClaim>::with_configuration_until(
+                self.configuration,
+                &exchange.subject,
+                &exchange.service,
+                exchange.private_claims.clone(),
+                exchange.expires_at,
+            )?;
+            audit_token(event, &token, &auth_param)?;
+            Ok(token.encode(self.keys.signing_for(&exchange.service))?)
+        });
+        self.metrics.record(
+            EventKind::TokenExchange,
+            "api_key",
+            &auth_param.service,
+            &token,
+        );
+        token
+    }
+
+    /// Make and encode an access token for a successful authentication, with the refresh token if there is one, and
+    /// an ID token if the `openid` scope is requested for a registered client by a user who is not anonymous.
+    /// `auth_time` is the time the user authenticated with credentials, if they did.
+    fn issue_token(
+        &self,
+        result: &auth::AuthenticationResult,
+        auth_param: &AuthParam,
+        auth_time: Option<DateTime<Utc>>,
+        event: &mut audit::Event,
+    ) -> Result<Token<PrivateClaim>, ::Error> {
+        let mut token = Token::<PrivateClaim>::with_configuration(
+            self.configuration,
+            &result.subject,
+            &auth_param.service,
+            result.private_claims.clone(),
+            result.refresh_payload.as_ref(),
+        )?;
+        if let Some(ref client_id) = auth_param.client_id {
+            if oidc::requested(&auth_param.scope) && event.event != EventKind::Anonymous &&
+                self.grants.issues_id_tokens_for(self.configuration, client_id)
+            {
+                let id_token = oidc::id_token(
+                    self.configuration,
+                    self.keys,
+                    &result.subject,
+                    &auth_param.service,
+                    client_id,
+                    token.private_claims()?,
+                    auth_time,
+                    auth_param.nonce.as_ref().map(|nonce| nonce.as_str()),
+                )?;
+                token.id_token = Some(id_token);
+            }
+        }
+        audit_token(event, &token, auth_param)?;
+        let token = token.encode(self.keys.signing_for(&auth_param.service))?;
+
+        let token = if self.configuration.refresh_token_enabled() && token.has_refresh_token() {
+            let refresh_token_key = self.keys.encryption.as_ref().expect(
+                "Refresh token was enabled but encryption key is missing",
+            );
+            token.encrypt_refresh_token(&self.keys.signing, refresh_token_key)?
+        } else {
+            token
+        };
+
+        Ok(token)
+    }
 }
 
 /// Access token retrieval via initial authentication route
 #[get("/?<auth_param>", rank = 1)]
+#[cfg_attr(feature = "clippy_lints", allow(too_many_arguments))] // Request guards
 fn token_getter(
     authorization: auth::Authorization<auth::Basic>,
+    one_time_password: auth::OneTimePassword,
     auth_param: AuthParam,
+    client: Client,
+    user_agent: UserAgent,
     configuration: State<Configuration>,
+    grants: State<oauth::Grants>,
     keys: State<Keys>,
     authenticator: State<Box<auth::BasicAuthenticator>>,
+    throttle: State<Throttle>,
+    auditor: State<Auditor>,
+    metrics: State<Arc<Metrics>>,
 ) -> Result<Token<PrivateClaim>, ::Error> {
+    let issuer = Issuer {
+        client: &client,
+        user_agent: &user_agent,
+        configuration: &configuration,
+        grants: &grants,
+        keys: &keys,
+        throttle: &throttle,
+        auditor: &auditor,
+        metrics: &metrics,
+    };
 
-    auth_param.verify(&authorization)?;
-    authenticator
-        .prepare_authentication_response(&authorization, auth_param.offline_token.unwrap_or(false))
-        .and_then(|result| {
-            let token = Token::<PrivateClaim>::with_configuration(
-                &configuration,
-                &result.subject,
-                &auth_param.service,
-                result.private_claims.clone(),
-                result.refresh_payload.as_ref(),
-            )?;
-            let signing_key = &keys.signing;
-            let token = token.encode(signing_key)?;
-
-            let token = if configuration.refresh_token_enabled() && token.has_refresh_token() {
-                let refresh_token_key = keys.encryption.as_ref().expect(
-                    "Refresh token was enabled but encryption key is missing",
-                );
-                token.encrypt_refresh_token(signing_key, refresh_token_key)?
-            } else {
-                token
-            };
-
-            Ok(token)
-        })
+    let username = authorization.username();
+    issuer.login(
+        &**authenticator,
+        "basic",
+        &authorization,
+        one_time_password.0.as_ref().map(|one_time_password| one_time_password.as_str()),
+        Some(&username),
+        &auth_param,
+    )
 }
 
 /// Access token retrieval via refresh token route
 #[get("/?<auth_param>", rank = 2)]
+#[cfg_attr(feature = "clippy_lints", allow(too_many_arguments))] // Request guards
 fn refresh_token(
     authorization: auth::Authorization<auth::Bearer>,
     auth_param: AuthParam,
+    client: Client,
+    user_agent: UserAgent,
     configuration: State<Configuration>,
+    grants: State<oauth::Grants>,
     keys: State<Keys>,
     authenticator: State<Box<auth::BasicAuthenticator>>,
+    throttle: State<Throttle>,
+    auditor: State<Auditor>,
+    metrics: State<Arc<Metrics>>,
 ) -> Result<Token<PrivateClaim>, ::Error> {
+    let issuer = Issuer {
+        client: &client,
+        user_agent: &user_agent,
+        configuration: &configuration,
+        grants: &grants,
+        keys: &keys,
+        throttle: &throttle,
+        auditor: &auditor,
+        metrics: &metrics,
+    };
 
-    if !configuration.refresh_token_enabled() {
-        return Err(::Error::BadRequest(
-            "Refresh token is not enabled".to_string(),
-        ));
-    }
-    let refresh_token_configuration = configuration.refresh_token();
+    issuer.refresh(&**authenticator, &authorization, &auth_param)
+}
 
-    auth_param.verify(&authorization)?;
-    let refresh_token = RefreshToken::new_encrypted(&authorization.token());
-    let refresh_token = refresh_token.decrypt(
-        &keys.signature_verification,
-        keys.decryption.as_ref().expect(
-            "Refresh token was enabled but decryption key is missing",
-        ),
-        configuration
-            .signature_algorithm
-            .unwrap_or_default(),
-        refresh_token_configuration.cek_algorithm,
-        refresh_token_configuration.enc_algorithm,
-    )?;
-
-    refresh_token.validate(
-        &auth_param.service,
-        &configuration,
+/// Access token retrieval with an API key sent as the entire `Authorization` header
+#[get("/?<auth_param>", rank = 3)]
+#[cfg_attr(feature = "clippy_lints", allow(too_many_arguments))] // Request guards
+fn api_key_getter(
+    authorization: auth::Authorization<String>,
+    auth_param: AuthParam,
+    client: Client,
+    user_agent: UserAgent,
+    configuration: State<Configuration>,
+    grants: State<oauth::Grants>,
+    keys: State<Keys>,
+    authenticator: State<Option<Box<auth::StringAuthenticator>>>,
+    throttle: State<Throttle>,
+    auditor: State<Auditor>,
+    metrics: State<Arc<Metrics>>,
+) -> Result<Token<PrivateClaim>, ::Error> {
+    let authenticator = match *authenticator {
+        Some(ref authenticator) => authenticator,
+        None => return auth_param.missing_authorization(&configuration),
+    };
+    let issuer = Issuer {
+        client: &client,
+        user_agent: &user_agent,
+        configuration: &configuration,
+        grants: &grants,
+        keys: &keys,
+        throttle: &throttle,
+        auditor: &auditor,
+        metrics: &metrics,
+    };
+
+    // Throttle by the client ID, which is everything before the first `:` for API keys. Values without a `:` are
+    // secrets in their entirety, so they are only throttled by the address of the client.
+    let key = authorization.string();
+    let mut parts = key.splitn(2, ':');
+    let client_id = match (parts.next(), parts.next()) {
+        (Some(client_id), Some(_)) => Some(client_id),
+        _ => None,
+    };
+    issuer.login(
+        &**authenticator,
+        "api_key",
+        &authorization,
         None,
-    )?;
-
-    authenticator
-        .prepare_refresh_response(refresh_token.payload()?)
-        .and_then(|result| {
-            let token = Token::<PrivateClaim>::with_configuration(
-                &configuration,
-                &result.subject,
-                &auth_param.service,
-                result.private_claims.clone(),
+        client_id,
+        &auth_param,
+    )
+}
+
+/// Route to catch missing Authorization. Tokens are issued for the scopes granted to anonymous users, if configured.
+#[get("/?<auth_param>", rank = 4)]
+#[cfg_attr(feature = "clippy_lints", allow(too_many_arguments))] // Request guards
+fn bad_request(
+    auth_param: AuthParam,
+    client: Client,
+    user_agent: UserAgent,
+    configuration: State<Configuration>,
+    grants: State<oauth::Grants>,
+    keys: State<Keys>,
+    throttle: State<Throttle>,
+    auditor: State<Auditor>,
+    metrics: State<Arc<Metrics>>,
+) -> Result<Token<PrivateClaim>, ::Error> {
+    let anonymous = match configuration.anonymous {
+        Some(ref anonymous) => anonymous,
+        None => return auth_param.missing_authorization(&configuration),
+    };
+    let issuer = Issuer {
+        client: &client,
+        user_agent: &user_agent,
+        configuration: &configuration,
+        grants: &grants,
+        keys: &keys,
+        throttle: &throttle,
+        auditor: &auditor,
+        metrics: &metrics,
+    };
+
+    issuer.anonymous(anonymous, &auth_param)
+}
+
+/// OAuth 2.0 token endpoint. See the [`oauth`](../oauth/index.html) module documentation.
+#[post("/token", data = "<request>")]
+#[cfg_attr(feature = "clippy_lints", allow(too_many_arguments))] // Request guards
+fn oauth_token(
+    request: LenientForm<oauth::TokenRequest>,
+    client_authorization: Option<auth::Authorization<auth::Basic>>,
+    client: Client,
+    user_agent: UserAgent,
+    configuration: State<Configuration>,
+    grants: State<oauth::Grants>,
+    keys: State<Keys>,
+    basic_authenticator: State<Box<auth::BasicAuthenticator>>,
+    string_authenticator: State<Option<Box<auth::StringAuthenticator>>>,
+    codes: State<AuthorizationCodes>,
+    devices: State<DeviceAuthorizations>,
+    throttle: State<Throttle>,
+    auditor: State<Auditor>,
+    metrics: State<Arc<Metrics>>,
+) -> Result<oauth::AccessTokenResponse, oauth::Error> {
+    let issuer = Issuer {
+        client: &client,
+        user_agent: &user_agent,
+        configuration: &configuration,
+        grants: &grants,
+        keys: &keys,
+        throttle: &throttle,
+        auditor: &auditor,
+        metrics: &metrics,
+    };
+    let request = request.into_inner();
+
+    // The service and scope of the authorization code and device code grants were requested with the code
+    if request.grant_type == oauth::AUTHORIZATION_CODE_GRANT {
+        let (code, client_id, code_verifier) = request.authorization_code()?;
+        let grant = codes.redeem(&code, &client_id, &request.redirect_uri, &code_verifier)?;
+        let auth_param = AuthParam {
+            service: grant.service.clone(),
+            scope: grant.scope.clone(),
+            offline_token: None,
+            nonce: grant.nonce.clone(),
+            client_id: Some(client_id.clone()),
+        };
+        let token = issuer.exchange(
+            EventKind::AuthorizationCode,
+            &auth_param,
+            &grant.username,
+            &grant.result,
+            grant.auth_time,
+        )?;
+        return Ok(oauth::AccessTokenResponse::new(&token, &auth_param.scope)?);
+    }
+    if request.grant_type == oauth::DEVICE_CODE_GRANT {
+        let (device_code, client_id) = request.device_code()?;
+        let (device_request, approval) = devices.poll(&device_code, &client_id)?;
+        let auth_param = AuthParam {
+            service: device_request.service,
+            scope: device_request.scope,
+            offline_token: None,
+            nonce: None,
+            client_id: Some(client_id.clone()),
+        };
+        let token = issuer.exchange(
+            EventKind::DeviceCode,
+            &auth_param,
+            &approval.username,
+            &approval.result,
+            approval.auth_time,
+        )?;
+        return Ok(oauth::AccessTokenResponse::new(&token, &auth_param.scope)?);
+    }
+    if request.grant_type == oauth::TOKEN_EXCHANGE_GRANT {
+        let client_authenticator = ClientAuthenticator::new(&string_authenticator)?;
+        let (client_id, client_secret) = request.client_credentials(client_authorization.as_ref())?;
+        let result = client_authenticator.authenticate(&throttle, &client, &client_id, &client_secret)?;
+        let allowed = |service: &str| client_authenticator.allows(&result, service);
+        let exchange = token_exchange::verify(
+            &configuration,
+            &grants.token_exchange,
+            &keys,
+            &request,
+            &client_id,
+            allowed,
+        )?;
+        let token = issuer.exchange_token(&exchange, &client_id)?;
+        let mut response = oauth::AccessTokenResponse::new(&token, &exchange.scope)?;
+        response.issued_token_type = Some(token_exchange::ACCESS_TOKEN_TYPE_URI.to_string());
+        return Ok(response);
+    }
+
+    let auth_param = AuthParam {
+        service: request.resource(&configuration)?,
+        scope: request.scope.clone().unwrap_or_default(),
+        offline_token: None,
+        nonce: request.nonce.clone(),
+        client_id: request.client_id.clone(),
+    };
+
+    let token = match request.grant_type.as_str() {
+        oauth::PASSWORD_GRANT => {
+            let (username, password) = match (request.username, request.password) {
+                (Some(username), Some(password)) => (username, password),
+                _ => Err(oauth::Error::new(
+                    oauth::ErrorCode::InvalidRequest,
+                    "The `username` and `password` parameters are required",
+                ))?,
+            };
+            let authorization = auth::Authorization(hyper::header::Authorization(auth::Basic {
+                username: username.clone(),
+                password: Some(password),
+            }));
+            let auth_param = AuthParam {
+                offline_token: Some(configuration.refresh_token_enabled()),
+                ..auth_param.clone()
+            };
+            issuer.login(
+                &**basic_authenticator,
+                "basic",
+                &authorization,
                 None,
-            )?;
-            let token = token.encode(&keys.signing)?;
-            Ok(token)
-        })
+                Some(&username),
+                &auth_param,
+            )?
+        }
+        oauth::CLIENT_CREDENTIALS_GRANT => {
+            let (client_id, client_secret) = request.client_credentials(client_authorization.as_ref())?;
+            let authenticator = ClientAuthenticator::new(&string_authenticator)?;
+            let key = format!("{}:{}", client_id, client_secret);
+            let authorization = auth::Authorization(hyper::header::Authorization(key));
+            let result = issuer.login(
+                authenticator.authenticator,
+                "api_key",
+                &authorization,
+                None,
+                Some(&client_id),
+                &auth_param,
+            );
+            result.map_err(oauth::Error::from_client_error)?
+        }
+        oauth::REFRESH_TOKEN_GRANT => {
+            let refresh_token = request.refresh_token.ok_or_else(|| {
+                oauth::Error::new(
+                    oauth::ErrorCode::InvalidRequest,
+                    "The `refresh_token` parameter is required",
+                )
+            })?;
+            let authorization = auth::Authorization(hyper::header::Authorization(
+                auth::Bearer { token: refresh_token },
+            ));
+            issuer.refresh(&**basic_authenticator, &authorization, &auth_param)?
+        }
+        grant_type => {
+            Err(oauth::Error::new(
+                oauth::ErrorCode::UnsupportedGrantType,
+                &format!("Unsupported grant type `{}`", grant_type),
+            ))?
+        }
+    };
+
+    Ok(oauth::AccessTokenResponse::new(&token, &auth_param.scope)?)
 }
 
-/// Route to catch missing Authorization
-#[get("/?<auth_param>", rank = 3)]
-fn bad_request(auth_param: AuthParam, configuration: State<Configuration>) -> Result<(), ::Error> {
-    let _ = auth_param;
-    auth::missing_authorization(&configuration.issuer.to_string())
+/// Login form of the authorization endpoint. See the [`authorization_code`](../authorization_code/index.html)
+/// module documentation.
+#[get("/authorize?<request>")]
+fn authorize_form(
+    request: AuthorizationRequest,
+    configuration: State<Configuration>,
+    grants: State<oauth::Grants>,
+) -> AuthorizationResponse {
+    match request.verify(&configuration, &grants.authorization_code) {
+        Ok(verified) => AuthorizationResponse::login(&request, &verified, None),
+        Err(response) => response,
+    }
+}
+
+/// Log in with the login form of the authorization endpoint, and redirect to the client with an authorization code.
+/// See the [`authorization_code`](../authorization_code/index.html) module documentation.
+#[post("/authorize", data = "<request>")]
+#[cfg_attr(feature = "clippy_lints", allow(too_many_arguments))] // Request guards
+fn authorize(
+    request: LenientForm<AuthorizationRequest>,
+    client: Client,
+    user_agent: UserAgent,
+    configuration: State<Configuration>,
+    grants: State<oauth::Grants>,
+    keys: State<Keys>,
+    authenticator: State<Box<auth::BasicAuthenticator>>,
+    codes: State<AuthorizationCodes>,
+    throttle: State<Throttle>,
+    auditor: State<Auditor>,
+    metrics: State<Arc<Metrics>>,
+) -> AuthorizationResponse {
+    let request = request.into_inner();
+    let verified = match request.verify(&configuration, &grants.authorization_code) {
+        Ok(verified) => verified,
+        Err(response) => return response,
+    };
+    let (username, password) = match (&request.username, &request.password) {
+        (&Some(ref username), &Some(ref password)) if !username.is_empty() => (username, password),
+        _ => return AuthorizationResponse::login(&request, &verified, Some("The username and password are required")),
+    };
+    let issuer = Issuer {
+        client: &client,
+        user_agent: &user_agent,
+        configuration: &configuration,
+        grants: &grants,
+        keys: &keys,
+        throttle: &throttle,
+        auditor: &auditor,
+        metrics: &metrics,
+    };
+
+    match issuer.authorize(&**authenticator, &codes, &request, &verified, username, password) {
+        Ok(code) => AuthorizationResponse::redirect_code(&verified.redirect_uri, &code, &request.state),
+        Err(::Error::Auth(auth::Error::AuthenticationFailure)) => {
+            AuthorizationResponse::login(&request, &verified, Some("The username or password is incorrect"))
+        }
+        Err(::Error::Auth(auth::Error::TooManyAttempts { .. })) => {
+            AuthorizationResponse::login(&request, &verified, Some("Too many failed attempts. Try again later."))
+        }
+        Err(::Error::Auth(auth::Error::Forbidden)) => {
+            let error = oauth::Error::new(
+                oauth::ErrorCode::AccessDenied,
+                "The user is not allowed to request tokens for the service",
+            );
+            AuthorizationResponse::redirect_error(&verified.redirect_uri, &error, &request.state)
+        }
+        Err(e) => AuthorizationResponse::redirect_error(&verified.redirect_uri, &From::from(e), &request.state),
+    }
+}
+
+/// Device authorization endpoint. See the [`device`](../device/index.html) module documentation.
+#[post("/device/code", data = "<request>")]
+#[cfg_attr(feature = "clippy_lints", allow(too_many_arguments))] // Request guards
+fn device_authorization(
+    request: LenientForm<DeviceAuthorizationRequest>,
+    client: Client,
+    configuration: State<Configuration>,
+    grants: State<oauth::Grants>,
+    endpoints: Option<State<oauth::Endpoints>>,
+    devices: State<DeviceAuthorizations>,
+    throttle: State<Throttle>,
+) -> Result<DeviceAuthorizationResponse, oauth::Error> {
+    let request = request.into_inner().verify(&configuration, &grants.device)?;
+    throttle.limit(&client, "device_code")?;
+    let device = grants.device.as_ref().expect("Device authorization grant was verified to be enabled");
+    let verification_uri = endpoints.and_then(|endpoints| endpoints.device_verification.clone());
+    let verification_uri = match oauth::resolve(&configuration, &verification_uri)? {
+        Some(verification_uri) => verification_uri,
+        None => {
+            warn_!("The device verification page is not mounted with `rowdy::mount`");
+            Err(oauth::Error::new(
+                oauth::ErrorCode::ServerError,
+                "The device verification page is not available",
+            ))?
+        }
+    };
+    Ok(devices.issue(request, device, &verification_uri)?)
+}
+
+/// Verification page of the device authorization grant, with the user code of the query filled in. See the
+/// [`device`](../device/index.html) module documentation.
+#[get("/device?<request>")]
+fn device_verification_form(
+    request: VerificationRequest,
+    client: Client,
+    grants: State<oauth::Grants>,
+    devices: State<DeviceAuthorizations>,
+    throttle: State<Throttle>,
+) -> Result<AuthorizationResponse, ::Error> {
+    let device = match grants.device {
+        Some(ref device) => device,
+        None => {
+            return Ok(AuthorizationResponse::error_page(
+                Status::NotFound,
+                "The device authorization grant is not enabled",
+            ))
+        }
+    };
+    Ok(match request.user_code {
+        Some(ref user_code) => {
+            match find_pending_device(&devices, &throttle, &client, user_code)? {
+                Ok(pending) => request.page(device, Some(&pending), None),
+                Err(error) => request.page(device, None, Some(error)),
+            }
+        }
+        None => request.page(device, None, None),
+    })
+}
+
+/// Verification page of the device authorization grant, for users who open it without a user code. Routes with a
+/// query only match requests with one.
+#[get("/device", rank = 2)]
+fn device_verification_blank_form(
+    client: Client,
+    grants: State<oauth::Grants>,
+    devices: State<DeviceAuthorizations>,
+    throttle: State<Throttle>,
+) -> Result<AuthorizationResponse, ::Error> {
+    device_verification_form(Default::default(), client, grants, devices, throttle)
+}
+
+/// Find the pending request of `user_code`. Unknown user codes count as failures of the client, so that codes cannot
+/// be guessed. The request is found, or there is a message to show on the verification page.
+fn find_pending_device(
+    devices: &DeviceAuthorizations,
+    throttle: &Throttle,
+    client: &Client,
+    user_code: &str,
+) -> Result<Result<device::DeviceRequest, &'static str>, ::Error> {
+    let pending = throttle.attempt(client, None, || match devices.pending(user_code)? {
+        Some(pending) => Ok(pending),
+        None => Err(auth::Error::AuthenticationFailure)?,
+    });
+    match pending {
+        Ok(pending) => Ok(Ok(pending)),
+        Err(::Error::Auth(auth::Error::AuthenticationFailure)) => Ok(Err("The code is invalid or has expired")),
+        Err(::Error::Auth(auth::Error::TooManyAttempts { .. })) => {
+            Ok(Err("Too many failed attempts. Try again later."))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Approve or deny the request of a device on the verification page. See the [`device`](../device/index.html)
+/// module documentation.
+#[post("/device", data = "<request>")]
+#[cfg_attr(feature = "clippy_lints", allow(too_many_arguments))] // Request guards
+fn device_verification(
+    request: LenientForm<VerificationRequest>,
+    client: Client,
+    user_agent: UserAgent,
+    configuration: State<Configuration>,
+    grants: State<oauth::Grants>,
+    keys: State<Keys>,
+    authenticator: State<Box<auth::BasicAuthenticator>>,
+    devices: State<DeviceAuthorizations>,
+    throttle: State<Throttle>,
+    auditor: State<Auditor>,
+    metrics: State<Arc<Metrics>>,
+) -> Result<AuthorizationResponse, ::Error> {
+    let request = request.into_inner();
+    let device = match grants.device {
+        Some(ref device) => device,
+        None => {
+            return Ok(AuthorizationResponse::error_page(
+                Status::NotFound,
+                "The device authorization grant is not enabled",
+            ))
+        }
+    };
+    let user_code = request.user_code.clone().unwrap_or_default();
+    let pending = match find_pending_device(&devices, &throttle, &client, &user_code)? {
+        Ok(pending) => pending,
+        Err(error) => return Ok(request.page(device, None, Some(error))),
+    };
+    let (username, password) = match (&request.username, &request.password) {
+        (&Some(ref username), &Some(ref password)) if !username.is_empty() => (username, password),
+        _ => return Ok(request.page(device, Some(&pending), Some("The username and password are required"))),
+    };
+    let issuer = Issuer {
+        client: &client,
+        user_agent: &user_agent,
+        configuration: &configuration,
+        grants: &grants,
+        keys: &keys,
+        throttle: &throttle,
+        auditor: &auditor,
+        metrics: &metrics,
+    };
+
+    let approve = !request.is_denied();
+    let decision = issuer.decide_device(
+        &**authenticator,
+        &devices,
+        &user_code,
+        &pending,
+        username,
+        password,
+        approve,
+    );
+    let error = match decision {
+        Ok(true) => return Ok(request.decided_page(approve)),
+        Ok(false) => "The code is invalid or has expired",
+        Err(::Error::Auth(auth::Error::AuthenticationFailure)) => "The username or password is incorrect",
+        Err(::Error::Auth(auth::Error::TooManyAttempts { .. })) => "Too many failed attempts. Try again later.",
+        Err(::Error::Auth(auth::Error::Forbidden)) => "You are not allowed to request tokens for the service",
+        Err(e) => Err(e)?,
+    };
+    Ok(request.page(device, Some(&pending), Some(error)))
+}
+
+/// OAuth 2.0 token introspection endpoint. See the [`oauth`](../oauth/index.html) module documentation.
+#[post("/introspect", data = "<request>")]
+#[cfg_attr(feature = "clippy_lints", allow(too_many_arguments))] // Request guards
+fn introspect(
+    request: LenientForm<oauth::IntrospectionRequest>,
+    client_authorization: Option<auth::Authorization<auth::Basic>>,
+    client: Client,
+    configuration: State<Configuration>,
+    keys: State<Keys>,
+    string_authenticator: State<Option<Box<auth::StringAuthenticator>>>,
+    throttle: State<Throttle>,
+) -> Result<oauth::IntrospectionResponse, oauth::Error> {
+    let request = request.into_inner();
+    let client_authenticator = ClientAuthenticator::new(&string_authenticator)?;
+    let (client_id, client_secret) = request.client_credentials(client_authorization.as_ref())?;
+    let result = client_authenticator.authenticate(&throttle, &client, &client_id, &client_secret)?;
+
+    // Services only learn about tokens intended for a service they are allowed to request tokens for
+    let response = oauth::IntrospectionResponse::new(&request.token, &configuration, &keys);
+    let allowed = response.audience().iter().any(
+        |service| client_authenticator.allows(&result, service),
+    );
+    if response.active && !allowed {
+        return Ok(oauth::IntrospectionResponse::inactive());
+    }
+    Ok(response)
+}
+
+/// Record the subject and ID of a token that is about to be issued in the audit event.
+/// The token must not be encoded yet.
+fn audit_token(event: &mut audit::Event, token: &Token<PrivateClaim>, auth_param: &AuthParam) -> Result<(), ::Error> {
+    let registered = token.registered_claims()?;
+    event.subject = registered.subject.as_ref().map(|subject| subject.to_string());
+    event.token_id = registered.id.clone();
+    // Tokens are not restricted by scope, so the scope requested is granted
+    event.granted_scope = Some(auth_param.scope.clone());
+    Ok(())
 }
 
 /// A simple "Ping Pong" route to check the health of the server
@@ -135,40 +1090,255 @@ fn ping() -> &'static str {
     "Pong"
 }
 
+/// Health of a component checked by the readiness route
+#[derive(Serialize, Debug)]
+struct ComponentHealth {
+    status: &'static str,
+}
+
+/// Response of the readiness route
+#[derive(Serialize, Debug)]
+struct Readiness {
+    status: &'static str,
+    components: BTreeMap<&'static str, ComponentHealth>,
+}
+
+impl ComponentHealth {
+    /// The health of the component `name`. The errors of health checks, such as the messages of LDAP servers, are
+    /// only logged.
+    fn new(name: &str, result: Result<(), ::Error>) -> Self {
+        match result {
+            Ok(()) => ComponentHealth { status: "ok" },
+            Err(e) => {
+                error_!("Readiness check of `{}` failed: {}", name, e);
+                ComponentHealth { status: "unavailable" }
+            }
+        }
+    }
+
+    fn is_ok(&self) -> bool {
+        self.status == "ok"
+    }
+}
+
+/// Readiness route that checks the health of the authenticators. Responds with `503 Service Unavailable` if any of
+/// them is unhealthy, and the status of each in JSON.
+#[get("/health/ready")]
+fn readiness(
+    basic_authenticator: State<Box<auth::BasicAuthenticator>>,
+    string_authenticator: State<Option<Box<auth::StringAuthenticator>>>,
+) -> Result<status::Custom<content::Json<String>>, ::Error> {
+    let mut components = BTreeMap::new();
+    let _ = components.insert(
+        "basic_authenticator",
+        ComponentHealth::new("basic_authenticator", basic_authenticator.health_check()),
+    );
+    if let Some(ref string_authenticator) = *string_authenticator {
+        let _ = components.insert(
+            "api_key_authenticator",
+            ComponentHealth::new("api_key_authenticator", string_authenticator.health_check()),
+        );
+    }
+
+    let ready = components.values().all(ComponentHealth::is_ok);
+    let (http_status, body) = if ready {
+        (Status::Ok, Readiness { status: "ok", components })
+    } else {
+        (Status::ServiceUnavailable, Readiness { status: "unavailable", components })
+    };
+    let body = serde_json::to_string(&body)?;
+    Ok(status::Custom(http_status, content::Json(body)))
+}
+
+/// Metrics in the Prometheus text format, unless they are served on a separate port
+#[get("/metrics")]
+fn metrics_getter(
+    authorization: Option<auth::Authorization<auth::Bearer>>,
+    metrics: State<Arc<Metrics>>,
+) -> Result<Option<content::Plain<String>>, ::Error> {
+    if metrics.separate_server().is_some() {
+        return Ok(None);
+    }
+    render_metrics(authorization, &metrics)
+}
+
+/// Metrics in the Prometheus text format on the separate metrics server
+#[get("/metrics")]
+fn metrics_server_getter(
+    authorization: Option<auth::Authorization<auth::Bearer>>,
+    metrics: State<Arc<Metrics>>,
+) -> Result<Option<content::Plain<String>>, ::Error> {
+    render_metrics(authorization, &metrics)
+}
+
+fn render_metrics(
+    authorization: Option<auth::Authorization<auth::Bearer>>,
+    metrics: &Metrics,
+) -> Result<Option<content::Plain<String>>, ::Error> {
+    if !metrics.is_enabled() {
+        return Ok(None);
+    }
+    let token = authorization.map(|authorization| authorization.token());
+    if !metrics.authorize(token.as_ref().map(|token| &**token)) {
+        Err(auth::Error::AuthenticationFailure)?;
+    }
+    Ok(Some(content::Plain(metrics.render())))
+}
+
+/// OAuth 2.0 authorization server metadata. See the [`oauth`](../oauth/index.html) module documentation.
+#[get("/.well-known/oauth-authorization-server")]
+fn authorization_server_metadata(
+    configuration: State<Configuration>,
+    grants: State<oauth::Grants>,
+    endpoints: Option<State<oauth::Endpoints>>,
+) -> Result<Option<content::Json<String>>, ::Error> {
+    let endpoints = match endpoints {
+        Some(endpoints) => endpoints,
+        None => {
+            warn_!("Routes were not mounted with `rowdy::mount`, so the endpoints are unknown");
+            return Ok(None);
+        }
+    };
+    let metadata = oauth::AuthorizationServerMetadata::new(&configuration, &grants, &endpoints)?;
+    let metadata = serde_json::to_string(&metadata)?;
+    Ok(Some(content::Json(metadata)))
+}
+
+/// OpenID Connect user info of the subject of the access token sent as a `Bearer` token. See the
+/// [`oidc`](../oidc/index.html) module documentation.
+#[get("/userinfo")]
+fn userinfo(
+    authorization: Option<auth::Authorization<auth::Bearer>>,
+    configuration: State<Configuration>,
+    keys: State<Keys>,
+) -> Result<content::Json<String>, ::Error> {
+    let authorization = authorization.ok_or_else(|| {
+        auth::Error::InvalidToken("An access token is required".to_string())
+    })?;
+    let response = oauth::IntrospectionResponse::new(&authorization.token(), &configuration, &keys);
+    let subject = match response {
+        oauth::IntrospectionResponse {
+            active: true,
+            token_type: Some(ref token_type),
+            registered: Some(jwt::RegisteredClaims { subject: Some(ref subject), .. }),
+            ..
+        } if token_type == oauth::ACCESS_TOKEN_TYPE => subject.to_string(),
+        _ => Err(auth::Error::InvalidToken("The access token is not active".to_string()))?,
+    };
+    let private_claims = response.private.unwrap_or(JsonValue::Null);
+    let user_info = configuration.openid.user_info(&subject, &private_claims);
+    Ok(content::Json(serde_json::to_string(&user_info)?))
+}
+
+/// OpenID Connect discovery document. See the [`oidc`](../oidc/index.html) module documentation.
+#[get("/.well-known/openid-configuration")]
+fn openid_configuration(
+    configuration: State<Configuration>,
+    grants: State<oauth::Grants>,
+    endpoints: Option<State<oauth::Endpoints>>,
+) -> Result<Option<content::Json<String>>, ::Error> {
+    let endpoints = match endpoints {
+        Some(endpoints) => endpoints,
+        None => {
+            warn_!("Routes were not mounted with `rowdy::mount`, so the endpoints are unknown");
+            return Ok(None);
+        }
+    };
+    let metadata = oidc::ProviderMetadata::new(&configuration, &grants, &endpoints)?;
+    let metadata = serde_json::to_string(&metadata)?;
+    Ok(Some(content::Json(metadata)))
+}
+
+/// JSON Web Key Set of the RSA public keys tokens are signed with. See the [`jwks`](../jwks/index.html) module
+/// documentation.
+#[get("/jwks")]
+fn jwks(configuration: State<Configuration>, keys: State<Keys>) -> Result<content::Json<String>, ::Error> {
+    let set = JsonWebKeySet::from_keys(&configuration, &keys)?;
+    Ok(content::Json(serde_json::to_string(&set)?))
+}
+
 /// Return routes provided by rowdy
 pub fn routes() -> Vec<Route> {
     routes![
         token_getter,
         refresh_token,
+        api_key_getter,
         bad_request,
+        authorize_form,
+        authorize,
+        device_authorization,
+        device_verification_form,
+        device_verification_blank_form,
+        device_verification,
+        oauth_token,
+        introspect,
+        authorization_server_metadata,
+        userinfo,
+        openid_configuration,
+        jwks,
         ping,
+        readiness,
+        metrics_getter,
     ]
 }
 
+/// Return the routes of the separate metrics server
+pub(crate) fn metrics_server_routes() -> Vec<Route> {
+    routes![metrics_server_getter]
+}
+
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs;
+    use std::io::Read;
     use std::time::Duration;
     use std::str::FromStr;
 
     use hyper;
     use jwt;
     use rocket::Rocket;
-    use rocket::http::{Header, Status};
+    use rocket::http::{ContentType, Header, Status};
     use rocket::local::Client;
     use serde_json;
 
-    use ByteSequence;
+    use {ByteSequence, JsonValue};
     use super::*;
     use token::{Secret, RefreshTokenConfiguration};
 
     fn ignite() -> Rocket {
-        // Ignite rocket
+        ignite_with(None, None, None)
+    }
+
+    fn ignite_with(
+        throttle: Option<::throttle::Configuration>,
+        audit: Option<::audit::Configuration>,
+        metrics: Option<::metrics::Configuration>,
+    ) -> Rocket {
+        ignite_with_configuration(configuration_with(throttle, audit, metrics))
+    }
+
+    fn ignite_with_configuration(
+        configuration: ::Configuration<::auth::tests::MockAuthenticatorConfiguration>,
+    ) -> Rocket {
+        let rocket = not_err!(configuration.ignite());
+        ::mount(rocket, "/")
+    }
+
+    fn configuration_with(
+        throttle: Option<::throttle::Configuration>,
+        audit: Option<::audit::Configuration>,
+        metrics: Option<::metrics::Configuration>,
+    ) -> ::Configuration<::auth::tests::MockAuthenticatorConfiguration> {
         let allowed_origins = ["https://www.example.com"];
         let (allowed_origins, _) = ::cors::AllowedOrigins::some(&allowed_origins);
         let token_configuration = Configuration {
             issuer: FromStr::from_str("https://www.acme.com").unwrap(),
+            realm: None,
             allowed_origins: allowed_origins,
             audience: jwt::SingleOrMultiple::Single(not_err!(FromStr::from_str("https://www.example.com"))),
+            include_all_audiences: false,
             signature_algorithm: Some(jwt::jwa::SignatureAlgorithm::HS512),
             secret: Secret::ByteSequence(ByteSequence::String("secret".to_string())),
             expiry_duration: Duration::from_secs(120),
@@ -178,14 +1348,37 @@ mod tests {
                 key: Secret::ByteSequence(ByteSequence::Bytes(vec![0; 256 / 8])),
                 expiry_duration: Duration::from_secs(86400),
             }),
+            services: Default::default(),
+            anonymous: None,
+            openid: Default::default(),
         };
-        let configuration = ::Configuration {
+        ::Configuration {
             token: token_configuration,
+            authorization_code: None,
+            device: None,
+            token_exchange: None,
             basic_authenticator: ::auth::tests::MockAuthenticatorConfiguration {},
-        };
+            #[cfg(feature = "api_key_authenticator")]
+            api_key_authenticator: Some(::auth::ApiKeyAuthenticatorConfiguration {
+                keys_path: "test/fixtures/api_keys.json".to_string(),
+            }),
+            throttle: throttle,
+            audit: audit,
+            metrics: metrics,
+        }
+    }
 
-        let rocket = not_err!(configuration.ignite());
-        rocket.mount("/", routes())
+    fn ignite_with_anonymous() -> Rocket {
+        let mut configuration = configuration_with(None, None, None);
+        let json = r#"{
+            "access": {
+                "https://www.example.com": [
+                    { "type": "repository", "name": "library/*", "actions": ["pull"] }
+                ]
+            }
+        }"#;
+        configuration.token.anonymous = Some(not_err!(serde_json::from_str(json)));
+        ignite_with_configuration(configuration)
     }
 
     #[test]
@@ -200,6 +1393,46 @@ mod tests {
         assert_eq!("Pong", body_str);
     }
 
+    #[test]
+    fn readiness_reports_components() {
+        let rocket = ignite();
+        let client = not_err!(Client::new(rocket));
+
+        let mut response = client.get("/health/ready").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        let body: JsonValue = not_err!(serde_json::from_str(&body_str));
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["components"]["basic_authenticator"]["status"], "ok");
+        if cfg!(feature = "api_key_authenticator") {
+            assert_eq!(body["components"]["api_key_authenticator"]["status"], "ok");
+        }
+    }
+
+    #[test]
+    fn readiness_does_not_report_errors() {
+        let error = ::Error::BackendUnavailable("ldap://10.0.0.1:389: Connection refused".to_string());
+        let health = ComponentHealth::new("basic_authenticator", Err(error));
+        assert!(!health.is_ok());
+        let serialized = not_err!(serde_json::to_string(&health));
+        assert_eq!(serialized, r#"{"status":"unavailable"}"#);
+    }
+
+    #[test]
+    fn invalid_refresh_tokens_are_described_safely() {
+        let description = |error: ::Error| match error {
+            ::Error::Auth(auth::Error::InvalidToken(description)) => description,
+            e => panic!("Unexpected error {:?}", e),
+        };
+
+        let error = ::std::io::Error::new(::std::io::ErrorKind::NotFound, "/etc/rowdy/refresh.key");
+        let error = invalid_refresh_token(token::Error::IOError(error));
+        assert_eq!(description(error), "An unexpected error occurred");
+
+        let error = invalid_refresh_token(token::Error::Expired);
+        assert_eq!(description(error), token::Error::Expired.to_string());
+    }
+
     #[test]
     fn token_getter_options_test() {
         let rocket = ignite();
@@ -329,27 +1562,69 @@ mod tests {
             .get_one("Access-Control-Allow-Origin")
             .expect("to exist");
         assert_eq!("https://www.example.com", origin_header);
+
+        let www_header: Vec<_> = response.headers().get("WWW-Authenticate").collect();
+        assert_eq!(www_header, vec!["Basic realm=\"https://www.acme.com/\""]);
     }
 
     #[test]
     #[allow(deprecated)]
-    fn token_getter_get_missing_credentials() {
-        // Ignite rocket
+    fn token_getter_ignores_one_time_passwords_without_totp() {
         let rocket = ignite();
         let client = not_err!(Client::new(rocket));
 
-        // Make headers
-        let origin_header = Header::from(not_err!(
-            hyper::header::Origin::from_str("https://www.example.com")
-        ));
-
-        // Make and dispatch request
-        let req = client
-            .get("/?service=https://www.example.com&scope=all")
-            .header(origin_header);
-        let response = req.dispatch();
-
-        // Assert
+        let auth_header = hyper::header::Authorization(auth::Basic {
+            username: "mei".to_owned(),
+            password: Some("冻住，不许走!".to_string()),
+        });
+        let auth_header = Header::new(
+            "Authorization",
+            hyper::header::HeaderFormatter(&auth_header).to_string(),
+        );
+        let response = client
+            .get("/?service=https://www.example.com&scope=all")
+            .header(auth_header)
+            .header(Header::new(auth::ONE_TIME_PASSWORD_HEADER, "123456"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    #[test]
+    fn token_refresh_with_invalid_token_is_challenged() {
+        let rocket = ignite();
+        let client = not_err!(Client::new(rocket));
+
+        let response = client
+            .get("/?service=https://www.example.com&scope=all")
+            .header(Header::new("Authorization", "Bearer not-a-refresh-token"))
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Unauthorized);
+        let www_header = not_none!(response.headers().get_one("WWW-Authenticate"));
+        assert!(www_header.starts_with(
+            "Bearer realm=\"https://www.acme.com/\", error=\"invalid_token\", error_description=",
+        ));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn token_getter_get_missing_credentials() {
+        // Ignite rocket
+        let rocket = ignite();
+        let client = not_err!(Client::new(rocket));
+
+        // Make headers
+        let origin_header = Header::from(not_err!(
+            hyper::header::Origin::from_str("https://www.example.com")
+        ));
+
+        // Make and dispatch request
+        let req = client
+            .get("/?service=https://www.example.com&scope=all")
+            .header(origin_header);
+        let response = req.dispatch();
+
+        // Assert
         assert_eq!(response.status(), Status::Unauthorized);
         let origin_header = response
             .headers()
@@ -358,7 +1633,220 @@ mod tests {
         assert_eq!("https://www.example.com", origin_header);
 
         let www_header: Vec<_> = response.headers().get("WWW-Authenticate").collect();
-        assert_eq!(www_header, vec!["Basic realm=https://www.acme.com/"]);
+        assert_eq!(
+            www_header,
+            vec![
+                "Basic realm=\"https://www.acme.com/\", service=\"https://www.example.com\", scope=\"all\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn token_getter_issues_anonymous_tokens_for_public_scopes() {
+        let rocket = ignite_with_anonymous();
+        let client = not_err!(Client::new(rocket));
+
+        let mut response = client
+            .get("/?service=https://www.example.com&scope=repository:library/ubuntu:pull")
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        let deserialized: Token<PrivateClaim> = not_err!(serde_json::from_str(&body_str));
+        let token = not_err!(deserialized.decode(
+            &jwt::jws::Secret::bytes_from_str("secret"),
+            jwt::jwa::SignatureAlgorithm::HS512,
+        ));
+        assert!(token.refresh_token.is_none());
+
+        let registered = not_err!(token.registered_claims());
+        assert_eq!(registered.subject, Some(FromStr::from_str("anonymous").unwrap()));
+
+        let private = not_err!(token.private_claims());
+        let access: Vec<token::Access> = not_err!(serde_json::from_value(private["access"].clone()));
+        assert_eq!(access.len(), 1);
+        assert!(access[0].allows("repository", "library/ubuntu", "pull"));
+        assert!(!access[0].allows("repository", "library/ubuntu", "push"));
+    }
+
+    #[test]
+    fn token_getter_challenges_anonymous_requests_for_other_scopes() {
+        let rocket = ignite_with_anonymous();
+        let client = not_err!(Client::new(rocket));
+
+        let scopes = [
+            "repository:library/ubuntu:pull,push",
+            "repository:acme/secret:pull",
+            "all",
+            "",
+            "repository:library/ubuntu:",
+        ];
+        for scope in &scopes {
+            let response = client
+                .get(format!("/?service=https://www.example.com&scope={}", scope))
+                .dispatch();
+            assert_eq!(response.status(), Status::Unauthorized);
+            let www_header = not_none!(response.headers().get_one("WWW-Authenticate"));
+            assert!(www_header.starts_with("Basic realm=\"https://www.acme.com/\""));
+        }
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn token_getter_get_throttled() {
+        // Ignite rocket
+        let rocket = ignite_with(
+            Some(::throttle::Configuration {
+                free_attempts: 1,
+                backoff: Duration::from_secs(60),
+                ..Default::default()
+            }),
+            None,
+            None,
+        );
+        let client = not_err!(Client::new(rocket));
+
+        let auth_header = |password: &str| {
+            let auth_header = hyper::header::Authorization(auth::Basic {
+                username: "mei".to_owned(),
+                password: Some(password.to_string()),
+            });
+            Header::new(
+                "Authorization",
+                hyper::header::HeaderFormatter(&auth_header).to_string(),
+            )
+        };
+
+        // Failures are allowed until the free attempts are used up
+        for _ in 0..2 {
+            let req = client
+                .get("/?service=https://www.example.com&scope=all")
+                .header(auth_header("let me in"));
+            let response = req.dispatch();
+            assert_eq!(response.status(), Status::Unauthorized);
+        }
+
+        // Even the correct password is refused afterwards
+        let req = client
+            .get("/?service=https://www.example.com&scope=all")
+            .header(auth_header("冻住，不许走!"));
+        let response = req.dispatch();
+
+        // Assert
+        assert_eq!(response.status(), Status::TooManyRequests);
+        let retry_after: u64 = not_err!(not_none!(response.headers().get_one("Retry-After")).parse());
+        assert!(retry_after > 0 && retry_after <= 60);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn token_getter_is_audited() {
+        let path = env::temp_dir().join("rowdy_token_getter_is_audited.log");
+        let _ = fs::remove_file(&path);
+        let rocket = ignite_with(
+            None,
+            Some(::audit::Configuration::File { path: not_none!(path.to_str()).to_string() }),
+            None,
+        );
+        let client = not_err!(Client::new(rocket));
+
+        for password in &["let me in", "冻住，不许走!"] {
+            let auth_header = hyper::header::Authorization(auth::Basic {
+                username: "mei".to_owned(),
+                password: Some(password.to_string()),
+            });
+            let auth_header = Header::new(
+                "Authorization",
+                hyper::header::HeaderFormatter(&auth_header).to_string(),
+            );
+            let req = client
+                .get("/?service=https://www.example.com&scope=all")
+                .header(auth_header)
+                .header(Header::new("User-Agent", "docker/17.06.0-ce"));
+            let _ = req.dispatch();
+        }
+
+        let mut log = String::new();
+        let _ = not_err!(not_err!(fs::File::open(&path)).read_to_string(&mut log));
+        let events: Vec<JsonValue> = log.lines()
+            .map(|line| not_err!(serde_json::from_str(line)))
+            .collect();
+        assert_eq!(events.len(), 2);
+
+        assert_eq!(events[0]["event"], "login");
+        assert_eq!(events[0]["outcome"], "failure");
+        assert_eq!(events[0]["reason"], "Authentication has failed");
+        assert_eq!(events[0]["username"], "mei");
+        assert_eq!(events[0]["user_agent"], "docker/17.06.0-ce");
+
+        assert_eq!(events[1]["outcome"], "success");
+        assert_eq!(events[1]["subject"], "mei");
+        assert_eq!(events[1]["service"], "https://www.example.com");
+        assert_eq!(events[1]["requested_scope"], "all");
+        assert_eq!(events[1]["granted_scope"], "all");
+        assert!(not_none!(events[1]["token_id"].as_str()).starts_with("urn:uuid:"));
+
+        // No secrets are logged
+        assert!(!log.contains("let me in"));
+        assert!(!log.contains("冻住，不许走!"));
+        not_err!(fs::remove_file(&path));
+    }
+
+    #[test]
+    fn metrics_are_not_served_by_default() {
+        let rocket = ignite();
+        let client = not_err!(Client::new(rocket));
+
+        let response = client.get("/metrics").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn metrics_require_the_bearer_token() {
+        let rocket = ignite_with(
+            None,
+            None,
+            Some(::metrics::Configuration {
+                bearer_token: Some("scraper".to_string()),
+                ..Default::default()
+            }),
+        );
+        let client = not_err!(Client::new(rocket));
+
+        let auth_header = hyper::header::Authorization(auth::Basic {
+            username: "mei".to_owned(),
+            password: Some("冻住，不许走!".to_string()),
+        });
+        let auth_header = Header::new(
+            "Authorization",
+            hyper::header::HeaderFormatter(&auth_header).to_string(),
+        );
+        let response = client
+            .get("/?service=https://www.example.com&scope=all")
+            .header(auth_header)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client.get("/metrics").dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = client
+            .get("/metrics")
+            .header(Header::new("Authorization", "Bearer wrong"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let mut response = client
+            .get("/metrics")
+            .header(Header::new("Authorization", "Bearer scraper"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        assert!(body_str.contains(
+            "rowdy_tokens_issued_total{grant=\"login\",service=\"https://www.example.com\"} 1\n",
+        ));
+        assert!(body_str.contains("rowdy_authentication_duration_seconds_count{authenticator=\"basic\"} 1\n"));
     }
 
     #[test]
@@ -385,15 +1873,21 @@ mod tests {
             .get("/?service=foobar&scope=all")
             .header(origin_header)
             .header(auth_header);
-        let response = req.dispatch();
+        let mut response = req.dispatch();
 
         // Assert
         assert_eq!(response.status(), Status::Forbidden);
         let origin_header = response
             .headers()
             .get_one("Access-Control-Allow-Origin")
-            .expect("to exist");
+            .expect("to exist")
+            .to_string();
         assert_eq!("https://www.example.com", origin_header);
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        let body: JsonValue = not_err!(serde_json::from_str(&body_str));
+        assert_eq!(body["errors"][0]["code"], "INVALID_SERVICE");
     }
 
     /// Tests that we can request a refresh token and then get a new access token with the issued refresh token
@@ -517,14 +2011,1079 @@ mod tests {
             )
             .header(origin_header)
             .header(auth_header);
-        let response = req.dispatch();
+        let mut response = req.dispatch();
 
         // Assert
         assert_eq!(response.status(), Status::BadRequest);
         let origin_header = response
             .headers()
             .get_one("Access-Control-Allow-Origin")
-            .expect("to exist");
+            .expect("to exist")
+            .to_string();
         assert_eq!("https://www.example.com", origin_header);
+
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        let body: JsonValue = not_err!(serde_json::from_str(&body_str));
+        assert_eq!(body["errors"][0]["code"], "OFFLINE_TOKEN_NOT_ALLOWED");
+    }
+
+    #[test]
+    #[cfg(feature = "api_key_authenticator")]
+    fn api_key_getter_get_test() {
+        let rocket = ignite();
+        let client = not_err!(Client::new(rocket));
+
+        let req = client
+            .get("/?service=https://www.example.com&scope=all")
+            .header(Header::new("Authorization", "registry-ci:password"));
+        let mut response = req.dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        let deserialized: Token<PrivateClaim> = not_err!(serde_json::from_str(&body_str));
+        let actual_token = not_err!(deserialized.decode(
+            &jwt::jws::Secret::bytes_from_str("secret"),
+            jwt::jwa::SignatureAlgorithm::HS512,
+        ));
+        let registered = not_err!(actual_token.registered_claims());
+        assert_eq!(registered.subject, Some(FromStr::from_str("ci").unwrap()));
+        let private = not_err!(actual_token.private_claims());
+        assert_eq!(private["client_id"], "registry-ci");
+    }
+
+    #[test]
+    #[cfg(feature = "api_key_authenticator")]
+    fn api_key_getter_get_invalid_key() {
+        let rocket = ignite();
+        let client = not_err!(Client::new(rocket));
+
+        let req = client
+            .get("/?service=https://www.example.com&scope=all")
+            .header(Header::new("Authorization", "registry-ci:wrong"));
+        let response = req.dispatch();
+
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[test]
+    fn api_key_getter_does_not_throttle_by_secrets() {
+        let path = env::temp_dir().join("rowdy_api_key_getter_does_not_throttle_by_secrets.json");
+        let _ = fs::remove_file(&path);
+        let rocket = ignite_with(
+            Some(::throttle::Configuration {
+                trust_forwarded_for: true,
+                store: ::throttle::StoreConfiguration::File { path: not_none!(path.to_str()).to_string() },
+                ..Default::default()
+            }),
+            None,
+            None,
+        );
+        let client = not_err!(Client::new(rocket));
+
+        for key in &["Token s3cr3t", "registry-ci:wrong"] {
+            let response = client
+                .get("/?service=https://www.example.com&scope=all")
+                .header(Header::new("Authorization", *key))
+                .header(Header::new("X-Forwarded-For", "192.0.2.1"))
+                .dispatch();
+            assert_eq!(response.status(), Status::Unauthorized);
+        }
+
+        if cfg!(feature = "api_key_authenticator") {
+            let mut store = String::new();
+            let _ = not_err!(not_err!(fs::File::open(&path)).read_to_string(&mut store));
+            assert!(store.contains("address:192.0.2.1"));
+            assert!(store.contains("username:registry-ci"));
+            assert!(!store.contains("s3cr3t"));
+            not_err!(fs::remove_file(&path));
+        }
+    }
+
+    #[test]
+    fn api_key_getter_does_not_log_secrets() {
+        let path = env::temp_dir().join("rowdy_api_key_getter_does_not_log_secrets.log");
+        let _ = fs::remove_file(&path);
+        let rocket = ignite_with(
+            None,
+            Some(::audit::Configuration::File { path: not_none!(path.to_str()).to_string() }),
+            None,
+        );
+        let client = not_err!(Client::new(rocket));
+
+        for key in &["Token s3cr3t", "registry-ci:wrong"] {
+            let response = client
+                .get("/?service=https://www.example.com&scope=all")
+                .header(Header::new("Authorization", *key))
+                .dispatch();
+            assert_eq!(response.status(), Status::Unauthorized);
+        }
+
+        if cfg!(feature = "api_key_authenticator") {
+            let mut log = String::new();
+            let _ = not_err!(not_err!(fs::File::open(&path)).read_to_string(&mut log));
+            let events: Vec<JsonValue> = log.lines()
+                .map(|line| not_err!(serde_json::from_str(line)))
+                .collect();
+            assert_eq!(events.len(), 2);
+            assert!(events[0].get("username").map_or(true, |username| username.is_null()));
+            assert_eq!(events[1]["username"], "registry-ci");
+            assert!(!log.contains("s3cr3t"));
+            assert!(!log.contains("wrong"));
+            not_err!(fs::remove_file(&path));
+        }
+    }
+
+    /// Percent encode the parameters into a form encoded request body
+    fn form(parameters: &[(&str, &str)]) -> String {
+        let encode = |s: &str| {
+            s.bytes()
+                .map(|b| match b {
+                    b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+                    b => format!("%{:02X}", b),
+                })
+                .collect::<String>()
+        };
+        parameters
+            .iter()
+            .map(|&(key, value)| format!("{}={}", encode(key), encode(value)))
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+
+    #[test]
+    fn oauth_token_password_grant() {
+        let rocket = ignite();
+        let client = not_err!(Client::new(rocket));
+
+        let body = form(
+            &[
+                ("grant_type", "password"),
+                ("username", "mei"),
+                ("password", "冻住，不许走!"),
+                ("scope", "all"),
+            ],
+        );
+        let mut response = client
+            .post("/token")
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("Cache-Control"), Some("no-store"));
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        let body: oauth::AccessTokenResponse = not_err!(serde_json::from_str(&body_str));
+        assert_eq!(body.token_type, "Bearer");
+        assert_eq!(body.expires_in, 120);
+        assert_eq!(body.scope, Some("all".to_string()));
+        assert!(body.refresh_token.is_some());
+
+        let token = jwt::JWT::<PrivateClaim, jwt::Empty>::new_encoded(&body.access_token);
+        let token = not_err!(token.into_decoded(
+            &jwt::jws::Secret::bytes_from_str("secret"),
+            jwt::jwa::SignatureAlgorithm::HS512,
+        ));
+        let registered = &not_err!(token.payload()).registered;
+        assert_eq!(
+            Some(jwt::SingleOrMultiple::Single(
+                FromStr::from_str("https://www.example.com").unwrap(),
+            )),
+            registered.audience
+        );
+
+        // The refresh token can be exchanged for another access token
+        let body = form(
+            &[
+                ("grant_type", "refresh_token"),
+                ("refresh_token", &not_none!(body.refresh_token)),
+                ("resource", "https://www.example.com"),
+            ],
+        );
+        let mut response = client
+            .post("/token")
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        let body: oauth::AccessTokenResponse = not_err!(serde_json::from_str(&body_str));
+        assert!(body.refresh_token.is_none());
+        assert!(body.scope.is_none());
+    }
+
+    #[test]
+    fn oauth_token_invalid_grant() {
+        let rocket = ignite();
+        let client = not_err!(Client::new(rocket));
+
+        let body = form(
+            &[
+                ("grant_type", "password"),
+                ("username", "Aladin"),
+                ("password", "let me in"),
+            ],
+        );
+        let mut response = client
+            .post("/token")
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::BadRequest);
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        let body: JsonValue = not_err!(serde_json::from_str(&body_str));
+        assert_eq!(body["error"], "invalid_grant");
+
+        let response = client
+            .post("/token")
+            .header(ContentType::Form)
+            .body(form(&[("grant_type", "password"), ("username", "mei")]))
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn oauth_token_unsupported_grant_type() {
+        let rocket = ignite();
+        let client = not_err!(Client::new(rocket));
+
+        let mut response = client
+            .post("/token")
+            .header(ContentType::Form)
+            .body(form(&[("grant_type", "implicit")]))
+            .dispatch();
+
+        assert_eq!(response.status(), Status::BadRequest);
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        let body: JsonValue = not_err!(serde_json::from_str(&body_str));
+        assert_eq!(body["error"], "unsupported_grant_type");
+    }
+
+    #[test]
+    #[cfg(feature = "api_key_authenticator")]
+    fn oauth_token_client_credentials_grant() {
+        let rocket = ignite();
+        let client = not_err!(Client::new(rocket));
+
+        let auth_header = hyper::header::Authorization(auth::Basic {
+            username: "registry-ci".to_owned(),
+            password: Some("password".to_string()),
+        });
+        let auth_header = Header::new(
+            "Authorization",
+            hyper::header::HeaderFormatter(&auth_header).to_string(),
+        );
+        let mut response = client
+            .post("/token")
+            .header(ContentType::Form)
+            .header(auth_header)
+            .body(form(&[("grant_type", "client_credentials")]))
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Ok);
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        let body: oauth::AccessTokenResponse = not_err!(serde_json::from_str(&body_str));
+        assert!(body.refresh_token.is_none());
+        let token = jwt::JWT::<PrivateClaim, jwt::Empty>::new_encoded(&body.access_token);
+        let token = not_err!(token.into_decoded(
+            &jwt::jws::Secret::bytes_from_str("secret"),
+            jwt::jwa::SignatureAlgorithm::HS512,
+        ));
+        let registered = &not_err!(token.payload()).registered;
+        assert_eq!(registered.subject, Some(FromStr::from_str("ci").unwrap()));
+    }
+
+    #[test]
+    #[cfg(feature = "api_key_authenticator")]
+    fn oauth_token_invalid_client() {
+        let rocket = ignite();
+        let client = not_err!(Client::new(rocket));
+
+        let body = form(
+            &[
+                ("grant_type", "client_credentials"),
+                ("client_id", "registry-ci"),
+                ("client_secret", "wrong"),
+            ],
+        );
+        let mut response = client
+            .post("/token")
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Unauthorized);
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        let body: JsonValue = not_err!(serde_json::from_str(&body_str));
+        assert_eq!(body["error"], "invalid_client");
+    }
+
+    #[test]
+    #[cfg(not(feature = "api_key_authenticator"))]
+    fn oauth_token_client_credentials_grant_does_not_accept_user_credentials() {
+        let rocket = ignite();
+        let client = not_err!(Client::new(rocket));
+
+        let body = form(
+            &[
+                ("grant_type", "client_credentials"),
+                ("client_id", "mei"),
+                ("client_secret", "冻住，不许走!"),
+            ],
+        );
+        let mut response = client
+            .post("/token")
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Unauthorized);
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        let body: JsonValue = not_err!(serde_json::from_str(&body_str));
+        assert_eq!(body["error"], "invalid_client");
+    }
+
+    /// Credentials of a service for the authenticator of API keys
+    #[cfg(feature = "api_key_authenticator")]
+    fn service_authorization() -> Header<'static> {
+        let auth_header = hyper::header::Authorization(auth::Basic {
+            username: "registry-ci".to_string(),
+            password: Some("password".to_string()),
+        });
+        Header::new(
+            "Authorization",
+            hyper::header::HeaderFormatter(&auth_header).to_string(),
+        )
+    }
+
+    #[cfg(feature = "api_key_authenticator")]
+    fn introspect_token(client: &Client, token: &str) -> JsonValue {
+        let mut response = client
+            .post("/introspect")
+            .header(ContentType::Form)
+            .header(service_authorization())
+            .body(form(&[("token", token)]))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("Cache-Control"), Some("no-store"));
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        not_err!(serde_json::from_str(&body_str))
+    }
+
+    #[test]
+    #[cfg(feature = "api_key_authenticator")]
+    fn introspect_tokens() {
+        let rocket = ignite();
+        let client = not_err!(Client::new(rocket));
+
+        let body = form(
+            &[
+                ("grant_type", "password"),
+                ("username", "mei"),
+                ("password", "冻住，不许走!"),
+            ],
+        );
+        let mut response = client
+            .post("/token")
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        let body: oauth::AccessTokenResponse = not_err!(serde_json::from_str(&body_str));
+
+        let introspection = introspect_token(&client, &body.access_token);
+        assert_eq!(introspection["active"], true);
+        assert_eq!(introspection["token_type"], "access_token");
+        assert_eq!(introspection["sub"], "mei");
+        assert_eq!(introspection["iss"], "https://www.acme.com/");
+        assert!(introspection["exp"].is_i64());
+
+        let introspection = introspect_token(&client, &not_none!(body.refresh_token));
+        assert_eq!(introspection["active"], true);
+        assert_eq!(introspection["token_type"], "refresh_token");
+        assert_eq!(introspection["sub"], "mei");
+        assert!(!introspection.to_string().contains("冻住"));
+
+        let introspection = introspect_token(&client, "not.a.token");
+        assert_eq!(introspection["active"], false);
+        assert!(introspection.get("sub").is_none());
+    }
+
+    #[test]
+    fn introspect_requires_client_authentication() {
+        let rocket = ignite();
+        let client = not_err!(Client::new(rocket));
+
+        let mut response = client
+            .post("/introspect")
+            .header(ContentType::Form)
+            .body(form(&[("token", "not.a.token")]))
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Unauthorized);
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        let body: JsonValue = not_err!(serde_json::from_str(&body_str));
+        assert_eq!(body["error"], "invalid_client");
+    }
+
+    #[test]
+    #[cfg(not(feature = "api_key_authenticator"))]
+    fn introspect_does_not_accept_user_credentials() {
+        let rocket = ignite();
+        let client = not_err!(Client::new(rocket));
+
+        let auth_header = hyper::header::Authorization(auth::Basic {
+            username: "mei".to_string(),
+            password: Some("冻住，不许走!".to_string()),
+        });
+        let mut response = client
+            .post("/introspect")
+            .header(ContentType::Form)
+            .header(Header::new(
+                "Authorization",
+                hyper::header::HeaderFormatter(&auth_header).to_string(),
+            ))
+            .body(form(&[("token", "not.a.token")]))
+            .dispatch();
+
+        assert_eq!(response.status(), Status::Unauthorized);
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        let body: JsonValue = not_err!(serde_json::from_str(&body_str));
+        assert_eq!(body["error"], "invalid_client");
+    }
+
+    #[test]
+    fn authorization_server_metadata_lists_mounted_endpoints() {
+        let rocket = ignite();
+        let client = not_err!(Client::new(rocket));
+
+        let mut response = client.get("/.well-known/oauth-authorization-server").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        let metadata: oauth::AuthorizationServerMetadata = not_err!(serde_json::from_str(&body_str));
+        assert_eq!(metadata.issuer, "https://www.acme.com/");
+        assert_eq!(metadata.token_endpoint, Some("https://www.acme.com/token".to_string()));
+        assert_eq!(metadata.introspection_endpoint, Some("https://www.acme.com/introspect".to_string()));
+        assert_eq!(metadata.revocation_endpoint, None);
+        assert_eq!(metadata.jwks_uri, Some("https://www.acme.com/jwks".to_string()));
+        assert_eq!(
+            metadata.grant_types_supported,
+            vec!["password", "client_credentials", "refresh_token"]
+        );
+        assert_eq!(
+            metadata.access_token_signing_alg_values_supported,
+            vec![jwt::jwa::SignatureAlgorithm::HS512]
+        );
+    }
+
+    #[test]
+    fn oauth_token_issues_id_tokens_for_the_openid_scope() {
+        let mut configuration = configuration_with(None, None, None);
+        let json = r#"{
+            "clients": {
+                "grafana": { "redirect_uris": ["https://grafana.example.com/login"] },
+                "https://www.example.com": { "redirect_uris": ["https://www.example.com/login"] }
+            }
+        }"#;
+        configuration.authorization_code = Some(not_err!(serde_json::from_str(json)));
+        let rocket = ignite_with_configuration(configuration);
+        let client = not_err!(Client::new(rocket));
+
+        let body = form(
+            &[
+                ("grant_type", "password"),
+                ("username", "mei"),
+                ("password", "冻住，不许走!"),
+                ("scope", "openid"),
+                ("nonce", "n-0S6_WzA2Mj"),
+                ("client_id", "grafana"),
+            ],
+        );
+        let mut response = client
+            .post("/token")
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        let body: oauth::AccessTokenResponse = not_err!(serde_json::from_str(&body_str));
+
+        let encoded_id_token = not_none!(body.id_token);
+        let id_token = jwt::JWT::<JsonValue, jwt::Empty>::new_encoded(&encoded_id_token);
+        let id_token = not_err!(id_token.into_decoded(
+            &jwt::jws::Secret::bytes_from_str("secret"),
+            jwt::jwa::SignatureAlgorithm::HS512,
+        ));
+        let claims = not_err!(id_token.payload());
+        assert_eq!(not_none!(claims.registered.subject.as_ref()).to_string(), "mei");
+        assert_eq!(
+            Some(jwt::SingleOrMultiple::Single(FromStr::from_str("grafana").unwrap())),
+            claims.registered.audience
+        );
+        assert_eq!(claims.private["nonce"], "n-0S6_WzA2Mj");
+        assert_eq!(claims.private["token_use"], "id");
+        assert!(claims.private["auth_time"].is_i64());
+
+        // ID tokens are not access tokens
+        let authorization = Header::new("Authorization", format!("Bearer {}", encoded_id_token));
+        let response = client.get("/userinfo").header(authorization).dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        // ID tokens are only issued when requested by a registered client that is not a service
+        for parameters in &[
+            vec![("grant_type", "password"), ("username", "mei"), ("password", "冻住，不许走!")],
+            vec![
+                ("grant_type", "password"),
+                ("username", "mei"),
+                ("password", "冻住，不许走!"),
+                ("scope", "openid"),
+            ],
+            vec![
+                ("grant_type", "password"),
+                ("username", "mei"),
+                ("password", "冻住，不许走!"),
+                ("scope", "openid"),
+                ("client_id", "unregistered"),
+            ],
+            vec![
+                ("grant_type", "password"),
+                ("username", "mei"),
+                ("password", "冻住，不许走!"),
+                ("scope", "openid"),
+                ("client_id", "https://www.example.com"),
+            ],
+        ] {
+            let mut response = client
+                .post("/token")
+                .header(ContentType::Form)
+                .body(form(parameters))
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+            let body: oauth::AccessTokenResponse = not_err!(serde_json::from_str(&body_str));
+            assert!(body.id_token.is_none());
+        }
+    }
+
+    #[test]
+    fn userinfo_describes_the_subject_of_access_tokens() {
+        let rocket = ignite();
+        let client = not_err!(Client::new(rocket));
+
+        let body = form(
+            &[
+                ("grant_type", "password"),
+                ("username", "mei"),
+                ("password", "冻住，不许走!"),
+                ("scope", "openid"),
+            ],
+        );
+        let mut response = client
+            .post("/token")
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        let body: oauth::AccessTokenResponse = not_err!(serde_json::from_str(&body_str));
+
+        let authorization = Header::new("Authorization", format!("Bearer {}", body.access_token));
+        let mut response = client.get("/userinfo").header(authorization).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        let user_info: JsonValue = not_err!(serde_json::from_str(&body_str));
+        assert_eq!(user_info["sub"], "mei");
+
+        // Refresh tokens are not accepted
+        let authorization = Header::new("Authorization", format!("Bearer {}", not_none!(body.refresh_token)));
+        let response = client.get("/userinfo").header(authorization).dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        let challenge = not_none!(response.headers().get_one("WWW-Authenticate"));
+        assert!(challenge.starts_with("Bearer "));
+
+        let response = client.get("/userinfo").dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[test]
+    fn openid_configuration_lists_mounted_endpoints() {
+        let rocket = ignite();
+        let client = not_err!(Client::new(rocket));
+
+        let mut response = client.get("/.well-known/openid-configuration").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        let metadata: oidc::ProviderMetadata = not_err!(serde_json::from_str(&body_str));
+        assert_eq!(metadata.issuer, "https://www.acme.com/");
+        assert_eq!(metadata.userinfo_endpoint, Some("https://www.acme.com/userinfo".to_string()));
+        assert_eq!(metadata.jwks_uri, Some("https://www.acme.com/jwks".to_string()));
+        assert!(metadata.scopes_supported.contains(&"openid".to_string()));
+        assert_eq!(metadata.subject_types_supported, vec!["public"]);
+        assert!(metadata.claims_supported.contains(&"email".to_string()));
+    }
+
+    #[test]
+    fn symmetric_keys_are_not_published() {
+        let rocket = ignite();
+        let client = not_err!(Client::new(rocket));
+
+        let mut response = client.get("/jwks").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        assert_eq!(body_str, r#"{"keys":[]}"#);
+    }
+
+    fn ignite_with_authorization_code() -> Rocket {
+        let mut configuration = configuration_with(None, None, None);
+        let json = r#"{
+            "clients": {
+                "registry-ui": { "redirect_uris": ["https://ui.example.com/callback"] }
+            }
+        }"#;
+        configuration.authorization_code = Some(not_err!(serde_json::from_str(json)));
+        ignite_with_configuration(configuration)
+    }
+
+    /// Code verifier and challenge from RFC 7636 Appendix B
+    const CODE_VERIFIER: &'static str = "dBjftJeZ4CK-mf6Yl7Ld2svJhR0S0j4LyMrMkmgpTIo";
+    const CODE_CHALLENGE: &'static str = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+
+    fn authorization_request(username: &str, password: &str) -> Vec<(&'static str, String)> {
+        vec![
+            ("response_type", "code".to_string()),
+            ("client_id", "registry-ui".to_string()),
+            ("redirect_uri", "https://ui.example.com/callback".to_string()),
+            ("scope", "openid".to_string()),
+            ("state", "af0ifjsldkj".to_string()),
+            ("code_challenge", CODE_CHALLENGE.to_string()),
+            ("code_challenge_method", "S256".to_string()),
+            ("username", username.to_string()),
+            ("password", password.to_string()),
+        ]
+    }
+
+    fn parameters(request: &[(&'static str, String)]) -> Vec<(&'static str, &str)> {
+        request.iter().map(|&(key, ref value)| (key, value.as_str())).collect()
+    }
+
+    #[test]
+    fn authorization_code_grant() {
+        let rocket = ignite_with_authorization_code();
+        let client = not_err!(Client::new(rocket));
+
+        let request = authorization_request("", "");
+        let mut response = client.get(format!("/authorize?{}", form(&parameters(&request[..7])))).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("X-Frame-Options"), Some("DENY"));
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        assert!(body_str.contains(r#"action="authorize""#));
+        assert!(body_str.contains(CODE_CHALLENGE));
+
+        let request = authorization_request("mei", "冻住，不许走!");
+        let response = client
+            .post("/authorize")
+            .header(ContentType::Form)
+            .body(form(&parameters(&request)))
+            .dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+        let location: hyper::Url = not_err!(not_none!(response.headers().get_one("Location")).parse());
+        assert!(location.as_str().starts_with("https://ui.example.com/callback?"));
+        let query: HashMap<_, _> = location.query_pairs().into_owned().collect();
+        assert_eq!(query["state"], "af0ifjsldkj");
+        let code = not_none!(query.get("code"));
+
+        let body = form(
+            &[
+                ("grant_type", "authorization_code"),
+                ("code", code.as_str()),
+                ("client_id", "registry-ui"),
+                ("redirect_uri", "https://ui.example.com/callback"),
+                ("code_verifier", CODE_VERIFIER),
+            ],
+        );
+        let mut response = client
+            .post("/token")
+            .header(ContentType::Form)
+            .body(body.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        let token: oauth::AccessTokenResponse = not_err!(serde_json::from_str(&body_str));
+        assert_eq!(token.scope, Some("openid".to_string()));
+        assert!(token.id_token.is_some());
+
+        // Codes can only be exchanged once
+        let mut response = client
+            .post("/token")
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        let error: JsonValue = not_err!(serde_json::from_str(&body_str));
+        assert_eq!(error["error"], "invalid_grant");
+    }
+
+    #[test]
+    fn authorization_code_login_form_is_shown_again_for_wrong_passwords() {
+        let rocket = ignite_with_authorization_code();
+        let client = not_err!(Client::new(rocket));
+
+        let request = authorization_request("mei", "wrong");
+        let mut response = client
+            .post("/authorize")
+            .header(ContentType::Form)
+            .body(form(&parameters(&request)))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.headers().get_one("Location").is_none());
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        assert!(body_str.contains("The username or password is incorrect"));
+        assert!(body_str.contains(r#"value="mei""#));
+    }
+
+    #[test]
+    fn authorization_code_grant_is_not_enabled_by_default() {
+        let rocket = ignite();
+        let client = not_err!(Client::new(rocket));
+
+        let request = authorization_request("", "");
+        let response = client.get(format!("/authorize?{}", form(&parameters(&request[..7])))).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+
+        let rocket = ignite_with_authorization_code();
+        let client = not_err!(Client::new(rocket));
+        let mut response = client.get("/.well-known/oauth-authorization-server").dispatch();
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        let metadata: oauth::AuthorizationServerMetadata = not_err!(serde_json::from_str(&body_str));
+        assert_eq!(metadata.authorization_endpoint, Some("https://www.acme.com/authorize".to_string()));
+        assert!(metadata.grant_types_supported.contains(&"authorization_code".to_string()));
+        assert_eq!(metadata.code_challenge_methods_supported, vec!["S256"]);
+    }
+
+    fn ignite_with_device(throttle: Option<::throttle::Configuration>) -> Rocket {
+        let mut configuration = configuration_with(throttle, None, None);
+        let json = r#"{ "clients": { "registry-cli": { "name": "Registry CLI" } } }"#;
+        configuration.device = Some(not_err!(serde_json::from_str(json)));
+        ignite_with_configuration(configuration)
+    }
+
+    fn request_device_code(client: &Client) -> device::DeviceAuthorizationResponse {
+        let mut response = client
+            .post("/device/code")
+            .header(ContentType::Form)
+            .body(form(&[("client_id", "registry-cli"), ("scope", "all")]))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("Cache-Control"), Some("no-store"));
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        not_err!(serde_json::from_str(&body_str))
+    }
+
+    fn poll_device_code(client: &Client, device_code: &str) -> (Status, JsonValue) {
+        let body = form(
+            &[
+                ("grant_type", oauth::DEVICE_CODE_GRANT),
+                ("device_code", device_code),
+                ("client_id", "registry-cli"),
+            ],
+        );
+        let mut response = client
+            .post("/token")
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch();
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        (response.status(), not_err!(serde_json::from_str(&body_str)))
+    }
+
+    fn verify_device(client: &Client, user_code: &str, password: &str, action: &str) -> String {
+        let body = form(
+            &[
+                ("user_code", user_code),
+                ("username", "mei"),
+                ("password", password),
+                ("action", action),
+            ],
+        );
+        let mut response = client
+            .post("/device")
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        not_none!(response.body().and_then(|body| body.into_string()))
+    }
+
+    #[test]
+    fn device_code_grant() {
+        let rocket = ignite_with_device(None);
+        let client = not_err!(Client::new(rocket));
+
+        let device = request_device_code(&client);
+        assert_eq!(device.verification_uri, "https://www.acme.com/device");
+        assert_eq!(device.interval, 5);
+
+        let (status, error) = poll_device_code(&client, &device.device_code);
+        assert_eq!(status, Status::BadRequest);
+        assert_eq!(error["error"], "authorization_pending");
+
+        let mut response = client.get(format!("/device?user_code={}", device.user_code)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        assert!(body_str.contains("Registry CLI is requesting access to https://www.example.com"));
+
+        let body_str = verify_device(&client, &device.user_code, "wrong", "approve");
+        assert!(body_str.contains("The username or password is incorrect"));
+        let body_str = verify_device(&client, &device.user_code, "冻住，不许走!", "approve");
+        assert!(body_str.contains("The device is connected"));
+
+        let (status, body) = poll_device_code(&client, &device.device_code);
+        assert_eq!(status, Status::Ok);
+        let body: oauth::AccessTokenResponse = not_err!(serde_json::from_value(body));
+        assert_eq!(body.scope, Some("all".to_string()));
+
+        // Device codes can only be exchanged once
+        let (status, error) = poll_device_code(&client, &device.device_code);
+        assert_eq!(status, Status::BadRequest);
+        assert_eq!(error["error"], "invalid_grant");
+    }
+
+    #[test]
+    fn device_code_grant_can_be_denied() {
+        let rocket = ignite_with_device(None);
+        let client = not_err!(Client::new(rocket));
+
+        let mut response = client.get("/device").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        assert!(body_str.contains(r#"action="device""#));
+
+        // Denying a request also requires the user to log in
+        let device = request_device_code(&client);
+        let body_str = verify_device(&client, &device.user_code, "", "deny");
+        assert!(body_str.contains("The username or password is incorrect"));
+        let body_str = verify_device(&client, &device.user_code, "冻住，不许走!", "deny");
+        assert!(body_str.contains("denied"));
+        let (status, error) = poll_device_code(&client, &device.device_code);
+        assert_eq!(status, Status::BadRequest);
+        assert_eq!(error["error"], "access_denied");
+
+        let body_str = verify_device(&client, &device.user_code, "冻住，不许走!", "approve");
+        assert!(body_str.contains("The code is invalid or has expired"));
+    }
+
+    #[test]
+    fn device_codes_and_user_codes_are_throttled_by_address() {
+        let rocket = ignite_with_device(Some(::throttle::Configuration {
+            trust_forwarded_for: true,
+            free_attempts: 1,
+            backoff: Duration::from_secs(60),
+            ..Default::default()
+        }));
+        let client = not_err!(Client::new(rocket));
+
+        let statuses: Vec<Status> = (0..3)
+            .map(|_| {
+                client
+                    .post("/device/code")
+                    .header(ContentType::Form)
+                    .header(Header::new("X-Forwarded-For", "192.0.2.1"))
+                    .body(form(&[("client_id", "registry-cli"), ("scope", "all")]))
+                    .dispatch()
+                    .status()
+            })
+            .collect();
+        assert_eq!(statuses, vec![Status::Ok, Status::Ok, Status::TooManyRequests]);
+
+        let device = request_device_code(&client);
+        let verification_page = |user_code: &str| {
+            let mut response = client
+                .get(format!("/device?user_code={}", user_code))
+                .header(Header::new("X-Forwarded-For", "192.0.2.2"))
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            not_none!(response.body().and_then(|body| body.into_string()))
+        };
+        for _ in 0..2 {
+            assert!(verification_page("BCDF-GHJK").contains("The code is invalid or has expired"));
+        }
+        // Once throttled, even valid codes are refused
+        assert!(verification_page(&device.user_code).contains("Too many failed attempts"));
+
+        let mut response = client
+            .post("/device")
+            .header(ContentType::Form)
+            .header(Header::new("X-Forwarded-For", "192.0.2.2"))
+            .body(form(&[("user_code", device.user_code.as_str()), ("action", "deny")]))
+            .dispatch();
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        assert!(body_str.contains("Too many failed attempts"));
+
+        // Other clients are not affected
+        let body_str = verify_device(&client, &device.user_code, "冻住，不许走!", "approve");
+        assert!(body_str.contains("The device is connected"));
+    }
+
+    #[test]
+    fn device_code_grant_is_not_enabled_by_default() {
+        let rocket = ignite();
+        let client = not_err!(Client::new(rocket));
+
+        let response = client
+            .post("/device/code")
+            .header(ContentType::Form)
+            .body(form(&[("client_id", "registry-cli")]))
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+
+        let rocket = ignite_with_device(None);
+        let client = not_err!(Client::new(rocket));
+        let mut response = client.get("/.well-known/oauth-authorization-server").dispatch();
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        let metadata: oauth::AuthorizationServerMetadata = not_err!(serde_json::from_str(&body_str));
+        assert_eq!(
+            metadata.device_authorization_endpoint,
+            Some("https://www.acme.com/device/code".to_string())
+        );
+        assert!(metadata.grant_types_supported.contains(&oauth::DEVICE_CODE_GRANT.to_string()));
+        assert!(metadata.token_endpoint_auth_methods_supported.contains(&"none".to_string()));
+    }
+
+    #[cfg(feature = "api_key_authenticator")]
+    fn ignite_with_token_exchange() -> Rocket {
+        ignite_with_configuration(token_exchange_configuration())
+    }
+
+    #[cfg(feature = "api_key_authenticator")]
+    fn token_exchange_configuration() -> ::Configuration<::auth::tests::MockAuthenticatorConfiguration> {
+        let mut configuration = configuration_with(None, None, None);
+        configuration.token.audience = jwt::SingleOrMultiple::Multiple(vec![
+            not_err!(FromStr::from_str("https://www.example.com")),
+            not_err!(FromStr::from_str("https://api.example.com")),
+            not_err!(FromStr::from_str("https://other.example.com")),
+        ]);
+        let json = r#"{ "services": { "https://www.example.com": ["https://api.example.com"] } }"#;
+        configuration.token_exchange = Some(not_err!(serde_json::from_str(json)));
+        configuration
+    }
+
+    #[cfg(feature = "api_key_authenticator")]
+    fn exchange_token(client: &Client, subject_token: &str, resource: &str) -> (Status, JsonValue) {
+        let body = form(
+            &[
+                ("grant_type", oauth::TOKEN_EXCHANGE_GRANT),
+                ("subject_token", subject_token),
+                ("subject_token_type", token_exchange::ACCESS_TOKEN_TYPE_URI),
+                ("resource", resource),
+                ("scope", "read"),
+            ],
+        );
+        let mut response = client
+            .post("/token")
+            .header(ContentType::Form)
+            .header(service_authorization())
+            .body(body)
+            .dispatch();
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        (response.status(), not_err!(serde_json::from_str(&body_str)))
+    }
+
+    #[cfg(feature = "api_key_authenticator")]
+    fn subject_token(client: &Client) -> oauth::AccessTokenResponse {
+        let body = form(
+            &[
+                ("grant_type", "password"),
+                ("username", "mei"),
+                ("password", "冻住，不许走!"),
+                ("resource", "https://www.example.com"),
+            ],
+        );
+        let mut response = client
+            .post("/token")
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body_str = not_none!(response.body().and_then(|body| body.into_string()));
+        not_err!(serde_json::from_str(&body_str))
+    }
+
+    #[test]
+    #[cfg(feature = "api_key_authenticator")]
+    fn token_exchange_grant() {
+        let rocket = ignite_with_token_exchange();
+        let client = not_err!(Client::new(rocket));
+
+        let subject_token = subject_token(&client);
+        let (status, body) = exchange_token(&client, &subject_token.access_token, "https://api.example.com");
+        assert_eq!(status, Status::Ok);
+        let body: oauth::AccessTokenResponse = not_err!(serde_json::from_value(body));
+        assert_eq!(body.issued_token_type, Some(token_exchange::ACCESS_TOKEN_TYPE_URI.to_string()));
+        assert_eq!(body.scope, Some("read".to_string()));
+        assert!(body.refresh_token.is_none());
+        assert!(body.expires_in <= subject_token.expires_in);
+
+        let token = jwt::JWT::<PrivateClaim, jwt::Empty>::new_encoded(&body.access_token);
+        let token = not_err!(token.into_decoded(
+            &jwt::jws::Secret::bytes_from_str("secret"),
+            jwt::jwa::SignatureAlgorithm::HS512,
+        ));
+        let claims = not_err!(token.payload());
+        assert_eq!(not_none!(claims.registered.subject.as_ref()).to_string(), "mei");
+        assert_eq!(
+            Some(jwt::SingleOrMultiple::Single(
+                FromStr::from_str("https://api.example.com").unwrap(),
+            )),
+            claims.registered.audience
+        );
+        assert_eq!(claims.private["act"]["sub"], "registry-ci");
+        assert_eq!(claims.private["scope"], "read");
+
+        // Only the configured services can be exchanged for
+        let (status, error) = exchange_token(&client, &subject_token.access_token, "https://other.example.com");
+        assert_eq!(status, Status::BadRequest);
+        assert_eq!(error["error"], "invalid_target");
+
+        let (status, error) = exchange_token(&client, "not.a.token", "https://api.example.com");
+        assert_eq!(status, Status::BadRequest);
+        assert_eq!(error["error"], "invalid_grant");
+    }
+
+    #[test]
+    #[cfg(feature = "api_key_authenticator")]
+    fn exchanged_tokens_keep_their_restrictions_for_services_with_allowed_private_claims() {
+        let mut configuration = token_exchange_configuration();
+        let service = not_err!(serde_json::from_str(r#"{ "private_claims": ["groups"] }"#));
+        let _ = configuration.token.services.insert("https://api.example.com".to_string(), service);
+        let rocket = ignite_with_configuration(configuration);
+        let client = not_err!(Client::new(rocket));
+
+        let subject_token = subject_token(&client);
+        let (status, body) = exchange_token(&client, &subject_token.access_token, "https://api.example.com");
+        assert_eq!(status, Status::Ok);
+        let body: oauth::AccessTokenResponse = not_err!(serde_json::from_value(body));
+
+        let token = jwt::JWT::<PrivateClaim, jwt::Empty>::new_encoded(&body.access_token);
+        let token = not_err!(token.into_decoded(
+            &jwt::jws::Secret::bytes_from_str("secret"),
+            jwt::jwa::SignatureAlgorithm::HS512,
+        ));
+        let claims = not_err!(token.payload());
+        assert_eq!(claims.private["act"]["sub"], "registry-ci");
+        assert_eq!(claims.private["scope"], "read");
+    }
+
+    #[test]
+    #[cfg(feature = "api_key_authenticator")]
+    fn token_exchange_grant_is_not_enabled_by_default() {
+        let rocket = ignite();
+        let client = not_err!(Client::new(rocket));
+
+        let (status, error) = exchange_token(&client, "not.a.token", "https://www.example.com");
+        assert_eq!(status, Status::BadRequest);
+        assert_eq!(error["error"], "unsupported_grant_type");
     }
 }
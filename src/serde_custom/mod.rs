@@ -1,2 +1,3 @@
 //! Custom serde serialization and deserialization.
 pub mod duration;
+pub mod option_duration;
@@ -0,0 +1,54 @@
+//! Custom serializer and deserializer for `Option<std::time::Duration>`. Serializes to seconds, and deserializes from
+//! seconds.
+use std::time::Duration;
+use serde::{Deserialize, Serializer, Deserializer};
+
+/// Serialize an `Option<Duration>` into an `Option<u64>` representing the seconds
+pub fn serialize<S>(duration: &Option<Duration>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match *duration {
+        Some(ref duration) => serializer.serialize_some(&duration.as_secs()),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// From an `Option<u64>`, deserialize into an `Option<Duration>` with the `u64` in seconds
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let duration = Option::<u64>::deserialize(deserializer)?;
+    Ok(duration.map(Duration::from_secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use serde_json;
+
+    #[derive(Serialize, Deserialize, Eq, PartialEq, Debug)]
+    struct TestStruct {
+        #[serde(with = "super", default, skip_serializing_if = "Option::is_none")]
+        duration: Option<Duration>,
+    }
+
+    #[test]
+    fn serialization_round_trip() {
+        let structure = TestStruct { duration: Some(Duration::from_secs(1234)) };
+
+        let expected_json = "{\"duration\":1234}";
+        let actual_json = not_err!(serde_json::to_string(&structure));
+        assert_eq!(expected_json, actual_json);
+
+        let deserialized_struct: TestStruct = not_err!(serde_json::from_str(&actual_json));
+        assert_eq!(structure, deserialized_struct);
+    }
+
+    #[test]
+    fn missing_durations_are_deserialized_as_none() {
+        let deserialized_struct: TestStruct = not_err!(serde_json::from_str("{}"));
+        assert_eq!(deserialized_struct, TestStruct { duration: None });
+    }
+}
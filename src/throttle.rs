@@ -0,0 +1,669 @@
+//! Throttling of failed authentication attempts
+//!
+//! Failed authentication attempts are counted by the IP address of the client, and by the username attempted.
+//! After `free_attempts` failures, further attempts are refused with a `429 Too Many Requests` response and a
+//! `Retry-After` header for an exponentially increasing duration. After `lockout_threshold` failures, attempts are
+//! refused for the `lockout_duration`. Failures are forgotten after `window` passes without another failure, and a
+//! successful authentication resets the count for the username.
+//!
+//! Because attempts are refused before the authenticator is called, this also protects directories behind the
+//! authenticator, like LDAP, from locking out users because of guessed passwords.
+//!
+//! Unknown user codes entered on the device verification page count as failures of the client address. Requests for
+//! device codes are counted by the client address even when they succeed, and are throttled in the same way.
+//!
+//! # Configuration Example
+//!
+//! Throttling is enabled by the `throttle` key of [`rowdy::Configuration`](../struct.Configuration.html). All keys
+//! are optional.
+//!
+//! ```json
+//! {
+//!     "trust_forwarded_for": false,
+//!     "free_attempts": 3,
+//!     "backoff": 1,
+//!     "max_backoff": 60,
+//!     "lockout_threshold": 10,
+//!     "lockout_duration": 900,
+//!     "window": 3600,
+//!     "store": { "File": { "path": "throttle.json" } }
+//! }
+//! ```
+use std::cmp;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use chrono::Utc;
+use jwt::jwa;
+use rocket::Outcome;
+use rocket::request::{self, FromRequest, Request};
+use serde_json;
+
+use auth;
+
+/// Stores are pruned of stale entries after this many failures
+const PRUNE_INTERVAL: usize = 1024;
+
+/// Failed authentication attempts for a key
+#[derive(Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize, Debug)]
+pub struct Entry {
+    /// Number of failures within the window
+    pub failures: u32,
+    /// Seconds since the Unix epoch of the last failure
+    pub last_failure: u64,
+    /// Seconds since the Unix epoch until which attempts are refused
+    pub blocked_until: u64,
+}
+
+/// Entries, indexed by their key
+pub type Entries = HashMap<String, Entry>;
+
+/// Storage for the entries of the `Throttle`
+pub trait Store: Send + Sync {
+    /// Retrieve the entry for `key`, if any
+    fn get(&self, key: &str) -> Result<Option<Entry>, ::Error>;
+
+    /// Atomically replace the entry for `key` with the result of `update`. The entry is removed if `update` returns
+    /// `None`.
+    fn update(&self, key: &str, update: &Fn(Option<Entry>) -> Option<Entry>) -> Result<(), ::Error>;
+
+    /// Remove the entries for which `retain` returns `false`
+    fn prune(&self, retain: &Fn(&Entry) -> bool) -> Result<(), ::Error>;
+}
+
+/// A `Store` that keeps the entries in memory. Entries are lost on restart, and not shared between instances.
+#[derive(Default, Debug)]
+pub struct MemoryStore {
+    entries: Mutex<Entries>,
+}
+
+impl MemoryStore {
+    /// Create a new empty `MemoryStore`
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn lock(&self) -> Result<::std::sync::MutexGuard<Entries>, ::Error> {
+        Ok(self.entries.lock().map_err(
+            |_| "Throttle store lock is poisoned".to_string(),
+        )?)
+    }
+}
+
+impl Store for MemoryStore {
+    fn get(&self, key: &str) -> Result<Option<Entry>, ::Error> {
+        Ok(self.lock()?.get(key).cloned())
+    }
+
+    fn update(&self, key: &str, update: &Fn(Option<Entry>) -> Option<Entry>) -> Result<(), ::Error> {
+        let mut entries = self.lock()?;
+        match update(entries.get(key).cloned()) {
+            Some(entry) => {
+                let _ = entries.insert(key.to_string(), entry);
+            }
+            None => {
+                let _ = entries.remove(key);
+            }
+        }
+        Ok(())
+    }
+
+    fn prune(&self, retain: &Fn(&Entry) -> bool) -> Result<(), ::Error> {
+        let mut entries = self.lock()?;
+        entries.retain(|_, entry| retain(entry));
+        Ok(())
+    }
+}
+
+/// A `Store` that keeps the entries in a JSON file, which can be shared between instances on the same host or a
+/// shared file system. The file is read and replaced on every update. The entries are written to a temporary file
+/// that is renamed over the file, so the file is never partially written. Updates from different instances at the
+/// same time can overwrite each other. A file that cannot be parsed is logged and treated as empty.
+///
+/// There is no database backed store, such as SQLite. Use a `FileStore` on a shared file system to share entries
+/// between hosts.
+#[derive(Debug)]
+pub struct FileStore {
+    path: String,
+    lock: Mutex<()>,
+}
+
+impl FileStore {
+    /// Create a new `FileStore` at `path`. The file is created on the first update.
+    pub fn new(path: &str) -> Self {
+        FileStore {
+            path: path.to_string(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn read(&self) -> Result<Entries, ::Error> {
+        if !Path::new(&self.path).exists() {
+            return Ok(Entries::new());
+        }
+        let file = File::open(&self.path)?;
+        match serde_json::from_reader(file) {
+            Ok(entries) => Ok(entries),
+            Err(e) => {
+                warn_!("Ignoring the throttle store at {}, which cannot be parsed: {}", self.path, e);
+                Ok(Entries::new())
+            }
+        }
+    }
+
+    /// Write the entries to a temporary file next to the file, and rename it over the file
+    fn write(&self, entries: &Entries) -> Result<(), ::Error> {
+        use jwt::jwa::SecureRandom;
+
+        let mut suffix = [0; 8];
+        jwa::rng().fill(&mut suffix).map_err(
+            |_| "Unable to generate the name of a temporary file".to_string(),
+        )?;
+        let suffix: String = suffix.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let temporary = format!("{}.{}.tmp", self.path, suffix);
+        let written = File::create(&temporary)
+            .map_err(::Error::from)
+            .and_then(|file| Ok(serde_json::to_writer(file, entries)?))
+            .and_then(|_| Ok(fs::rename(&temporary, &self.path)?));
+        if written.is_err() {
+            let _ = fs::remove_file(&temporary);
+        }
+        written
+    }
+
+    /// Read, modify and write the entries while holding the lock
+    fn modify(&self, modify: &Fn(&mut Entries)) -> Result<(), ::Error> {
+        let _lock = self.lock.lock().map_err(
+            |_| "Throttle store lock is poisoned".to_string(),
+        )?;
+        let mut entries = self.read()?;
+        modify(&mut entries);
+        self.write(&entries)
+    }
+}
+
+impl Store for FileStore {
+    fn get(&self, key: &str) -> Result<Option<Entry>, ::Error> {
+        let _lock = self.lock.lock().map_err(
+            |_| "Throttle store lock is poisoned".to_string(),
+        )?;
+        Ok(self.read()?.get(key).cloned())
+    }
+
+    fn update(&self, key: &str, update: &Fn(Option<Entry>) -> Option<Entry>) -> Result<(), ::Error> {
+        self.modify(&|entries| match update(entries.get(key).cloned()) {
+            Some(entry) => {
+                let _ = entries.insert(key.to_string(), entry);
+            }
+            None => {
+                let _ = entries.remove(key);
+            }
+        })
+    }
+
+    fn prune(&self, retain: &Fn(&Entry) -> bool) -> Result<(), ::Error> {
+        self.modify(&|entries| entries.retain(|_, entry| retain(entry)))
+    }
+}
+
+/// Where the entries of the `Throttle` are stored
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize, Debug)]
+pub enum StoreConfiguration {
+    /// In memory. See `MemoryStore`.
+    Memory,
+    /// In a JSON file. See `FileStore`.
+    File {
+        /// Path to the file. This should be relative to the working directory, or an absolute path.
+        path: String,
+    },
+}
+
+impl Default for StoreConfiguration {
+    fn default() -> Self {
+        StoreConfiguration::Memory
+    }
+}
+
+/// Throttle configuration. Usually deserialized as part of [`rowdy::Configuration`] from JSON for use.
+/// See the module documentation for an example.
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct Configuration {
+    /// Whether to use the first address in the `X-Forwarded-For` header as the address of the client. Only enable
+    /// this behind a reverse proxy that sets the header. Defaults to `false`.
+    pub trust_forwarded_for: bool,
+    /// Number of failures before attempts are refused. Defaults to 3.
+    pub free_attempts: u32,
+    /// Seconds that attempts are refused for after the first failure beyond `free_attempts`. This doubles with each
+    /// further failure. Defaults to 1.
+    #[serde(with = "::serde_custom::duration")]
+    pub backoff: Duration,
+    /// Maximum seconds that attempts are refused for before the lockout. Defaults to 60.
+    #[serde(with = "::serde_custom::duration")]
+    pub max_backoff: Duration,
+    /// Number of failures after which attempts are refused for the `lockout_duration`. Set to 0 to disable the
+    /// lockout. Defaults to 10.
+    pub lockout_threshold: u32,
+    /// Seconds that attempts are refused for after the `lockout_threshold` is reached. Defaults to 900.
+    #[serde(with = "::serde_custom::duration")]
+    pub lockout_duration: Duration,
+    /// Seconds after the last failure when the failures are forgotten. Defaults to 3600.
+    #[serde(with = "::serde_custom::duration")]
+    pub window: Duration,
+    /// Where the failures are stored. Defaults to `Memory`.
+    pub store: StoreConfiguration,
+}
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Configuration {
+            trust_forwarded_for: false,
+            free_attempts: 3,
+            backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            lockout_threshold: 10,
+            lockout_duration: Duration::from_secs(900),
+            window: Duration::from_secs(3600),
+            store: Default::default(),
+        }
+    }
+}
+
+/// The addresses of the client making a request. This request guard always succeeds.
+#[derive(Clone, Debug)]
+pub struct Client {
+    /// Address of the remote end of the connection, if known
+    pub remote: Option<IpAddr>,
+    /// The first address in the `X-Forwarded-For` header, if any
+    pub forwarded_for: Option<IpAddr>,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Client {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        let forwarded_for = request
+            .headers()
+            .get_one("X-Forwarded-For")
+            .and_then(|addresses| addresses.split(',').next())
+            .and_then(|address| address.trim().parse().ok());
+        Outcome::Success(Client {
+            remote: request.remote().map(|address| address.ip()),
+            forwarded_for,
+        })
+    }
+}
+
+/// Throttles failed authentication attempts. This is managed by Rocket and should be retrieved with `State`.
+pub struct Throttle {
+    configuration: Configuration,
+    store: Box<Store>,
+    enabled: bool,
+    failures: AtomicUsize,
+}
+
+impl Throttle {
+    /// Create a new `Throttle` with the store in the configuration
+    pub fn new(configuration: Configuration) -> Self {
+        let store: Box<Store> = match configuration.store {
+            StoreConfiguration::Memory => Box::new(MemoryStore::new()),
+            StoreConfiguration::File { ref path } => Box::new(FileStore::new(path)),
+        };
+        Self::with_store(configuration, store)
+    }
+
+    /// Create a new `Throttle` with a custom store
+    pub fn with_store(configuration: Configuration, store: Box<Store>) -> Self {
+        Throttle {
+            configuration,
+            store,
+            enabled: true,
+            failures: AtomicUsize::new(0),
+        }
+    }
+
+    /// Create a `Throttle` that allows all attempts
+    pub fn disabled() -> Self {
+        Throttle {
+            configuration: Default::default(),
+            store: Box::new(MemoryStore::new()),
+            enabled: false,
+            failures: AtomicUsize::new(0),
+        }
+    }
+
+    /// Call `authenticate` unless the client or the username is throttled. Failures due to
+    /// `auth::Error::AuthenticationFailure` are recorded for both, and successes reset the failures of the username.
+    pub fn attempt<T, F>(&self, client: &Client, username: Option<&str>, authenticate: F) -> Result<T, ::Error>
+    where
+        F: FnOnce() -> Result<T, ::Error>,
+    {
+        if !self.enabled {
+            return authenticate();
+        }
+
+        let now = Utc::now().timestamp() as u64;
+        let username_key = username.map(|username| format!("username:{}", username.to_lowercase()));
+        let mut keys = vec![];
+        if let Some(address) = self.client_address(client) {
+            keys.push(format!("address:{}", address));
+        }
+        if let Some(ref username_key) = username_key {
+            keys.push(username_key.clone());
+        }
+
+        self.check(&keys, now)?;
+        match authenticate() {
+            Ok(result) => {
+                if let Some(ref username_key) = username_key {
+                    self.store.update(username_key, &|_| None)?;
+                }
+                Ok(result)
+            }
+            Err(::Error::Auth(auth::Error::AuthenticationFailure)) => {
+                self.record_failure(&keys, now)?;
+                Err(auth::Error::AuthenticationFailure)?
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Count a request of `client` that is limited even when it succeeds, such as requesting a device code, and refuse
+    /// it if the client made too many. Requests are counted under `kind`, separately from failed attempts, and are
+    /// throttled in the same way.
+    pub fn limit(&self, client: &Client, kind: &str) -> Result<(), ::Error> {
+        if !self.enabled {
+            return Ok(());
+        }
+        let keys = match self.client_address(client) {
+            Some(address) => vec![format!("{}:{}", kind, address)],
+            None => return Ok(()),
+        };
+        let now = Utc::now().timestamp() as u64;
+        self.check(&keys, now)?;
+        self.record_failure(&keys, now)
+    }
+
+    fn client_address(&self, client: &Client) -> Option<IpAddr> {
+        if self.configuration.trust_forwarded_for {
+            client.forwarded_for.or(client.remote)
+        } else {
+            client.remote
+        }
+    }
+
+    /// Refuse the attempt if any of the keys is blocked at `now`
+    fn check(&self, keys: &[String], now: u64) -> Result<(), ::Error> {
+        let mut retry_after = 0;
+        for key in keys {
+            if let Some(entry) = self.store.get(key)? {
+                retry_after = cmp::max(retry_after, entry.blocked_until.saturating_sub(now));
+            }
+        }
+        if retry_after > 0 {
+            debug_!("Authentication attempt throttled for {} seconds", retry_after);
+            Err(auth::Error::TooManyAttempts { retry_after })?;
+        }
+        Ok(())
+    }
+
+    /// Seconds to refuse attempts for after `failures` failures
+    fn delay(&self, failures: u32) -> u64 {
+        let configuration = &self.configuration;
+        if configuration.lockout_threshold > 0 && failures >= configuration.lockout_threshold {
+            configuration.lockout_duration.as_secs()
+        } else if failures <= configuration.free_attempts {
+            0
+        } else {
+            let exponent = cmp::min(failures - configuration.free_attempts - 1, 32);
+            cmp::min(
+                configuration.backoff.as_secs().saturating_mul(1 << exponent),
+                configuration.max_backoff.as_secs(),
+            )
+        }
+    }
+
+    fn record_failure(&self, keys: &[String], now: u64) -> Result<(), ::Error> {
+        let window = self.configuration.window.as_secs();
+        for key in keys {
+            self.store.update(key, &|entry| {
+                let mut entry = entry.unwrap_or_default();
+                if now.saturating_sub(entry.last_failure) > window {
+                    entry.failures = 0;
+                }
+                entry.failures = entry.failures.saturating_add(1);
+                entry.last_failure = now;
+                entry.blocked_until = now + self.delay(entry.failures);
+                Some(entry)
+            })?;
+        }
+
+        if self.failures.fetch_add(1, Ordering::Relaxed) % PRUNE_INTERVAL == PRUNE_INTERVAL - 1 {
+            self.store.prune(&|entry| {
+                entry.blocked_until > now || now.saturating_sub(entry.last_failure) <= window
+            })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn configuration() -> Configuration {
+        Configuration {
+            free_attempts: 2,
+            backoff: Duration::from_secs(10),
+            max_backoff: Duration::from_secs(25),
+            lockout_threshold: 5,
+            lockout_duration: Duration::from_secs(100),
+            ..Default::default()
+        }
+    }
+
+    fn keys() -> Vec<String> {
+        vec!["address:127.0.0.1".to_string(), "username:mei".to_string()]
+    }
+
+    fn retry_after(result: Result<(), ::Error>) -> u64 {
+        match result {
+            Err(::Error::Auth(auth::Error::TooManyAttempts { retry_after })) => retry_after,
+            Ok(()) => 0,
+            Err(e) => panic!("Unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn configuration_defaults() {
+        let deserialized: Configuration = not_err!(serde_json::from_str("{}"));
+        assert_eq!(deserialized, Default::default());
+
+        let json = r#"{ "free_attempts": 5, "store": { "File": { "path": "throttle.json" } } }"#;
+        let deserialized: Configuration = not_err!(serde_json::from_str(json));
+        assert_eq!(deserialized.free_attempts, 5);
+        assert_eq!(deserialized.store, StoreConfiguration::File { path: "throttle.json".to_string() });
+        assert_eq!(deserialized.window, Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn delays_back_off_exponentially_then_lock_out() {
+        let throttle = Throttle::new(configuration());
+        let delays: Vec<u64> = (1..7).map(|failures| throttle.delay(failures)).collect();
+        assert_eq!(delays, vec![0, 0, 10, 20, 100, 100]);
+    }
+
+    #[test]
+    fn failures_are_throttled() {
+        let throttle = Throttle::new(configuration());
+        let keys = keys();
+
+        not_err!(throttle.record_failure(&keys, 1000));
+        not_err!(throttle.record_failure(&keys, 1000));
+        assert_eq!(retry_after(throttle.check(&keys, 1000)), 0);
+
+        not_err!(throttle.record_failure(&keys, 1000));
+        assert_eq!(retry_after(throttle.check(&keys, 1000)), 10);
+        assert_eq!(retry_after(throttle.check(&keys, 1004)), 6);
+        assert_eq!(retry_after(throttle.check(&keys, 1010)), 0);
+
+        // Only the username is blocked for other addresses
+        assert_eq!(
+            retry_after(throttle.check(&["address:10.0.0.1".to_string(), "username:mei".to_string()], 1000)),
+            10
+        );
+        assert_eq!(retry_after(throttle.check(&["username:foobar".to_string()], 1000)), 0);
+    }
+
+    #[test]
+    fn failures_are_forgotten_after_the_window() {
+        let throttle = Throttle::new(configuration());
+        let keys = keys();
+        for _ in 0..3 {
+            not_err!(throttle.record_failure(&keys, 1000));
+        }
+        not_err!(throttle.record_failure(&keys, 1000 + 3601));
+        assert_eq!(retry_after(throttle.check(&keys, 1000 + 3601)), 0);
+    }
+
+    #[test]
+    fn attempts_are_throttled_and_reset() {
+        let throttle = Throttle::new(configuration());
+        let client = Client {
+            remote: Some("127.0.0.1".parse().unwrap()),
+            forwarded_for: None,
+        };
+
+        for _ in 0..3 {
+            let result: Result<(), ::Error> = throttle.attempt(&client, Some("Mei"), || {
+                Err(auth::Error::AuthenticationFailure)?
+            });
+            assert_matches_non_debug!(result, Err(::Error::Auth(auth::Error::AuthenticationFailure)));
+        }
+
+        let result = throttle.attempt(&client, Some("mei"), || Ok(()));
+        assert_matches_non_debug!(result, Err(::Error::Auth(auth::Error::TooManyAttempts { .. })));
+
+        // Errors that are not authentication failures are not counted
+        let entry = not_none!(not_err!(throttle.store.get("username:mei")));
+        assert_eq!(entry.failures, 3);
+
+        // A success resets the username, but not the address
+        not_err!(throttle.store.update("address:127.0.0.1", &|_| None));
+        not_err!(throttle.store.update("username:mei", &|entry| {
+            entry.map(|entry| Entry { blocked_until: 0, ..entry })
+        }));
+        not_err!(throttle.attempt(&client, Some("mei"), || Ok(())));
+        assert!(not_err!(throttle.store.get("username:mei")).is_none());
+    }
+
+    #[test]
+    fn limited_requests_are_counted_by_address() {
+        let throttle = Throttle::new(configuration());
+        let client = Client {
+            remote: Some("127.0.0.1".parse().unwrap()),
+            forwarded_for: None,
+        };
+
+        for _ in 0..3 {
+            not_err!(throttle.limit(&client, "device_code"));
+        }
+        let result = throttle.limit(&client, "device_code");
+        assert_matches_non_debug!(result, Err(::Error::Auth(auth::Error::TooManyAttempts { .. })));
+
+        // Other kinds of requests and failed attempts are counted separately
+        assert!(not_err!(throttle.store.get("address:127.0.0.1")).is_none());
+        not_err!(throttle.attempt(&client, None, || Ok(())));
+
+        let unknown = Client {
+            remote: None,
+            forwarded_for: None,
+        };
+        for _ in 0..5 {
+            not_err!(throttle.limit(&unknown, "device_code"));
+        }
+    }
+
+    #[test]
+    fn forwarded_for_is_only_used_when_trusted() {
+        let client = Client {
+            remote: Some("127.0.0.1".parse().unwrap()),
+            forwarded_for: Some("10.0.0.1".parse().unwrap()),
+        };
+
+        let throttle = Throttle::new(configuration());
+        assert_eq!(throttle.client_address(&client), client.remote);
+
+        let throttle = Throttle::new(Configuration {
+            trust_forwarded_for: true,
+            ..configuration()
+        });
+        assert_eq!(throttle.client_address(&client), client.forwarded_for);
+    }
+
+    #[test]
+    fn disabled_throttle_allows_everything() {
+        let throttle = Throttle::disabled();
+        let client = Client {
+            remote: None,
+            forwarded_for: None,
+        };
+        for _ in 0..10 {
+            let result: Result<(), ::Error> = throttle.attempt(&client, Some("mei"), || {
+                Err(auth::Error::AuthenticationFailure)?
+            });
+            assert_matches_non_debug!(result, Err(::Error::Auth(auth::Error::AuthenticationFailure)));
+        }
+    }
+
+    #[test]
+    fn file_store_round_trip() {
+        let path = env::temp_dir().join("rowdy_throttle_file_store_test.json");
+        let _ = ::std::fs::remove_file(&path);
+        let store = FileStore::new(not_none!(path.to_str()));
+
+        let entry = Entry {
+            failures: 1,
+            last_failure: 1000,
+            blocked_until: 1010,
+        };
+        not_err!(store.update("username:mei", &|_| Some(entry)));
+        assert_eq!(not_err!(store.get("username:mei")), Some(entry));
+
+        // Shared with another store at the same path
+        let other = FileStore::new(not_none!(path.to_str()));
+        assert_eq!(not_err!(other.get("username:mei")), Some(entry));
+
+        not_err!(other.prune(&|entry| entry.blocked_until > 2000));
+        assert!(not_err!(store.get("username:mei")).is_none());
+        not_err!(::std::fs::remove_file(&path));
+    }
+
+    #[test]
+    fn file_store_ignores_files_that_cannot_be_parsed() {
+        let path = env::temp_dir().join("rowdy_throttle_file_store_corrupt_test.json");
+        {
+            use std::io::Write;
+            let mut file = not_err!(File::create(&path));
+            not_err!(file.write_all(b"{\"username:mei\": {\"fail"));
+        }
+        let store = FileStore::new(not_none!(path.to_str()));
+        assert!(not_err!(store.get("username:mei")).is_none());
+
+        let entry = Entry {
+            failures: 1,
+            last_failure: 1000,
+            blocked_until: 1010,
+        };
+        not_err!(store.update("username:mei", &|_| Some(entry)));
+        assert_eq!(not_err!(store.get("username:mei")), Some(entry));
+        not_err!(::std::fs::remove_file(&path));
+    }
+}
@@ -3,28 +3,35 @@
 //! This module provides the `Token` struct which encapsulates a JSON Web Token or `JWT`.
 //! Clients will pass the encapsulated JWT to services that require it. The JWT should be considered opaque
 //! to clients. The `Token` struct contains enough information for the client to act on, including expiry times.
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::borrow::Borrow;
 use std::error;
 use std::fmt;
 use std::fs::File;
-use std::io::{self, Cursor, Read};
+#[cfg(feature = "server")]
+use std::io::Cursor;
+use std::io::{self, Read};
 use std::ops::Deref;
 use std::str::FromStr;
 use std::time::Duration;
 
-use cors;
 use chrono::{self, DateTime, Utc};
+#[cfg(feature = "server")]
+use cors;
 use jwt::{self, jws, jwa, jwk};
+#[cfg(feature = "server")]
 use rocket::Request;
+#[cfg(feature = "server")]
 use rocket::http::{ContentType, Status, Method};
+#[cfg(feature = "server")]
 use rocket::response::{Response, Responder};
 use serde::Serialize;
 use serde::de::DeserializeOwned;
 use serde_json;
 use uuid::Uuid;
 
-use {ByteSequence, JsonValue};
+use {ByteSequence, ErrorKind, JsonValue};
+use oidc;
 
 /// Token errors
 #[derive(Debug)]
@@ -53,6 +60,20 @@ pub enum Error {
     InvalidIssuer,
     /// Raised when the audience is invalid
     InvalidAudience,
+    /// Raised when the token has expired
+    Expired,
+    /// Raised when the token is not valid yet
+    NotYetValid,
+    /// Raised when a registered claim that is required is missing from the token
+    MissingRequiredClaim(&'static str),
+    /// Raised when a refresh token is used or requested, but refresh tokens are not enabled
+    RefreshTokenDisabled,
+    /// Raised when an offline token is requested when authenticating with a refresh token
+    OfflineTokenNotAllowed,
+    /// Raised when a key is required for an operation, such as encrypting refresh tokens, but none is configured
+    KeyRequired,
+    /// Raised when the type of key configured is not supported for an operation
+    UnsupportedKey,
 
     /// Generic Error
     GenericError(String),
@@ -90,6 +111,15 @@ impl error::Error for Error {
             Error::InvalidService => "Service requested is not in the list of intended audiences",
             Error::InvalidIssuer => "The token has an invalid issuer",
             Error::InvalidAudience => "The token has invalid audience",
+            Error::Expired => "The token has expired",
+            Error::NotYetValid => "The token is not valid yet",
+            Error::MissingRequiredClaim(_) => "The token is missing a required claim",
+            Error::RefreshTokenDisabled => "Refresh token is not enabled",
+            Error::OfflineTokenNotAllowed => {
+                "Offline token cannot be requested for when authenticating with a refresh token"
+            }
+            Error::KeyRequired => "A key is required for this operation",
+            Error::UnsupportedKey => "The type of key is not supported for this operation",
             Error::JWTError(ref e) => e.description(),
             Error::IOError(ref e) => e.description(),
             Error::TokenSerializationError(ref e) => e.description(),
@@ -102,7 +132,40 @@ impl error::Error for Error {
             Error::JWTError(ref e) => Some(e),
             Error::IOError(ref e) => Some(e),
             Error::TokenSerializationError(ref e) => Some(e),
-            _ => Some(self),
+            _ => None,
+        }
+    }
+}
+
+impl Error {
+    /// The kind of the error
+    pub fn kind(&self) -> ErrorKind {
+        use jwt::errors::Error::*;
+
+        match *self {
+            Error::InvalidService => ErrorKind::InvalidService,
+            Error::RefreshTokenDisabled => ErrorKind::RefreshTokenDisabled,
+            Error::OfflineTokenNotAllowed => ErrorKind::OfflineTokenNotAllowed,
+            Error::InvalidIssuer |
+            Error::InvalidAudience |
+            Error::Expired |
+            Error::NotYetValid |
+            Error::MissingRequiredClaim(_) => ErrorKind::InvalidToken,
+            Error::KeyRequired => ErrorKind::Configuration,
+            Error::UnsupportedKey => ErrorKind::Unsupported,
+            Error::IOError(_) => ErrorKind::KeyLoading,
+            Error::JWTError(ref e) => {
+                match *e {
+                    ValidationError(_) |
+                    JsonError(_) |
+                    DecodeBase64(_) |
+                    Utf8(_) |
+                    UnspecifiedCryptographicError => ErrorKind::InvalidToken,
+                    IOError(_) => ErrorKind::KeyLoading,
+                    _ => ErrorKind::Internal,
+                }
+            }
+            _ => ErrorKind::Internal,
         }
     }
 }
@@ -114,29 +177,20 @@ impl fmt::Display for Error {
             Error::IOError(ref e) => fmt::Display::fmt(e, f),
             Error::TokenSerializationError(ref e) => fmt::Display::fmt(e, f),
             Error::GenericError(ref e) => fmt::Display::fmt(e, f),
+            Error::MissingRequiredClaim(claim) => write!(f, "{}: `{}`", error::Error::description(self), claim),
             _ => write!(f, "{}", error::Error::description(self)),
         }
     }
 }
 
+#[cfg(feature = "server")]
 impl<'r> Responder<'r> for Error {
     fn respond_to(self, _: &Request) -> Result<Response<'r>, Status> {
         error_!("Token Error: {:?}", self);
         match self {
             Error::InvalidService | Error::InvalidIssuer | Error::InvalidAudience => Err(Status::Forbidden),
-            Error::JWTError(ref e) => {
-                use jwt::errors::Error::*;
-
-                let status = match *e {
-                    ValidationError(_) |
-                    JsonError(_) |
-                    DecodeBase64(_) |
-                    Utf8(_) |
-                    UnspecifiedCryptographicError => Status::Unauthorized,
-                    _ => Status::InternalServerError,
-                };
-                Err(status)
-            }
+            Error::RefreshTokenDisabled | Error::OfflineTokenNotAllowed => Err(Status::BadRequest),
+            ref e if e.kind() == ErrorKind::InvalidToken => Err(Status::Unauthorized),
             _ => Err(Status::InternalServerError),
         }
     }
@@ -187,7 +241,7 @@ fn make_registered_claims(
 
 /// Make a new JWS
 #[cfg_attr(feature = "clippy_lints", allow(too_many_arguments))] // Internal function
-fn make_token<P: Serialize + DeserializeOwned + 'static>(
+pub(crate) fn make_token<P: Serialize + DeserializeOwned + 'static>(
     subject: &str,
     issuer: &jwt::StringOrUri,
     audience: &jwt::SingleOrMultiple<jwt::StringOrUri>,
@@ -209,7 +263,7 @@ fn make_token<P: Serialize + DeserializeOwned + 'static>(
 }
 
 /// Verify that the service requested for is allowed in the configuration
-fn verify_service(config: &Configuration, service: &str) -> Result<(), Error> {
+pub(crate) fn verify_service(config: &Configuration, service: &str) -> Result<(), Error> {
     if !config.audience.contains(&FromStr::from_str(service)?) {
         Err(Error::InvalidService)
     } else {
@@ -238,12 +292,164 @@ fn verify_audience(config: &Configuration, audience: &jwt::SingleOrMultiple<jwt:
     }
 }
 
+/// Verify that the issuer and audience of a token were issued with the configuration, and that the token is
+/// valid at this time. By default, the issued at, not before and expiry times are required.
+pub(crate) fn verify_registered_claims(
+    config: &Configuration,
+    registered: &jwt::RegisteredClaims,
+    options: Option<jwt::TemporalValidationOptions>,
+) -> Result<(), Error> {
+    let options = options.or_else(|| {
+        Some(jwt::TemporalValidationOptions {
+            issued_at_required: true,
+            not_before_required: true,
+            expiry_required: true,
+            ..Default::default()
+        })
+    });
+
+    let issuer = registered.issuer.as_ref().ok_or_else(
+        || Error::InvalidIssuer,
+    )?;
+    let audience = registered.audience.as_ref().ok_or_else(
+        || Error::InvalidAudience,
+    )?;
+
+    verify_audience(config, audience)
+        .and_then(|_| verify_issuer(config, issuer))
+        .and_then(|_| {
+            registered.validate_times(options).map_err(|e| {
+                Error::JWTError(jwt::errors::Error::ValidationError(e))
+            })
+        })
+}
+
+/// Options for validating the registered claims of an access token with
+/// [`Token::validate_with_options`](struct.Token.html#method.validate_with_options)
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ValidationOptions {
+    /// Difference between the clocks of the issuer and the validator that is allowed when checking times.
+    /// Defaults to none.
+    pub leeway: Duration,
+    /// Whether the issued at (`iat`) claim is required. Defaults to `true`.
+    pub issued_at_required: bool,
+    /// Whether the not before (`nbf`) claim is required. Defaults to `true`.
+    pub not_before_required: bool,
+    /// Whether the expiry (`exp`) claim is required. Defaults to `true`.
+    pub expiry_required: bool,
+}
+
+impl Default for ValidationOptions {
+    fn default() -> Self {
+        ValidationOptions {
+            leeway: Duration::from_secs(0),
+            issued_at_required: true,
+            not_before_required: true,
+            expiry_required: true,
+        }
+    }
+}
+
+/// Verify that the times of a token are valid at `now`, with the leeway and required claims of `options`
+pub(crate) fn validate_times(
+    registered: &jwt::RegisteredClaims,
+    now: DateTime<Utc>,
+    options: &ValidationOptions,
+) -> Result<(), Error> {
+    let leeway = chrono::Duration::from_std(options.leeway).map_err(
+        |e| e.to_string(),
+    )?;
+
+    match registered.issued_at {
+        Some(ref issued_at) if **issued_at > now + leeway => Err(Error::NotYetValid)?,
+        None if options.issued_at_required => Err(Error::MissingRequiredClaim("iat"))?,
+        _ => {}
+    }
+    match registered.not_before {
+        Some(ref not_before) if **not_before > now + leeway => Err(Error::NotYetValid)?,
+        None if options.not_before_required => Err(Error::MissingRequiredClaim("nbf"))?,
+        _ => {}
+    }
+    match registered.expiry {
+        Some(ref expiry) if now > **expiry + leeway => Err(Error::Expired),
+        None if options.expiry_required => Err(Error::MissingRequiredClaim("exp")),
+        _ => Ok(()),
+    }
+}
+
+/// An entry of the `access` claim of Docker Registry tokens, granting `actions` on the resource of `type` and `name`.
+/// See the [Docker Registry documentation](https://docs.docker.com/registry/spec/auth/jwt/).
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize, Debug)]
+pub struct Access {
+    /// Type of the resource, such as `repository`
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Name of the resource
+    pub name: String,
+    /// Actions allowed on the resource, such as `pull` and `push`
+    pub actions: Vec<String>,
+}
+
+impl Access {
+    /// Whether `action` is allowed on the resource of `kind` and `name`
+    pub fn allows(&self, kind: &str, name: &str, action: &str) -> bool {
+        self.kind == kind && self.name == name && self.actions.iter().any(|allowed| allowed == action)
+    }
+
+    /// Parse the Docker Registry `scope` request parameter, which is a space separated list of `type:name:actions`
+    /// with comma separated actions, such as `repository:library/ubuntu:pull`. Returns `None` if the scope is not in
+    /// this format or an entry has no actions.
+    pub fn from_scope(scope: &str) -> Option<Vec<Access>> {
+        let mut access = Vec::new();
+        for scope in scope.split_whitespace() {
+            // Names can contain `:`, such as the port of a registry, but types and actions cannot
+            let (kind, rest) = match scope.find(':') {
+                Some(index) => (&scope[..index], &scope[index + 1..]),
+                None => return None,
+            };
+            let (name, actions) = match rest.rfind(':') {
+                Some(index) => (&rest[..index], &rest[index + 1..]),
+                None => return None,
+            };
+            let actions: Vec<String> = actions
+                .split(',')
+                .filter(|action| !action.is_empty())
+                .map(|action| action.to_string())
+                .collect();
+            if kind.is_empty() || name.is_empty() || actions.is_empty() {
+                return None;
+            }
+            access.push(Access {
+                kind: kind.to_string(),
+                name: name.to_string(),
+                actions,
+            });
+        }
+        Some(access)
+    }
+
+    /// Whether `action` is allowed on the resource of `kind` and `name`, where the name of this entry may end with
+    /// `*` to allow every resource whose name starts with the rest
+    fn allows_matching(&self, kind: &str, name: &str, action: &str) -> bool {
+        let name_matches = if self.name.ends_with('*') {
+            name.starts_with(&self.name[..self.name.len() - 1])
+        } else {
+            self.name == name
+        };
+        self.kind == kind && name_matches && self.actions.iter().any(|allowed| allowed == action)
+    }
+}
+
 /// A wrapper around `cors::Options` for options specific to the token retrival route
+#[cfg(feature = "server")]
 pub type TokenGetterCorsOptions = cors::Cors;
 
+#[cfg(feature = "server")]
 const TOKEN_GETTER_METHODS: &[Method] = &[Method::Get];
+#[cfg(feature = "server")]
 const TOKEN_GETTER_HEADERS: &[&str] = &[
     "Authorization",
+    ::auth::ONE_TIME_PASSWORD_HEADER,
     "Accept",
     "Accept-Language",
     "Content-Language",
@@ -294,18 +500,30 @@ const TOKEN_GETTER_HEADERS: &[&str] = &[
 ///
 /// Variations for the fields `allowed_origins`, `audience` and `secret` exist. Refer to their type documentation for
 /// examples.
+///
+/// The settings of the OAuth 2.0 grants that are only served by the server are part of
+/// [`rowdy::Configuration`](../struct.Configuration.html) instead.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Configuration {
     /// The issuer of the token. Usually the URI of the authentication server.
     /// The issuer URI will also be used in the UUID generation of the tokens, and is also the `realm` for
     /// authentication purposes.
     pub issuer: jwt::StringOrUri,
+    /// The URL of the token endpoint that is sent as the `realm` of authentication challenges, if it is not the
+    /// `issuer`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub realm: Option<String>,
     /// Origins that are allowed to issue CORS request. This is needed for browser
     /// access to the authentication server, but tools like `curl` do not obey nor enforce the CORS convention.
-    ///
+    /// Requires the `server` feature.
+    #[cfg(feature = "server")]
     pub allowed_origins: cors::AllOrSome<HashSet<cors::headers::Url>>,
     /// The audience intended for your tokens. The `service` request paremeter will be validated against this
     pub audience: jwt::SingleOrMultiple<jwt::StringOrUri>,
+    /// Issue tokens whose audience is the entire configured `audience` instead of only the `service` requested.
+    /// Tokens issued this way are accepted by every service of the audience. Defaults to `false`.
+    #[serde(default)]
+    pub include_all_audiences: bool,
     /// Defaults to `none`
     #[serde(skip_serializing_if = "Option::is_none")]
     pub signature_algorithm: Option<jwa::SignatureAlgorithm>,
@@ -322,15 +540,97 @@ pub struct Configuration {
     /// Customise refresh token options. Set to `None` to disable refresh tokens
     #[serde(skip_serializing_if = "Option::is_none", default)]
     pub refresh_token: Option<RefreshTokenConfiguration>,
+    /// Settings that override the ones above for the tokens of particular services, keyed by the `service`.
+    /// Services that are not listed use the settings above. See [`ServiceConfiguration`] for the settings.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub services: HashMap<String, ServiceConfiguration>,
+    /// Issue tokens without credentials for the scopes that are granted to anonymous users. Requests without the
+    /// `Authorization` header are challenged for credentials when this is unset. See [`AnonymousConfiguration`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anonymous: Option<AnonymousConfiguration>,
+    /// Settings of OpenID Connect ID tokens and user info.
+    /// See [`oidc::Configuration`](../oidc/struct.Configuration.html).
+    #[serde(default)]
+    pub openid: oidc::Configuration,
 }
 
 const DEFAULT_EXPIRY_DURATION: u64 = 86400;
+/// Private claims that restrict what a token can be used for, and are kept regardless of the allow-list of a service:
+/// the actor of an exchanged token, and the scope and Docker Registry access it was narrowed to
+const RESTRICTION_CLAIMS: &[&str] = &["act", "scope", "access"];
 impl Configuration {
     fn default_expiry_duration() -> Duration {
         Duration::from_secs(DEFAULT_EXPIRY_DURATION)
     }
 
+    /// Returns the settings that override the configuration for `service`, if any
+    pub fn service(&self, service: &str) -> Option<&ServiceConfiguration> {
+        find_service(&self.services, service)
+    }
+
+    /// Returns the expiry duration of the access tokens of `service`
+    pub fn expiry_duration_for(&self, service: &str) -> Duration {
+        match self.service(service).and_then(|service| service.expiry_duration) {
+            Some(expiry_duration) => expiry_duration,
+            None => self.expiry_duration,
+        }
+    }
+
+    /// Returns the algorithm the access tokens of `service` are signed with
+    pub fn signature_algorithm_for(&self, service: &str) -> Option<jwa::SignatureAlgorithm> {
+        match self.service(service).and_then(|service| service.signature_algorithm) {
+            Some(signature_algorithm) => Some(signature_algorithm),
+            None => self.signature_algorithm,
+        }
+    }
+
+    /// Returns the expiry duration of the refresh tokens of `service`
+    ///
+    /// # Panics
+    /// Panics if refresh token is not enabled
+    pub fn refresh_token_expiry_duration_for(&self, service: &str) -> Duration {
+        match self.service(service).and_then(|service| service.refresh_token_expiry_duration) {
+            Some(expiry_duration) => expiry_duration,
+            None => self.refresh_token().expiry_duration,
+        }
+    }
+
+    /// Returns the private claims to include in the access tokens of `service`. If the service has an allow-list of
+    /// private claims, only the listed claims of an object are kept, along with the `RESTRICTION_CLAIMS`.
+    fn private_claims_for<T>(&self, service: &str, private_claims: T) -> Result<T, Error>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        let allowed = match self.service(service).and_then(|service| service.private_claims.as_ref()) {
+            Some(allowed) => allowed,
+            None => return Ok(private_claims),
+        };
+        match serde_json::to_value(private_claims)? {
+            JsonValue::Object(claims) => {
+                let claims = claims
+                    .into_iter()
+                    .filter(|&(ref name, _)| {
+                        allowed.contains(name) || RESTRICTION_CLAIMS.contains(&name.as_str())
+                    })
+                    .collect();
+                Ok(serde_json::from_value(JsonValue::Object(claims))?)
+            }
+            claims => Ok(serde_json::from_value(claims)?),
+        }
+    }
+
+    /// Returns the audience of the tokens issued for `service`, which is only the service unless
+    /// `include_all_audiences` is set. The service should have been verified against the configured audience.
+    fn audience_for(&self, service: &str) -> Result<jwt::SingleOrMultiple<jwt::StringOrUri>, Error> {
+        if self.include_all_audiences {
+            Ok(self.audience.clone())
+        } else {
+            Ok(jwt::SingleOrMultiple::Single(FromStr::from_str(service)?))
+        }
+    }
+
     /// Return a new CORS Option
+    #[cfg(feature = "server")]
     pub(crate) fn cors_option(&self) -> TokenGetterCorsOptions {
         cors::Cors {
             allowed_origins: self.allowed_origins.clone(),
@@ -350,6 +650,14 @@ impl Configuration {
         }
     }
 
+    /// Returns the `realm` of authentication challenges, which is the `issuer` unless configured
+    pub fn realm(&self) -> String {
+        match self.realm {
+            Some(ref realm) => realm.to_string(),
+            None => self.issuer.to_string(),
+        }
+    }
+
     /// Returns whether refresh tokens are enabled
     pub fn refresh_token_enabled(&self) -> bool {
         self.refresh_token.is_some()
@@ -372,11 +680,23 @@ impl Configuration {
             (None, None)
         };
 
+        let mut services = HashMap::new();
+        for (service, configuration) in &self.services {
+            if let Some(ref secret) = configuration.secret {
+                let keys = ServiceKeys {
+                    signing: secret.for_signing()?,
+                    signature_verification: secret.for_verification()?,
+                };
+                let _ = services.insert(service.to_string(), keys);
+            }
+        }
+
         Ok(Keys {
             signing: self.secret.for_signing()?,
             signature_verification: self.secret.for_verification()?,
             encryption: encryption,
             decryption: decryption,
+            services: services,
         })
     }
 }
@@ -408,6 +728,114 @@ pub struct RefreshTokenConfiguration {
     pub expiry_duration: Duration,
 }
 
+/// Settings that override the token configuration for the tokens of a service. Settings that are left unset use
+/// those of the token configuration.
+///
+/// # Examples
+///
+/// A legacy service that needs short lived tokens signed with a shared secret, and only the `groups` private claim:
+///
+/// ```json
+/// {
+///     "https://legacy.example.com": {
+///         "expiry_duration": 300,
+///         "signature_algorithm": "HS256",
+///         "secret": "shared secret",
+///         "private_claims": ["groups"]
+///     }
+/// }
+/// ```
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ServiceConfiguration {
+    /// Expiry duration of access tokens, in seconds
+    #[serde(with = "::serde_custom::option_duration", default, skip_serializing_if = "Option::is_none")]
+    pub expiry_duration: Option<Duration>,
+    /// Expiry duration of refresh tokens, in seconds. Only used if refresh tokens are enabled.
+    #[serde(with = "::serde_custom::option_duration", default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token_expiry_duration: Option<Duration>,
+    /// Algorithm access tokens are signed with. This should be set along with `secret`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature_algorithm: Option<jwa::SignatureAlgorithm>,
+    /// Secret access tokens are signed with. Refresh tokens are always signed with the secret of the token
+    /// configuration, because only rowdy reads them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<Secret>,
+    /// Names of the private claims to include in access tokens. All private claims are included when unset. The
+    /// `act`, `scope` and `access` claims, which restrict what the token can be used for, are always included.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub private_claims: Option<Vec<String>>,
+}
+
+/// Anonymous access for requests without the `Authorization` header, such as pulls of public repositories from a
+/// Docker registry. A token is issued to the anonymous `subject` only if every action of the `scope` requested is
+/// granted to anonymous users. The token has the actions in the Docker Registry `access` private claim. Otherwise,
+/// the request is challenged for credentials.
+///
+/// # Examples
+///
+/// Anonymous pulls of the repositories under `library/` of a registry:
+///
+/// ```json
+/// {
+///     "subject": "anonymous",
+///     "access": {
+///         "https://registry.example.com": [
+///             { "type": "repository", "name": "library/*", "actions": ["pull"] }
+///         ]
+///     }
+/// }
+/// ```
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AnonymousConfiguration {
+    /// Subject of anonymous tokens. Defaults to `anonymous`.
+    #[serde(default = "default_anonymous_subject")]
+    pub subject: String,
+    /// Access granted to anonymous users, keyed by the `service`. Names may end with `*` to grant access to every
+    /// resource whose name starts with the rest.
+    #[serde(default)]
+    pub access: HashMap<String, Vec<Access>>,
+}
+
+fn default_anonymous_subject() -> String {
+    "anonymous".to_string()
+}
+
+impl AnonymousConfiguration {
+    /// Returns the access to grant an anonymous request for `scope` of `service`, if every action requested is
+    /// granted to anonymous users. Requests without any action are not granted, because tokens without access would
+    /// still be accepted by services that only check the audience and subject.
+    pub fn grant(&self, service: &str, scope: &str) -> Option<Vec<Access>> {
+        let granted = match find_service(&self.access, service) {
+            Some(granted) => granted,
+            None => return None,
+        };
+        let requested = match Access::from_scope(scope) {
+            Some(ref requested) if requested.is_empty() => return None,
+            Some(requested) => requested,
+            None => return None,
+        };
+        let allowed = requested.iter().all(|requested| {
+            requested.actions.iter().all(|action| {
+                granted.iter().any(|granted| {
+                    granted.allows_matching(&requested.kind, &requested.name, action)
+                })
+            })
+        });
+        if allowed { Some(requested) } else { None }
+    }
+}
+
+/// Find the entry of `service` in a map keyed by services, comparing them as the `aud` claim would
+pub(crate) fn find_service<'a, V>(map: &'a HashMap<String, V>, service: &str) -> Option<&'a V> {
+    let service = match jwt::StringOrUri::from_str(service) {
+        Ok(service) => service,
+        Err(_) => return None,
+    };
+    map.iter()
+        .find(|&(key, _)| jwt::StringOrUri::from_str(key).ok().as_ref() == Some(&service))
+        .map(|(_, value)| value)
+}
+
 /// Private claims that will be included in the JWT.
 pub type PrivateClaim = JsonValue;
 
@@ -555,6 +983,11 @@ impl RefreshToken {
         Ok(&self.claims_set()?.private)
     }
 
+    /// Retrieve a reference to the registered claims of the decrypted refresh token
+    pub fn registered_claims(&self) -> Result<&jwt::RegisteredClaims, Error> {
+        Ok(&self.claims_set()?.registered)
+    }
+
     /// Validate the times and claims of the refresh token
     pub fn validate(
         &self,
@@ -564,19 +997,7 @@ impl RefreshToken {
     ) -> Result<(), Error> {
         use std::str::FromStr;
 
-        let options = options.or_else(|| {
-            Some(jwt::TemporalValidationOptions {
-                issued_at_required: true,
-                not_before_required: true,
-                expiry_required: true,
-                ..Default::default()
-            })
-        });
-
         let claims_set = self.claims_set()?;
-        let issuer = claims_set.registered.issuer.as_ref().ok_or_else(
-            || Error::InvalidIssuer,
-        )?;
         let audience = claims_set.registered.audience.as_ref().ok_or_else(|| {
             Error::InvalidAudience
         })?;
@@ -587,13 +1008,7 @@ impl RefreshToken {
             } else {
                 Err(Error::InvalidAudience)
             })
-            .and_then(|_| verify_audience(config, audience))
-            .and_then(|_| verify_issuer(config, issuer))
-            .and_then(|_| {
-                claims_set.registered.validate_times(options).map_err(|e| {
-                    Error::JWTError(jwt::errors::Error::ValidationError(e))
-                })
-            })
+            .and_then(|_| verify_registered_claims(config, &claims_set.registered, options))
     }
 
     /// Convenience function to convert a decrypted payload to string
@@ -634,6 +1049,9 @@ pub struct Token<T> {
     /// Refresh token, if enabled and requested for
     #[serde(skip_serializing_if = "Option::is_none")]
     pub refresh_token: Option<RefreshToken>,
+    /// OpenID Connect ID token, if the `openid` scope was requested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
 }
 
 impl<T> Clone for Token<T>
@@ -646,6 +1064,7 @@ where
             expires_in: self.expires_in,
             issued_at: self.issued_at,
             refresh_token: self.refresh_token.clone(),
+            id_token: self.id_token.clone(),
         }
     }
 }
@@ -660,16 +1079,38 @@ impl<T: Serialize + DeserializeOwned + 'static> Token<T> {
         refresh_token_payload: Option<&JsonValue>,
         now: DateTime<Utc>,
     ) -> Result<Self, ::Error> {
+        let expiry_duration = config.expiry_duration_for(service);
+        Self::with_configuration_time_and_expiry(
+            config,
+            subject,
+            service,
+            private_claims,
+            refresh_token_payload,
+            now,
+            expiry_duration,
+        )
+    }
+
+    fn with_configuration_time_and_expiry(
+        config: &Configuration,
+        subject: &str,
+        service: &str,
+        private_claims: T,
+        refresh_token_payload: Option<&JsonValue>,
+        now: DateTime<Utc>,
+        expiry_duration: Duration,
+    ) -> Result<Self, ::Error> {
 
         verify_service(config, service)?;
+        let audience = config.audience_for(service)?;
 
         let access_token = make_token(
             subject,
             &config.issuer,
-            &config.audience,
-            config.expiry_duration,
-            private_claims,
-            config.signature_algorithm,
+            &audience,
+            expiry_duration,
+            config.private_claims_for(service, private_claims)?,
+            config.signature_algorithm_for(service),
             now,
         )?;
         let refresh_token = match config.refresh_token {
@@ -680,8 +1121,8 @@ impl<T: Serialize + DeserializeOwned + 'static> Token<T> {
                         Some(RefreshToken::new_decrypted(
                             subject,
                             &config.issuer,
-                            &config.audience,
-                            refresh_token_config.expiry_duration,
+                            &audience,
+                            config.refresh_token_expiry_duration_for(service),
                             payload,
                             config.signature_algorithm,
                             refresh_token_config.cek_algorithm,
@@ -704,9 +1145,10 @@ impl<T: Serialize + DeserializeOwned + 'static> Token<T> {
 
         let token = Token::<T> {
             token: access_token,
-            expires_in: config.expiry_duration,
+            expires_in: expiry_duration,
             issued_at: *issued_at.deref(),
             refresh_token: refresh_token,
+            id_token: None,
         };
         Ok(token)
     }
@@ -729,6 +1171,21 @@ impl<T: Serialize + DeserializeOwned + 'static> Token<T> {
         )
     }
 
+    /// Based on the configuration, make a token for the subject that expires no later than `expires_at`, such as the
+    /// expiry of the token it was exchanged for. The token does not have a refresh token.
+    pub fn with_configuration_until(
+        config: &Configuration,
+        subject: &str,
+        service: &str,
+        private_claims: T,
+        expires_at: DateTime<Utc>,
+    ) -> Result<Self, ::Error> {
+        let now = Utc::now();
+        let remaining = expires_at.signed_duration_since(now).to_std().map_err(|_| Error::Expired)?;
+        let expiry_duration = ::std::cmp::min(config.expiry_duration_for(service), remaining);
+        Self::with_configuration_time_and_expiry(config, subject, service, private_claims, None, now, expiry_duration)
+    }
+
     /// Consumes self and encode the embedded JWT with signature.
     /// If the JWT is already encoded, this returns an error
     pub fn encode(mut self, secret: &jws::Secret) -> Result<Self, Error> {
@@ -753,6 +1210,7 @@ impl<T: Serialize + DeserializeOwned + 'static> Token<T> {
         }
     }
 
+    #[cfg(feature = "server")]
     fn serialize(self) -> Result<String, Error> {
         if self.is_decoded() {
             Err(Error::TokenNotEncoded)?
@@ -761,6 +1219,7 @@ impl<T: Serialize + DeserializeOwned + 'static> Token<T> {
         Ok(serialized)
     }
 
+    #[cfg(feature = "server")]
     fn respond<'r>(self) -> Result<Response<'r>, Error> {
         let serialized = self.serialize()?;
         Response::build()
@@ -782,6 +1241,42 @@ impl<T: Serialize + DeserializeOwned + 'static> Token<T> {
         !self.is_decoded()
     }
 
+    /// Validate the decoded access token for `service` at the time `now`: the service must be one of the configured
+    /// audience, the token must be intended for it and issued with the configuration, and the token must be valid at
+    /// `now`. The issued at, not before and expiry times are required, and no leeway is allowed.
+    pub fn validate(&self, service: &str, config: &Configuration, now: DateTime<Utc>) -> Result<(), Error> {
+        self.validate_with_options(service, config, now, &Default::default())
+    }
+
+    /// Validate the decoded access token like [`validate`](#method.validate), with the leeway and required claims of
+    /// `options`
+    pub fn validate_with_options(
+        &self,
+        service: &str,
+        config: &Configuration,
+        now: DateTime<Utc>,
+        options: &ValidationOptions,
+    ) -> Result<(), Error> {
+        if self.is_encoded() {
+            Err(Error::TokenNotDecoded)?
+        }
+        let registered = &self.token.payload()?.registered;
+        let issuer = registered.issuer.as_ref().ok_or_else(
+            || Error::InvalidIssuer,
+        )?;
+        let audience = registered.audience.as_ref().ok_or_else(
+            || Error::InvalidAudience,
+        )?;
+
+        verify_service(config, service)?;
+        if !audience.contains(&FromStr::from_str(service)?) {
+            Err(Error::InvalidAudience)?
+        }
+        verify_audience(config, audience)?;
+        verify_issuer(config, issuer)?;
+        validate_times(registered, now, options)
+    }
+
     /// Convenience function to extract the registered claims from a decoded token
     pub fn registered_claims(&self) -> Result<&jwt::RegisteredClaims, ::Error> {
         match self.token {
@@ -872,6 +1367,7 @@ impl<T: Serialize + DeserializeOwned + 'static> Token<T> {
     }
 }
 
+#[cfg(feature = "server")]
 impl<'r, T: Serialize + DeserializeOwned + 'static> Responder<'r> for Token<T> {
     fn respond_to(self, request: &Request) -> Result<Response<'r>, Status> {
         match self.respond() {
@@ -1011,9 +1507,7 @@ impl Secret {
     /// Create a JWK for the purpose of encryption
     pub(super) fn for_encryption(&self) -> Result<jwk::JWK<jwt::Empty>, Error> {
         match *self {
-            Secret::None => Err(Error::GenericError(
-                "A key is required for encryption".to_string(),
-            )),
+            Secret::None => Err(Error::KeyRequired),
             Secret::ByteSequence(ref bytes) => Ok(jwk::JWK::new_octect_key(
                 &bytes.as_bytes(),
                 Default::default(),
@@ -1024,7 +1518,7 @@ impl Secret {
                     Default::default(),
                 ))
             }
-            Secret::RSAKeyPair { .. } => Err(Error::GenericError("Not supported yet".to_string())),
+            Secret::RSAKeyPair { .. } => Err(Error::UnsupportedKey),
         }
     }
 
@@ -1054,6 +1548,51 @@ pub struct Keys {
     pub encryption: Option<jwk::JWK<jwt::Empty>>,
     /// Key used to decrypt tokens. Used if Refresh tokens are enabled.
     pub decryption: Option<jwk::JWK<jwt::Empty>>,
+    /// Keys of the services with their own `secret`, keyed by the `service` in the configuration
+    pub services: HashMap<String, ServiceKeys>,
+}
+
+impl Keys {
+    /// Key used to sign the access tokens of `service`
+    pub fn signing_for(&self, service: &str) -> &jws::Secret {
+        match find_service(&self.services, service) {
+            Some(keys) => &keys.signing,
+            None => &self.signing,
+        }
+    }
+
+    /// Key used to verify the signatures of the access tokens of `service`
+    pub fn signature_verification_for(&self, service: &str) -> &jws::Secret {
+        match find_service(&self.services, service) {
+            Some(keys) => &keys.signature_verification,
+            None => &self.signature_verification,
+        }
+    }
+
+    /// Returns whether tokens for the `audience` are signed with the key of `service`, or with the key of rowdy if
+    /// `service` is `None`. The key of a service only signs tokens for that service alone, so that services holding
+    /// a shared secret cannot sign tokens for other services.
+    pub fn signs_for(&self, service: Option<&str>, audience: &jwt::SingleOrMultiple<jwt::StringOrUri>) -> bool {
+        match service {
+            Some(service) => {
+                let service = jwt::StringOrUri::from_str(service).ok();
+                audience.iter().next().is_some() && audience.iter().all(|audience| Some(audience) == service.as_ref())
+            }
+            None => {
+                audience
+                    .iter()
+                    .all(|audience| find_service(&self.services, &audience.to_string()).is_none())
+            }
+        }
+    }
+}
+
+/// Keys of a service with its own `secret` in [`ServiceConfiguration`](struct.ServiceConfiguration.html)
+pub struct ServiceKeys {
+    /// Key used to sign access tokens
+    pub signing: jws::Secret,
+    /// Key used to verify the signatures of access tokens
+    pub signature_verification: jws::Secret,
 }
 
 #[cfg(test)]
@@ -1061,7 +1600,7 @@ mod tests {
     use std::str::FromStr;
     use std::time::Duration;
 
-    use chrono::{DateTime, NaiveDateTime, Utc};
+    use chrono::{self, DateTime, NaiveDateTime, Utc};
     use serde_json;
 
     use {JsonValue, JsonMap};
@@ -1095,17 +1634,25 @@ mod tests {
             None
         };
 
+        #[cfg(feature = "server")]
         let allowed_origins = ["https://www.example.com"];
+        #[cfg(feature = "server")]
         let (allowed_origins, _) = ::cors::AllowedOrigins::some(&allowed_origins);
 
         Configuration {
             issuer: FromStr::from_str("https://www.acme.com").unwrap(),
+            realm: None,
+            #[cfg(feature = "server")]
             allowed_origins: allowed_origins,
             audience: jwt::SingleOrMultiple::Single(FromStr::from_str("https://www.example.com/").unwrap()),
+            include_all_audiences: false,
             signature_algorithm: Some(jwt::jwa::SignatureAlgorithm::HS512),
             secret: Secret::ByteSequence(ByteSequence::String("secret".to_string())),
             expiry_duration: Duration::from_secs(120),
             refresh_token: refresh_token,
+            services: Default::default(),
+            anonymous: None,
+            openid: Default::default(),
         }
     }
 
@@ -1147,6 +1694,7 @@ mod tests {
             expires_in: Duration::from_secs(120),
             issued_at: Utc::now(),
             refresh_token: refresh_token,
+            id_token: None,
         }
     }
 
@@ -1253,6 +1801,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "server")]
     fn token_serialization_smoke_test() {
         let expected_token = make_token(false);
         let token = not_err!(expected_token.clone().encode(&jwt::jws::Secret::bytes_from_str("secret")));
@@ -1265,6 +1814,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "server")]
     fn token_response_smoke_test() {
         let expected_token = make_token(false);
         let token = not_err!(expected_token.clone().encode(&jwt::jws::Secret::bytes_from_str("secret")));
@@ -1426,6 +1976,227 @@ mod tests {
         assert_eq!(*actual_refresh_token_payload, refresh_token_payload);
     }
 
+    fn make_config_with_audiences(include_all_audiences: bool) -> Configuration {
+        let mut configuration = make_config(true);
+        configuration.audience = jwt::SingleOrMultiple::Multiple(vec![
+            FromStr::from_str("https://www.example.com/").unwrap(),
+            FromStr::from_str("https://www.foobar.com/").unwrap(),
+        ]);
+        configuration.include_all_audiences = include_all_audiences;
+        configuration
+    }
+
+    #[test]
+    fn tokens_are_issued_for_the_service_only() {
+        let configuration = make_config_with_audiences(false);
+        let now = Utc::now();
+        let token = not_err!(Token::<TestClaims>::with_configuration_and_time(&configuration,
+                                                                              "Donald Trump",
+                                                                              "https://www.example.com/",
+                                                                              Default::default(),
+                                                                              Some(&refresh_token_payload()),
+                                                                              now));
+
+        let expected_audience = Some(jwt::SingleOrMultiple::Single(
+            FromStr::from_str("https://www.example.com/").unwrap(),
+        ));
+        let registered = not_err!(token.registered_claims());
+        assert_eq!(registered.audience, expected_audience);
+        not_err!(token.validate("https://www.example.com/", &configuration, now));
+        assert_matches_non_debug!(token.validate("https://www.foobar.com/", &configuration, now),
+                                  Err(Error::InvalidAudience));
+
+        let refresh_token = not_none!(token.refresh_token());
+        assert_eq!(not_err!(refresh_token.registered_claims()).audience, expected_audience);
+        not_err!(refresh_token.validate("https://www.example.com/", &configuration, None));
+        assert_matches_non_debug!(refresh_token.validate("https://www.foobar.com/", &configuration, None),
+                                  Err(Error::InvalidAudience));
+    }
+
+    #[test]
+    fn tokens_are_issued_for_all_audiences_when_configured() {
+        let configuration = make_config_with_audiences(true);
+        let now = Utc::now();
+        let token = not_err!(Token::<TestClaims>::with_configuration_and_time(&configuration,
+                                                                              "Donald Trump",
+                                                                              "https://www.example.com/",
+                                                                              Default::default(),
+                                                                              Some(&refresh_token_payload()),
+                                                                              now));
+
+        let registered = not_err!(token.registered_claims());
+        assert_eq!(registered.audience, Some(configuration.audience.clone()));
+        not_err!(token.validate("https://www.foobar.com/", &configuration, now));
+
+        let refresh_token = not_none!(token.refresh_token());
+        not_err!(refresh_token.validate("https://www.foobar.com/", &configuration, None));
+    }
+
+    #[test]
+    fn access_is_parsed_from_scope() {
+        let access = not_none!(Access::from_scope(
+            "repository:library/ubuntu:pull,push repository:registry.example.com:5000/acme/app:pull",
+        ));
+        assert_eq!(access.len(), 2);
+        assert_eq!(access[0].kind, "repository");
+        assert_eq!(access[0].name, "library/ubuntu");
+        assert_eq!(access[0].actions, vec!["pull", "push"]);
+        assert_eq!(access[1].name, "registry.example.com:5000/acme/app");
+        assert_eq!(access[1].actions, vec!["pull"]);
+
+        assert!(not_none!(Access::from_scope("")).is_empty());
+        assert!(Access::from_scope("all").is_none());
+        assert!(Access::from_scope("repository:pull").is_none());
+        assert!(Access::from_scope("repository:library/ubuntu:").is_none());
+        assert!(Access::from_scope("repository:library/ubuntu:,").is_none());
+    }
+
+    #[test]
+    fn anonymous_access_is_granted_for_public_scopes_only() {
+        let json = r#"{
+            "access": {
+                "https://www.example.com/": [
+                    { "type": "repository", "name": "library/*", "actions": ["pull"] },
+                    { "type": "repository", "name": "acme/docs", "actions": ["pull", "push"] }
+                ]
+            }
+        }"#;
+        let anonymous: AnonymousConfiguration = not_err!(serde_json::from_str(json));
+        assert_eq!(anonymous.subject, "anonymous");
+
+        let service = "https://www.example.com/";
+        let access = not_none!(anonymous.grant(service, "repository:library/ubuntu:pull"));
+        assert!(access[0].allows("repository", "library/ubuntu", "pull"));
+        assert!(anonymous.grant(service, "repository:acme/docs:pull,push").is_some());
+
+        assert!(anonymous.grant(service, "repository:library/ubuntu:pull,push").is_none());
+        assert!(anonymous.grant(service, "repository:acme/docs-private:pull").is_none());
+        assert!(anonymous.grant(service, "repository:library/ubuntu:pull repository:acme/app:pull").is_none());
+        assert!(anonymous.grant(service, "all").is_none());
+        assert!(anonymous.grant("https://www.foobar.com/", "repository:library/ubuntu:pull").is_none());
+    }
+
+    #[test]
+    fn anonymous_access_is_not_granted_without_actions() {
+        let json = r#"{
+            "access": {
+                "https://www.example.com/": [{ "type": "repository", "name": "library/*", "actions": ["pull"] }]
+            }
+        }"#;
+        let anonymous: AnonymousConfiguration = not_err!(serde_json::from_str(json));
+
+        let service = "https://www.example.com/";
+        assert!(anonymous.grant(service, "").is_none());
+        assert!(anonymous.grant(service, " ").is_none());
+        assert!(anonymous.grant(service, "repository:library/ubuntu:").is_none());
+        assert!(anonymous.grant(service, "repository:library/ubuntu:pull repository:acme/app:").is_none());
+    }
+
+    fn make_config_with_service() -> Configuration {
+        let mut configuration = make_config(true);
+        let service = ServiceConfiguration {
+            expiry_duration: Some(Duration::from_secs(300)),
+            refresh_token_expiry_duration: Some(Duration::from_secs(3600)),
+            signature_algorithm: Some(jwt::jwa::SignatureAlgorithm::HS256),
+            secret: Some(Secret::ByteSequence(ByteSequence::String("legacy".to_string()))),
+            private_claims: Some(vec!["company".to_string()]),
+        };
+        let _ = configuration.services.insert("https://www.example.com".to_string(), service);
+        configuration
+    }
+
+    #[test]
+    fn service_configuration_deserialization() {
+        let json = r#"{
+            "https://www.example.com": {
+                "expiry_duration": 300,
+                "signature_algorithm": "HS256",
+                "secret": "legacy",
+                "private_claims": ["company"]
+            }
+        }"#;
+        let services: HashMap<String, ServiceConfiguration> = not_err!(serde_json::from_str(json));
+        let service = not_none!(services.get("https://www.example.com"));
+        assert_eq!(service.expiry_duration, Some(Duration::from_secs(300)));
+        assert_eq!(service.refresh_token_expiry_duration, None);
+        assert_eq!(service.signature_algorithm, Some(jwt::jwa::SignatureAlgorithm::HS256));
+        assert_eq!(service.private_claims, Some(vec!["company".to_string()]));
+    }
+
+    #[test]
+    fn restriction_claims_are_kept_for_services_with_allowed_private_claims() {
+        let configuration = make_config_with_service();
+        let private_claims: JsonValue = not_err!(serde_json::from_str(
+            r#"{
+                "company": "ACME",
+                "department": "Toilet Cleaning",
+                "act": { "sub": "registry-ci" },
+                "scope": "repository:library/ubuntu:pull",
+                "access": [{ "type": "repository", "name": "library/ubuntu", "actions": ["pull"] }]
+            }"#,
+        ));
+        let filtered = not_err!(configuration.private_claims_for("https://www.example.com", private_claims.clone()));
+
+        let mut expected = private_claims;
+        let _ = not_none!(expected.as_object_mut()).remove("department");
+        assert_eq!(filtered, expected);
+    }
+
+    #[test]
+    fn tokens_use_the_settings_of_their_service() {
+        let configuration = make_config_with_service();
+        let keys = not_err!(configuration.keys());
+
+        let mut private_claims = JsonMap::with_capacity(2);
+        let _ = private_claims.insert("company".to_string(), From::from("ACME"));
+        let _ = private_claims.insert("department".to_string(), From::from("Toilet Cleaning"));
+
+        let now = DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(0, 0), Utc);
+        let token = not_err!(Token::<JsonValue>::with_configuration_and_time(&configuration,
+                                                                            "Donald Trump",
+                                                                            "https://www.example.com/",
+                                                                            JsonValue::Object(private_claims),
+                                                                            Some(&refresh_token_payload()),
+                                                                            now));
+
+        assert_eq!(token.expires_in, Duration::from_secs(300));
+        let registered = not_err!(token.registered_claims());
+        assert_eq!(registered.expiry, Some((now + chrono::Duration::seconds(300)).into()));
+        let refresh_token = not_none!(token.refresh_token());
+        assert_eq!(not_err!(refresh_token.registered_claims()).expiry,
+                   Some((now + chrono::Duration::seconds(3600)).into()));
+
+        let mut expected_private_claims = JsonMap::with_capacity(1);
+        let _ = expected_private_claims.insert("company".to_string(), From::from("ACME"));
+        assert_eq!(*not_err!(token.private_claims()), JsonValue::Object(expected_private_claims));
+
+        let header = not_err!(token.header());
+        assert_eq!(header.registered.algorithm, jwt::jwa::SignatureAlgorithm::HS256);
+
+        let token = not_err!(token.encode(keys.signing_for("https://www.example.com/")));
+        let _ = not_err!(token.decode(&jwt::jws::Secret::bytes_from_str("legacy"),
+                                      jwt::jwa::SignatureAlgorithm::HS256));
+    }
+
+    #[test]
+    fn unlisted_services_use_the_token_configuration() {
+        let configuration = make_config_with_service();
+        let keys = not_err!(configuration.keys());
+
+        assert!(configuration.service("https://www.foobar.com/").is_none());
+        assert_eq!(configuration.expiry_duration_for("https://www.foobar.com/"), Duration::from_secs(120));
+        assert_eq!(configuration.refresh_token_expiry_duration_for("https://www.foobar.com/"),
+                   Duration::from_secs(86400));
+        assert_eq!(configuration.signature_algorithm_for("https://www.foobar.com/"),
+                   Some(jwt::jwa::SignatureAlgorithm::HS512));
+        assert_matches_non_debug!(*keys.signing_for("https://www.foobar.com/"),
+                                  jwt::jws::Secret::Bytes(ref bytes),
+                                  assert_eq!(bytes, b"secret"));
+        assert_matches_non_debug!(*keys.signing_for("https://www.example.com/"),
+                                  jwt::jws::Secret::Bytes(ref bytes),
+                                  assert_eq!(bytes, b"legacy"));
+    }
+
     #[test]
     #[should_panic(expected = "InvalidService")]
     fn validates_service_correctly() {
@@ -1616,4 +2387,231 @@ mod tests {
             .validate("https://www.example.com/", &configuration, None)
             .unwrap();
     }
+
+    fn make_access_token(configuration: &Configuration, now: DateTime<Utc>) -> Token<TestClaims> {
+        not_err!(Token::<TestClaims>::with_configuration_and_time(
+            configuration,
+            "Donald Trump",
+            "https://www.example.com/",
+            Default::default(),
+            None,
+            now,
+        ))
+    }
+
+    /// Make an access token at the time `now` and modify its registered claims
+    fn make_modified_access_token<F>(configuration: &Configuration, now: DateTime<Utc>, modify: F) -> Token<TestClaims>
+    where
+        F: FnOnce(&mut jwt::RegisteredClaims),
+    {
+        let mut token = make_access_token(configuration, now);
+        modify(&mut not_err!(token.token.payload_mut()).registered);
+        token
+    }
+
+    #[test]
+    fn access_token_validates_correctly() {
+        let configuration = make_config(false);
+        let now = Utc::now();
+        let token = make_access_token(&configuration, now);
+        not_err!(token.validate("https://www.example.com/", &configuration, now));
+    }
+
+    /// Encoded tokens have to be decoded before they are validated
+    #[test]
+    #[should_panic(expected = "TokenNotDecoded")]
+    fn access_token_validates_encoded_token() {
+        let configuration = make_config(false);
+        let now = Utc::now();
+        let token = make_access_token(&configuration, now);
+        let token = not_err!(token.encode(&jwt::jws::Secret::bytes_from_str("secret")));
+        token
+            .validate("https://www.example.com/", &configuration, now)
+            .unwrap();
+    }
+
+    /// Token does not have an issuer field
+    #[test]
+    #[should_panic(expected = "InvalidIssuer")]
+    fn access_token_validates_missing_issuer() {
+        let configuration = make_config(false);
+        let now = Utc::now();
+        let token = make_modified_access_token(&configuration, now, |registered| registered.issuer = None);
+        token
+            .validate("https://www.example.com/", &configuration, now)
+            .unwrap();
+    }
+
+    /// Token does not have an audience field
+    #[test]
+    #[should_panic(expected = "InvalidAudience")]
+    fn access_token_validates_missing_audience() {
+        let configuration = make_config(false);
+        let now = Utc::now();
+        let token = make_modified_access_token(&configuration, now, |registered| registered.audience = None);
+        token
+            .validate("https://www.example.com/", &configuration, now)
+            .unwrap();
+    }
+
+    /// An invalid service was requested for
+    #[test]
+    #[should_panic(expected = "InvalidService")]
+    fn access_token_validates_invalid_service() {
+        let configuration = make_config(false);
+        let now = Utc::now();
+        let token = make_access_token(&configuration, now);
+        token
+            .validate("https://www.invalid.com/", &configuration, now)
+            .unwrap();
+    }
+
+    /// Configuration has the right audience request configured, but the token does not indicate that it is for the
+    /// audience requested
+    #[test]
+    #[should_panic(expected = "InvalidAudience")]
+    fn access_token_validates_mismatch_service_and_audience() {
+        let mut configuration = make_config(false);
+        let now = Utc::now();
+        let token = make_access_token(&configuration, now);
+        configuration.audience = jwt::SingleOrMultiple::Single(FromStr::from_str("https://www.invalid.com/").unwrap());
+        token
+            .validate("https://www.invalid.com/", &configuration, now)
+            .unwrap();
+    }
+
+    /// Token's audience is not a subset of the connfigured audience
+    #[test]
+    #[should_panic(expected = "InvalidAudience")]
+    fn access_token_validates_invalid_audience() {
+        let configuration = make_config(false);
+        let now = Utc::now();
+        let token = make_modified_access_token(&configuration, now, |registered| {
+            registered.audience = Some(jwt::SingleOrMultiple::Multiple(vec![
+                FromStr::from_str("https://www.invalid.com/").unwrap(),
+                FromStr::from_str("https://www.example.com/").unwrap(),
+            ]));
+        });
+        token
+            .validate("https://www.example.com/", &configuration, now)
+            .unwrap();
+    }
+
+    /// Token's issuer is not expected
+    #[test]
+    #[should_panic(expected = "InvalidIssuer")]
+    fn access_token_validates_invalid_issuer() {
+        let configuration = make_config(false);
+        let now = Utc::now();
+        let token = make_modified_access_token(&configuration, now, |registered| {
+            registered.issuer = Some(FromStr::from_str("https://www.invalid.com/").unwrap());
+        });
+        token
+            .validate("https://www.example.com/", &configuration, now)
+            .unwrap();
+    }
+
+    /// Issued at time is required
+    #[test]
+    #[should_panic(expected = "MissingRequiredClaim(\"iat\")")]
+    fn access_token_validates_missing_issued_at() {
+        let configuration = make_config(false);
+        let now = Utc::now();
+        let token = make_modified_access_token(&configuration, now, |registered| registered.issued_at = None);
+        token
+            .validate("https://www.example.com/", &configuration, now)
+            .unwrap();
+    }
+
+    /// Not before time is required
+    #[test]
+    #[should_panic(expected = "MissingRequiredClaim(\"nbf\")")]
+    fn access_token_validates_missing_not_before() {
+        let configuration = make_config(false);
+        let now = Utc::now();
+        let token = make_modified_access_token(&configuration, now, |registered| registered.not_before = None);
+        token
+            .validate("https://www.example.com/", &configuration, now)
+            .unwrap();
+    }
+
+    /// Expiry time is required
+    #[test]
+    #[should_panic(expected = "MissingRequiredClaim(\"exp\")")]
+    fn access_token_validates_missing_expiry() {
+        let configuration = make_config(false);
+        let now = Utc::now();
+        let token = make_modified_access_token(&configuration, now, |registered| registered.expiry = None);
+        token
+            .validate("https://www.example.com/", &configuration, now)
+            .unwrap();
+    }
+
+    /// Claims that are not required can be missing
+    #[test]
+    fn access_token_validates_optional_claims() {
+        let configuration = make_config(false);
+        let now = Utc::now();
+        let token = make_modified_access_token(&configuration, now, |registered| {
+            registered.issued_at = None;
+            registered.not_before = None;
+            registered.expiry = None;
+        });
+        let options = ValidationOptions {
+            issued_at_required: false,
+            not_before_required: false,
+            expiry_required: false,
+            ..Default::default()
+        };
+        not_err!(token.validate_with_options("https://www.example.com/", &configuration, now, &options));
+    }
+
+    /// Token has expired
+    #[test]
+    #[should_panic(expected = "Expired")]
+    fn access_token_validates_expiry() {
+        let configuration = make_config(false);
+        let now = Utc::now();
+        let token = make_access_token(&configuration, now);
+        let later = now + chrono::Duration::seconds(121);
+        token
+            .validate("https://www.example.com/", &configuration, later)
+            .unwrap();
+    }
+
+    /// Token is not valid yet
+    #[test]
+    #[should_panic(expected = "NotYetValid")]
+    fn access_token_validates_not_before() {
+        let configuration = make_config(false);
+        let now = Utc::now();
+        let token = make_access_token(&configuration, now);
+        let earlier = now - chrono::Duration::seconds(1);
+        token
+            .validate("https://www.example.com/", &configuration, earlier)
+            .unwrap();
+    }
+
+    /// Times are checked with the leeway
+    #[test]
+    fn access_token_validates_with_leeway() {
+        let configuration = make_config(false);
+        let now = Utc::now();
+        let token = make_access_token(&configuration, now);
+        let options = ValidationOptions {
+            leeway: Duration::from_secs(60),
+            ..Default::default()
+        };
+
+        let later = now + chrono::Duration::seconds(150);
+        not_err!(token.validate_with_options("https://www.example.com/", &configuration, later, &options));
+        let earlier = now - chrono::Duration::seconds(30);
+        not_err!(token.validate_with_options("https://www.example.com/", &configuration, earlier, &options));
+
+        let later = now + chrono::Duration::seconds(181);
+        assert_matches_non_debug!(
+            token.validate_with_options("https://www.example.com/", &configuration, later, &options),
+            Err(Error::Expired)
+        );
+    }
 }
@@ -0,0 +1,300 @@
+//! Token exchange grant for delegation and downscoping
+//!
+//! A service that holds the access token of a user may need to call another service on behalf of the user. Instead
+//! of forwarding the credentials of the user, the service exchanges the access token at `POST /token` for a token
+//! for the other service as described in [RFC 8693](https://tools.ietf.org/html/rfc8693). The service authenticates
+//! with its client ID and secret, which are verified as an API key, and sends:
+//!
+//! - `grant_type`: `urn:ietf:params:oauth:grant-type:token-exchange`
+//! - `subject_token`: The access token of the user, which must have been issued by rowdy and not have expired
+//! - `subject_token_type`: `urn:ietf:params:oauth:token-type:access_token`
+//! - `resource`: The service the new token is intended for
+//! - `scope`: Optionally, a narrower scope than the scope of the subject token
+//!
+//! The subject token must be intended for a service the client is allowed to request tokens for, and the
+//! configuration must allow that service to exchange its tokens for tokens for the `resource`. The new token has the
+//! subject and private claims of the subject token, and an `act` claim with the client ID of the service as `sub`.
+//! If the subject token was itself exchanged, its `act` claim is nested in the new one. The new token does not
+//! expire later than the subject token, and no refresh token is issued.
+//!
+//! Access tokens are not restricted by scope unless they were exchanged for a narrower scope, which is then recorded
+//! in the `scope` claim. Tokens with a `scope` claim can only be exchanged for the same scopes or fewer. Tokens with
+//! the Docker Registry `access` claim, such as anonymous tokens, can only be exchanged for actions they allow.
+//!
+//! # Configuration Example
+//!
+//! The token exchange grant is enabled by the `token_exchange` key of the
+//! [configuration](../struct.Configuration.html). The services whose tokens can be exchanged are keyed by the
+//! service, with the services their tokens can be exchanged for.
+//!
+//! ```json
+//! {
+//!     "services": {
+//!         "https://a.example.com": ["https://b.example.com", "https://c.example.com"]
+//!     }
+//! }
+//! ```
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+
+use {JsonMap, JsonValue};
+use jwt;
+use oauth;
+use token::{self, Access};
+
+/// Token type of access tokens in token exchange requests and responses
+pub const ACCESS_TOKEN_TYPE_URI: &'static str = "urn:ietf:params:oauth:token-type:access_token";
+
+/// Settings of the token exchange grant. See the module documentation for an example.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct Configuration {
+    /// Services whose access tokens can be exchanged, keyed by the service, with the services their tokens can be
+    /// exchanged for
+    #[serde(default)]
+    pub services: HashMap<String, Vec<String>>,
+}
+
+impl Configuration {
+    /// Whether tokens intended for `from` can be exchanged for tokens intended for `to`. Services are compared as the
+    /// `aud` claim would.
+    pub fn allows(&self, from: &str, to: &str) -> bool {
+        let to = match jwt::StringOrUri::from_str(to) {
+            Ok(to) => to,
+            Err(_) => return false,
+        };
+        match token::find_service(&self.services, from) {
+            Some(services) => {
+                services.iter().any(|service| {
+                    jwt::StringOrUri::from_str(service).ok().as_ref() == Some(&to)
+                })
+            }
+            None => false,
+        }
+    }
+}
+
+/// A subject token that was verified to be exchanged for a token for `service`
+#[derive(Clone, PartialEq, Debug)]
+pub struct Exchange {
+    /// Subject of the subject token
+    pub subject: String,
+    /// The service the new token is intended for
+    pub service: String,
+    /// The scope of the new token
+    pub scope: String,
+    /// Private claims of the new token, with the `act` claim of the client
+    pub private_claims: JsonValue,
+    /// Expiry of the subject token
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Verify the token exchange `request` of the client `actor`, which has authenticated, against the settings of the
+/// grant, if it is enabled. `allowed` returns whether the client is allowed to request tokens for a service.
+pub fn verify<F>(
+    configuration: &token::Configuration,
+    token_exchange: &Option<Configuration>,
+    keys: &token::Keys,
+    request: &oauth::TokenRequest,
+    actor: &str,
+    allowed: F,
+) -> Result<Exchange, oauth::Error>
+where
+    F: Fn(&str) -> bool,
+{
+    let exchange = token_exchange.as_ref().ok_or_else(|| {
+        oauth::Error::new(
+            oauth::ErrorCode::UnsupportedGrantType,
+            "The token exchange grant is not enabled",
+        )
+    })?;
+    let subject_token = match (&request.subject_token, &request.subject_token_type) {
+        (&Some(ref subject_token), &Some(ref subject_token_type)) if subject_token_type == ACCESS_TOKEN_TYPE_URI => {
+            subject_token
+        }
+        (&Some(_), &Some(_)) => {
+            Err(oauth::Error::new(
+                oauth::ErrorCode::InvalidRequest,
+                "Only access tokens can be exchanged",
+            ))?
+        }
+        _ => {
+            Err(oauth::Error::new(
+                oauth::ErrorCode::InvalidRequest,
+                "The `subject_token` and `subject_token_type` parameters are required",
+            ))?
+        }
+    };
+    let requested_token_type = request.requested_token_type.as_ref().map(|token_type| token_type.as_str());
+    if requested_token_type.unwrap_or(ACCESS_TOKEN_TYPE_URI) != ACCESS_TOKEN_TYPE_URI {
+        Err(oauth::Error::new(
+            oauth::ErrorCode::InvalidRequest,
+            "Only access tokens can be requested",
+        ))?;
+    }
+    let service = request.resource(configuration)?;
+
+    let invalid_grant = || oauth::Error::new(oauth::ErrorCode::InvalidGrant, "The subject token is invalid");
+    let response = oauth::IntrospectionResponse::new(subject_token, configuration, keys);
+    let (registered, private_claims) = match response {
+        oauth::IntrospectionResponse {
+            active: true,
+            token_type: Some(ref token_type),
+            registered: Some(ref registered),
+            ref private,
+        } if token_type == oauth::ACCESS_TOKEN_TYPE => (registered, private),
+        _ => Err(invalid_grant())?,
+    };
+    let subject = match registered.subject {
+        Some(ref subject) => subject.to_string(),
+        None => Err(invalid_grant())?,
+    };
+    let expires_at = match registered.expiry {
+        Some(ref expiry) => **expiry,
+        None => Err(invalid_grant())?,
+    };
+
+    // Clients only exchange tokens intended for a service they are allowed to request tokens for
+    let sources: Vec<String> = response.audience().into_iter().filter(|source| allowed(source)).collect();
+    if sources.is_empty() {
+        Err(invalid_grant())?;
+    }
+    if !sources.iter().any(|source| exchange.allows(source, &service)) {
+        Err(oauth::Error::new(
+            oauth::ErrorCode::InvalidTarget,
+            "The subject token cannot be exchanged for a token for the service",
+        ))?;
+    }
+
+    let mut private_claims = match *private_claims {
+        Some(JsonValue::Object(ref claims)) => claims.clone(),
+        _ => JsonMap::new(),
+    };
+    let scope = narrow_scope(&mut private_claims, &request.scope)?;
+    let mut act = JsonMap::with_capacity(2);
+    let _ = act.insert("sub".to_string(), From::from(actor));
+    if let Some(previous) = private_claims.remove("act") {
+        let _ = act.insert("act".to_string(), previous);
+    }
+    let _ = private_claims.insert("act".to_string(), JsonValue::Object(act));
+
+    Ok(Exchange {
+        subject,
+        service,
+        scope,
+        private_claims: JsonValue::Object(private_claims),
+        expires_at,
+    })
+}
+
+/// Restrict the `scope` and `access` claims of the subject token to the `requested` scope, and return the scope of
+/// the new token. The claims are kept if no scope is requested.
+fn narrow_scope(
+    private_claims: &mut JsonMap<String, JsonValue>,
+    requested: &Option<String>,
+) -> Result<String, oauth::Error> {
+    let granted_scope = private_claims.get("scope").and_then(|scope| scope.as_str()).map(|scope| scope.to_string());
+    let requested = match *requested {
+        Some(ref requested) if !requested.trim().is_empty() => requested,
+        _ => return Ok(granted_scope.unwrap_or_default()),
+    };
+    let invalid_scope = || oauth::Error::new(
+        oauth::ErrorCode::InvalidScope,
+        "The scope requested exceeds the scope of the subject token",
+    );
+
+    if let Some(granted_scope) = granted_scope {
+        let granted: Vec<&str> = granted_scope.split_whitespace().collect();
+        if !requested.split_whitespace().all(|scope| granted.contains(&scope)) {
+            Err(invalid_scope())?;
+        }
+    }
+    let granted_access = match private_claims.get("access") {
+        Some(access) => {
+            let access: Vec<Access> = ::serde_json::from_value(access.clone()).map_err(|_| invalid_scope())?;
+            Some(access)
+        }
+        None => None,
+    };
+    if let Some(granted_access) = granted_access {
+        let requested_access = Access::from_scope(requested).ok_or_else(&invalid_scope)?;
+        let allowed = requested_access.iter().all(|requested| {
+            requested.actions.iter().all(|action| {
+                granted_access.iter().any(
+                    |granted| granted.allows(&requested.kind, &requested.name, action),
+                )
+            })
+        });
+        if !allowed {
+            Err(invalid_scope())?;
+        }
+        let requested_access = ::serde_json::to_value(&requested_access).map_err(|e| {
+            oauth::Error::from(::Error::from(token::Error::TokenSerializationError(e)))
+        })?;
+        let _ = private_claims.insert("access".to_string(), requested_access);
+    }
+
+    let _ = private_claims.insert("scope".to_string(), From::from(requested.as_str()));
+    Ok(requested.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use super::*;
+
+    fn claims(json: &str) -> JsonMap<String, JsonValue> {
+        match not_err!(serde_json::from_str(json)) {
+            JsonValue::Object(claims) => claims,
+            _ => panic!("Claims are not an object"),
+        }
+    }
+
+    #[test]
+    fn exchanges_are_configured_by_service() {
+        let configuration: Configuration = not_err!(serde_json::from_str(
+            r#"{ "services": { "https://a.example.com": ["https://b.example.com"] } }"#,
+        ));
+        assert!(configuration.allows("https://a.example.com/", "https://b.example.com"));
+        assert!(!configuration.allows("https://b.example.com", "https://a.example.com"));
+        assert!(!configuration.allows("https://a.example.com", "https://c.example.com"));
+    }
+
+    #[test]
+    fn unrestricted_tokens_are_narrowed_to_the_scope_requested() {
+        let mut private_claims = claims(r#"{ "name": "Mei" }"#);
+        assert_eq!(not_err!(narrow_scope(&mut private_claims, &None)), "");
+        assert!(!private_claims.contains_key("scope"));
+
+        let scope = not_err!(narrow_scope(&mut private_claims, &Some("read write".to_string())));
+        assert_eq!(scope, "read write");
+        assert_eq!(private_claims["scope"], "read write");
+
+        // Scopes can only be narrowed further
+        let scope = not_err!(narrow_scope(&mut private_claims, &Some("read".to_string())));
+        assert_eq!(scope, "read");
+        let error = narrow_scope(&mut private_claims, &Some("read write".to_string())).unwrap_err();
+        assert_eq!(error.error, oauth::ErrorCode::InvalidScope);
+    }
+
+    #[test]
+    fn docker_access_is_narrowed_to_the_actions_requested() {
+        let mut private_claims = claims(
+            r#"{ "access": [{ "type": "repository", "name": "library/ubuntu", "actions": ["pull", "push"] }] }"#,
+        );
+        let scope = not_err!(narrow_scope(
+            &mut private_claims,
+            &Some("repository:library/ubuntu:pull".to_string()),
+        ));
+        assert_eq!(scope, "repository:library/ubuntu:pull");
+        assert_eq!(private_claims["access"][0]["actions"], JsonValue::Array(vec![From::from("pull")]));
+
+        let error = narrow_scope(&mut private_claims, &Some("repository:library/ubuntu:push".to_string()))
+            .unwrap_err();
+        assert_eq!(error.error, oauth::ErrorCode::InvalidScope);
+        let error = narrow_scope(&mut private_claims, &Some("all".to_string())).unwrap_err();
+        assert_eq!(error.error, oauth::ErrorCode::InvalidScope);
+    }
+}
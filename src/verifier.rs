@@ -0,0 +1,671 @@
+//! Verification of access tokens in services built with Rocket
+//!
+//! Services that accept access tokens issued by rowdy can manage a [`Verifier`](struct.Verifier.html) and use the
+//! [`VerifiedToken`](struct.VerifiedToken.html) request guard. The guard reads the `Bearer` token in the
+//! `Authorization` header, verifies its signature, issuer, audience and times, and yields the claims of the token.
+//!
+//! Failures of the guard are `401 Unauthorized`. Rocket does not pass the errors of request guards to catchers, so
+//! take the guard as a `Result` and return its [`Error`](enum.Error.html), which responds with the
+//! `WWW-Authenticate` header described in [RFC 6750](https://tools.ietf.org/html/rfc6750#section-3). The other
+//! `401` responses of the service are left to its own catchers.
+//!
+//! Key sets are read from a local `path`. They can also be fetched from a `url`, but only over plain `http` because
+//! there is no TLS connector for the HTTP client. Anyone on the network path could then serve their own keys, so a
+//! `url` is refused unless `insecure` is set to acknowledge this. Keys fetched from the URL of a key set are fetched
+//! again when a token is signed with a key that is not known, so that the keys of rowdy can be rotated without
+//! restarting the service.
+//!
+//! The request guard requires the `server` feature. Without it, services that are not built with Rocket can still use
+//! [`Verifier::verify`](struct.Verifier.html#method.verify) directly.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! #![feature(plugin)]
+//! #![plugin(rocket_codegen)]
+//!
+//! extern crate rocket;
+//! extern crate rowdy;
+//! extern crate serde_json;
+//!
+//! use rowdy::JsonValue;
+//! use rowdy::verifier::{self, Verifier, VerifiedToken};
+//!
+//! type Verified = Result<VerifiedToken<JsonValue>, verifier::Error>;
+//!
+//! #[get("/images/<name>")]
+//! fn image(name: String, token: Verified) -> Result<String, verifier::Error> {
+//!     let token = token?;
+//!     token.require_access("repository", &name, "pull")?;
+//!     Ok(format!("Hello {:?}", token.registered.subject))
+//! }
+//!
+//! # fn main() {
+//! let json = r#"{
+//!     "issuer": "https://www.acme.com",
+//!     "service": "https://www.example.com",
+//!     "signature_algorithm": "RS256",
+//!     "keys": { "KeySet": { "path": "jwks.json" } },
+//!     "leeway": 60
+//! }"#;
+//! let configuration: verifier::Configuration = serde_json::from_str(json).unwrap();
+//! let verifier = Verifier::new(configuration).unwrap();
+//!
+//! rocket::ignite()
+//!     .manage(verifier)
+//!     .mount("/", routes![image])
+//!     .launch();
+//! # }
+//! ```
+use std::error;
+use std::fmt;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use jwt::{self, jwa, jws};
+#[cfg(feature = "server")]
+use rocket::{Outcome, State};
+#[cfg(feature = "server")]
+use rocket::http::{Header, Status};
+#[cfg(feature = "server")]
+use rocket::request::{self, FromRequest, Request};
+#[cfg(feature = "server")]
+use rocket::response::{Responder, Response};
+use serde::de::DeserializeOwned;
+use serde_json;
+
+use JsonValue;
+#[cfg(feature = "server")]
+use auth;
+use jwks::JsonWebKeySet;
+use oidc;
+use token::{self, Access, Secret};
+
+/// Seconds to wait for a key set to be fetched
+const KEY_SET_TIMEOUT: u64 = 10;
+
+/// Where the keys to verify signatures with are read from
+#[derive(Serialize, Deserialize, Debug)]
+pub enum KeySource {
+    /// A secret, in the same way as the `secret` of the token configuration of rowdy
+    Secret(Secret),
+    /// A JSON Web Key Set. See the [`jwks`](../jwks/index.html) module documentation.
+    KeySet {
+        /// URL of the key set. Only `http` URLs are supported, so `insecure` has to be set as well.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        url: Option<String>,
+        /// Path to a local copy of the key set, which is read if the key set cannot be fetched from `url`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+        /// Allow the key set to be fetched from `url` without TLS. Defaults to `false`.
+        #[serde(default)]
+        insecure: bool,
+    },
+}
+
+/// Configuration of a [`Verifier`](struct.Verifier.html). Usually deserialized from JSON.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Configuration {
+    /// The issuer of the tokens, which is the `issuer` of the token configuration of rowdy
+    pub issuer: jwt::StringOrUri,
+    /// The service that tokens must be intended for. This is one of the `audience` of the token configuration of
+    /// rowdy.
+    pub service: jwt::StringOrUri,
+    /// The algorithm tokens are signed with. Defaults to `HS256`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature_algorithm: Option<jwa::SignatureAlgorithm>,
+    /// Where the keys to verify signatures with are read from
+    pub keys: KeySource,
+    /// Difference in seconds between the clocks of rowdy and the service that is allowed when checking times
+    #[serde(with = "::serde_custom::duration", default = "Configuration::default_leeway")]
+    pub leeway: Duration,
+    /// The realm in the `WWW-Authenticate` header of responses to unauthorized requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub realm: Option<String>,
+    /// Minimum seconds between fetches of a key set from its URL when tokens are signed with keys that are not known.
+    /// Defaults to 5 minutes.
+    #[serde(with = "::serde_custom::duration", default = "Configuration::default_key_refresh_interval")]
+    pub key_refresh_interval: Duration,
+}
+
+impl Configuration {
+    fn default_leeway() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    fn default_key_refresh_interval() -> Duration {
+        Duration::from_secs(300)
+    }
+}
+
+/// The keys to verify signatures with, and when they were last read from their source
+struct Secrets {
+    secrets: Vec<jws::Secret>,
+    read_at: Instant,
+}
+
+/// Verifies access tokens. This is managed by Rocket and used by the [`VerifiedToken`](struct.VerifiedToken.html)
+/// request guard.
+pub struct Verifier {
+    issuer: jwt::StringOrUri,
+    service: jwt::StringOrUri,
+    signature_algorithm: jwa::SignatureAlgorithm,
+    keys: KeySource,
+    secrets: RwLock<Secrets>,
+    key_refresh_interval: Duration,
+    leeway: Duration,
+    #[cfg_attr(not(feature = "server"), allow(dead_code))]
+    realm: Option<String>,
+}
+
+impl Verifier {
+    /// Create a verifier, reading the keys from their source
+    pub fn new(configuration: Configuration) -> Result<Self, ::Error> {
+        let secrets = match configuration.keys {
+            KeySource::Secret(ref secret) => vec![secret.for_verification()?],
+            KeySource::KeySet {
+                ref url,
+                ref path,
+                insecure,
+            } => Self::read_key_set(url, path, insecure)?.secrets(),
+        };
+        if secrets.is_empty() {
+            Err(::Error::KeyLoadingError("There are no keys to verify signatures with".to_string()))?;
+        }
+
+        Ok(Verifier {
+            issuer: configuration.issuer,
+            service: configuration.service,
+            signature_algorithm: configuration.signature_algorithm.unwrap_or_default(),
+            keys: configuration.keys,
+            secrets: RwLock::new(Secrets {
+                secrets,
+                read_at: Instant::now(),
+            }),
+            key_refresh_interval: configuration.key_refresh_interval,
+            leeway: configuration.leeway,
+            realm: configuration.realm,
+        })
+    }
+
+    fn read_key_set(url: &Option<String>, path: &Option<String>, insecure: bool) -> Result<JsonWebKeySet, ::Error> {
+        if let Some(ref url) = *url {
+            if !insecure {
+                Err(::Error::ConfigurationError(format!(
+                    "The key set at {} would be fetched without TLS. Use a local copy at `path`, or set `insecure`.",
+                    url
+                )))?;
+            }
+            match JsonWebKeySet::from_url(url, Duration::from_secs(KEY_SET_TIMEOUT)) {
+                Ok(key_set) => return Ok(key_set),
+                Err(e) if path.is_some() => warn_!("Reading the local copy of the key set instead: {}", e),
+                Err(e) => return Err(e),
+            }
+        }
+        match *path {
+            Some(ref path) => JsonWebKeySet::from_file(path),
+            None => Err(::Error::ConfigurationError(
+                "Either the URL or the path of the key set is required".to_string(),
+            )),
+        }
+    }
+
+    /// Fetch the key set again if it has a URL and was not read within the `key_refresh_interval`. Returns whether
+    /// the keys were replaced.
+    fn refresh_keys(&self) -> bool {
+        let url = match self.keys {
+            KeySource::KeySet { url: Some(ref url), .. } => url,
+            _ => return false,
+        };
+        {
+            // Only one request fetches the key set in each interval
+            let mut secrets = self.secrets.write().unwrap_or_else(|e| e.into_inner());
+            if secrets.read_at.elapsed() < self.key_refresh_interval {
+                return false;
+            }
+            secrets.read_at = Instant::now();
+        }
+
+        debug_!("Fetching the key set from {} again", url);
+        let fetched = match JsonWebKeySet::from_url(url, Duration::from_secs(KEY_SET_TIMEOUT)) {
+            Ok(key_set) => key_set.secrets(),
+            Err(e) => {
+                warn_!("Keeping the current keys: {}", e);
+                return false;
+            }
+        };
+        if fetched.is_empty() {
+            warn_!("Keeping the current keys: the key set fetched from {} has no keys", url);
+            return false;
+        }
+        self.secrets.write().unwrap_or_else(|e| e.into_inner()).secrets = fetched;
+        true
+    }
+
+    /// Decode the token with the first key that verifies its signature
+    fn decode(&self, token: &str) -> Option<jwt::JWT<JsonValue, jwt::Empty>> {
+        let secrets = self.secrets.read().unwrap_or_else(|e| e.into_inner());
+        secrets
+            .secrets
+            .iter()
+            .filter_map(|secret| {
+                jwt::JWT::<JsonValue, jwt::Empty>::new_encoded(token)
+                    .into_decoded(secret, self.signature_algorithm)
+                    .ok()
+            })
+            .next()
+    }
+
+    /// Verify an encoded access token. The signature must be verified by one of the keys. If it is not, the key set
+    /// is fetched again in case the token is signed with a new key.
+    pub fn verify<T: DeserializeOwned>(&self, token: &str) -> Result<VerifiedToken<T>, Error> {
+        let decoded = match self.decode(token) {
+            Some(decoded) => Some(decoded),
+            None if self.refresh_keys() => self.decode(token),
+            None => None,
+        };
+        let decoded = decoded.ok_or_else(|| Error::InvalidToken("The signature of the token is invalid".to_string()))?;
+        let claims_set = decoded.payload().map_err(
+            |e| Error::InvalidToken(e.to_string()),
+        )?;
+        self.validate(&claims_set.registered)?;
+        if oidc::is_id_token(&claims_set.private) {
+            Err(Error::InvalidToken("ID tokens are not access tokens".to_string()))?
+        }
+
+        let access = match claims_set.private.get("access") {
+            Some(access) => {
+                serde_json::from_value(access.clone()).map_err(|e| {
+                    Error::InvalidToken(format!("The `access` claim is invalid: {}", e))
+                })?
+            }
+            None => vec![],
+        };
+        let private = serde_json::from_value(claims_set.private.clone()).map_err(|e| {
+            Error::InvalidToken(format!("The private claims are invalid: {}", e))
+        })?;
+
+        Ok(VerifiedToken {
+            registered: claims_set.registered.clone(),
+            private,
+            access,
+        })
+    }
+
+    fn validate(&self, registered: &jwt::RegisteredClaims) -> Result<(), Error> {
+        let invalid = |description: &str| Err(Error::InvalidToken(description.to_string()));
+
+        if registered.issuer.as_ref() != Some(&self.issuer) {
+            return invalid("The token has an invalid issuer");
+        }
+        match registered.audience {
+            Some(ref audience) if audience.contains(&self.service) => {}
+            _ => return invalid("The token is not intended for this service"),
+        }
+
+        let options = token::ValidationOptions {
+            leeway: self.leeway,
+            issued_at_required: false,
+            not_before_required: false,
+            expiry_required: true,
+        };
+        token::validate_times(registered, Utc::now(), &options).map_err(|e| Error::InvalidToken(e.to_string()))
+    }
+}
+
+/// The claims of a verified access token. This is a request guard that requires a [`Verifier`](struct.Verifier.html)
+/// to be managed by Rocket.
+#[derive(Clone, Debug)]
+pub struct VerifiedToken<T> {
+    /// The registered claims
+    pub registered: jwt::RegisteredClaims,
+    /// The private claims
+    pub private: T,
+    /// The Docker Registry `access` claim, if any
+    pub access: Vec<Access>,
+}
+
+impl<T> VerifiedToken<T> {
+    /// Whether the `access` claim allows `action` on the resource of `kind` and `name`
+    pub fn allows(&self, kind: &str, name: &str, action: &str) -> bool {
+        self.access.iter().any(|access| access.allows(kind, name, action))
+    }
+
+    /// Return an `Err(Error::InsufficientScope)` unless the `access` claim allows `action` on the resource of `kind`
+    /// and `name`
+    pub fn require_access(&self, kind: &str, name: &str, action: &str) -> Result<(), Error> {
+        if self.allows(kind, name, action) {
+            Ok(())
+        } else {
+            Err(Error::InsufficientScope { scope: format!("{}:{}:{}", kind, name, action) })
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+impl<'a, 'r, T: DeserializeOwned> FromRequest<'a, 'r> for VerifiedToken<T> {
+    type Error = Error;
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Error> {
+        let verifier = match request.guard::<State<Verifier>>() {
+            Outcome::Success(verifier) => verifier,
+            _ => {
+                error_!("A `Verifier` must be managed by Rocket to verify tokens");
+                return Outcome::Failure((Status::InternalServerError, Error::NotManaged));
+            }
+        };
+        let authorization = match request.headers().get_one("Authorization") {
+            Some(authorization) => authorization,
+            None => return Outcome::Failure((Status::Unauthorized, Error::MissingToken)),
+        };
+        let result = auth::Authorization::<auth::Bearer>::new(authorization)
+            .map_err(|_| Error::InvalidToken("The `Authorization` header is not a bearer token".to_string()))
+            .and_then(|authorization| verifier.verify(&authorization.token()));
+        match result {
+            Ok(token) => Outcome::Success(token),
+            Err(e) => {
+                debug_!("Token verification failed: {}", e);
+                Outcome::Failure((e.status(), e))
+            }
+        }
+    }
+}
+
+/// Errors of token verification
+#[derive(Debug)]
+pub enum Error {
+    /// The request has no `Authorization` header
+    MissingToken,
+    /// The token is malformed, or its signature or claims are invalid
+    InvalidToken(String),
+    /// The token does not allow the request
+    InsufficientScope {
+        /// The `access` needed, as `type:name:action`
+        scope: String,
+    },
+    /// No `Verifier` is managed by Rocket
+    NotManaged,
+}
+
+impl Error {
+    /// The HTTP status of the error
+    #[cfg(feature = "server")]
+    pub fn status(&self) -> Status {
+        match *self {
+            Error::MissingToken |
+            Error::InvalidToken(_) => Status::Unauthorized,
+            Error::InsufficientScope { .. } => Status::Forbidden,
+            Error::NotManaged => Status::InternalServerError,
+        }
+    }
+
+    /// The value of the `WWW-Authenticate` header for the error
+    pub fn challenge(&self, realm: Option<&str>) -> String {
+        let mut parameters = vec![];
+        if let Some(realm) = realm {
+            parameters.push(("realm", realm));
+        }
+        match *self {
+            Error::InvalidToken(ref description) => {
+                parameters.push(("error", "invalid_token"));
+                parameters.push(("error_description", description.as_str()));
+            }
+            Error::InsufficientScope { ref scope } => {
+                parameters.push(("error", "insufficient_scope"));
+                parameters.push(("scope", scope.as_str()));
+            }
+            Error::MissingToken | Error::NotManaged => {}
+        }
+        ::challenge("Bearer", &parameters)
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::MissingToken => "The request header `Authorization` is required but is missing",
+            Error::InvalidToken(ref description) => description,
+            Error::InsufficientScope { .. } => "The token does not allow the request",
+            Error::NotManaged => "No `Verifier` is managed by Rocket",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InsufficientScope { ref scope } => {
+                write!(f, "{}: `{}` is required", error::Error::description(self), scope)
+            }
+            _ => write!(f, "{}", error::Error::description(self)),
+        }
+    }
+}
+
+#[cfg(feature = "server")]
+impl<'r> Responder<'r> for Error {
+    fn respond_to(self, request: &Request) -> Result<Response<'r>, Status> {
+        if let Error::NotManaged = self {
+            return Err(Status::InternalServerError);
+        }
+        let realm = match request.guard::<State<Verifier>>() {
+            Outcome::Success(verifier) => verifier.realm.clone(),
+            _ => None,
+        };
+        let challenge = self.challenge(realm.as_ref().map(String::as_str));
+        Response::build()
+            .status(self.status())
+            .header(Header::new("WWW-Authenticate", challenge))
+            .ok()
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::{self, DateTime};
+    use rocket::{self, Rocket};
+    use rocket::local::Client;
+
+    use super::*;
+    use ByteSequence;
+
+    #[get("/")]
+    fn protected(token: Result<VerifiedToken<JsonValue>, Error>) -> Result<String, Error> {
+        let token = token?;
+        Ok(token.registered.subject.map(|subject| subject.to_string()).unwrap_or_default())
+    }
+
+    #[get("/other")]
+    fn other() -> Status {
+        Status::Unauthorized
+    }
+
+    #[error(401)]
+    fn unauthorized() -> &'static str {
+        "Unauthorized by the application"
+    }
+
+    fn verifier() -> Verifier {
+        not_err!(Verifier::new(Configuration {
+            issuer: not_err!(FromStr::from_str("https://www.acme.com")),
+            service: not_err!(FromStr::from_str("https://www.example.com")),
+            signature_algorithm: Some(jwa::SignatureAlgorithm::HS512),
+            keys: KeySource::Secret(Secret::ByteSequence(ByteSequence::String("secret".to_string()))),
+            leeway: Duration::from_secs(60),
+            realm: Some("https://www.acme.com/token".to_string()),
+            key_refresh_interval: Duration::from_secs(300),
+        }))
+    }
+
+    fn ignite() -> Rocket {
+        rocket::ignite()
+            .manage(verifier())
+            .mount("/", routes![protected, other])
+            .catch(errors![unauthorized])
+    }
+
+    fn make_token(audience: &str, expiry: DateTime<Utc>, private: JsonValue) -> String {
+        let now = Utc::now();
+        let header = jws::Header::from_registered_header(jws::RegisteredHeader {
+            algorithm: jwa::SignatureAlgorithm::HS512,
+            ..Default::default()
+        });
+        let claims_set = jwt::ClaimsSet {
+            registered: jwt::RegisteredClaims {
+                issuer: Some(not_err!(FromStr::from_str("https://www.acme.com"))),
+                subject: Some(not_err!(FromStr::from_str("mei"))),
+                audience: Some(jwt::SingleOrMultiple::Single(not_err!(FromStr::from_str(audience)))),
+                issued_at: Some(now.into()),
+                not_before: Some(now.into()),
+                expiry: Some(expiry.into()),
+                ..Default::default()
+            },
+            private,
+        };
+        let token = jwt::JWT::new_decoded(header, claims_set);
+        let token = not_err!(token.into_encoded(&jws::Secret::Bytes(b"secret".to_vec())));
+        not_err!(token.encoded()).to_string()
+    }
+
+    fn request(client: &Client, token: Option<&str>) -> (Status, Option<String>, Option<String>) {
+        let mut request = client.get("/");
+        if let Some(token) = token {
+            request = request.header(Header::new("Authorization", format!("Bearer {}", token)));
+        }
+        let mut response = request.dispatch();
+        let challenge = response.headers().get_one("WWW-Authenticate").map(|s| s.to_string());
+        let body = response.body().and_then(|body| body.into_string());
+        (response.status(), challenge, body)
+    }
+
+    #[test]
+    fn valid_tokens_are_verified() {
+        let client = not_err!(Client::new(ignite()));
+        let expiry = Utc::now() + chrono::Duration::minutes(5);
+        let token = make_token("https://www.example.com", expiry, json_object());
+
+        let (status, _, body) = request(&client, Some(&token));
+        assert_eq!(status, Status::Ok);
+        assert_eq!(body, Some("mei".to_string()));
+    }
+
+    #[test]
+    fn expired_tokens_are_rejected_after_the_leeway() {
+        let client = not_err!(Client::new(ignite()));
+
+        // Within the leeway
+        let token = make_token("https://www.example.com", Utc::now() - chrono::Duration::seconds(30), json_object());
+        let (status, _, _) = request(&client, Some(&token));
+        assert_eq!(status, Status::Ok);
+
+        let token = make_token("https://www.example.com", Utc::now() - chrono::Duration::minutes(5), json_object());
+        let (status, challenge, _) = request(&client, Some(&token));
+        assert_eq!(status, Status::Unauthorized);
+        let challenge = not_none!(challenge);
+        assert!(challenge.starts_with("Bearer realm=\"https://www.acme.com/token\""));
+        assert!(challenge.contains("error=\"invalid_token\""));
+    }
+
+    #[test]
+    fn tokens_for_other_services_are_rejected() {
+        let verifier = verifier();
+        let expiry = Utc::now() + chrono::Duration::minutes(5);
+        let token = make_token("https://www.foobar.com", expiry, json_object());
+
+        let error = verifier.verify::<JsonValue>(&token).unwrap_err();
+        assert_matches_non_debug!(error, Error::InvalidToken(_));
+    }
+
+    #[test]
+    fn id_tokens_are_rejected() {
+        let verifier = verifier();
+        let expiry = Utc::now() + chrono::Duration::minutes(5);
+        let private = not_err!(serde_json::from_str(r#"{"token_use": "id", "name": "Mei"}"#));
+        let token = make_token("https://www.example.com", expiry, private);
+
+        let error = verifier.verify::<JsonValue>(&token).unwrap_err();
+        assert_matches_non_debug!(error, Error::InvalidToken(_));
+    }
+
+    #[test]
+    fn tokens_with_invalid_signatures_are_rejected() {
+        let client = not_err!(Client::new(ignite()));
+        let expiry = Utc::now() + chrono::Duration::minutes(5);
+        let token = make_token("https://www.example.com", expiry, json_object());
+        let token = format!("{}x", token);
+
+        let (status, challenge, _) = request(&client, Some(&token));
+        assert_eq!(status, Status::Unauthorized);
+        assert!(not_none!(challenge).contains("error=\"invalid_token\""));
+    }
+
+    #[test]
+    fn missing_tokens_are_challenged_without_an_error() {
+        let client = not_err!(Client::new(ignite()));
+
+        let (status, challenge, _) = request(&client, None);
+        assert_eq!(status, Status::Unauthorized);
+        assert_eq!(challenge, Some("Bearer realm=\"https://www.acme.com/token\"".to_string()));
+    }
+
+    #[test]
+    fn unauthorized_responses_of_the_application_are_left_alone() {
+        let client = not_err!(Client::new(ignite()));
+
+        let mut response = client.get("/other").dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert!(response.headers().get_one("WWW-Authenticate").is_none());
+        assert_eq!(
+            response.body().and_then(|body| body.into_string()),
+            Some("Unauthorized by the application".to_string())
+        );
+    }
+
+    #[test]
+    fn key_set_urls_require_the_insecure_opt_in() {
+        let json = r#"{
+            "issuer": "https://www.acme.com",
+            "service": "https://www.example.com",
+            "keys": { "KeySet": { "url": "http://rowdy/jwks", "path": "jwks.json" } }
+        }"#;
+        let configuration: Configuration = not_err!(serde_json::from_str(json));
+        match Verifier::new(configuration) {
+            Err(::Error::ConfigurationError(_)) => {}
+            _ => panic!("Expected a configuration error"),
+        }
+    }
+
+    #[test]
+    fn keys_are_not_refreshed_without_a_key_set_url() {
+        let verifier = verifier();
+        assert!(!verifier.refresh_keys());
+    }
+
+    #[test]
+    fn access_is_checked() {
+        let verifier = verifier();
+        let expiry = Utc::now() + chrono::Duration::minutes(5);
+        let private = not_err!(serde_json::from_str(
+            r#"{"access": [{"type": "repository", "name": "samalba/my-app", "actions": ["pull"]}]}"#,
+        ));
+        let token = make_token("https://www.example.com", expiry, private);
+        let token = not_err!(verifier.verify::<JsonValue>(&token));
+
+        assert!(token.allows("repository", "samalba/my-app", "pull"));
+        assert!(!token.allows("repository", "samalba/my-app", "push"));
+        not_err!(token.require_access("repository", "samalba/my-app", "pull"));
+        let error = token.require_access("repository", "samalba/other-app", "pull").unwrap_err();
+        assert_eq!(error.status(), Status::Forbidden);
+        assert_eq!(
+            error.challenge(None),
+            "Bearer error=\"insufficient_scope\", scope=\"repository:samalba/other-app:pull\""
+        );
+    }
+
+    fn json_object() -> JsonValue {
+        JsonValue::Object(Default::default())
+    }
+}